@@ -1,10 +1,8 @@
 #![deny(unaligned_references)]
 #![allow(dead_code)]
 
-use std::borrow::Cow;
 use std::cmp::{max, min};
 use std::collections::BTreeSet;
-use std::convert::identity;
 use std::mem::size_of;
 use std::num::NonZeroU64;
 use std::sync::{Arc, Mutex};
@@ -14,33 +12,36 @@ use std::{thread, time};
 use anyhow::{format_err, Result};
 use clap::Parser;
 use debug_print::debug_println;
-use enumflags2::BitFlags;
 use log::{error, info};
 use rand::rngs::OsRng;
-use safe_transmute::{
-    guard::SingleManyGuard,
-    to_bytes::{transmute_one_to_bytes, transmute_to_bytes},
-    transmute_many, transmute_many_pedantic, transmute_one_pedantic,
-};
+use safe_transmute::to_bytes::transmute_to_bytes;
 use sloggers::file::FileLoggerBuilder;
 use sloggers::types::Severity;
 use sloggers::Build;
+use solana_client::client_error::{ClientError, ClientErrorKind};
 use solana_client::rpc_client::RpcClient;
-use solana_client::rpc_config::RpcSendTransactionConfig;
+use solana_client::rpc_config::{
+    RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcSendTransactionConfig,
+};
+use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
 use solana_sdk::commitment_config::CommitmentConfig;
-use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::instruction::{AccountMeta, Instruction, InstructionError};
 use solana_sdk::program_pack::Pack;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Signature;
 use solana_sdk::signature::{Keypair, Signer};
-use solana_sdk::transaction::Transaction;
+use solana_sdk::transaction::{Transaction, TransactionError};
 use spl_token::instruction as token_instruction;
 use warp::Filter;
 
 use serum_common::client::rpc::{
-    create_and_init_mint, create_token_account, mint_to_new_account, send_txn, simulate_transaction,
+    create_and_init_mint, create_token_account, mint_to_new_account, simulate_transaction,
 };
 use serum_common::client::Cluster;
+use serum_dex::client::{
+    decode_event_queue, decode_market, decode_open_orders, decode_request_queue, AccountLayout,
+};
+use serum_dex::error::DecodedDexError;
 use serum_dex::instruction::{
     cancel_order_by_client_order_id as cancel_order_by_client_order_id_ix,
     cancel_orders_by_client_order_ids as cancel_orders_by_client_order_ids_ix,
@@ -49,17 +50,33 @@ use serum_dex::instruction::{
 };
 use serum_dex::matching::{OrderType, Side};
 use serum_dex::state::gen_vault_signer_key;
-use serum_dex::state::Event;
-use serum_dex::state::EventQueueHeader;
-use serum_dex::state::QueueHeader;
-use serum_dex::state::Request;
-use serum_dex::state::RequestQueueHeader;
-use serum_dex::state::{AccountFlag, Market, MarketState, MarketStateV2};
+use serum_dex::state::{AccountFlag, EventView};
 
 pub fn with_logging<F: FnOnce()>(_to: &str, fnc: F) {
     fnc();
 }
 
+/// Names the DEX error behind a failed instruction, so logs show the
+/// `DexErrorCode` or the failed assertion instead of a bare custom code.
+fn describe_transaction_error(err: &TransactionError) -> String {
+    if let TransactionError::InstructionError(index, InstructionError::Custom(custom)) = err {
+        if let Some(decoded) = DecodedDexError::from_custom(*custom) {
+            return format!("instruction {} failed with {}", index, decoded);
+        }
+    }
+    format!("{:?}", err)
+}
+
+fn send_txn(client: &RpcClient, txn: &Transaction, simulate: bool) -> Result<Signature> {
+    serum_common::client::rpc::send_txn(client, txn, simulate).map_err(|e| {
+        let description = match e.downcast_ref::<ClientError>().map(ClientError::kind) {
+            Some(ClientErrorKind::TransactionError(err)) => describe_transaction_error(err),
+            _ => return e,
+        };
+        e.context(description)
+    })
+}
+
 fn read_keypair_file(s: &str) -> Result<Keypair> {
     solana_sdk::signature::read_keypair_file(s)
         .map_err(|_| format_err!("failed to read keypair from {}", s))
@@ -169,6 +186,9 @@ pub enum Command {
         dex_program_id: Pubkey,
         market: Pubkey,
     },
+    PrintOpenOrders {
+        orders: Pubkey,
+    },
     WholeShebang {
         payer: String,
         dex_program_id: Pubkey,
@@ -310,11 +330,13 @@ pub fn start(opts: Opts) -> Result<()> {
         } => {
             let market_keys = get_keys_for_market(&client, dex_program_id, &market)?;
             let event_q_data = client.get_account_data(&market_keys.event_q)?;
-            let inner: Cow<[u64]> = remove_dex_account_padding(&event_q_data)?;
-            let (header, events_seg0, events_seg1) = parse_event_queue(&inner)?;
-            debug_println!("Header:\n{:#x?}", header);
-            debug_println!("Seg0:\n{:#x?}", events_seg0);
-            debug_println!("Seg1:\n{:#x?}", events_seg1);
+            let events = decode_event_queue(&event_q_data)?;
+            debug_println!("Events:\n{:#x?}", events);
+        }
+        Command::PrintOpenOrders { ref orders } => {
+            let orders_data = client.get_account_data(orders)?;
+            let open_orders = decode_open_orders(&orders_data)?;
+            println!("{:#?}", open_orders);
         }
         Command::WholeShebang {
             ref dex_program_id,
@@ -388,112 +410,71 @@ pub struct MarketPubkeys {
     pub coin_vault: Box<Pubkey>,
     pub pc_vault: Box<Pubkey>,
     pub vault_signer_key: Box<Pubkey>,
+    pub trigger_book: Option<Box<Pubkey>>,
 }
 
-#[cfg(target_endian = "little")]
-fn remove_dex_account_padding<'a>(data: &'a [u8]) -> Result<Cow<'a, [u64]>> {
-    use serum_dex::state::{ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING};
-    let head = &data[..ACCOUNT_HEAD_PADDING.len()];
-    if data.len() < ACCOUNT_HEAD_PADDING.len() + ACCOUNT_TAIL_PADDING.len() {
-        return Err(format_err!(
-            "dex account length {} is too small to contain valid padding",
-            data.len()
-        ));
-    }
-    if head != ACCOUNT_HEAD_PADDING {
-        return Err(format_err!("dex account head padding mismatch"));
-    }
-    let tail = &data[data.len() - ACCOUNT_TAIL_PADDING.len()..];
-    if tail != ACCOUNT_TAIL_PADDING {
-        return Err(format_err!("dex account tail padding mismatch"));
-    }
-    let inner_data_range = ACCOUNT_HEAD_PADDING.len()..(data.len() - ACCOUNT_TAIL_PADDING.len());
-    let inner: &'a [u8] = &data[inner_data_range];
-    let words: Cow<'a, [u64]> = match transmute_many_pedantic::<u64>(inner) {
-        Ok(word_slice) => Cow::Borrowed(word_slice),
-        Err(transmute_error) => {
-            let word_vec = transmute_error.copy().map_err(|e| e.without_src())?;
-            Cow::Owned(word_vec)
-        }
-    };
-    Ok(words)
-}
-
-#[cfg(target_endian = "little")]
 fn get_keys_for_market<'a>(
     client: &'a RpcClient,
     program_id: &'a Pubkey,
     market: &'a Pubkey,
 ) -> Result<MarketPubkeys> {
     let account_data: Vec<u8> = client.get_account_data(&market)?;
-    let words: Cow<[u64]> = remove_dex_account_padding(&account_data)?;
-    let market_state: MarketState = {
-        let account_flags = Market::account_flags(&account_data)?;
-        if account_flags.intersects(AccountFlag::Permissioned) {
-            let state = transmute_one_pedantic::<MarketStateV2>(transmute_to_bytes(&words))
-                .map_err(|e| e.without_src())?;
-            state.check_flags(true)?;
-            state.inner
-        } else {
-            let state = transmute_one_pedantic::<MarketState>(transmute_to_bytes(&words))
-                .map_err(|e| e.without_src())?;
-            state.check_flags(true)?;
-            state
-        }
-    };
+    let market_state = decode_market(&account_data)?;
     let vault_signer_key =
         gen_vault_signer_key(market_state.vault_signer_nonce, market, program_id)?;
-    assert_eq!(
-        transmute_to_bytes(&identity(market_state.own_address)),
-        market.as_ref()
-    );
+    let trigger_book = if market_state.account_flags.contains(AccountFlag::Triggers) {
+        get_trigger_book(client, program_id, market)?.map(Box::new)
+    } else {
+        None
+    };
+    assert_eq!(&market_state.own_address, market);
     Ok(MarketPubkeys {
         market: Box::new(*market),
-        req_q: Box::new(Pubkey::new(transmute_one_to_bytes(&identity(
-            market_state.req_q,
-        )))),
-        event_q: Box::new(Pubkey::new(transmute_one_to_bytes(&identity(
-            market_state.event_q,
-        )))),
-        bids: Box::new(Pubkey::new(transmute_one_to_bytes(&identity(
-            market_state.bids,
-        )))),
-        asks: Box::new(Pubkey::new(transmute_one_to_bytes(&identity(
-            market_state.asks,
-        )))),
-        coin_vault: Box::new(Pubkey::new(transmute_one_to_bytes(&identity(
-            market_state.coin_vault,
-        )))),
-        pc_vault: Box::new(Pubkey::new(transmute_one_to_bytes(&identity(
-            market_state.pc_vault,
-        )))),
+        req_q: Box::new(market_state.req_q),
+        event_q: Box::new(market_state.event_q),
+        bids: Box::new(market_state.bids),
+        asks: Box::new(market_state.asks),
+        coin_vault: Box::new(market_state.coin_vault),
+        pc_vault: Box::new(market_state.pc_vault),
         vault_signer_key: Box::new(vault_signer_key),
+        trigger_book,
     })
 }
 
-fn parse_event_queue(data_words: &[u64]) -> Result<(EventQueueHeader, &[Event], &[Event])> {
-    let (header_words, event_words) = data_words.split_at(size_of::<EventQueueHeader>() >> 3);
-    let header: EventQueueHeader =
-        transmute_one_pedantic(transmute_to_bytes(header_words)).map_err(|e| e.without_src())?;
-    let events: &[Event] = transmute_many::<_, SingleManyGuard>(transmute_to_bytes(event_words))
-        .map_err(|e| e.without_src())?;
-    let (tail_seg, head_seg) = events.split_at(header.head() as usize);
-    let head_len = head_seg.len().min(header.count() as usize);
-    let tail_len = header.count() as usize - head_len;
-    Ok((header, &head_seg[..head_len], &tail_seg[..tail_len]))
+// Finds a market's trigger book by the market address in its header.
+fn get_trigger_book(
+    client: &RpcClient,
+    program_id: &Pubkey,
+    market: &Pubkey,
+) -> Result<Option<Pubkey>> {
+    use serum_dex::state::ACCOUNT_HEAD_PADDING;
+    let flags = (AccountFlag::Initialized | AccountFlag::Triggers).bits();
+    let memcmp = |offset: usize, bytes: &[u8]| {
+        RpcFilterType::Memcmp(Memcmp {
+            offset: ACCOUNT_HEAD_PADDING.len() + offset,
+            bytes: MemcmpEncodedBytes::Bytes(bytes.to_vec()),
+            encoding: None,
+        })
+    };
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![
+            memcmp(0, &flags.to_le_bytes()),
+            memcmp(size_of::<u64>(), market.as_ref()),
+        ]),
+        account_config: RpcAccountInfoConfig::default(),
+        with_context: None,
+    };
+    let accounts = client.get_program_accounts_with_config(program_id, config)?;
+    Ok(accounts.first().map(|(key, _)| *key))
 }
 
-fn parse_req_queue(data_words: &[u64]) -> Result<(RequestQueueHeader, &[Request], &[Request])> {
-    let (header_words, request_words) = data_words.split_at(size_of::<RequestQueueHeader>() >> 3);
-    let header: RequestQueueHeader =
-        transmute_one_pedantic(transmute_to_bytes(header_words)).map_err(|e| e.without_src())?;
-    let request: &[Request] =
-        transmute_many::<_, SingleManyGuard>(transmute_to_bytes(request_words))
-            .map_err(|e| e.without_src())?;
-    let (tail_seg, head_seg) = request.split_at(header.head() as usize);
-    let head_len = head_seg.len().min(header.count() as usize);
-    let tail_len = header.count() as usize - head_len;
-    Ok((header, &head_seg[..head_len], &tail_seg[..tail_len]))
+// The OpenOrders account an event settles into.
+fn event_owner(event: &EventView) -> [u64; 4] {
+    match *event {
+        EventView::Fill { owner, .. }
+        | EventView::Out { owner, .. }
+        | EventView::FillDetail { owner, .. } => owner,
+    }
 }
 
 fn hash_accounts(val: &[u64; 4]) -> u64 {
@@ -561,12 +542,9 @@ fn consume_events_loop(
             .value
             .ok_or(format_err!("Failed to retrieve account"))?
             .data;
-        let inner: Cow<[u64]> = remove_dex_account_padding(&event_q_data)?;
-        let (_header, seg0, seg1) = parse_event_queue(&inner)?;
-        let req_inner: Cow<[u64]> = remove_dex_account_padding(&req_q_data)?;
-        let (_req_header, req_seg0, req_seg1) = parse_event_queue(&req_inner)?;
-        let event_q_len = seg0.len() + seg1.len();
-        let req_q_len = req_seg0.len() + req_seg1.len();
+        let events = decode_event_queue(&event_q_data)?;
+        let event_q_len = events.len();
+        let req_q_len = decode_request_queue(&req_q_data)?.len();
         info!(
             "Size of request queue is {}, market {}, coin {}, pc {}",
             req_q_len, market, coin_wallet, pc_wallet
@@ -591,7 +569,7 @@ fn consume_events_loop(
                 "Total event queue length: {}, market {}, coin {}, pc {}",
                 event_q_len, market, coin_wallet, pc_wallet
             );
-            let accounts = seg0.iter().chain(seg1.iter()).map(|event| event.owner);
+            let accounts = events.iter().map(event_owner);
             let mut used_accounts = BTreeSet::new();
             for account in accounts {
                 used_accounts.insert(account);
@@ -631,6 +609,9 @@ fn consume_events_loop(
             {
                 account_metas.push(AccountMeta::new(**pubkey, false));
             }
+            if let Some(trigger_book) = &market_keys.trigger_book {
+                account_metas.push(AccountMeta::new(**trigger_book, false));
+            }
             debug_println!("Number of workers: {}", num_workers);
             let end_time = std::time::Instant::now();
             info!(
@@ -784,16 +765,15 @@ pub fn consume_events_instruction(
     pc_wallet: &Pubkey,
 ) -> Result<Option<Instruction>> {
     let event_q_data = client.get_account_data(&state.event_q)?;
-    let inner: Cow<[u64]> = remove_dex_account_padding(&event_q_data)?;
-    let (_header, seg0, seg1) = parse_event_queue(&inner)?;
+    let events = decode_event_queue(&event_q_data)?;
 
-    if seg0.len() + seg1.len() == 0 {
+    if events.is_empty() {
         info!("Total event queue length: 0, returning early");
         return Ok(None);
     } else {
-        info!("Total event queue length: {}", seg0.len() + seg1.len());
+        info!("Total event queue length: {}", events.len());
     }
-    let accounts = seg0.iter().chain(seg1.iter()).map(|event| event.owner);
+    let accounts = events.iter().map(event_owner);
     let mut orders_accounts: Vec<_> = accounts.collect();
     orders_accounts.sort_unstable();
     orders_accounts.dedup();
@@ -809,6 +789,9 @@ pub fn consume_events_instruction(
     for pubkey in [&state.market, &state.event_q, coin_wallet, pc_wallet].iter() {
         account_metas.push(AccountMeta::new(**pubkey, false));
     }
+    if let Some(trigger_book) = &state.trigger_book {
+        account_metas.push(AccountMeta::new(**trigger_book, false));
+    }
 
     let instruction_data: Vec<u8> =
         MarketInstruction::ConsumeEvents(account_metas.len() as u16).pack();
@@ -865,7 +848,7 @@ fn whole_shebang(client: &RpcClient, program_id: &Pubkey, payer: &Keypair) -> Re
     let mut orders = None;
 
     debug_println!("Initializing open orders");
-    init_open_orders(client, program_id, payer, &market_keys, &mut orders)?;
+    init_open_orders(client, program_id, payer, &market_keys, &mut orders, None)?;
 
     debug_println!("Placing successful bid...");
     let now = SystemTime::now()
@@ -917,9 +900,17 @@ fn whole_shebang(client: &RpcClient, program_id: &Pubkey, payer: &Keypair) -> Re
     );
     assert!(result.is_err());
 
-    debug_println!("Placing 3 offers...");
+    debug_println!("Placing 3 offers from an account with room for more slots...");
 
     let mut orders = None;
+    init_open_orders(
+        client,
+        program_id,
+        payer,
+        &market_keys,
+        &mut orders,
+        Some(256),
+    )?;
 
     for i in 0..3 {
         place_order(
@@ -945,6 +936,15 @@ fn whole_shebang(client: &RpcClient, program_id: &Pubkey, payer: &Keypair) -> Re
     }
 
     debug_println!("Ask account: {}", orders.unwrap());
+    let open_orders = decode_open_orders(&client.get_account_data(&orders.unwrap())?)?;
+    assert_eq!(open_orders.layout, AccountLayout::OpenOrdersV2);
+    assert_eq!(open_orders.slot_capacity, 256);
+    let client_order_ids: Vec<u64> = open_orders
+        .orders
+        .iter()
+        .map(|order| order.client_order_id)
+        .collect();
+    assert_eq!(client_order_ids, [985982, 985983, 985984]);
 
     // Cancel 1st open offer (985982), 3rd open offer (985984), and fake orders
     cancel_orders_by_client_order_ids(
@@ -1021,7 +1021,10 @@ pub fn cancel_order_by_client_order_id(
     let result = simulate_transaction(client, &txn, true, CommitmentConfig::confirmed())?;
     debug_println!("{:#?}", result.value.logs);
     if let Some(e) = result.value.err {
-        return Err(format_err!("simulate_transaction error: {:?}", e));
+        return Err(format_err!(
+            "simulate_transaction error: {}",
+            describe_transaction_error(&e)
+        ));
     }
 
     send_txn(client, &txn, false)?;
@@ -1053,7 +1056,10 @@ pub fn cancel_orders_by_client_order_ids(
     let result = simulate_transaction(client, &txn, true, CommitmentConfig::confirmed())?;
     debug_println!("{:#?}", result.value.logs);
     if let Some(e) = result.value.err {
-        return Err(format_err!("simulate_transaction error: {:?}", e));
+        return Err(format_err!(
+            "simulate_transaction error: {}",
+            describe_transaction_error(&e)
+        ));
     }
 
     send_txn(client, &txn, false)?;
@@ -1083,7 +1089,10 @@ pub fn close_open_orders(
     let result = simulate_transaction(client, &txn, true, CommitmentConfig::confirmed())?;
     if let Some(e) = result.value.err {
         debug_println!("{:#?}", result.value.logs);
-        return Err(format_err!("simulate_transaction error: {:?}", e));
+        return Err(format_err!(
+            "simulate_transaction error: {}",
+            describe_transaction_error(&e)
+        ));
     }
 
     send_txn(client, &txn, false)?;
@@ -1096,6 +1105,7 @@ pub fn init_open_orders(
     owner: &Keypair,
     state: &MarketPubkeys,
     orders: &mut Option<Pubkey>,
+    slot_capacity: Option<usize>,
 ) -> Result<()> {
     let mut instructions = Vec::new();
     let orders_keypair;
@@ -1103,12 +1113,13 @@ pub fn init_open_orders(
     let orders_pubkey = match *orders {
         Some(pk) => pk,
         None => {
-            let (orders_key, instruction) = create_dex_account(
-                client,
-                program_id,
-                &owner.pubkey(),
-                size_of::<serum_dex::state::OpenOrders>(),
-            )?;
+            // Accounts larger than the default layout get more order slots.
+            let unpadded_len = match slot_capacity {
+                Some(slot_capacity) => serum_dex::state::OpenOrdersV2::data_len(slot_capacity),
+                None => size_of::<serum_dex::state::OpenOrders>(),
+            };
+            let (orders_key, instruction) =
+                create_dex_account(client, program_id, &owner.pubkey(), unpadded_len)?;
             orders_keypair = orders_key;
             signers.push(&orders_keypair);
             instructions.push(instruction);
@@ -1243,7 +1254,10 @@ fn settle_funds(
         debug_println!("Simulating SettleFunds instruction ...");
         let result = simulate_transaction(client, &txn, true, CommitmentConfig::single())?;
         if let Some(e) = result.value.err {
-            return Err(format_err!("simulate_transaction error: {:?}", e));
+            return Err(format_err!(
+                "simulate_transaction error: {}",
+                describe_transaction_error(&e)
+            ));
         }
         debug_println!("{:#?}", result.value);
         if result.value.err.is_none() {
@@ -1302,6 +1316,8 @@ pub fn list_market(
         pc_lot_size,
         vault_signer_nonce,
         100,
+        1,
+        1,
     )?;
     debug_println!(
         "initialize_market_instruction: {:#?}",
@@ -1331,7 +1347,10 @@ pub fn list_market(
     debug_println!("txn:\n{:#x?}", txn);
     let result = simulate_transaction(client, &txn, true, CommitmentConfig::single())?;
     if let Some(e) = result.value.err {
-        return Err(format_err!("simulate_transaction error: {:?}", e));
+        return Err(format_err!(
+            "simulate_transaction error: {}",
+            describe_transaction_error(&e)
+        ));
     }
     debug_println!("{:#?}", result.value);
     debug_println!("Listing {} ...", market_key.pubkey());
@@ -1346,6 +1365,7 @@ pub fn list_market(
         coin_vault: Box::new(coin_vault.pubkey()),
         pc_vault: Box::new(pc_vault.pubkey()),
         vault_signer_key: Box::new(vault_signer_pk),
+        trigger_book: None,
     })
 }
 
@@ -1428,17 +1448,22 @@ pub fn match_orders(
 ) -> Result<()> {
     let instruction_data: Vec<u8> = MarketInstruction::MatchOrders(2).pack();
 
+    let mut accounts = vec![
+        AccountMeta::new(*state.market, false),
+        AccountMeta::new(*state.req_q, false),
+        AccountMeta::new(*state.event_q, false),
+        AccountMeta::new(*state.bids, false),
+        AccountMeta::new(*state.asks, false),
+        AccountMeta::new(*coin_wallet, false),
+        AccountMeta::new(*pc_wallet, false),
+    ];
+    // Armed stop orders are only matched when the trigger book is passed.
+    if let Some(trigger_book) = &state.trigger_book {
+        accounts.push(AccountMeta::new(**trigger_book, false));
+    }
     let instruction = Instruction {
         program_id: *program_id,
-        accounts: vec![
-            AccountMeta::new(*state.market, false),
-            AccountMeta::new(*state.req_q, false),
-            AccountMeta::new(*state.event_q, false),
-            AccountMeta::new(*state.bids, false),
-            AccountMeta::new(*state.asks, false),
-            AccountMeta::new(*coin_wallet, false),
-            AccountMeta::new(*pc_wallet, false),
-        ],
+        accounts,
         data: instruction_data,
     };
 
@@ -1453,7 +1478,10 @@ pub fn match_orders(
     debug_println!("Simulating order matching ...");
     let result = simulate_transaction(&client, &txn, true, CommitmentConfig::single())?;
     if let Some(e) = result.value.err {
-        return Err(format_err!("simulate_transaction error: {:?}", e));
+        return Err(format_err!(
+            "simulate_transaction error: {}",
+            describe_transaction_error(&e)
+        ));
     }
     debug_println!("{:#?}", result.value);
     if result.value.err.is_none() {
@@ -1586,9 +1614,7 @@ async fn read_queue_length_loop(
             .value
             .expect("Failed to retrieve account")
             .data;
-        let inner: Cow<[u64]> = remove_dex_account_padding(&event_q_data).unwrap();
-        let (_header, seg0, seg1) = parse_event_queue(&inner).unwrap();
-        let len = seg0.len() + seg1.len();
+        let len = decode_event_queue(&event_q_data).unwrap().len();
         format!("{{ \"length\": {}  }}", len)
     });
 
@@ -434,7 +434,7 @@ fn run_action<'bump>(
             };
             let (side, order_id, client_order_id) = {
                 if let Some(orders) = owner.open_orders() {
-                    if let Some(side) = orders.slot_side(slot) {
+                    if let Some(side) = orders.slot_side(slot.into()) {
                         (
                             side,
                             orders.orders[slot as usize],
@@ -278,6 +278,8 @@ pub fn setup_market(bump: &Bump, is_permissioned: bool) -> MarketAccounts {
         pc_lot_size,
         vault_signer_nonce,
         pc_dust_threshold,
+        1,
+        1,
     )
     .unwrap();
 
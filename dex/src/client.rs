@@ -0,0 +1,421 @@
+//! Decoders for DEX account data as fetched over RPC, and for instructions
+//! as they appear in transactions. Each account decoder checks the account's
+//! padding and flags and copies what it holds into owned values, so callers
+//! don't need to know the on-chain layouts.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::mem::size_of;
+
+use bytemuck::{cast_slice, from_bytes};
+use enumflags2::BitFlags;
+use serde::Serialize;
+use solana_program::instruction::Instruction;
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+
+use crate::error::{DexError, DexErrorCode, DexResult, SourceFileId};
+use crate::fees::{FeeSchedule, FeeTier};
+use crate::instruction::{AccountRole, AuthorityKind, MarketInstruction};
+use crate::matching::Side;
+use crate::state::{
+    load_book_side, AccountFlag, Event, EventQueueHeader, EventView, MarketState, MarketStateV2,
+    MarketStats, OpenOrders, OpenOrdersAccount, QueueHeader, Request, RequestQueueHeader,
+    RequestView, ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING,
+};
+
+declare_check_assert_macros!(SourceFileId::Client);
+
+/// The kind and layout version of a DEX account.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccountLayout {
+    Market,
+    /// A permissioned market, laid out as `MarketStateV2`.
+    MarketV2,
+    OpenOrders,
+    /// An OpenOrders account with room for more than 128 orders.
+    OpenOrdersV2,
+    Bids,
+    Asks,
+    EventQueue,
+    RequestQueue,
+    TriggerBook,
+}
+
+#[derive(Clone, Debug)]
+pub struct DecodedMarket {
+    pub account_flags: BitFlags<AccountFlag>,
+    pub own_address: Pubkey,
+    pub vault_signer_nonce: u64,
+    pub coin_mint: Pubkey,
+    pub pc_mint: Pubkey,
+    pub coin_vault: Pubkey,
+    pub coin_deposits_total: u64,
+    pub coin_fees_accrued: u64,
+    pub pc_vault: Pubkey,
+    pub pc_deposits_total: u64,
+    pub pc_fees_accrued: u64,
+    pub pc_dust_threshold: u64,
+    pub req_q: Pubkey,
+    pub event_q: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+    pub fee_rate_bps: u64,
+    pub referrer_rebates_accrued: u64,
+    /// The fields only permissioned markets have.
+    pub v2: Option<DecodedMarketV2>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DecodedMarketV2 {
+    pub open_orders_authority: Pubkey,
+    pub prune_authority: Pubkey,
+    pub consume_events_authority: Pubkey,
+    pub fee_schedule: FeeSchedule,
+    pub tick_size: u64,
+    pub min_base_qty: u64,
+    /// Zero on markets initialized before admins existed, which fall back
+    /// to the open orders authority.
+    pub admin: Pubkey,
+    /// Zero unless the market overrides the global key.
+    pub disable_authority: Pubkey,
+    pub fee_sweeper: Pubkey,
+    pub pending_authority: Option<(AuthorityKind, Pubkey)>,
+    pub stats: MarketStats,
+    pub price_band_bps: u64,
+}
+
+/// An order resting in an OpenOrders slot.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OpenOrdersSlotInfo {
+    pub slot: u16,
+    pub side: Side,
+    pub order_id: u128,
+    pub price: u64,
+    pub client_order_id: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct DecodedOpenOrders {
+    pub layout: AccountLayout,
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub native_coin_free: u64,
+    pub native_coin_total: u64,
+    pub native_pc_free: u64,
+    pub native_pc_total: u64,
+    pub referrer_rebates_accrued: u64,
+    pub slot_capacity: usize,
+    /// The filled slots, in slot order.
+    pub orders: Vec<OpenOrdersSlotInfo>,
+}
+
+/// An order resting on the book. For icebergs, `quantity` is the visible
+/// slice and `hidden_quantity` what's left in reserve.
+#[derive(Copy, Clone, Debug)]
+pub struct BookOrder {
+    /// The order's key in the book, which changes when an iceberg replenishes.
+    pub key: u128,
+    /// The id the order was placed with, as its OpenOrders slot holds it.
+    pub order_id: u128,
+    pub price: u64,
+    pub quantity: u64,
+    pub hidden_quantity: u64,
+    pub owner: Pubkey,
+    pub owner_slot: u16,
+    pub client_order_id: u64,
+    pub fee_tier: FeeTier,
+    pub expiry_ts: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct DecodedBookSide {
+    pub side: Side,
+    /// Best price first, then in time priority.
+    pub orders: Vec<BookOrder>,
+}
+
+/// A DEX instruction with its accounts labelled by role.
+#[derive(Clone, Debug, Serialize)]
+pub struct DecodedInstruction {
+    pub name: &'static str,
+    pub instruction: MarketInstruction,
+    pub accounts: Vec<DecodedAccount>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DecodedAccount {
+    /// How the instruction's docs say the account may be used. Accounts the
+    /// program recognizes by owner can't always be told apart by position,
+    /// so those list every role they could have.
+    pub roles: Vec<AccountRole>,
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+/// Decodes an instruction's data and labels each of its accounts with the
+/// roles `MarketInstruction::account_roles` allows at its position. Fails if
+/// the data isn't a DEX instruction or the number of accounts doesn't fit
+/// its roles.
+pub fn describe(instruction: &Instruction) -> Result<DecodedInstruction, DexError> {
+    let market_instruction =
+        MarketInstruction::unpack(&instruction.data).ok_or(ProgramError::InvalidArgument)?;
+    let roles = market_instruction.account_roles();
+    let metas = &instruction.accounts;
+    if metas.len() < roles.required.len() {
+        return Err(ProgramError::NotEnoughAccountKeys.into());
+    }
+    let layouts = roles.layouts(metas.len());
+    if layouts.is_empty() {
+        return Err(ProgramError::InvalidArgument.into());
+    }
+    let accounts = metas
+        .iter()
+        .enumerate()
+        .map(|(i, meta)| {
+            let mut roles: Vec<AccountRole> = Vec::new();
+            for layout in &layouts {
+                if !roles.contains(&layout[i]) {
+                    roles.push(layout[i]);
+                }
+            }
+            DecodedAccount {
+                roles,
+                pubkey: meta.pubkey,
+                is_signer: meta.is_signer,
+                is_writable: meta.is_writable,
+            }
+        })
+        .collect();
+    Ok(DecodedInstruction {
+        name: market_instruction.name(),
+        instruction: market_instruction,
+        accounts,
+    })
+}
+
+pub fn account_layout(account_data: &[u8]) -> DexResult<AccountLayout> {
+    let data = strip_padding(account_data)?;
+    check_assert!(data.len() >= size_of::<u64>())?;
+    let flags = BitFlags::<AccountFlag>::from_bits(u64::from_le_bytes(
+        <[u8; 8]>::try_from(&data[..8]).unwrap(),
+    ))
+    .map_err(|_| DexErrorCode::InvalidMarketFlags)?;
+    if !flags.contains(AccountFlag::Initialized) {
+        return Err(DexErrorCode::InvalidMarketFlags.into());
+    }
+    let layout = if flags.contains(AccountFlag::Market) {
+        if flags.contains(AccountFlag::Permissioned) {
+            AccountLayout::MarketV2
+        } else {
+            AccountLayout::Market
+        }
+    } else if flags.contains(AccountFlag::OpenOrders) {
+        if data.len() == size_of::<OpenOrders>() {
+            AccountLayout::OpenOrders
+        } else {
+            AccountLayout::OpenOrdersV2
+        }
+    } else if flags.contains(AccountFlag::Bids) {
+        AccountLayout::Bids
+    } else if flags.contains(AccountFlag::Asks) {
+        AccountLayout::Asks
+    } else if flags.contains(AccountFlag::EventQueue) {
+        AccountLayout::EventQueue
+    } else if flags.contains(AccountFlag::RequestQueue) {
+        AccountLayout::RequestQueue
+    } else if flags.contains(AccountFlag::Triggers) {
+        AccountLayout::TriggerBook
+    } else {
+        return Err(DexErrorCode::InvalidMarketFlags.into());
+    };
+    Ok(layout)
+}
+
+pub fn decode_market(account_data: &[u8]) -> DexResult<DecodedMarket> {
+    let layout = account_layout(account_data)?;
+    let data = strip_padding(account_data)?;
+    let (state, v2) = match layout {
+        AccountLayout::Market => {
+            check_assert!(data.len() >= size_of::<MarketState>())?;
+            let state: &MarketState = from_bytes(&data[..size_of::<MarketState>()]);
+            (*state, None)
+        }
+        AccountLayout::MarketV2 => {
+            check_assert!(data.len() >= size_of::<MarketStateV2>())?;
+            let state: &MarketStateV2 = from_bytes(&data[..size_of::<MarketStateV2>()]);
+            let pending_authority = match state.pending_authority {
+                key if key == Pubkey::default() => None,
+                key => {
+                    let kind = AuthorityKind::try_from(state.pending_authority_kind as u8)
+                        .or(check_unreachable!())?;
+                    Some((kind, key))
+                }
+            };
+            let v2 = DecodedMarketV2 {
+                open_orders_authority: state.open_orders_authority,
+                prune_authority: state.prune_authority,
+                consume_events_authority: state.consume_events_authority,
+                fee_schedule: state.fee_schedule,
+                tick_size: state.tick_size,
+                min_base_qty: state.min_base_qty,
+                admin: state.admin,
+                disable_authority: state.disable_authority,
+                fee_sweeper: state.fee_sweeper,
+                pending_authority,
+                stats: state.stats,
+                price_band_bps: state.price_band_bps,
+            };
+            (state.inner, Some(v2))
+        }
+        _ => return Err(DexErrorCode::InvalidMarketFlags.into()),
+    };
+    Ok(DecodedMarket {
+        account_flags: BitFlags::from_bits_truncate(state.account_flags),
+        own_address: to_pubkey(state.own_address),
+        vault_signer_nonce: state.vault_signer_nonce,
+        coin_mint: to_pubkey(state.coin_mint),
+        pc_mint: to_pubkey(state.pc_mint),
+        coin_vault: to_pubkey(state.coin_vault),
+        coin_deposits_total: state.coin_deposits_total,
+        coin_fees_accrued: state.coin_fees_accrued,
+        pc_vault: to_pubkey(state.pc_vault),
+        pc_deposits_total: state.pc_deposits_total,
+        pc_fees_accrued: state.pc_fees_accrued,
+        pc_dust_threshold: state.pc_dust_threshold,
+        req_q: to_pubkey(state.req_q),
+        event_q: to_pubkey(state.event_q),
+        bids: to_pubkey(state.bids),
+        asks: to_pubkey(state.asks),
+        coin_lot_size: state.coin_lot_size,
+        pc_lot_size: state.pc_lot_size,
+        fee_rate_bps: state.fee_rate_bps,
+        referrer_rebates_accrued: state.referrer_rebates_accrued,
+        v2,
+    })
+}
+
+pub fn decode_open_orders(account_data: &[u8]) -> DexResult<DecodedOpenOrders> {
+    let layout = account_layout(account_data)?;
+    if !matches!(
+        layout,
+        AccountLayout::OpenOrders | AccountLayout::OpenOrdersV2
+    ) {
+        return Err(DexErrorCode::WrongOrdersAccount.into());
+    }
+    // OpenOrdersAccount borrows its data mutably, so give it a copy.
+    let data = RefCell::new(strip_padding(account_data)?.to_vec());
+    let open_orders = OpenOrdersAccount::new(std::cell::RefMut::map(data.borrow_mut(), |data| {
+        data.as_mut_slice()
+    }))?;
+    let orders = (0..open_orders.slot_capacity())
+        .map(|slot| slot as u16)
+        .filter_map(|slot| {
+            let side = open_orders.slot_side(slot)?;
+            let order_id = open_orders.order_id(slot);
+            Some(OpenOrdersSlotInfo {
+                slot,
+                side,
+                order_id,
+                price: (order_id >> 64) as u64,
+                client_order_id: open_orders.client_order_id(slot),
+            })
+        })
+        .collect();
+    Ok(DecodedOpenOrders {
+        layout,
+        market: to_pubkey(open_orders.market),
+        owner: to_pubkey(open_orders.owner),
+        native_coin_free: open_orders.native_coin_free,
+        native_coin_total: open_orders.native_coin_total,
+        native_pc_free: open_orders.native_pc_free,
+        native_pc_total: open_orders.native_pc_total,
+        referrer_rebates_accrued: open_orders.referrer_rebates_accrued,
+        slot_capacity: open_orders.slot_capacity(),
+        orders,
+    })
+}
+
+pub fn decode_book_side(account_data: &[u8]) -> DexResult<DecodedBookSide> {
+    let side = match account_layout(account_data)? {
+        AccountLayout::Bids => Side::Bid,
+        AccountLayout::Asks => Side::Ask,
+        _ => return Err(DexErrorCode::WrongBidsAccount.into()),
+    };
+    let book = load_book_side(account_data, side)?;
+    let orders = book
+        .iter(side == Side::Bid)
+        .map(|leaf| {
+            let key = leaf.order_id();
+            let reserve = book.find_reserve(key);
+            BookOrder {
+                key,
+                order_id: reserve.map_or(key, |reserve| reserve.order_id()),
+                price: leaf.price().get(),
+                quantity: leaf.quantity(),
+                hidden_quantity: reserve.map_or(0, |reserve| reserve.quantity()),
+                owner: to_pubkey(leaf.owner()),
+                owner_slot: leaf.owner_slot(),
+                client_order_id: leaf.client_order_id(),
+                fee_tier: leaf.fee_tier(),
+                expiry_ts: reserve.map_or(0, |reserve| reserve.expiry_ts()),
+            }
+        })
+        .collect();
+    Ok(DecodedBookSide { side, orders })
+}
+
+/// The events waiting to be consumed, oldest first.
+pub fn decode_event_queue(account_data: &[u8]) -> DexResult<Vec<EventView>> {
+    if account_layout(account_data)? != AccountLayout::EventQueue {
+        return Err(DexErrorCode::WrongEventQueueAccount.into());
+    }
+    queue_items::<EventQueueHeader>(strip_padding(account_data)?)?
+        .iter()
+        .map(Event::as_view)
+        .collect()
+}
+
+/// The requests waiting to be processed, oldest first.
+pub fn decode_request_queue(account_data: &[u8]) -> DexResult<Vec<RequestView>> {
+    if account_layout(account_data)? != AccountLayout::RequestQueue {
+        return Err(DexErrorCode::WrongRequestQueueAccount.into());
+    }
+    queue_items::<RequestQueueHeader>(strip_padding(account_data)?)?
+        .iter()
+        .map(Request::as_view)
+        .collect()
+}
+
+fn strip_padding(account_data: &[u8]) -> DexResult<&[u8]> {
+    let padding_len = ACCOUNT_HEAD_PADDING.len() + ACCOUNT_TAIL_PADDING.len();
+    check_assert!(account_data.len() >= padding_len)?;
+    let (head, rest) = account_data.split_at(ACCOUNT_HEAD_PADDING.len());
+    let (data, tail) = rest.split_at(rest.len() - ACCOUNT_TAIL_PADDING.len());
+    if head != ACCOUNT_HEAD_PADDING {
+        return Err(DexErrorCode::WrongAccountHeadPadding.into());
+    }
+    if tail != ACCOUNT_TAIL_PADDING {
+        return Err(DexErrorCode::WrongAccountTailPadding.into());
+    }
+    Ok(data)
+}
+
+// Copies the items of a ring buffer queue out in order, starting at its head.
+fn queue_items<H: QueueHeader>(data: &[u8]) -> DexResult<Vec<H::Item>> {
+    check_assert!(data.len() >= size_of::<H>())?;
+    let (header, buf) = data.split_at(size_of::<H>());
+    let header: &H = from_bytes(header);
+    let buf: &[H::Item] = cast_slice(&buf[..buf.len() - buf.len() % size_of::<H::Item>()]);
+    check_assert!(header.count() as usize <= buf.len())?;
+    Ok((0..header.count())
+        .map(|index| buf[((header.head() + index) as usize) % buf.len()])
+        .collect())
+}
+
+fn to_pubkey(key: [u64; 4]) -> Pubkey {
+    Pubkey::new_from_array(bytemuck::cast(key))
+}
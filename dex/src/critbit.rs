@@ -15,20 +15,22 @@ use std::{
 
 pub type NodeHandle = u32;
 
-#[derive(IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, IntoPrimitive, TryFromPrimitive)]
 #[repr(u32)]
-enum NodeTag {
+pub(crate) enum NodeTag {
     Uninitialized = 0,
     InnerNode = 1,
     LeafNode = 2,
     FreeNode = 3,
     LastFreeNode = 4,
+    ReserveNode = 5,
+    ExtensionNode = 6,
 }
 
 #[derive(Copy, Clone)]
 #[repr(packed)]
 #[allow(dead_code)]
-struct InnerNode {
+pub(crate) struct InnerNode {
     tag: u32,
     prefix_len: u32,
     key: u128,
@@ -38,6 +40,15 @@ struct InnerNode {
 unsafe impl Zeroable for InnerNode {}
 unsafe impl Pod for InnerNode {}
 
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(InnerNode {
+    tag,
+    prefix_len,
+    key,
+    children,
+    _padding,
+});
+
 impl InnerNode {
     fn walk_down(&self, search_key: u128) -> (NodeHandle, bool) {
         let crit_bit_mask = (1u128 << 127) >> self.prefix_len;
@@ -46,6 +57,27 @@ impl InnerNode {
     }
 }
 
+/// Splits an OpenOrders slot number into the `owner_slot` byte that leaves,
+/// requests, events and trigger orders keep it in and the high nibble of the
+/// byte after it, whose low nibble holds `low_nibble`. Slots below 256 leave
+/// the high nibble clear, so records written before slots went past 255 read
+/// the same.
+#[inline]
+pub(crate) fn split_owner_slot(owner_slot: u16, low_nibble: u8) -> (u8, u8) {
+    debug_assert!(owner_slot < 1 << 12 && low_nibble < 1 << 4);
+    (
+        owner_slot as u8,
+        ((owner_slot >> 8) as u8) << 4 | low_nibble,
+    )
+}
+
+/// The inverse of `split_owner_slot`, without the low nibble.
+#[inline]
+pub(crate) fn join_owner_slot(owner_slot: u8, next_byte: u8) -> u16 {
+    u16::from(next_byte >> 4) << 8 | u16::from(owner_slot)
+}
+
+// The high nibble of `fee_tier` holds bits 8 and up of the owner slot.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(packed)]
 pub struct LeafNode {
@@ -61,20 +93,33 @@ pub struct LeafNode {
 unsafe impl Zeroable for LeafNode {}
 unsafe impl Pod for LeafNode {}
 
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(LeafNode {
+    tag,
+    owner_slot,
+    fee_tier,
+    padding,
+    key,
+    owner,
+    quantity,
+    client_order_id,
+});
+
 impl LeafNode {
     #[inline]
     pub fn new(
-        owner_slot: u8,
+        owner_slot: u16,
         key: u128,
         owner: [u64; 4],
         quantity: u64,
         fee_tier: FeeTier,
         client_order_id: u64,
     ) -> Self {
+        let (owner_slot, fee_tier) = split_owner_slot(owner_slot, fee_tier.into());
         LeafNode {
             tag: NodeTag::LeafNode.into(),
             owner_slot,
-            fee_tier: fee_tier.into(),
+            fee_tier,
             padding: [0; 2],
             key,
             owner,
@@ -85,7 +130,7 @@ impl LeafNode {
 
     #[inline]
     pub fn fee_tier(&self) -> FeeTier {
-        FeeTier::try_from_primitive(self.fee_tier).unwrap()
+        FeeTier::try_from_primitive(self.fee_tier & 0xf).unwrap()
     }
 
     #[inline]
@@ -114,8 +159,8 @@ impl LeafNode {
     }
 
     #[inline]
-    pub fn owner_slot(&self) -> u8 {
-        self.owner_slot
+    pub fn owner_slot(&self) -> u16 {
+        join_owner_slot(self.owner_slot, self.fee_tier)
     }
 
     #[inline]
@@ -124,10 +169,127 @@ impl LeafNode {
     }
 }
 
+// The hidden part of an iceberg order, or the expiry of an order placed with
+// one. Neither fits in a leaf without changing its layout, so they're kept in
+// a list outside the tree that is searched by the key of the visible leaf.
+// Orders that expire but aren't icebergs have a display quantity of 0.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(packed)]
+pub struct ReserveNode {
+    tag: u32,
+    next: u32,
+    key: u128,
+    order_id: u128,
+    display_quantity: u64,
+    quantity: u64,
+    expiry_ts: u32,
+    _padding: [u32; 3],
+}
+unsafe impl Zeroable for ReserveNode {}
+unsafe impl Pod for ReserveNode {}
+
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(ReserveNode {
+    tag,
+    next,
+    key,
+    order_id,
+    display_quantity,
+    quantity,
+    expiry_ts,
+    _padding,
+});
+
+impl ReserveNode {
+    #[inline]
+    pub fn new(
+        key: u128,
+        order_id: u128,
+        display_quantity: u64,
+        quantity: u64,
+        expiry_ts: u32,
+    ) -> Self {
+        ReserveNode {
+            tag: NodeTag::ReserveNode.into(),
+            next: 0,
+            key,
+            order_id,
+            display_quantity,
+            quantity,
+            expiry_ts,
+            _padding: Zeroable::zeroed(),
+        }
+    }
+
+    /// The key of the visible leaf this reserve replenishes.
+    #[inline]
+    pub fn key(&self) -> u128 {
+        self.key
+    }
+
+    /// The id the order was placed with, which its owner's OpenOrders keeps
+    /// even after the visible leaf has been replenished under a new key.
+    #[inline]
+    pub fn order_id(&self) -> u128 {
+        self.order_id
+    }
+
+    #[inline]
+    pub fn display_quantity(&self) -> u64 {
+        self.display_quantity
+    }
+
+    #[inline]
+    pub fn quantity(&self) -> u64 {
+        self.quantity
+    }
+
+    #[inline]
+    pub fn is_iceberg(&self) -> bool {
+        self.display_quantity != 0
+    }
+
+    /// Unix timestamp after which the order can no longer be matched, or 0 if
+    /// it never expires.
+    #[inline]
+    pub fn expiry_ts(&self) -> u32 {
+        self.expiry_ts
+    }
+
+    #[inline]
+    pub fn is_expired(&self, now: i64) -> bool {
+        let expiry_ts = self.expiry_ts;
+        expiry_ts != 0 && now > i64::from(expiry_ts)
+    }
+}
+
+// Takes the last node of a book the first time it needs to hold a reserve,
+// and keeps the slab state that doesn't fit in `SlabHeader`, whose layout
+// existing books already use. The allocator never hands that node out again.
+#[derive(Copy, Clone)]
+#[repr(packed)]
+#[allow(dead_code)]
+pub(crate) struct ExtensionNode {
+    tag: u32,
+    // One more than the handle of the first reserve node, or 0 if there are
+    // none.
+    reserve_head: u32,
+    _padding: [u64; 8],
+}
+unsafe impl Zeroable for ExtensionNode {}
+unsafe impl Pod for ExtensionNode {}
+
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(ExtensionNode {
+    tag,
+    reserve_head,
+    _padding,
+});
+
 #[derive(Copy, Clone)]
 #[repr(packed)]
 #[allow(dead_code)]
-struct FreeNode {
+pub(crate) struct FreeNode {
     tag: u32,
     next: u32,
     _padding: [u64; 8],
@@ -135,6 +297,13 @@ struct FreeNode {
 unsafe impl Zeroable for FreeNode {}
 unsafe impl Pod for FreeNode {}
 
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(FreeNode {
+    tag,
+    next,
+    _padding,
+});
+
 const fn _const_max(a: usize, b: usize) -> usize {
     let gt = (a > b) as usize;
     gt * a + (1 - gt) * b
@@ -143,20 +312,28 @@ const fn _const_max(a: usize, b: usize) -> usize {
 const _INNER_NODE_SIZE: usize = size_of::<InnerNode>();
 const _LEAF_NODE_SIZE: usize = size_of::<LeafNode>();
 const _FREE_NODE_SIZE: usize = size_of::<FreeNode>();
+const _RESERVE_NODE_SIZE: usize = size_of::<ReserveNode>();
+const _EXTENSION_NODE_SIZE: usize = size_of::<ExtensionNode>();
 const _NODE_SIZE: usize = 72;
 
 const _INNER_NODE_ALIGN: usize = align_of::<InnerNode>();
 const _LEAF_NODE_ALIGN: usize = align_of::<LeafNode>();
 const _FREE_NODE_ALIGN: usize = align_of::<FreeNode>();
+const _RESERVE_NODE_ALIGN: usize = align_of::<ReserveNode>();
+const _EXTENSION_NODE_ALIGN: usize = align_of::<ExtensionNode>();
 const _NODE_ALIGN: usize = 1;
 
 const_assert_eq!(_NODE_SIZE, _INNER_NODE_SIZE);
 const_assert_eq!(_NODE_SIZE, _LEAF_NODE_SIZE);
 const_assert_eq!(_NODE_SIZE, _FREE_NODE_SIZE);
+const_assert_eq!(_NODE_SIZE, _RESERVE_NODE_SIZE);
+const_assert_eq!(_NODE_SIZE, _EXTENSION_NODE_SIZE);
 
 const_assert_eq!(_NODE_ALIGN, _INNER_NODE_ALIGN);
 const_assert_eq!(_NODE_ALIGN, _LEAF_NODE_ALIGN);
 const_assert_eq!(_NODE_ALIGN, _FREE_NODE_ALIGN);
+const_assert_eq!(_NODE_ALIGN, _RESERVE_NODE_ALIGN);
+const_assert_eq!(_NODE_ALIGN, _EXTENSION_NODE_ALIGN);
 
 #[derive(Copy, Clone)]
 #[repr(packed)]
@@ -186,7 +363,6 @@ impl AnyNode {
         }
     }
 
-    #[cfg(test)]
     fn prefix_len(&self) -> u32 {
         match self.case().unwrap() {
             NodeRef::Inner(&InnerNode { prefix_len, .. }) => prefix_len,
@@ -252,7 +428,7 @@ const_assert_eq!(_NODE_ALIGN, align_of::<AnyNode>());
 
 #[derive(Copy, Clone)]
 #[repr(packed)]
-struct SlabHeader {
+pub(crate) struct SlabHeader {
     bump_index: u64,
     free_list_len: u64,
     free_list_head: u32,
@@ -263,6 +439,15 @@ struct SlabHeader {
 unsafe impl Zeroable for SlabHeader {}
 unsafe impl Pod for SlabHeader {}
 
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(SlabHeader {
+    bump_index,
+    free_list_len,
+    free_list_head,
+    root_node,
+    leaf_count,
+});
+
 const SLAB_HEADER_LEN: usize = size_of::<SlabHeader>();
 
 #[cfg(debug_assertions)]
@@ -293,14 +478,31 @@ impl Slab {
     /// };
     /// ```
     #[inline]
-    pub fn new(bytes: &mut [u8]) -> &mut Self {
-        let len_without_header = bytes.len().checked_sub(SLAB_HEADER_LEN).unwrap();
-        let slop = len_without_header % size_of::<AnyNode>();
-        let truncated_len = bytes.len() - slop;
+    pub fn new(bytes: &mut [u8]) -> DexResult<&mut Self> {
+        let truncated_len = Self::truncated_len(bytes.len())?;
         let bytes = &mut bytes[..truncated_len];
         let slab: &mut Self = unsafe { &mut *(bytes as *mut [u8] as *mut Slab) };
         slab.check_size_align(); // check alignment
-        slab
+        Ok(slab)
+    }
+
+    /// Read-only counterpart of `Slab::new`, for reading a book from account
+    /// bytes that are not borrowed mutably.
+    #[inline]
+    pub fn from_bytes(bytes: &[u8]) -> DexResult<&Self> {
+        let truncated_len = Self::truncated_len(bytes.len())?;
+        let bytes = &bytes[..truncated_len];
+        let slab: &Self = unsafe { &*(bytes as *const [u8] as *const Slab) };
+        slab.check_size_align(); // check alignment
+        Ok(slab)
+    }
+
+    // The header followed by as many whole nodes as fit.
+    fn truncated_len(len: usize) -> DexResult<usize> {
+        let len_without_header = len
+            .checked_sub(SLAB_HEADER_LEN)
+            .ok_or(DexErrorCode::SlabTooSmall)?;
+        Ok(len - len_without_header % size_of::<AnyNode>())
     }
 
     #[inline]
@@ -377,10 +579,13 @@ pub trait SlabView<T> {
 
 impl SlabView<AnyNode> for Slab {
     fn capacity(&self) -> u64 {
-        self.nodes().len() as u64
+        self.node_limit() as u64
     }
 
     fn clear(&mut self) {
+        if let Some(extension) = self.extension_mut() {
+            extension.reserve_head = 0;
+        }
         let (header, _nodes) = self.parts_mut();
         *header = SlabHeader {
             bump_index: 0,
@@ -389,7 +594,7 @@ impl SlabView<AnyNode> for Slab {
 
             root_node: 0,
             leaf_count: 0,
-        }
+        };
     }
 
     fn is_empty(&self) -> bool {
@@ -405,7 +610,7 @@ impl SlabView<AnyNode> for Slab {
         let node = self.nodes().get(key as usize)?;
         let tag = NodeTag::try_from(node.tag);
         match tag {
-            Ok(NodeTag::InnerNode) | Ok(NodeTag::LeafNode) => Some(node),
+            Ok(NodeTag::InnerNode) | Ok(NodeTag::LeafNode) | Ok(NodeTag::ReserveNode) => Some(node),
             _ => None,
         }
     }
@@ -414,21 +619,22 @@ impl SlabView<AnyNode> for Slab {
         let node = self.nodes_mut().get_mut(key as usize)?;
         let tag = NodeTag::try_from(node.tag);
         match tag {
-            Ok(NodeTag::InnerNode) | Ok(NodeTag::LeafNode) => Some(node),
+            Ok(NodeTag::InnerNode) | Ok(NodeTag::LeafNode) | Ok(NodeTag::ReserveNode) => Some(node),
             _ => None,
         }
     }
 
     fn insert(&mut self, val: &AnyNode) -> Result<u32, ()> {
         match NodeTag::try_from(identity(val.tag)) {
-            Ok(NodeTag::InnerNode) | Ok(NodeTag::LeafNode) => (),
+            Ok(NodeTag::InnerNode) | Ok(NodeTag::LeafNode) | Ok(NodeTag::ReserveNode) => (),
             _ => unreachable!(),
         };
 
+        let node_limit = self.node_limit();
         let (header, nodes) = self.parts_mut();
 
         if header.free_list_len == 0 {
-            if header.bump_index as usize == nodes.len() {
+            if header.bump_index as usize == node_limit {
                 return Err(());
             }
 
@@ -486,6 +692,42 @@ impl SlabView<AnyNode> for Slab {
     }
 }
 
+/// In-order iterator over the leaves of a `Slab`, see `Slab::iter`.
+pub struct SlabIter<'a> {
+    slab: &'a Slab,
+    stack: Vec<NodeHandle>,
+    descending: bool,
+}
+
+impl<'a> Iterator for SlabIter<'a> {
+    type Item = &'a LeafNode;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let handle = self.stack.pop()?;
+            match self.slab.get(handle)?.case()? {
+                NodeRef::Leaf(leaf) => return Some(leaf),
+                NodeRef::Inner(&InnerNode { children, .. }) => {
+                    // The child pushed last is visited first.
+                    if self.descending {
+                        self.stack.extend_from_slice(&children);
+                    } else {
+                        self.stack.extend_from_slice(&[children[1], children[0]]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The total quantity resting at one price, in lots.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct L2Level {
+    pub price: u64,
+    pub quantity: u64,
+    pub order_count: u32,
+}
+
 #[derive(Debug)]
 pub enum SlabTreeError {
     OutOfSpace,
@@ -524,6 +766,53 @@ impl Slab {
         self.find_min_max(true)
     }
 
+    /// Iterates over the leaves in key order, which is price order with ties
+    /// broken by time priority. Bids are best first when `descending`, asks
+    /// when not.
+    pub fn iter(&self, descending: bool) -> SlabIter<'_> {
+        SlabIter {
+            slab: self,
+            stack: self.root().into_iter().collect(),
+            descending,
+        }
+    }
+
+    /// Aggregates the visible quantity of the leaves by price, walking from
+    /// the best price as in `iter`. Stops after `max_levels` levels, or once
+    /// the levels returned add up to at least `max_quantity`.
+    pub fn l2_depth(
+        &self,
+        descending: bool,
+        max_levels: Option<usize>,
+        max_quantity: Option<u64>,
+    ) -> Vec<L2Level> {
+        let mut levels: Vec<L2Level> = Vec::new();
+        let mut total_quantity = 0u64;
+        for leaf in self.iter(descending) {
+            let price = leaf.price().get();
+            match levels.last_mut() {
+                Some(level) if level.price == price => {
+                    level.quantity += leaf.quantity();
+                    level.order_count += 1;
+                }
+                _ => {
+                    if matches!(max_levels, Some(max) if levels.len() >= max)
+                        || matches!(max_quantity, Some(max) if total_quantity >= max)
+                    {
+                        break;
+                    }
+                    levels.push(L2Level {
+                        price,
+                        quantity: leaf.quantity(),
+                        order_count: 1,
+                    });
+                }
+            }
+            total_quantity += leaf.quantity();
+        }
+        levels
+    }
+
     #[inline]
     pub fn insert_leaf(
         &mut self,
@@ -599,8 +888,7 @@ impl Slab {
         }
     }
 
-    #[cfg(test)]
-    fn find_by_key(&self, search_key: u128) -> Option<NodeHandle> {
+    pub(crate) fn find_by_key(&self, search_key: u128) -> Option<NodeHandle> {
         let mut node_handle: NodeHandle = self.root()?;
         loop {
             let node_ref = self.get(node_handle).unwrap();
@@ -726,28 +1014,207 @@ impl Slab {
         self.remove_by_key(self.get(self.find_max()?)?.key()?)
     }
 
-    #[cfg(test)]
-    fn traverse(&self) -> Vec<&LeafNode> {
-        fn walk_rec<'a>(slab: &'a Slab, sub_root: NodeHandle, buf: &mut Vec<&'a LeafNode>) {
-            match slab.get(sub_root).unwrap().case().unwrap() {
-                NodeRef::Leaf(leaf) => {
-                    buf.push(leaf);
+    // The number of nodes the allocator may hand out.
+    fn node_limit(&self) -> usize {
+        self.nodes().len() - self.extension().is_some() as usize
+    }
+
+    fn extension(&self) -> Option<&ExtensionNode> {
+        let nodes = self.nodes();
+        if self.header().bump_index as usize >= nodes.len() {
+            return None;
+        }
+        let node = nodes.last()?;
+        match NodeTag::try_from(node.tag) {
+            Ok(NodeTag::ExtensionNode) => Some(cast_ref(node)),
+            _ => None,
+        }
+    }
+
+    fn extension_mut(&mut self) -> Option<&mut ExtensionNode> {
+        self.extension()?;
+        Some(cast_mut(self.nodes_mut().last_mut().unwrap()))
+    }
+
+    // Claims the last node for the extension node if the book doesn't have
+    // one yet. Once the allocator has reached it, a free last node is taken
+    // off the free list and a live one is moved to a free node first.
+    fn ensure_extension(&mut self) -> Result<(), SlabTreeError> {
+        if self.extension().is_some() {
+            return Ok(());
+        }
+        let last = match self.nodes().len().checked_sub(1) {
+            Some(last) => last as NodeHandle,
+            None => return Err(SlabTreeError::OutOfSpace),
+        };
+        if self.header().bump_index as usize > last as usize {
+            let contents = self.nodes()[last as usize];
+            match NodeTag::try_from(contents.tag) {
+                Ok(NodeTag::FreeNode) | Ok(NodeTag::LastFreeNode) => self.unlink_free(last),
+                Ok(NodeTag::InnerNode) | Ok(NodeTag::LeafNode) => {
+                    if self.header().free_list_len == 0 {
+                        return Err(SlabTreeError::OutOfSpace);
+                    }
+                    let moved = self.insert(&contents).unwrap();
+                    self.repoint(last, moved, contents.key().unwrap());
                 }
-                NodeRef::Inner(inner) => {
-                    walk_rec(slab, inner.children[0], buf);
-                    walk_rec(slab, inner.children[1], buf);
+                _ => unreachable!(),
+            }
+            self.header_mut().bump_index -= 1;
+        }
+        let extension: &mut ExtensionNode = cast_mut(&mut self.nodes_mut()[last as usize]);
+        *extension = ExtensionNode {
+            tag: NodeTag::ExtensionNode.into(),
+            reserve_head: 0,
+            _padding: Zeroable::zeroed(),
+        };
+        Ok(())
+    }
+
+    // Takes a node off the free list without handing it out.
+    fn unlink_free(&mut self, handle: NodeHandle) {
+        let next = cast_ref::<AnyNode, FreeNode>(&self.nodes()[handle as usize]).next;
+        let is_tail =
+            identity(self.nodes()[handle as usize].tag) == u32::from(NodeTag::LastFreeNode);
+        if self.header().free_list_head == handle {
+            self.header_mut().free_list_head = next;
+        } else {
+            let mut prev = self.header().free_list_head;
+            loop {
+                let prev_node: &mut FreeNode = cast_mut(&mut self.nodes_mut()[prev as usize]);
+                if prev_node.next == handle {
+                    prev_node.next = next;
+                    if is_tail {
+                        prev_node.tag = NodeTag::LastFreeNode.into();
+                    }
+                    break;
                 }
+                prev = prev_node.next;
+            }
+        }
+        self.header_mut().free_list_len -= 1;
+    }
+
+    // Points whatever refers to the tree node at `from`, which has the given
+    // key, at `to` instead.
+    fn repoint(&mut self, from: NodeHandle, to: NodeHandle, key: u128) {
+        if self.header().root_node == from {
+            self.header_mut().root_node = to;
+            return;
+        }
+        let mut parent = self.header().root_node;
+        loop {
+            let (child, crit_bit) = match self.get(parent).unwrap().case().unwrap() {
+                NodeRef::Inner(inner) => inner.walk_down(key),
+                NodeRef::Leaf(_) => unreachable!(),
+            };
+            if child == from {
+                let inner: &mut InnerNode = cast_mut(self.get_mut(parent).unwrap());
+                inner.children[crit_bit as usize] = to;
+                return;
+            }
+            parent = child;
+        }
+    }
+
+    pub fn has_reserves(&self) -> bool {
+        matches!(self.extension(), Some(extension) if extension.reserve_head != 0)
+    }
+
+    fn reserves(&self) -> impl Iterator<Item = (NodeHandle, &ReserveNode)> {
+        let mut next = self
+            .extension()
+            .map_or(0, |extension| extension.reserve_head);
+        std::iter::from_fn(move || {
+            let handle = next.checked_sub(1)?;
+            let reserve: &ReserveNode = cast_ref(&self.nodes()[handle as usize]);
+            next = reserve.next;
+            Some((handle, reserve))
+        })
+    }
+
+    pub fn insert_reserve(&mut self, reserve: &ReserveNode) -> Result<(), SlabTreeError> {
+        self.ensure_extension()?;
+        let mut reserve = *reserve;
+        reserve.next = self.extension().unwrap().reserve_head;
+        let handle = self
+            .insert(cast_ref(&reserve))
+            .map_err(|()| SlabTreeError::OutOfSpace)?;
+        self.extension_mut().unwrap().reserve_head = handle + 1;
+        Ok(())
+    }
+
+    /// Finds the reserve behind the visible leaf with the given key.
+    pub fn find_reserve(&self, key: u128) -> Option<&ReserveNode> {
+        if !self.has_reserves() {
+            return None;
+        }
+        self.reserves()
+            .map(|(_, reserve)| reserve)
+            .find(|reserve| identity(reserve.key) == key)
+    }
+
+    /// Finds the reserve of the iceberg that was placed with the given id.
+    pub fn find_reserve_by_order_id(&self, order_id: u128) -> Option<&ReserveNode> {
+        if !self.has_reserves() {
+            return None;
+        }
+        self.reserves()
+            .map(|(_, reserve)| reserve)
+            .find(|reserve| identity(reserve.order_id) == order_id)
+    }
+
+    /// Returns the keys of the leaves whose reserves match `predicate`,
+    /// searching at most `limit` reserves.
+    pub(crate) fn find_reserves_by<F: Fn(&ReserveNode) -> bool>(
+        &self,
+        limit: &mut u16,
+        predicate: F,
+    ) -> Vec<u128> {
+        let mut found = Vec::new();
+        for (_, reserve) in self.reserves() {
+            if *limit == 0 {
+                break;
+            }
+            *limit -= 1;
+            if predicate(reserve) {
+                found.push(reserve.key());
             }
         }
+        found
+    }
 
-        let mut buf = Vec::with_capacity(self.header().leaf_count as usize);
-        if let Some(r) = self.root() {
-            walk_rec(self, r, &mut buf);
+    pub fn remove_reserve(&mut self, key: u128) -> Option<ReserveNode> {
+        if !self.has_reserves() {
+            return None;
+        }
+        let mut prev = None;
+        let mut found = None;
+        for (handle, reserve) in self.reserves() {
+            if identity(reserve.key) == key {
+                found = Some((handle, reserve.next));
+                break;
+            }
+            prev = Some(handle);
         }
-        if buf.len() != buf.capacity() {
+        let (handle, next) = found?;
+        match prev {
+            None => self.extension_mut().unwrap().reserve_head = next,
+            Some(prev) => {
+                let prev: &mut ReserveNode = cast_mut(self.get_mut(prev).unwrap());
+                prev.next = next;
+            }
+        }
+        Some(cast(self.remove(handle).unwrap()))
+    }
+
+    #[cfg(test)]
+    fn traverse(&self) -> Vec<&LeafNode> {
+        let buf: Vec<&LeafNode> = self.iter(false).collect();
+        if buf.len() != self.header().leaf_count as usize {
             self.hexdump();
         }
-        assert_eq!(buf.len(), buf.capacity());
+        assert_eq!(buf.len(), self.header().leaf_count as usize);
         buf
     }
 
@@ -795,6 +1262,7 @@ impl Slab {
                 check_rec(self, c1, node.prefix_len(), node_key, true, &mut count);
             }
         }
+        count += self.reserves().count() as u64;
         assert_eq!(
             count + self.header().free_list_len as u64,
             identity(self.header().bump_index)
@@ -825,6 +1293,7 @@ impl Slab {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::state::OpenOrdersV2;
     use bytemuck::bytes_of;
     use rand::prelude::*;
 
@@ -836,7 +1305,7 @@ mod tests {
             let mut aligned_buf = vec![0u64; 10_000];
             let bytes: &mut [u8] = cast_slice_mut(aligned_buf.as_mut_slice());
 
-            let slab: &mut Slab = Slab::new(bytes);
+            let slab: &mut Slab = Slab::new(bytes).unwrap();
             let mut model: BTreeMap<u128, LeafNode> = BTreeMap::new();
 
             let mut all_keys = vec![];
@@ -847,7 +1316,7 @@ mod tests {
             assert_eq!(slab.find_max(), None);
 
             for i in 0..100 {
-                let offset = rng.gen();
+                let offset = rng.gen_range(0, OpenOrdersV2::MAX_SLOTS as u16);
                 let key = rng.gen();
                 let owner = rng.gen();
                 let qty = rng.gen();
@@ -894,7 +1363,7 @@ mod tests {
 
         let mut aligned_buf = vec![0u64; 1_250_000];
         let bytes: &mut [u8] = &mut cast_slice_mut(aligned_buf.as_mut_slice());
-        let slab: &mut Slab = Slab::new(bytes);
+        let slab: &mut Slab = Slab::new(bytes).unwrap();
         let mut model: BTreeMap<u128, LeafNode> = BTreeMap::new();
 
         let mut all_keys = vec![];
@@ -938,7 +1407,7 @@ mod tests {
 
                 match weights[dist.sample(&mut rng)].0 {
                     op @ Op::InsertNew | op @ Op::InsertDup => {
-                        let offset = rng.gen();
+                        let offset = rng.gen_range(0, OpenOrdersV2::MAX_SLOTS as u16);
                         let key = match op {
                             Op::InsertNew => rng.gen(),
                             Op::InsertDup => *all_keys.choose(&mut rng).unwrap(),
@@ -997,4 +1466,64 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn reserves_create_extension_node() {
+        let leaf = LeafNode::new(0, 1 << 64, [1; 4], 10, FeeTier::Base, 0);
+        let reserve = ReserveNode::new(1 << 64, 1 << 64, 10, 90, 0);
+
+        let mut aligned_buf = vec![0u64; 1_000];
+        let slab = Slab::new(cast_slice_mut(aligned_buf.as_mut_slice())).unwrap();
+        let capacity = slab.capacity();
+        slab.insert_leaf(&leaf).unwrap();
+        slab.insert_reserve(&reserve).unwrap();
+        assert!(slab.has_reserves());
+        assert_eq!(slab.capacity(), capacity - 1);
+        assert_eq!(slab.find_reserve(1 << 64), Some(&reserve));
+        assert_eq!(identity(slab.header().leaf_count), 1);
+        slab.check_invariants();
+
+        assert_eq!(slab.remove_by_key(1 << 64), Some(leaf));
+        assert_eq!(slab.remove_reserve(1 << 64), Some(reserve));
+        assert!(!slab.has_reserves());
+        assert!(slab.is_empty());
+        slab.check_invariants();
+        slab.clear();
+        assert_eq!(slab.capacity(), capacity - 1);
+        slab.check_invariants();
+    }
+
+    #[test]
+    fn full_book_gives_up_last_node_for_extension() {
+        // Room for the header and nine nodes, which five leaves fill.
+        const WORDS: usize = (SLAB_HEADER_LEN + 9 * size_of::<AnyNode>()) / 8;
+        let keys: Vec<u128> = (1..=5).map(|i| i << 64).collect();
+        let mut full_buf = vec![0u64; WORDS];
+        {
+            let slab = Slab::new(cast_slice_mut(full_buf.as_mut_slice())).unwrap();
+            for &key in &keys {
+                slab.insert_leaf(&LeafNode::new(0, key, [1; 4], 10, FeeTier::Base, 0))
+                    .unwrap();
+            }
+            assert!(slab
+                .insert_reserve(&ReserveNode::new(keys[0], keys[0], 0, 0, 1))
+                .is_err());
+            slab.check_invariants();
+        }
+
+        // Whichever leaf makes room, the last node ends up free or moved.
+        for &removed in &keys {
+            let mut aligned_buf = full_buf.clone();
+            let slab = Slab::new(cast_slice_mut(aligned_buf.as_mut_slice())).unwrap();
+            slab.remove_by_key(removed).unwrap();
+            let key = *keys.iter().find(|&&key| key != removed).unwrap();
+            let reserve = ReserveNode::new(key, key, 0, 0, 1);
+            slab.insert_reserve(&reserve).unwrap();
+            slab.check_invariants();
+            assert_eq!(slab.find_reserve(key), Some(&reserve));
+            let remaining: Vec<u128> = slab.traverse().iter().map(|leaf| leaf.key).collect();
+            let expected: Vec<u128> = keys.iter().copied().filter(|&key| key != removed).collect();
+            assert_eq!(remaining, expected);
+        }
+    }
 }
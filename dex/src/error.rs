@@ -1,4 +1,4 @@
-use num_enum::{FromPrimitive, IntoPrimitive};
+use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
 use solana_program::program_error::ProgramError;
 use thiserror::Error;
 
@@ -114,6 +114,25 @@ pub enum DexErrorCode {
     OrderMaxTimestampExceeded,
 
     MinAmountNotMet,
+    WrongTriggerBookAccount,
+    TriggerBookFull,
+    TriggerOrderNotFound,
+    InvalidTriggerOrder,
+    FillOrKillNotFilled,
+    InvalidFeeSchedule,
+    InvalidMarketParameters,
+    PriceNotOnTick,
+    OrderSizeBelowMinimum,
+    InvalidIcebergOrder,
+    MarketNotEmpty,
+    MarketIsCancelOnly,
+    NoPendingAuthority,
+    PriceBandExceeded,
+    MarketNotDisabled,
+    InvalidAmendOrder,
+    AuthorityHasNoDefault,
+    InvalidOrderExpiry,
+    InvalidRentDestination,
 
     Unknown = 1000,
 
@@ -124,7 +143,7 @@ pub enum DexErrorCode {
 }
 
 #[repr(u8)]
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
 pub enum SourceFileId {
     #[error("src/state.rs")]
     State = 1,
@@ -132,6 +151,8 @@ pub enum SourceFileId {
     Matching = 2,
     #[error("src/critbit.rs")]
     Critbit = 3,
+    #[error("src/client.rs")]
+    Client = 4,
 }
 
 #[macro_export]
@@ -186,6 +207,150 @@ impl std::fmt::Display for DexErrorCode {
 
 impl std::error::Error for DexErrorCode {}
 
+impl DexErrorCode {
+    /// A short description of the error for logs.
+    pub fn message(self) -> &'static str {
+        use DexErrorCode::*;
+        match self {
+            InvalidMarketFlags => "the market account's flags are invalid",
+            InvalidAskFlags => "the asks account's flags are invalid",
+            InvalidBidFlags => "the bids account's flags are invalid",
+            InvalidQueueLength => "the queue account has an invalid length",
+            OwnerAccountNotProvided => "the owner account was not provided",
+            ConsumeEventsQueueFailure => "failed to pop from the event queue",
+            WrongCoinVault => "the coin vault is not the market's",
+            WrongPcVault => "the pc vault is not the market's",
+            WrongCoinMint => "the coin mint is not the market's",
+            WrongPcMint => "the pc mint is not the market's",
+            CoinVaultProgramId => "the coin vault is not owned by the token program",
+            PcVaultProgramId => "the pc vault is not owned by the token program",
+            CoinMintProgramId => "the coin mint is not owned by the token program",
+            PcMintProgramId => "the pc mint is not owned by the token program",
+            WrongCoinMintSize => "the coin mint has the wrong size",
+            WrongPcMintSize => "the pc mint has the wrong size",
+            WrongCoinVaultSize => "the coin vault has the wrong size",
+            WrongPcVaultSize => "the pc vault has the wrong size",
+            UninitializedVault => "a vault is not initialized",
+            UninitializedMint => "a mint is not initialized",
+            CoinMintUninitialized => "the coin mint is not initialized",
+            PcMintUninitialized => "the pc mint is not initialized",
+            WrongMint => "a token account has the wrong mint",
+            WrongVaultOwner => "a vault is not owned by the vault signer",
+            VaultHasDelegate => "a vault has a delegate",
+            AlreadyInitialized => "the account is already initialized",
+            WrongAccountDataAlignment => "the account data is misaligned",
+            WrongAccountDataPaddingLength => "the account data has the wrong length",
+            WrongAccountHeadPadding => "the account data does not start with the expected padding",
+            WrongAccountTailPadding => "the account data does not end with the expected padding",
+            RequestQueueEmpty => "the request queue is empty",
+            EventQueueTooSmall => "the event queue account is too small",
+            SlabTooSmall => "the bids or asks account is too small",
+            BadVaultSignerNonce => "the vault signer nonce does not derive the vault signer",
+            InsufficientFunds => "not enough funds to place the order",
+            SplAccountProgramId => "a token account is not owned by the token program",
+            SplAccountLen => "a token account has the wrong size",
+            WrongFeeDiscountAccountOwner => "the fee discount account is not the owner's",
+            WrongFeeDiscountMint => "the fee discount account is not for SRM or MSRM",
+            CoinPayerProgramId => "the coin payer is not owned by the token program",
+            PcPayerProgramId => "the pc payer is not owned by the token program",
+            ClientIdNotFound => "no order has that client order id",
+            TooManyOpenOrders => "the open orders account has no free slot",
+            FakeErrorSoWeDontChangeNumbers => "unused",
+            BorrowError => "an account is already borrowed",
+            WrongOrdersAccount => "the open orders account is not for this market or owner",
+            WrongBidsAccount => "the bids account is not the market's",
+            WrongAsksAccount => "the asks account is not the market's",
+            WrongRequestQueueAccount => "the request queue is not the market's",
+            WrongEventQueueAccount => "the event queue is not the market's",
+            RequestQueueFull => "the request queue is full",
+            EventQueueFull => "the event queue is full",
+            MarketIsDisabled => "the market is disabled",
+            WrongSigner => "a required signer did not sign",
+            TransferFailed => "a token transfer failed",
+            ClientOrderIdIsZero => "the client order id is zero",
+            WrongRentSysvarAccount => "the rent sysvar account is wrong",
+            RentNotProvided => "the rent sysvar was not provided",
+            OrdersNotRentExempt => "the open orders account is not rent exempt",
+            OrderNotFound => "the order was not found",
+            OrderNotYours => "the order belongs to another open orders account",
+            WouldSelfTrade => "the order would trade against the owner's own order",
+            InvalidOpenOrdersAuthority => "the open orders authority did not sign",
+            OrderMaxTimestampExceeded => "the order's max timestamp has passed",
+            MinAmountNotMet => "the fill is below the requested minimum",
+            WrongTriggerBookAccount => "the trigger book is not the market's",
+            TriggerBookFull => "the trigger book is full",
+            TriggerOrderNotFound => "the trigger order was not found",
+            InvalidTriggerOrder => "the trigger order is invalid",
+            FillOrKillNotFilled => "the fill-or-kill order could not be filled in full",
+            InvalidFeeSchedule => "the fee schedule is invalid",
+            InvalidMarketParameters => "the market parameters are invalid",
+            PriceNotOnTick => "the price is not a multiple of the tick size",
+            OrderSizeBelowMinimum => "the order size is below the market's minimum",
+            InvalidIcebergOrder => "the iceberg order is invalid",
+            MarketNotEmpty => "the market still has orders, events or funds",
+            MarketIsCancelOnly => "the market only accepts cancels",
+            NoPendingAuthority => "no authority transfer is pending for that kind",
+            PriceBandExceeded => "the fill is outside the market's price band",
+            MarketNotDisabled => "the market must be disabled first",
+            InvalidAmendOrder => "the amend is a no-op or the amended order can't rest",
+            AuthorityHasNoDefault => "that authority has no default to reset to",
+            InvalidOrderExpiry => "the order expiry is out of range",
+            InvalidRentDestination => "the rent destination is one of the accounts being closed",
+            Unknown => "unknown error",
+            AssertionError => "an internal check failed",
+        }
+    }
+}
+
+/// A `ProgramError::Custom` code returned by the program, unpacked. Failed
+/// assertions carry the line in the low 16 bits and the `SourceFileId` in
+/// the high 8 bits; every other code is a `DexErrorCode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedDexError {
+    pub code: DexErrorCode,
+    /// The file and line of a failed assertion.
+    pub location: Option<(SourceFileId, u16)>,
+}
+
+impl DecodedDexError {
+    /// Returns `None` for codes the program doesn't return, such as those
+    /// of other programs in the same transaction.
+    pub fn from_custom(custom: u32) -> Option<Self> {
+        let code = DexErrorCode::from(custom);
+        if u32::from(code) == custom {
+            return Some(DecodedDexError {
+                code,
+                location: None,
+            });
+        }
+        if custom & 0x00ff_0000 != 0 {
+            return None;
+        }
+        let file_id = SourceFileId::try_from_primitive((custom >> 24) as u8).ok()?;
+        Some(DecodedDexError {
+            code: DexErrorCode::AssertionError,
+            location: Some((file_id, custom as u16)),
+        })
+    }
+
+    pub fn from_program_error(err: &ProgramError) -> Option<Self> {
+        match err {
+            ProgramError::Custom(custom) => Self::from_custom(*custom),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for DecodedDexError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(fmt, "{:?}: {}", self.code, self.code.message())?;
+        if let Some((file_id, line)) = self.location {
+            write!(fmt, " at {}:{}", file_id, line)?;
+        }
+        Ok(())
+    }
+}
+
 impl std::convert::From<DexError> for ProgramError {
     fn from(e: DexError) -> ProgramError {
         match e {
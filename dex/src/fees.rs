@@ -1,3 +1,5 @@
+use bytemuck::{Pod, Zeroable};
+use enumflags2::BitFlags;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use solana_program::pubkey::Pubkey;
 use std::convert::TryInto;
@@ -24,6 +26,15 @@ mod stable_markets {
     pub mod usdh_usdc {
         solana_program::declare_id!("CaFjigEgJdtGPxQxRjneA1hzNcY5MsHoAAL6Et67QrC5");
     }
+
+    pub fn contains(market: &super::Pubkey) -> bool {
+        market == &usdt_usdc::ID
+            || market == &msol_sol::ID
+            || market == &ust_usdc::ID
+            || market == &ust_usdt::ID
+            || market == &stsol_sol::ID
+            || market == &usdh_usdc::ID
+    }
 }
 
 #[derive(Copy, Clone, IntoPrimitive, TryFromPrimitive, Debug)]
@@ -40,6 +51,41 @@ pub enum FeeTier {
     Stable,
 }
 
+pub const FEE_TIER_COUNT: usize = 8;
+pub const MAX_TAKER_FEE_TENTH_OF_BPS: u16 = 10_000;
+
+#[derive(Copy, Clone, BitFlags, Debug)]
+#[repr(u8)]
+pub(crate) enum FeeScheduleFlag {
+    Configured = 0x1,
+    Stable = 0x2,
+}
+
+/// Fee rates and tier thresholds of a market, indexed by `FeeTier`.
+/// Markets that never configured one use `FeeSchedule::DEFAULT`.
+#[derive(Copy, Clone, Debug)]
+#[repr(packed)]
+pub struct FeeSchedule {
+    flags: u8,
+    padding: [u8; 7],
+    taker_fee_tenth_of_bps: [u16; FEE_TIER_COUNT],
+    maker_rebate_tenth_of_bps: [u16; FEE_TIER_COUNT],
+    srm_thresholds: [u64; 5],
+    msrm_threshold: u64,
+}
+unsafe impl Zeroable for FeeSchedule {}
+unsafe impl Pod for FeeSchedule {}
+
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(FeeSchedule {
+    flags,
+    padding,
+    taker_fee_tenth_of_bps,
+    maker_rebate_tenth_of_bps,
+    srm_thresholds,
+    msrm_threshold,
+});
+
 #[repr(transparent)]
 #[derive(Copy, Clone)]
 struct U64F64(u128);
@@ -91,58 +137,120 @@ const fn rebate_tenth_of_bps(tenth_of_bps: u64) -> U64F64 {
 impl FeeTier {
     #[inline]
     pub fn from_srm_and_msrm_balances(market: &Pubkey, srm_held: u64, msrm_held: u64) -> FeeTier {
-        let one_srm = 1_000_000;
-
-        if market == &stable_markets::usdt_usdc::ID
-            || market == &stable_markets::msol_sol::ID
-            || market == &stable_markets::ust_usdc::ID
-            || market == &stable_markets::ust_usdt::ID
-            || market == &stable_markets::stsol_sol::ID
-            || market == &stable_markets::usdh_usdc::ID
+        FeeSchedule::DEFAULT.fee_tier(market, srm_held, msrm_held)
+    }
+}
+
+const ONE_SRM: u64 = 1_000_000;
+
+impl FeeSchedule {
+    pub const DEFAULT: FeeSchedule = FeeSchedule {
+        flags: 0,
+        padding: [0; 7],
+        taker_fee_tenth_of_bps: [40, 39, 38, 36, 34, 32, 30, 10],
+        maker_rebate_tenth_of_bps: [0; FEE_TIER_COUNT],
+        srm_thresholds: [
+            ONE_SRM * 100,
+            ONE_SRM * 1_000,
+            ONE_SRM * 10_000,
+            ONE_SRM * 100_000,
+            ONE_SRM * 1_000_000,
+        ],
+        msrm_threshold: 1,
+    };
+
+    /// Builds a configured schedule, or returns `None` if it could let a
+    /// trade pay out more in rebates than it collects in fees.
+    pub fn new(
+        taker_fee_tenth_of_bps: [u16; FEE_TIER_COUNT],
+        maker_rebate_tenth_of_bps: [u16; FEE_TIER_COUNT],
+        srm_thresholds: [u64; 5],
+        msrm_threshold: u64,
+        stable: bool,
+    ) -> Option<Self> {
+        let min_taker_fee = *taker_fee_tenth_of_bps.iter().min()?;
+        let max_taker_fee = *taker_fee_tenth_of_bps.iter().max()?;
+        let max_maker_rebate = *maker_rebate_tenth_of_bps.iter().max()?;
+        // The referrer gets a fifth of the taker fee and the maker rebate
+        // comes out of the rest.
+        if max_taker_fee > MAX_TAKER_FEE_TENTH_OF_BPS
+            || u64::from(min_taker_fee) * 4 < u64::from(max_maker_rebate) * 5
         {
+            return None;
+        }
+        if srm_thresholds.windows(2).any(|w| w[0] > w[1]) || msrm_threshold == 0 {
+            return None;
+        }
+        let mut flags = BitFlags::from_flag(FeeScheduleFlag::Configured);
+        if stable {
+            flags.insert(FeeScheduleFlag::Stable);
+        }
+        Some(FeeSchedule {
+            flags: flags.bits(),
+            padding: [0; 7],
+            taker_fee_tenth_of_bps,
+            maker_rebate_tenth_of_bps,
+            srm_thresholds,
+            msrm_threshold,
+        })
+    }
+
+    #[inline]
+    fn flags(&self) -> BitFlags<FeeScheduleFlag> {
+        BitFlags::from_bits_truncate(self.flags)
+    }
+
+    #[inline]
+    pub fn is_configured(&self) -> bool {
+        self.flags().contains(FeeScheduleFlag::Configured)
+    }
+
+    #[inline]
+    pub fn fee_tier(&self, market: &Pubkey, srm_held: u64, msrm_held: u64) -> FeeTier {
+        // Markets that haven't configured a schedule keep the stable pair
+        // discount they were listed with.
+        let stable = if self.is_configured() {
+            self.flags().contains(FeeScheduleFlag::Stable)
+        } else {
+            stable_markets::contains(market)
+        };
+        if stable {
             return FeeTier::Stable;
         }
 
+        let srm_thresholds = self.srm_thresholds;
         match () {
-            () if msrm_held >= 1 => FeeTier::MSRM,
-            () if srm_held >= one_srm * 1_000_000 => FeeTier::SRM6,
-            () if srm_held >= one_srm * 100_000 => FeeTier::SRM5,
-            () if srm_held >= one_srm * 10_000 => FeeTier::SRM4,
-            () if srm_held >= one_srm * 1_000 => FeeTier::SRM3,
-            () if srm_held >= one_srm * 100 => FeeTier::SRM2,
+            () if msrm_held >= self.msrm_threshold => FeeTier::MSRM,
+            () if srm_held >= srm_thresholds[4] => FeeTier::SRM6,
+            () if srm_held >= srm_thresholds[3] => FeeTier::SRM5,
+            () if srm_held >= srm_thresholds[2] => FeeTier::SRM4,
+            () if srm_held >= srm_thresholds[1] => FeeTier::SRM3,
+            () if srm_held >= srm_thresholds[0] => FeeTier::SRM2,
             () => FeeTier::Base,
         }
     }
 
     #[inline]
-    pub fn maker_rebate(self, pc_qty: u64) -> u64 {
-        rebate_tenth_of_bps(0).mul_u64(pc_qty).floor()
-    }
-
-    fn taker_rate(self) -> U64F64 {
-        use FeeTier::*;
-        match self {
-            Base => fee_tenth_of_bps(40),
-            SRM2 => fee_tenth_of_bps(39),
-            SRM3 => fee_tenth_of_bps(38),
-            SRM4 => fee_tenth_of_bps(36),
-            SRM5 => fee_tenth_of_bps(34),
-            SRM6 => fee_tenth_of_bps(32),
-            MSRM => fee_tenth_of_bps(30),
-            Stable => fee_tenth_of_bps(10),
-        }
+    pub fn maker_rebate(&self, tier: FeeTier, pc_qty: u64) -> u64 {
+        let rebate = self.maker_rebate_tenth_of_bps[u8::from(tier) as usize];
+        rebate_tenth_of_bps(rebate.into()).mul_u64(pc_qty).floor()
+    }
+
+    #[inline]
+    fn taker_rate(&self, tier: FeeTier) -> U64F64 {
+        fee_tenth_of_bps(self.taker_fee_tenth_of_bps[u8::from(tier) as usize].into())
     }
 
     #[inline]
-    pub fn taker_fee(self, pc_qty: u64) -> u64 {
-        let rate = self.taker_rate();
+    pub fn taker_fee(&self, tier: FeeTier, pc_qty: u64) -> u64 {
+        let rate = self.taker_rate(tier);
         let exact_fee: U64F64 = rate.mul_u64(pc_qty);
         exact_fee.floor() + ((exact_fee.frac_part() != 0) as u64)
     }
 
     #[inline]
-    pub fn remove_taker_fee(self, pc_qty_incl_fee: u64) -> u64 {
-        let rate = self.taker_rate();
+    pub fn remove_taker_fee(&self, tier: FeeTier, pc_qty_incl_fee: u64) -> u64 {
+        let rate = self.taker_rate(tier);
         U64F64::from_int(pc_qty_incl_fee)
             .div(U64F64::ONE.add(rate))
             .try_into()
@@ -163,8 +271,9 @@ mod tests {
     proptest! {
         #[test]
         fn positive_net_fees(tt: FeeTier, mt: FeeTier, qty in 1..=std::u64::MAX) {
-            let fee = tt.taker_fee(qty);
-            let rebate = mt.maker_rebate(qty) + referrer_rebate(fee);
+            let schedule = FeeSchedule::DEFAULT;
+            let fee = schedule.taker_fee(tt, qty);
+            let rebate = schedule.maker_rebate(mt, qty) + referrer_rebate(fee);
             assert!(fee > rebate);
             let net_bps_u64f64 = (fee - rebate) as u128 * 100_000;
             let three_bps = (qty as u128) * 3;
@@ -184,8 +293,9 @@ mod tests {
 
         #[test]
         fn market_order_cannot_cheat(tier: FeeTier, qty: u64) {
-            let qty_without_fees = tier.remove_taker_fee(qty);
-            let required_fee = tier.taker_fee(qty_without_fees) as i128;
+            let schedule = FeeSchedule::DEFAULT;
+            let qty_without_fees = schedule.remove_taker_fee(tier, qty);
+            let required_fee = schedule.taker_fee(tier, qty_without_fees) as i128;
             let actual_fee = qty as i128 - qty_without_fees as i128;
             assert!([required_fee + 1, required_fee].contains(&actual_fee),
                     "actual_fee = {}, required_fee = {}",
@@ -194,9 +304,32 @@ mod tests {
 
         #[test]
         fn test_add_remove_fees(tier: FeeTier, qty in 1..=(std::u64::MAX >> 1)) {
-            let qty_with_fees = qty + tier.taker_fee(qty);
-            let qty2 = tier.remove_taker_fee(qty_with_fees);
+            let schedule = FeeSchedule::DEFAULT;
+            let qty_with_fees = qty + schedule.taker_fee(tier, qty);
+            let qty2 = schedule.remove_taker_fee(tier, qty_with_fees);
             assert!([-1, 0, 1].contains(&(qty as i128 - qty2 as i128)))
         }
+
+        #[test]
+        fn configured_fees_cover_rebates(
+            taker_fee in 0..=MAX_TAKER_FEE_TENTH_OF_BPS,
+            maker_rebate in 0..=MAX_TAKER_FEE_TENTH_OF_BPS,
+            tt: FeeTier,
+            mt: FeeTier,
+            qty: u64,
+        ) {
+            let schedule = FeeSchedule::new(
+                [taker_fee; FEE_TIER_COUNT],
+                [maker_rebate; FEE_TIER_COUNT],
+                FeeSchedule::DEFAULT.srm_thresholds,
+                1,
+                false,
+            );
+            if let Some(schedule) = schedule {
+                let fee = schedule.taker_fee(tt, qty);
+                let rebate = schedule.maker_rebate(mt, qty) + referrer_rebate(fee);
+                assert!(fee >= rebate, "{}, {}", fee, rebate);
+            }
+        }
     }
 }
@@ -0,0 +1,878 @@
+//! A machine-readable description of the program's interface for client
+//! generators: every `MarketInstruction` with its bincode encoding and
+//! accounts, the layouts of the accounts the program owns, the flag bits
+//! and enums stored in them, and the `DexErrorCode` table. `idl/serum_dex.json` is [`idl`] serialized with
+//! `serde_json`; a test fails when the two disagree, and running it with
+//! `UPDATE_IDL=1` rewrites the file.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem::size_of;
+use std::num::NonZeroU64;
+
+use enumflags2::BitFlags;
+use num_enum::TryFromPrimitive;
+use serde::ser::{self, Impossible};
+use serde::Serialize;
+use solana_program::pubkey::Pubkey;
+
+use crate::critbit::{
+    ExtensionNode, FreeNode, InnerNode, LeafNode, NodeTag, ReserveNode, SlabHeader,
+};
+use crate::error::DexErrorCode;
+use crate::fees::{FeeSchedule, FeeScheduleFlag, FEE_TIER_COUNT};
+use crate::instruction::{
+    AccountRoles, AmendOrderInstruction, AuthorityKind, CancelAllOrdersInstruction,
+    CancelOrderInstruction, CancelOrderInstructionV2, InitializeMarketInstruction,
+    MarketInstruction, NewIcebergOrderInstruction, NewOrderInstructionV1, NewOrderInstructionV2,
+    NewOrderInstructionV3, NewOrderWithExpiryInstruction, NewTriggerOrderInstruction,
+    ProposeAuthorityInstruction, SelfTradeBehavior, SendTakeInstruction,
+    UpdateFeeScheduleInstruction,
+};
+use crate::matching::{OrderType, Side};
+use crate::state::{
+    AccountFlag, Event, EventFlag, EventQueueHeader, FillDetail, MarketState, MarketStateV2,
+    MarketStats, OpenOrders, OpenOrdersV2, OrderBookStateHeader, OutReason, Request, RequestFlag,
+    RequestQueueHeader, TriggerBookHeader, TriggerFlag, TriggerOrder, ACCOUNT_HEAD_PADDING,
+    ACCOUNT_TAIL_PADDING,
+};
+
+/// A type as bincode lays it out. Integers are little-endian.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IdlType {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    PublicKey,
+    /// Written without a length.
+    Array(Box<IdlType>, usize),
+    /// Written after a u64 length.
+    Vec(Box<IdlType>),
+    /// Written after a u8 tag, 0 for none and 1 for some.
+    Option(Box<IdlType>),
+    /// An entry of `types`. Enums are written as a u32 variant index.
+    Defined(&'static str),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct IdlField {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub ty: IdlType,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum IdlTypeDef {
+    Struct {
+        name: &'static str,
+        fields: Vec<IdlField>,
+    },
+    Enum {
+        name: &'static str,
+        variants: Vec<String>,
+    },
+}
+
+impl IdlTypeDef {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IdlTypeDef::Struct { name, .. } | IdlTypeDef::Enum { name, .. } => name,
+        }
+    }
+}
+
+/// Instruction data is a zero version byte, the u32 `discriminant`, then
+/// `args` if the variant has any.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdlInstruction {
+    pub name: &'static str,
+    pub discriminant: u32,
+    pub args: Option<IdlType>,
+    /// The length of the instruction data, unless `args` holds a `Vec` or
+    /// an `Option`.
+    pub data_len: Option<usize>,
+    pub accounts: AccountRoles,
+}
+
+/// A field of an account layout. Offsets are from the start of the
+/// struct, which follows the account's head padding.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct IdlLayoutField {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub ty: IdlType,
+    pub offset: usize,
+    pub size: usize,
+}
+
+impl IdlLayoutField {
+    pub fn new<S, T: IdlTyped>(
+        name: &'static str,
+        offset: usize,
+        _field: impl Fn(&S) -> T,
+    ) -> Self {
+        IdlLayoutField {
+            name,
+            ty: T::idl_type(),
+            offset,
+            size: size_of::<T>(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct IdlLayout {
+    pub name: &'static str,
+    pub size: usize,
+    pub fields: Vec<IdlLayoutField>,
+}
+
+/// An account that holds `headers` in order and then as many
+/// `element_size` byte elements as fit before the tail padding. When there
+/// are several `elements`, the `tag` type in the first bytes of each says
+/// which one it is.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdlAccount {
+    pub name: &'static str,
+    pub headers: Vec<&'static str>,
+    pub elements: Vec<&'static str>,
+    pub element_size: usize,
+    pub tag: Option<&'static str>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct IdlFlag {
+    pub name: String,
+    pub value: u64,
+}
+
+/// The bits of a flags field. Any combination of them can be set.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct IdlFlags {
+    pub name: &'static str,
+    pub flags: Vec<IdlFlag>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct IdlErrorCode {
+    pub code: u32,
+    pub name: String,
+    pub message: &'static str,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Idl {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub account_head_padding: &'static str,
+    pub account_tail_padding: &'static str,
+    pub instructions: Vec<IdlInstruction>,
+    pub accounts: Vec<IdlAccount>,
+    pub layouts: Vec<IdlLayout>,
+    pub flags: Vec<IdlFlags>,
+    pub types: Vec<IdlTypeDef>,
+    pub errors: Vec<IdlErrorCode>,
+}
+
+/// Types that can appear in an account layout.
+pub trait IdlTyped {
+    fn idl_type() -> IdlType;
+}
+
+/// A `#[repr(packed)]` account struct and the offset of each of its fields.
+pub trait HasIdlLayout {
+    fn idl_layout() -> IdlLayout;
+}
+
+macro_rules! impl_idl_typed {
+    ($($t:ty => $v:ident),* $(,)?) => {
+        $(impl IdlTyped for $t {
+            fn idl_type() -> IdlType {
+                IdlType::$v
+            }
+        })*
+    };
+}
+
+impl_idl_typed! {
+    bool => Bool,
+    u8 => U8,
+    u16 => U16,
+    u32 => U32,
+    u64 => U64,
+    u128 => U128,
+    i8 => I8,
+    i16 => I16,
+    i32 => I32,
+    i64 => I64,
+    i128 => I128,
+    Pubkey => PublicKey,
+}
+
+impl<T: IdlTyped, const N: usize> IdlTyped for [T; N] {
+    fn idl_type() -> IdlType {
+        IdlType::Array(Box::new(T::idl_type()), N)
+    }
+}
+
+/// Implements `HasIdlLayout` and `IdlTyped` for a packed struct. Invoke it
+/// next to the struct so private fields are in scope, and list every field
+/// in declaration order.
+macro_rules! impl_idl_layout {
+    ($t:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::idl::HasIdlLayout for $t {
+            fn idl_layout() -> $crate::idl::IdlLayout {
+                $crate::idl::IdlLayout {
+                    name: stringify!($t),
+                    size: std::mem::size_of::<$t>(),
+                    fields: vec![$(
+                        $crate::idl::IdlLayoutField::new(
+                            stringify!($field),
+                            std::mem::offset_of!($t, $field),
+                            |s: &$t| s.$field,
+                        ),
+                    )*],
+                }
+            }
+        }
+
+        impl $crate::idl::IdlTyped for $t {
+            fn idl_type() -> $crate::idl::IdlType {
+                $crate::idl::IdlType::Defined(stringify!($t))
+            }
+        }
+    };
+}
+pub(crate) use impl_idl_layout;
+
+#[derive(Debug)]
+pub struct TraceError(String);
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl ser::Error for TraceError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        TraceError(msg.to_string())
+    }
+}
+
+/// A serializer that records the shape of a value instead of its bytes.
+/// Structs it meets are collected into `structs`. Sequences and options
+/// must hold at least one element for their element type to be known.
+#[derive(Default)]
+struct Tracer {
+    structs: BTreeMap<&'static str, Vec<IdlField>>,
+}
+
+impl Tracer {
+    fn trace<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<IdlType, TraceError> {
+        value.serialize(self)
+    }
+}
+
+struct SeqTracer<'a> {
+    tracer: &'a mut Tracer,
+    len: Option<usize>,
+    element: Option<IdlType>,
+}
+
+impl SeqTracer<'_> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), TraceError> {
+        let ty = self.tracer.trace(value)?;
+        match &self.element {
+            Some(element) if *element != ty => Err(TraceError(format!(
+                "sequence mixes {:?} and {:?}",
+                element, ty
+            ))),
+            _ => {
+                self.element = Some(ty);
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(self) -> Result<IdlType, TraceError> {
+        let element = Box::new(
+            self.element
+                .ok_or_else(|| TraceError("cannot trace an empty sequence".to_string()))?,
+        );
+        Ok(match self.len {
+            Some(len) => IdlType::Array(element, len),
+            None => IdlType::Vec(element),
+        })
+    }
+}
+
+impl ser::SerializeSeq for SeqTracer<'_> {
+    type Ok = IdlType;
+    type Error = TraceError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), TraceError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<IdlType, TraceError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SeqTracer<'_> {
+    type Ok = IdlType;
+    type Error = TraceError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), TraceError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<IdlType, TraceError> {
+        self.finish()
+    }
+}
+
+struct StructTracer<'a> {
+    tracer: &'a mut Tracer,
+    name: &'static str,
+    fields: Vec<IdlField>,
+}
+
+impl ser::SerializeStruct for StructTracer<'_> {
+    type Ok = IdlType;
+    type Error = TraceError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), TraceError> {
+        let ty = self.tracer.trace(value)?;
+        self.fields.push(IdlField { name, ty });
+        Ok(())
+    }
+
+    fn end(self) -> Result<IdlType, TraceError> {
+        if let Some(fields) = self.tracer.structs.get(self.name) {
+            if *fields != self.fields {
+                return Err(TraceError(format!("{} traced two ways", self.name)));
+            }
+        }
+        self.tracer.structs.insert(self.name, self.fields);
+        Ok(IdlType::Defined(self.name))
+    }
+}
+
+fn unsupported<T>(what: &str) -> Result<T, TraceError> {
+    Err(TraceError(format!(
+        "bincode layout of {} is not described",
+        what
+    )))
+}
+
+impl<'a> ser::Serializer for &'a mut Tracer {
+    type Ok = IdlType;
+    type Error = TraceError;
+    type SerializeSeq = SeqTracer<'a>;
+    type SerializeTuple = SeqTracer<'a>;
+    type SerializeTupleStruct = Impossible<IdlType, TraceError>;
+    type SerializeTupleVariant = Impossible<IdlType, TraceError>;
+    type SerializeMap = Impossible<IdlType, TraceError>;
+    type SerializeStruct = StructTracer<'a>;
+    type SerializeStructVariant = Impossible<IdlType, TraceError>;
+
+    fn serialize_bool(self, _: bool) -> Result<IdlType, TraceError> {
+        Ok(IdlType::Bool)
+    }
+
+    fn serialize_i8(self, _: i8) -> Result<IdlType, TraceError> {
+        Ok(IdlType::I8)
+    }
+
+    fn serialize_i16(self, _: i16) -> Result<IdlType, TraceError> {
+        Ok(IdlType::I16)
+    }
+
+    fn serialize_i32(self, _: i32) -> Result<IdlType, TraceError> {
+        Ok(IdlType::I32)
+    }
+
+    fn serialize_i64(self, _: i64) -> Result<IdlType, TraceError> {
+        Ok(IdlType::I64)
+    }
+
+    fn serialize_i128(self, _: i128) -> Result<IdlType, TraceError> {
+        Ok(IdlType::I128)
+    }
+
+    fn serialize_u8(self, _: u8) -> Result<IdlType, TraceError> {
+        Ok(IdlType::U8)
+    }
+
+    fn serialize_u16(self, _: u16) -> Result<IdlType, TraceError> {
+        Ok(IdlType::U16)
+    }
+
+    fn serialize_u32(self, _: u32) -> Result<IdlType, TraceError> {
+        Ok(IdlType::U32)
+    }
+
+    fn serialize_u64(self, _: u64) -> Result<IdlType, TraceError> {
+        Ok(IdlType::U64)
+    }
+
+    fn serialize_u128(self, _: u128) -> Result<IdlType, TraceError> {
+        Ok(IdlType::U128)
+    }
+
+    fn serialize_f32(self, _: f32) -> Result<IdlType, TraceError> {
+        unsupported("f32")
+    }
+
+    fn serialize_f64(self, _: f64) -> Result<IdlType, TraceError> {
+        unsupported("f64")
+    }
+
+    fn serialize_char(self, _: char) -> Result<IdlType, TraceError> {
+        unsupported("char")
+    }
+
+    fn serialize_str(self, _: &str) -> Result<IdlType, TraceError> {
+        unsupported("str")
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<IdlType, TraceError> {
+        unsupported("bytes")
+    }
+
+    fn serialize_none(self) -> Result<IdlType, TraceError> {
+        Err(TraceError("cannot trace None".to_string()))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<IdlType, TraceError> {
+        Ok(IdlType::Option(Box::new(self.trace(value)?)))
+    }
+
+    fn serialize_unit(self) -> Result<IdlType, TraceError> {
+        unsupported("()")
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<IdlType, TraceError> {
+        unsupported(name)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<IdlType, TraceError> {
+        Ok(IdlType::Defined(name))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<IdlType, TraceError> {
+        self.trace(value)
+    }
+
+    // Only `MarketInstruction` itself has newtype variants; its tag is
+    // described separately, so this traces the payload alone.
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<IdlType, TraceError> {
+        self.trace(value)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SeqTracer<'a>, TraceError> {
+        Ok(SeqTracer {
+            tracer: self,
+            len: None,
+            element: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqTracer<'a>, TraceError> {
+        Ok(SeqTracer {
+            tracer: self,
+            len: Some(len),
+            element: None,
+        })
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, TraceError> {
+        unsupported(name)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, TraceError> {
+        unsupported(name)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, TraceError> {
+        unsupported("maps")
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<StructTracer<'a>, TraceError> {
+        Ok(StructTracer {
+            tracer: self,
+            name,
+            fields: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, TraceError> {
+        unsupported(name)
+    }
+}
+
+fn enum_def<E>(name: &'static str) -> IdlTypeDef
+where
+    E: TryFromPrimitive + fmt::Debug,
+    E::Primitive: From<u8>,
+{
+    IdlTypeDef::Enum {
+        name,
+        variants: (0..=u8::MAX)
+            .map_while(|i| E::try_from_primitive(i.into()).ok())
+            .map(|variant| format!("{:?}", variant))
+            .collect(),
+    }
+}
+
+macro_rules! flags_def {
+    ($t:ident) => {
+        IdlFlags {
+            name: stringify!($t),
+            flags: BitFlags::<$t>::all()
+                .iter()
+                .map(|flag| IdlFlag {
+                    name: format!("{:?}", flag),
+                    value: BitFlags::from_flag(flag).bits().into(),
+                })
+                .collect(),
+        }
+    };
+}
+
+/// One instruction of every kind, with any `Option` set and any `Vec`
+/// non-empty so that tracing reaches every type.
+pub fn instruction_samples() -> Vec<MarketInstruction> {
+    use MarketInstruction::*;
+    let one = NonZeroU64::new(1).unwrap();
+    let order = NewOrderInstructionV3 {
+        side: Side::Bid,
+        limit_price: one,
+        max_coin_qty: one,
+        max_native_pc_qty_including_fees: one,
+        self_trade_behavior: SelfTradeBehavior::DecrementTake,
+        order_type: OrderType::Limit,
+        client_order_id: 0,
+        limit: 0,
+        max_ts: i64::MAX,
+    };
+    vec![
+        InitializeMarket(InitializeMarketInstruction {
+            coin_lot_size: 0,
+            pc_lot_size: 0,
+            fee_rate_bps: 0,
+            vault_signer_nonce: 0,
+            pc_dust_threshold: 0,
+            tick_size: 0,
+            min_base_qty: 0,
+        }),
+        NewOrder(NewOrderInstructionV1 {
+            side: Side::Bid,
+            limit_price: one,
+            max_qty: one,
+            order_type: OrderType::Limit,
+            client_id: 0,
+        }),
+        MatchOrders(0),
+        ConsumeEvents(0),
+        CancelOrder(CancelOrderInstruction {
+            side: Side::Bid,
+            order_id: 0,
+            owner: [0; 4],
+            owner_slot: 0,
+        }),
+        SettleFunds,
+        CancelOrderByClientId(0),
+        DisableMarket,
+        SweepFees,
+        NewOrderV2(NewOrderInstructionV2 {
+            side: Side::Bid,
+            limit_price: one,
+            max_qty: one,
+            order_type: OrderType::Limit,
+            client_id: 0,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+        }),
+        NewOrderV3(order.clone()),
+        CancelOrderV2(CancelOrderInstructionV2 {
+            side: Side::Bid,
+            order_id: 0,
+        }),
+        CancelOrderByClientIdV2(0),
+        SendTake(SendTakeInstruction {
+            side: Side::Bid,
+            limit_price: one,
+            max_coin_qty: one,
+            max_native_pc_qty_including_fees: one,
+            min_coin_qty: 0,
+            min_native_pc_qty: 0,
+            limit: 0,
+        }),
+        CloseOpenOrders,
+        InitOpenOrders,
+        Prune(0),
+        ConsumeEventsPermissioned(0),
+        CancelOrdersByClientIds([0; 8]),
+        ReplaceOrderByClientId(order.clone()),
+        ReplaceOrdersByClientIds(vec![order.clone()]),
+        NewTriggerOrder(NewTriggerOrderInstruction {
+            side: Side::Bid,
+            trigger_price: one,
+            limit_price: one,
+            max_coin_qty: one,
+            max_native_pc_qty_including_fees: one,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            order_type: OrderType::Limit,
+            client_order_id: 0,
+        }),
+        CancelTriggerOrder(0),
+        PruneExpiredOrders(0),
+        UpdateFeeSchedule(UpdateFeeScheduleInstruction {
+            taker_fee_tenth_of_bps: [0; FEE_TIER_COUNT],
+            maker_rebate_tenth_of_bps: [0; FEE_TIER_COUNT],
+            srm_thresholds: [0; 5],
+            msrm_threshold: 0,
+            stable: false,
+        }),
+        NewIcebergOrder(NewIcebergOrderInstruction {
+            order: order.clone(),
+            display_qty: one,
+        }),
+        AmendOrder(AmendOrderInstruction {
+            side: Side::Bid,
+            order_id: 0,
+            limit_price: one,
+            max_coin_qty: one,
+            max_native_pc_qty_including_fees: one,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            order_type: OrderType::Limit,
+            limit: 0,
+        }),
+        CancelAllOrders(CancelAllOrdersInstruction {
+            side: Some(Side::Bid),
+            limit: 0,
+        }),
+        EnableFillDetails,
+        CloseMarket,
+        EnterCancelOnly,
+        ExitCancelOnly,
+        ProposeAuthority(ProposeAuthorityInstruction {
+            kind: AuthorityKind::Admin,
+            new_authority: [0; 32],
+        }),
+        AcceptAuthority(AuthorityKind::Admin),
+        SetPriceBand(0),
+        ResetAuthority(AuthorityKind::Disable),
+        DisableFillDetails,
+        InitTriggerBook,
+        NewOrderWithExpiry(NewOrderWithExpiryInstruction {
+            order,
+            expiry_ts: 0,
+        }),
+    ]
+}
+
+/// The encoded size of `ty`, or `None` if it varies.
+pub fn encoded_size(ty: &IdlType, types: &[IdlTypeDef]) -> Option<usize> {
+    Some(match ty {
+        IdlType::Bool | IdlType::U8 | IdlType::I8 => 1,
+        IdlType::U16 | IdlType::I16 => 2,
+        IdlType::U32 | IdlType::I32 => 4,
+        IdlType::U64 | IdlType::I64 => 8,
+        IdlType::U128 | IdlType::I128 => 16,
+        IdlType::PublicKey => 32,
+        IdlType::Array(element, len) => encoded_size(element, types)? * len,
+        IdlType::Vec(_) | IdlType::Option(_) => return None,
+        IdlType::Defined(name) => match types.iter().find(|def| def.name() == *name)? {
+            IdlTypeDef::Struct { fields, .. } => fields
+                .iter()
+                .map(|field| encoded_size(&field.ty, types))
+                .sum::<Option<usize>>()?,
+            IdlTypeDef::Enum { .. } => 4,
+        },
+    })
+}
+
+pub fn idl() -> Idl {
+    let mut tracer = Tracer::default();
+    let mut instructions = Vec::new();
+    for instruction in instruction_samples() {
+        let data = instruction.pack();
+        let discriminant = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+        let args = match data.len() {
+            5 => None,
+            _ => Some(tracer.trace(&instruction).unwrap()),
+        };
+        instructions.push(IdlInstruction {
+            name: instruction.name(),
+            discriminant,
+            args,
+            data_len: None,
+            accounts: instruction.account_roles(),
+        });
+    }
+
+    let mut types: Vec<IdlTypeDef> = tracer
+        .structs
+        .into_iter()
+        .map(|(name, fields)| IdlTypeDef::Struct { name, fields })
+        .collect();
+    types.extend(vec![
+        enum_def::<AuthorityKind>("AuthorityKind"),
+        enum_def::<NodeTag>("NodeTag"),
+        enum_def::<OrderType>("OrderType"),
+        enum_def::<OutReason>("OutReason"),
+        enum_def::<SelfTradeBehavior>("SelfTradeBehavior"),
+        enum_def::<Side>("Side"),
+    ]);
+    types.sort_by_key(|def| def.name());
+
+    for instruction in &mut instructions {
+        instruction.data_len = match &instruction.args {
+            None => Some(5),
+            Some(args) => encoded_size(args, &types).map(|len| 5 + len),
+        };
+    }
+
+    let errors = (0..=u32::from(DexErrorCode::AssertionError))
+        .filter_map(|code| {
+            let error = DexErrorCode::from(code);
+            (u32::from(error) == code).then(|| IdlErrorCode {
+                code,
+                name: format!("{:?}", error),
+                message: error.message(),
+            })
+        })
+        .collect();
+
+    Idl {
+        name: "serum_dex",
+        version: env!("CARGO_PKG_VERSION"),
+        account_head_padding: std::str::from_utf8(ACCOUNT_HEAD_PADDING).unwrap(),
+        account_tail_padding: std::str::from_utf8(ACCOUNT_TAIL_PADDING).unwrap(),
+        instructions,
+        accounts: vec![
+            IdlAccount {
+                name: "RequestQueue",
+                headers: vec!["RequestQueueHeader"],
+                elements: vec!["Request"],
+                element_size: size_of::<Request>(),
+                tag: None,
+            },
+            IdlAccount {
+                name: "EventQueue",
+                headers: vec!["EventQueueHeader"],
+                elements: vec!["Event", "FillDetail"],
+                element_size: size_of::<Event>(),
+                tag: Some("EventFlag"),
+            },
+            IdlAccount {
+                name: "Slab",
+                headers: vec!["OrderBookStateHeader", "SlabHeader"],
+                elements: vec![
+                    "InnerNode",
+                    "LeafNode",
+                    "FreeNode",
+                    "ReserveNode",
+                    "ExtensionNode",
+                ],
+                element_size: size_of::<LeafNode>(),
+                tag: Some("NodeTag"),
+            },
+            IdlAccount {
+                name: "TriggerBook",
+                headers: vec!["TriggerBookHeader"],
+                elements: vec!["TriggerOrder"],
+                element_size: size_of::<TriggerOrder>(),
+                tag: None,
+            },
+        ],
+        layouts: vec![
+            MarketState::idl_layout(),
+            MarketStateV2::idl_layout(),
+            FeeSchedule::idl_layout(),
+            MarketStats::idl_layout(),
+            OpenOrders::idl_layout(),
+            OpenOrdersV2::idl_layout(),
+            RequestQueueHeader::idl_layout(),
+            Request::idl_layout(),
+            EventQueueHeader::idl_layout(),
+            Event::idl_layout(),
+            FillDetail::idl_layout(),
+            OrderBookStateHeader::idl_layout(),
+            SlabHeader::idl_layout(),
+            InnerNode::idl_layout(),
+            LeafNode::idl_layout(),
+            FreeNode::idl_layout(),
+            ReserveNode::idl_layout(),
+            ExtensionNode::idl_layout(),
+            TriggerBookHeader::idl_layout(),
+            TriggerOrder::idl_layout(),
+        ],
+        flags: vec![
+            flags_def!(AccountFlag),
+            flags_def!(FeeScheduleFlag),
+            flags_def!(RequestFlag),
+            flags_def!(EventFlag),
+            flags_def!(TriggerFlag),
+        ],
+        types,
+        errors,
+    }
+}
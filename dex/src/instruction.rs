@@ -57,6 +57,11 @@ pub struct InitializeMarketInstruction {
     pub fee_rate_bps: u16,
     pub vault_signer_nonce: u64,
     pub pc_dust_threshold: u64,
+    // Limit prices must be a multiple of the tick size and orders must be for
+    // at least the minimum base quantity. Both are in lots and are only
+    // stored for permissioned markets.
+    pub tick_size: u64,
+    pub min_base_qty: u64,
 }
 
 #[derive(
@@ -69,6 +74,8 @@ pub enum SelfTradeBehavior {
     DecrementTake = 0,
     CancelProvide = 1,
     AbortTransaction = 2,
+    CancelTake = 3,
+    CancelBoth = 4,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
@@ -129,6 +136,100 @@ pub struct NewOrderInstructionV3 {
     pub max_ts: i64,
 }
 
+/// A limit order that shows at most `display_qty` on the book at a time. Each
+/// time the shown part is filled, the next slice is taken from the hidden
+/// reserve and rests behind the orders already at its price.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+pub struct NewIcebergOrderInstruction {
+    pub order: NewOrderInstructionV3,
+    #[cfg_attr(
+        test,
+        proptest(strategy = "(1u64..=std::u64::MAX).prop_map(|x| NonZeroU64::new(x).unwrap())")
+    )]
+    pub display_qty: NonZeroU64,
+}
+
+/// A NewOrderV3 whose resting part expires at `expiry_ts`, a Unix timestamp.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
+pub struct NewOrderWithExpiryInstruction {
+    pub order: NewOrderInstructionV3,
+    pub expiry_ts: i64,
+}
+
+/// Changes the price or size of a resting order. Shrinking an order at its
+/// current price keeps its place in the queue; any other change cancels it and
+/// places a new order of `order_type` at the back of the queue. Only the
+/// resting order types, Limit, PostOnly and PostOnlySlide, are accepted, so
+/// amending a post-only order as PostOnly keeps it from taking liquidity.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+pub struct AmendOrderInstruction {
+    pub side: Side,
+    pub order_id: u128,
+
+    #[cfg_attr(
+        test,
+        proptest(strategy = "(1u64..=std::u64::MAX).prop_map(|x| NonZeroU64::new(x).unwrap())")
+    )]
+    pub limit_price: NonZeroU64,
+
+    #[cfg_attr(
+        test,
+        proptest(strategy = "(1u64..=std::u64::MAX).prop_map(|x| NonZeroU64::new(x).unwrap())")
+    )]
+    pub max_coin_qty: NonZeroU64,
+    #[cfg_attr(
+        test,
+        proptest(strategy = "(1u64..=std::u64::MAX).prop_map(|x| NonZeroU64::new(x).unwrap())")
+    )]
+    pub max_native_pc_qty_including_fees: NonZeroU64,
+
+    pub self_trade_behavior: SelfTradeBehavior,
+    pub order_type: OrderType,
+    pub limit: u16,
+}
+
+/// A stop order that rests in the market's trigger book until a fill trades
+/// through `trigger_price`, at which point it is matched as a regular order
+/// at `limit_price`. A stop-market order is an `ImmediateOrCancel` trigger
+/// with a marketable limit price.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+pub struct NewTriggerOrderInstruction {
+    pub side: Side,
+
+    #[cfg_attr(
+        test,
+        proptest(strategy = "(1u64..=std::u64::MAX).prop_map(|x| NonZeroU64::new(x).unwrap())")
+    )]
+    pub trigger_price: NonZeroU64,
+
+    #[cfg_attr(
+        test,
+        proptest(strategy = "(1u64..=std::u64::MAX).prop_map(|x| NonZeroU64::new(x).unwrap())")
+    )]
+    pub limit_price: NonZeroU64,
+
+    #[cfg_attr(
+        test,
+        proptest(strategy = "(1u64..=std::u64::MAX).prop_map(|x| NonZeroU64::new(x).unwrap())")
+    )]
+    pub max_coin_qty: NonZeroU64,
+    #[cfg_attr(
+        test,
+        proptest(strategy = "(1u64..=std::u64::MAX).prop_map(|x| NonZeroU64::new(x).unwrap())")
+    )]
+    pub max_native_pc_qty_including_fees: NonZeroU64,
+
+    pub self_trade_behavior: SelfTradeBehavior,
+
+    pub order_type: OrderType,
+    pub client_order_id: u64,
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(test, derive(Arbitrary))]
 pub struct NewOrderInstructionV2 {
@@ -267,6 +368,90 @@ impl NewOrderInstructionV3 {
     }
 }
 
+impl AmendOrderInstruction {
+    fn unpack(data: &[u8; 54]) -> Option<Self> {
+        let (
+            &side_arr,
+            &oid_arr,
+            &price_arr,
+            &max_coin_qty_arr,
+            &max_native_pc_qty_arr,
+            &self_trade_behavior_arr,
+            &otype_arr,
+            &limit_arr,
+        ) = array_refs![data, 4, 16, 8, 8, 8, 4, 4, 2];
+
+        let side = Side::try_from_primitive(u32::from_le_bytes(side_arr).try_into().ok()?).ok()?;
+        let order_id = u128::from_le_bytes(oid_arr);
+        let limit_price = NonZeroU64::new(u64::from_le_bytes(price_arr))?;
+        let max_coin_qty = NonZeroU64::new(u64::from_le_bytes(max_coin_qty_arr))?;
+        let max_native_pc_qty_including_fees =
+            NonZeroU64::new(u64::from_le_bytes(max_native_pc_qty_arr))?;
+        let self_trade_behavior = SelfTradeBehavior::try_from_primitive(
+            u32::from_le_bytes(self_trade_behavior_arr)
+                .try_into()
+                .ok()?,
+        )
+        .ok()?;
+        let order_type =
+            OrderType::try_from_primitive(u32::from_le_bytes(otype_arr).try_into().ok()?).ok()?;
+        let limit = u16::from_le_bytes(limit_arr);
+
+        Some(AmendOrderInstruction {
+            side,
+            order_id,
+            limit_price,
+            max_coin_qty,
+            max_native_pc_qty_including_fees,
+            self_trade_behavior,
+            order_type,
+            limit,
+        })
+    }
+}
+
+impl NewTriggerOrderInstruction {
+    fn unpack(data: &[u8; 52]) -> Option<Self> {
+        let (
+            &side_arr,
+            &trigger_price_arr,
+            &price_arr,
+            &max_coin_qty_arr,
+            &max_native_pc_qty_arr,
+            &self_trade_behavior_arr,
+            &otype_arr,
+            &client_order_id_bytes,
+        ) = array_refs![data, 4, 8, 8, 8, 8, 4, 4, 8];
+
+        let side = Side::try_from_primitive(u32::from_le_bytes(side_arr).try_into().ok()?).ok()?;
+        let trigger_price = NonZeroU64::new(u64::from_le_bytes(trigger_price_arr))?;
+        let limit_price = NonZeroU64::new(u64::from_le_bytes(price_arr))?;
+        let max_coin_qty = NonZeroU64::new(u64::from_le_bytes(max_coin_qty_arr))?;
+        let max_native_pc_qty_including_fees =
+            NonZeroU64::new(u64::from_le_bytes(max_native_pc_qty_arr))?;
+        let self_trade_behavior = SelfTradeBehavior::try_from_primitive(
+            u32::from_le_bytes(self_trade_behavior_arr)
+                .try_into()
+                .ok()?,
+        )
+        .ok()?;
+        let order_type =
+            OrderType::try_from_primitive(u32::from_le_bytes(otype_arr).try_into().ok()?).ok()?;
+        let client_order_id = u64::from_le_bytes(client_order_id_bytes);
+
+        Some(NewTriggerOrderInstruction {
+            side,
+            trigger_price,
+            limit_price,
+            max_coin_qty,
+            max_native_pc_qty_including_fees,
+            self_trade_behavior,
+            order_type,
+            client_order_id,
+        })
+    }
+}
+
 impl NewOrderInstructionV1 {
     fn unpack(data: &[u8; 32]) -> Option<Self> {
         let (&side_arr, &price_arr, &max_qty_arr, &otype_arr, &client_id_bytes) =
@@ -283,6 +468,8 @@ impl NewOrderInstructionV1 {
             0 => OrderType::Limit,
             1 => OrderType::ImmediateOrCancel,
             2 => OrderType::PostOnly,
+            3 => OrderType::FillOrKill,
+            4 => OrderType::PostOnlySlide,
             _ => return None,
         };
         Some(NewOrderInstructionV1 {
@@ -321,6 +508,115 @@ impl CancelOrderInstructionV2 {
     }
 }
 
+/// Cancels the owner's orders on one side of the book, or on both if `side`
+/// is `None`, searching at most `limit` nodes.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
+pub struct CancelAllOrdersInstruction {
+    pub side: Option<Side>,
+    pub limit: u16,
+}
+
+impl CancelAllOrdersInstruction {
+    fn unpack(data: &[u8]) -> Option<Self> {
+        let (&[has_side], rest) = array_refs![data, 1; ..;];
+        let (side, limit_arr) = match (has_side, rest.len()) {
+            (0, 2) => (None, array_ref![rest, 0, 2]),
+            (1, 6) => {
+                let (&side_arr, limit_arr) = array_refs![array_ref![rest, 0, 6], 4, 2];
+                let side =
+                    Side::try_from_primitive(u32::from_le_bytes(side_arr).try_into().ok()?).ok()?;
+                (Some(side), limit_arr)
+            }
+            _ => return None,
+        };
+        Some(CancelAllOrdersInstruction {
+            side,
+            limit: u16::from_le_bytes(*limit_arr),
+        })
+    }
+}
+
+/// A key of a permissioned market that its admin can hand over with
+/// `ProposeAuthority` and `AcceptAuthority`. A market's own disable authority
+/// and fee sweeper work alongside the global `disable_authority` and
+/// `fee_sweeper`, which keep working on every market.
+#[derive(
+    PartialEq, Eq, Copy, Clone, Debug, TryFromPrimitive, IntoPrimitive, Serialize, Deserialize,
+)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
+#[repr(u8)]
+pub enum AuthorityKind {
+    Admin = 0,
+    OpenOrders = 1,
+    Prune = 2,
+    ConsumeEvents = 3,
+    Disable = 4,
+    FeeSweeper = 5,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
+pub struct ProposeAuthorityInstruction {
+    pub kind: AuthorityKind,
+    pub new_authority: [u8; 32],
+}
+
+impl ProposeAuthorityInstruction {
+    fn unpack(data: &[u8; 36]) -> Option<Self> {
+        let (&kind_arr, &new_authority) = array_refs![data, 4, 32];
+        let kind = AuthorityKind::try_from_primitive(u32::from_le_bytes(kind_arr).try_into().ok()?)
+            .ok()?;
+        Some(ProposeAuthorityInstruction {
+            kind,
+            new_authority,
+        })
+    }
+}
+
+/// Rates are in tenths of a basis point and indexed by `FeeTier`. SRM
+/// thresholds are the native SRM balances needed for tiers SRM2 to SRM6.
+/// Setting `stable` puts every order on the market in the Stable tier.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
+pub struct UpdateFeeScheduleInstruction {
+    pub taker_fee_tenth_of_bps: [u16; 8],
+    pub maker_rebate_tenth_of_bps: [u16; 8],
+    pub srm_thresholds: [u64; 5],
+    pub msrm_threshold: u64,
+    pub stable: bool,
+}
+
+impl UpdateFeeScheduleInstruction {
+    fn unpack(data: &[u8; 81]) -> Option<Self> {
+        let (&taker_fee_arr, &maker_rebate_arr, srm_thresholds_arr, &msrm_threshold_arr, &[stable]) =
+            array_refs![data, 16, 16, 40, 8, 1];
+        let mut srm_thresholds = [0u64; 5];
+        for (threshold, chunk) in srm_thresholds
+            .iter_mut()
+            .zip(srm_thresholds_arr.chunks_exact(8))
+        {
+            *threshold = u64::from_le_bytes(chunk.try_into().ok()?);
+        }
+        let stable = match stable {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+        Some(UpdateFeeScheduleInstruction {
+            taker_fee_tenth_of_bps: cast(taker_fee_arr),
+            maker_rebate_tenth_of_bps: cast(maker_rebate_arr),
+            srm_thresholds,
+            msrm_threshold: u64::from_le_bytes(msrm_threshold_arr),
+            stable,
+        })
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(test, derive(Arbitrary))]
 #[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
@@ -350,17 +646,29 @@ pub enum MarketInstruction {
     /// 8. `[]` the rent sysvar
     /// 9. `[]` (optional) the (M)SRM account used for fee discounts
     NewOrder(NewOrderInstructionV1),
+    /// Matches up to `limit` stop orders armed by fills that ConsumeEvents
+    /// has seen. Does nothing without the trigger book, since other orders
+    /// are matched as they are placed.
+    ///
     /// 0. `[writable]` market
     /// 1. `[writable]` req_q
     /// 2. `[writable]` event_q
     /// 3. `[writable]` bids
     /// 4. `[writable]` asks
+    /// 5. `[writable]` (optional) coin fee receivable account (unused)
+    /// 6. `[writable]` (optional) pc fee receivable account (unused)
+    /// 7. `[writable]` (optional) the market's trigger book
     MatchOrders(u16),
+    /// Consuming a maker fill arms the stop orders it triggers if the
+    /// market's trigger book is passed last. Without it the fills consumed
+    /// don't arm any stops.
+    ///
     /// ... `[writable]` OpenOrders
     /// accounts.len() - 4 `[writable]` market
     /// accounts.len() - 3 `[writable]` event queue
-    /// accounts.len() - 2 `[]`
-    /// accounts.len() - 1 `[]`
+    /// accounts.len() - 2 `[writable]` coin fee receivable account (unused)
+    /// accounts.len() - 1 `[writable]` pc fee receivable account (unused)
+    /// then `[writable]` (optional) the market's trigger book
     ConsumeEvents(u16),
     /// 0. `[]` market
     /// 1. `[writable]` OpenOrders
@@ -404,6 +712,15 @@ pub enum MarketInstruction {
     /// 8. `[]` the rent sysvar
     /// 9. `[]` (optional) the (M)SRM account used for fee discounts
     NewOrderV2(NewOrderInstructionV2),
+    /// The order is rejected if `max_ts` has already passed. Whatever rests
+    /// on the book stays there until it is cancelled or filled; see
+    /// NewOrderWithExpiry for orders that expire.
+    ///
+    /// Self-trade prevention across a wallet's OpenOrders accounts is best
+    /// effort: the program can't list a wallet's accounts, so it only sees
+    /// the ones passed after the trigger book. Resting orders of any other
+    /// account the owner has are traded with like anyone else's.
+    ///
     /// 0. `[writable]` the market
     /// 1. `[writable]` the OpenOrders account to use
     /// 2. `[writable]` the request queue
@@ -417,6 +734,8 @@ pub enum MarketInstruction {
     /// 10. `[]` spl token program
     /// 11. `[]` the rent sysvar
     /// 12. `[]` (optional) the (M)SRM account used for fee discounts
+    /// 13. `[writable]` (optional) the trigger book to arm and execute stop orders from
+    /// 14. `[]` (optional, any number) the owner's other OpenOrders accounts, for self-trade checks
     NewOrderV3(NewOrderInstructionV3),
     /// 0. `[writable]` market
     /// 1. `[writable]` bids
@@ -445,6 +764,7 @@ pub enum MarketInstruction {
     /// 10. `[]` spl token program
     /// 11. `[]` vault signer
     /// 12. `[]` (optional) the (M)SRM account used for fee discounts
+    /// 13. `[writable]` (optional) the trigger book to arm and execute stop orders from
     SendTake(SendTakeInstruction),
     /// 0. `[writable]` OpenOrders
     /// 1. `[signer]` the OpenOrders owner
@@ -456,6 +776,10 @@ pub enum MarketInstruction {
     /// 2. `[]` market
     /// 3. `[]`
     /// 4. `[signer]` open orders market authority (optional).
+    ///
+    /// The size of the OpenOrders account picks its layout: accounts sized for
+    /// `OpenOrders` hold 128 orders, accounts sized with `OpenOrdersV2::data_len`
+    /// hold the requested number of slots, up to `OpenOrdersV2::MAX_SLOTS`.
     InitOpenOrders,
     /// Removes all orders for a given open orders account from the orderbook.
     ///
@@ -471,6 +795,7 @@ pub enum MarketInstruction {
     /// accounts.len() - 3 `[writable]` market
     /// accounts.len() - 2 `[writable]` event queue
     /// accounts.len() - 1 `[signer]` crank authority
+    /// then `[writable]` (optional) the market's trigger book
     ConsumeEventsPermissioned(u16),
     /// 0. `[writable]` market
     /// 1. `[writable]` bids
@@ -492,6 +817,7 @@ pub enum MarketInstruction {
     /// 10. `[]` spl token program
     /// 11. `[]` the rent sysvar
     /// 12. `[]` (optional) the (M)SRM account used for fee discounts
+    /// 13. `[writable]` (optional) the trigger book to arm and execute stop orders from
     ReplaceOrderByClientId(NewOrderInstructionV3),
     /// 0. `[writable]` the market
     /// 1. `[writable]` the OpenOrders account to use
@@ -506,6 +832,7 @@ pub enum MarketInstruction {
     /// 10. `[]` spl token program
     /// 11. `[]` the rent sysvar
     /// 12. `[]` (optional) the (M)SRM account used for fee discounts
+    /// 13. `[writable]` (optional) the trigger book to arm and execute stop orders from
     #[cfg_attr(
         test,
         proptest(
@@ -513,6 +840,165 @@ pub enum MarketInstruction {
         )
     )]
     ReplaceOrdersByClientIds(Vec<NewOrderInstructionV3>),
+    /// Rests a stop order in the trigger book. Funds are locked in the
+    /// OpenOrders account immediately. The order is armed by the first
+    /// later fill through the trigger price, either in a NewOrderV3 or
+    /// SendTake that includes the trigger book or when ConsumeEvents
+    /// consumes the fill. Armed orders are matched by the next NewOrderV3,
+    /// SendTake or MatchOrders that includes the trigger book, each with its
+    /// own match limit, and are settled by ConsumeEvents.
+    ///
+    /// The market admin creates the trigger book with `InitTriggerBook`; a
+    /// market has only one.
+    ///
+    /// 0. `[writable]` the market
+    /// 1. `[writable]` the OpenOrders account to use
+    /// 2. `[writable]` the request queue
+    /// 3. `[]` the event queue
+    /// 4. `[writable]` the trigger book
+    /// 5. `[writable]` the (coin or price currency) account paying for the order
+    /// 6. `[signer]` owner of the OpenOrders account
+    /// 7. `[writable]` coin vault
+    /// 8. `[writable]` pc vault
+    /// 9. `[]` spl token program
+    /// 10. `[]` the rent sysvar
+    /// 11. `[]` (optional) the (M)SRM account used for fee discounts
+    NewTriggerOrder(NewTriggerOrderInstruction),
+    /// Removes a stop order from the trigger book and unlocks its funds.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[writable]` the trigger book
+    /// 2. `[writable]` OpenOrders
+    /// 3. `[signer]` the OpenOrders owner
+    CancelTriggerOrder(u128),
+    /// Removes resting orders whose expiry has passed. Anyone may call this;
+    /// the owners' funds are released when the queued Out events are consumed.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[writable]` bids
+    /// 2. `[writable]` asks
+    /// 3. `[writable]` event queue
+    PruneExpiredOrders(u16),
+    /// Replaces the market's fee schedule. Only permissioned markets have
+    /// room to store one. On markets without an admin the global disable
+    /// authority signs instead.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[signer]` market admin, or the disable authority if there's none
+    UpdateFeeSchedule(UpdateFeeScheduleInstruction),
+    /// Places a Limit or PostOnly order that only shows `display_qty` at a
+    /// time. The OpenOrders account keeps the order's original id, which
+    /// CancelOrderV2 accepts even after the order has been replenished.
+    ///
+    /// Takes the same accounts as NewOrderV3.
+    NewIcebergOrder(NewIcebergOrderInstruction),
+    /// Amends one of the owner's resting orders. A requeued order keeps its
+    /// client order id and expiry, but gets a new order id and trades as the
+    /// amend's order type; a PostOnly amend that would cross fails instead.
+    /// The new price and size must meet the market's tick and minimum size,
+    /// and differ from the order's current ones. Iceberg orders can't be
+    /// amended.
+    ///
+    /// Takes the same accounts as NewOrderV3.
+    AmendOrder(AmendOrderInstruction),
+    /// Cancels all of the owner's resting orders, unlocking their funds in
+    /// the OpenOrders account.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[writable]` bids
+    /// 2. `[writable]` asks
+    /// 3. `[writable]` OpenOrders
+    /// 4. `[signer]` the OpenOrders owner
+    /// 5. `[writable]` event_q
+    CancelAllOrders(CancelAllOrdersInstruction),
+    /// Makes the event queue follow every maker Fill with a FillDetail
+    /// carrying the trade price, the taker's order id, a timestamp and the
+    /// Fill's sequence number. Consumers must skip or decode the new events
+    /// before it is enabled. Only permissioned markets have an admin to
+    /// enable it.
+    ///
+    /// 0. `[]` market
+    /// 1. `[writable]` event queue
+    /// 2. `[signer]` market admin
+    EnableFillDetails,
+    /// Zeroes the market, its queues, its books and its trigger book, and
+    /// sends their lamports to the destination, which can't be one of them.
+    /// The market must be disabled, the books and queues empty, and all
+    /// deposits settled and fees swept.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[writable]` request queue
+    /// 2. `[writable]` event queue
+    /// 3. `[writable]` bids
+    /// 4. `[writable]` asks
+    /// 5. `[signer]` disable authority, or the market admin
+    /// 6. `[writable]` the destination account to send rent exemption SOL to
+    /// 7. `[writable]` the market's trigger book, required if it has one
+    CloseMarket,
+    /// Puts the market in cancel-only mode: instructions that place orders
+    /// fail, while cancels, ConsumeEvents and SettleFunds keep working.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[signer]` disable authority, or the market admin
+    EnterCancelOnly,
+    /// Resumes normal trading on a market in cancel-only mode.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[signer]` disable authority, or the market admin
+    ExitCancelOnly,
+    /// Proposes a new key for one of the market's authorities. The transfer
+    /// takes effect once the new key signs `AcceptAuthority`, and replaces
+    /// any earlier proposal that wasn't accepted.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[signer]` market admin
+    ProposeAuthority(ProposeAuthorityInstruction),
+    /// Accepts a transfer proposed with `ProposeAuthority`.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[signer]` the proposed authority
+    AcceptAuthority(AuthorityKind),
+    /// Sets how far, in basis points, takers may trade from the market's
+    /// last trade price within one instruction. Orders that would fill
+    /// outside the band fail, except triggered stop orders, which stop at
+    /// the band and cancel the rest. Zero turns the band off.
+    ///
+    /// The band only applies once the market has traded: until then there's
+    /// no last trade price to measure from.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[signer]` market admin
+    SetPriceBand(u16),
+    /// Drops the market's own disable authority or fee sweeper, so that only
+    /// the global key can act for it. The admin and the other authorities
+    /// have no default and can only be transferred.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[signer]` market admin
+    ResetAuthority(AuthorityKind),
+    /// Stops following maker Fills with FillDetails. FillDetails already in
+    /// the queue stay there until they are consumed.
+    ///
+    /// 0. `[]` market
+    /// 1. `[writable]` event queue
+    /// 2. `[signer]` market admin
+    DisableFillDetails,
+    /// Initializes the market's trigger book, which must be owned by the dex
+    /// program, rent exempt and exactly `TriggerBook::DATA_LEN` bytes long
+    /// plus padding. A market has only one.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[writable]` the trigger book
+    /// 2. `[signer]` market admin
+    /// 3. `[]` the rent sysvar
+    InitTriggerBook,
+    /// Places an order whose resting part expires at `expiry_ts`, which must
+    /// not have passed and must fit in a u32. Once it has expired the order
+    /// is removed the next time it would be matched, or by
+    /// PruneExpiredOrders.
+    ///
+    /// Takes the same accounts as NewOrderV3.
+    NewOrderWithExpiry(NewOrderWithExpiryInstruction),
 }
 
 impl MarketInstruction {
@@ -539,6 +1025,21 @@ impl MarketInstruction {
                     fee_rate_bps: u16::from_le_bytes(*fields.2),
                     vault_signer_nonce: u64::from_le_bytes(*fields.3),
                     pc_dust_threshold: u64::from_le_bytes(*fields.4),
+                    tick_size: 1,
+                    min_base_qty: 1,
+                }
+            }),
+            (0, 50) => MarketInstruction::InitializeMarket({
+                let data_array = array_ref![data, 0, 50];
+                let fields = array_refs![data_array, 8, 8, 2, 8, 8, 8, 8];
+                InitializeMarketInstruction {
+                    coin_lot_size: u64::from_le_bytes(*fields.0),
+                    pc_lot_size: u64::from_le_bytes(*fields.1),
+                    fee_rate_bps: u16::from_le_bytes(*fields.2),
+                    vault_signer_nonce: u64::from_le_bytes(*fields.3),
+                    pc_dust_threshold: u64::from_le_bytes(*fields.4),
+                    tick_size: u64::from_le_bytes(*fields.5),
+                    min_base_qty: u64::from_le_bytes(*fields.6),
                 }
             }),
             (1, 32) => MarketInstruction::NewOrder({
@@ -648,21 +1149,546 @@ impl MarketInstruction {
                     .collect::<Option<Vec<_>>>()?;
                 MarketInstruction::ReplaceOrdersByClientIds(new_orders)
             }
-            _ => return None,
-        })
-    }
-
-    #[cfg(test)]
-    #[inline]
-    pub fn unpack_serde(data: &[u8]) -> Result<Self, ()> {
-        match data.split_first() {
-            None => Err(()),
+            (21, 52) => MarketInstruction::NewTriggerOrder({
+                let data_arr = array_ref![data, 0, 52];
+                NewTriggerOrderInstruction::unpack(data_arr)?
+            }),
+            (22, 16) => {
+                let order_id = array_ref![data, 0, 16];
+                MarketInstruction::CancelTriggerOrder(u128::from_le_bytes(*order_id))
+            }
+            (23, 2) => {
+                let limit = array_ref![data, 0, 2];
+                MarketInstruction::PruneExpiredOrders(u16::from_le_bytes(*limit))
+            }
+            (24, 81) => MarketInstruction::UpdateFeeSchedule({
+                let data_arr = array_ref![data, 0, 81];
+                UpdateFeeScheduleInstruction::unpack(data_arr)?
+            }),
+            (25, 62) => MarketInstruction::NewIcebergOrder({
+                let data_arr = array_ref![data, 0, 62];
+                let (order_arr, display_qty_arr) = array_refs![data_arr, 54, 8];
+                NewIcebergOrderInstruction {
+                    order: NewOrderInstructionV3::unpack(order_arr)?,
+                    display_qty: NonZeroU64::new(u64::from_le_bytes(*display_qty_arr))?,
+                }
+            }),
+            (26, 54) => MarketInstruction::AmendOrder({
+                let data_arr = array_ref![data, 0, 54];
+                AmendOrderInstruction::unpack(data_arr)?
+            }),
+            (27, 3) | (27, 7) => {
+                MarketInstruction::CancelAllOrders(CancelAllOrdersInstruction::unpack(data)?)
+            }
+            (28, 0) => MarketInstruction::EnableFillDetails,
+            (29, 0) => MarketInstruction::CloseMarket,
+            (30, 0) => MarketInstruction::EnterCancelOnly,
+            (31, 0) => MarketInstruction::ExitCancelOnly,
+            (32, 36) => MarketInstruction::ProposeAuthority({
+                let data_arr = array_ref![data, 0, 36];
+                ProposeAuthorityInstruction::unpack(data_arr)?
+            }),
+            (33, 4) => {
+                let kind_arr = array_ref![data, 0, 4];
+                let kind = u32::from_le_bytes(*kind_arr).try_into().ok()?;
+                MarketInstruction::AcceptAuthority(AuthorityKind::try_from_primitive(kind).ok()?)
+            }
+            (34, 2) => {
+                let bps = array_ref![data, 0, 2];
+                MarketInstruction::SetPriceBand(u16::from_le_bytes(*bps))
+            }
+            (35, 4) => {
+                let kind_arr = array_ref![data, 0, 4];
+                let kind = u32::from_le_bytes(*kind_arr).try_into().ok()?;
+                MarketInstruction::ResetAuthority(AuthorityKind::try_from_primitive(kind).ok()?)
+            }
+            (36, 0) => MarketInstruction::DisableFillDetails,
+            (37, 0) => MarketInstruction::InitTriggerBook,
+            (38, 62) => MarketInstruction::NewOrderWithExpiry({
+                let data_arr = array_ref![data, 0, 62];
+                let (order_arr, expiry_ts_arr) = array_refs![data_arr, 54, 8];
+                NewOrderWithExpiryInstruction {
+                    order: NewOrderInstructionV3::unpack(order_arr)?,
+                    expiry_ts: i64::from_le_bytes(*expiry_ts_arr),
+                }
+            }),
+            _ => return None,
+        })
+    }
+
+    #[cfg(test)]
+    #[inline]
+    pub fn unpack_serde(data: &[u8]) -> Result<Self, ()> {
+        match data.split_first() {
+            None => Err(()),
             Some((&0u8, rest)) => bincode::deserialize(rest).map_err(|_| ()),
             Some((_, _rest)) => Err(()),
         }
     }
 }
 
+/// One of the accounts an instruction takes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AccountRole {
+    pub name: &'static str,
+    pub writable: bool,
+    pub signer: bool,
+}
+
+const fn writable(name: &'static str) -> AccountRole {
+    AccountRole {
+        name,
+        writable: true,
+        signer: false,
+    }
+}
+
+const fn readonly(name: &'static str) -> AccountRole {
+    AccountRole {
+        name,
+        writable: false,
+        signer: false,
+    }
+}
+
+const fn signer(name: &'static str) -> AccountRole {
+    AccountRole {
+        name,
+        writable: false,
+        signer: true,
+    }
+}
+
+/// The accounts an instruction takes, in order: any number of `leading`
+/// accounts, all of `required`, a prefix of `optional`, any of `by_owner` in
+/// order, then any number of `trailing` accounts. The program tells the
+/// `by_owner` accounts apart from each other and from the ones around them
+/// by their owner and flags, so position alone doesn't always say which role
+/// an account has.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AccountRoles {
+    pub leading: Option<AccountRole>,
+    pub required: &'static [AccountRole],
+    pub optional: &'static [AccountRole],
+    pub by_owner: &'static [AccountRole],
+    pub trailing: Option<AccountRole>,
+}
+
+impl AccountRoles {
+    const fn fixed(required: &'static [AccountRole]) -> Self {
+        AccountRoles {
+            leading: None,
+            required,
+            optional: &[],
+            by_owner: &[],
+            trailing: None,
+        }
+    }
+
+    const fn with_optional(
+        required: &'static [AccountRole],
+        optional: &'static [AccountRole],
+    ) -> Self {
+        AccountRoles {
+            leading: None,
+            required,
+            optional,
+            by_owner: &[],
+            trailing: None,
+        }
+    }
+
+    /// Every way `len` accounts can be assigned roles, one role per account.
+    pub fn layouts(&self, len: usize) -> Vec<Vec<AccountRole>> {
+        let mut layouts = Vec::new();
+        let extra = match len.checked_sub(self.required.len()) {
+            Some(extra) => extra,
+            None => return layouts,
+        };
+        let max_leading = if self.leading.is_some() { extra } else { 0 };
+        for leading_len in 0..=max_leading {
+            let rest = extra - leading_len;
+            for optional_len in 0..=self.optional.len().min(rest) {
+                let rest = rest - optional_len;
+                for mask in 0..1usize << self.by_owner.len() {
+                    let by_owner = self
+                        .by_owner
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| mask & (1 << i) != 0)
+                        .map(|(_, role)| *role);
+                    let by_owner_len = mask.count_ones() as usize;
+                    let trailing_len = match rest.checked_sub(by_owner_len) {
+                        Some(0) => 0,
+                        Some(n) if self.trailing.is_some() => n,
+                        _ => continue,
+                    };
+                    let mut layout = Vec::with_capacity(len);
+                    layout.extend(self.leading.iter().cycle().take(leading_len));
+                    layout.extend_from_slice(self.required);
+                    layout.extend_from_slice(&self.optional[..optional_len]);
+                    layout.extend(by_owner);
+                    layout.extend(self.trailing.iter().cycle().take(trailing_len));
+                    layouts.push(layout);
+                }
+            }
+        }
+        layouts
+    }
+}
+
+const FEE_DISCOUNT: AccountRole = readonly("(M)SRM fee discount account");
+const TRIGGER_BOOK: AccountRole = writable("trigger book");
+
+const INITIALIZE_MARKET_ACCOUNTS: AccountRoles = AccountRoles::with_optional(
+    &[
+        writable("market"),
+        writable("request queue"),
+        writable("event queue"),
+        writable("bids"),
+        writable("asks"),
+        writable("coin vault"),
+        writable("pc vault"),
+        readonly("coin mint"),
+        readonly("pc mint"),
+        readonly("rent sysvar"),
+    ],
+    &[
+        readonly("open orders market authority"),
+        readonly("prune authority"),
+        readonly("crank authority"),
+    ],
+);
+
+const NEW_ORDER_ACCOUNTS: AccountRoles = AccountRoles::with_optional(
+    &[
+        writable("market"),
+        writable("open orders"),
+        writable("request queue"),
+        writable("order payer"),
+        signer("open orders owner"),
+        writable("coin vault"),
+        writable("pc vault"),
+        readonly("spl token program"),
+        readonly("rent sysvar"),
+    ],
+    &[FEE_DISCOUNT],
+);
+
+const NEW_ORDER_V3_ACCOUNTS: AccountRoles = AccountRoles {
+    leading: None,
+    required: &[
+        writable("market"),
+        writable("open orders"),
+        writable("request queue"),
+        writable("event queue"),
+        writable("bids"),
+        writable("asks"),
+        writable("order payer"),
+        signer("open orders owner"),
+        writable("coin vault"),
+        writable("pc vault"),
+        readonly("spl token program"),
+        readonly("rent sysvar"),
+    ],
+    optional: &[],
+    by_owner: &[FEE_DISCOUNT, TRIGGER_BOOK],
+    trailing: Some(readonly("other open orders of the owner")),
+};
+
+const MATCH_ORDERS_ACCOUNTS: AccountRoles = AccountRoles {
+    leading: None,
+    required: &[
+        writable("market"),
+        writable("request queue"),
+        writable("event queue"),
+        writable("bids"),
+        writable("asks"),
+    ],
+    optional: &[
+        writable("coin fee receivable account"),
+        writable("pc fee receivable account"),
+    ],
+    by_owner: &[TRIGGER_BOOK],
+    trailing: None,
+};
+
+const CONSUME_EVENTS_ACCOUNTS: AccountRoles = AccountRoles {
+    leading: Some(writable("open orders")),
+    required: &[
+        writable("market"),
+        writable("event queue"),
+        writable("coin fee receivable account"),
+        writable("pc fee receivable account"),
+    ],
+    optional: &[],
+    by_owner: &[TRIGGER_BOOK],
+    trailing: None,
+};
+
+const CONSUME_EVENTS_PERMISSIONED_ACCOUNTS: AccountRoles = AccountRoles {
+    leading: Some(writable("open orders")),
+    required: &[
+        writable("market"),
+        writable("event queue"),
+        signer("crank authority"),
+    ],
+    optional: &[],
+    by_owner: &[TRIGGER_BOOK],
+    trailing: None,
+};
+
+const CANCEL_ORDER_ACCOUNTS: AccountRoles = AccountRoles::fixed(&[
+    readonly("market"),
+    writable("open orders"),
+    writable("request queue"),
+    signer("open orders owner"),
+]);
+
+const CANCEL_ORDER_V2_ACCOUNTS: AccountRoles = AccountRoles::fixed(&[
+    writable("market"),
+    writable("bids"),
+    writable("asks"),
+    writable("open orders"),
+    signer("open orders owner"),
+    writable("event queue"),
+]);
+
+const SETTLE_FUNDS_ACCOUNTS: AccountRoles = AccountRoles::with_optional(
+    &[
+        writable("market"),
+        writable("open orders"),
+        signer("open orders owner"),
+        writable("coin vault"),
+        writable("pc vault"),
+        writable("coin wallet"),
+        writable("pc wallet"),
+        readonly("vault signer"),
+        readonly("spl token program"),
+    ],
+    &[writable("referrer pc wallet")],
+);
+
+const DISABLE_MARKET_ACCOUNTS: AccountRoles =
+    AccountRoles::fixed(&[writable("market"), signer("disable authority")]);
+
+const SWEEP_FEES_ACCOUNTS: AccountRoles = AccountRoles::fixed(&[
+    writable("market"),
+    writable("pc vault"),
+    signer("fee sweeper"),
+    writable("fee receivable account"),
+    readonly("vault signer"),
+    readonly("spl token program"),
+]);
+
+const SEND_TAKE_ACCOUNTS: AccountRoles = AccountRoles {
+    leading: None,
+    required: &[
+        writable("market"),
+        writable("request queue"),
+        writable("event queue"),
+        writable("bids"),
+        writable("asks"),
+        writable("coin wallet"),
+        writable("pc wallet"),
+        signer("wallet owner"),
+        writable("coin vault"),
+        writable("pc vault"),
+        readonly("spl token program"),
+        readonly("vault signer"),
+    ],
+    optional: &[],
+    by_owner: &[FEE_DISCOUNT, TRIGGER_BOOK],
+    trailing: None,
+};
+
+const CLOSE_OPEN_ORDERS_ACCOUNTS: AccountRoles = AccountRoles::fixed(&[
+    writable("open orders"),
+    signer("open orders owner"),
+    writable("rent destination"),
+    readonly("market"),
+]);
+
+const INIT_OPEN_ORDERS_ACCOUNTS: AccountRoles = AccountRoles::with_optional(
+    &[
+        writable("open orders"),
+        signer("open orders owner"),
+        readonly("market"),
+        readonly("rent sysvar"),
+    ],
+    &[signer("open orders market authority")],
+);
+
+const PRUNE_ACCOUNTS: AccountRoles = AccountRoles::fixed(&[
+    writable("market"),
+    writable("bids"),
+    writable("asks"),
+    signer("prune authority"),
+    writable("open orders"),
+    readonly("open orders owner"),
+    writable("event queue"),
+]);
+
+const NEW_TRIGGER_ORDER_ACCOUNTS: AccountRoles = AccountRoles::with_optional(
+    &[
+        writable("market"),
+        writable("open orders"),
+        writable("request queue"),
+        readonly("event queue"),
+        writable("trigger book"),
+        writable("order payer"),
+        signer("open orders owner"),
+        writable("coin vault"),
+        writable("pc vault"),
+        readonly("spl token program"),
+        readonly("rent sysvar"),
+    ],
+    &[FEE_DISCOUNT],
+);
+
+const CANCEL_TRIGGER_ORDER_ACCOUNTS: AccountRoles = AccountRoles::fixed(&[
+    writable("market"),
+    writable("trigger book"),
+    writable("open orders"),
+    signer("open orders owner"),
+]);
+
+const PRUNE_EXPIRED_ORDERS_ACCOUNTS: AccountRoles = AccountRoles::fixed(&[
+    writable("market"),
+    writable("bids"),
+    writable("asks"),
+    writable("event queue"),
+]);
+
+const FILL_DETAILS_ACCOUNTS: AccountRoles = AccountRoles::fixed(&[
+    readonly("market"),
+    writable("event queue"),
+    signer("market admin"),
+]);
+
+const CLOSE_MARKET_ACCOUNTS: AccountRoles = AccountRoles::with_optional(
+    &[
+        writable("market"),
+        writable("request queue"),
+        writable("event queue"),
+        writable("bids"),
+        writable("asks"),
+        signer("disable authority or market admin"),
+        writable("rent destination"),
+    ],
+    &[writable("trigger book")],
+);
+
+const CANCEL_ONLY_ACCOUNTS: AccountRoles = AccountRoles::fixed(&[
+    writable("market"),
+    signer("disable authority or market admin"),
+]);
+
+const MARKET_ADMIN_ACCOUNTS: AccountRoles =
+    AccountRoles::fixed(&[writable("market"), signer("market admin")]);
+
+const UPDATE_FEE_SCHEDULE_ACCOUNTS: AccountRoles = AccountRoles::fixed(&[
+    writable("market"),
+    signer("market admin or disable authority"),
+]);
+
+const ACCEPT_AUTHORITY_ACCOUNTS: AccountRoles =
+    AccountRoles::fixed(&[writable("market"), signer("proposed authority")]);
+
+const INIT_TRIGGER_BOOK_ACCOUNTS: AccountRoles = AccountRoles::fixed(&[
+    writable("market"),
+    TRIGGER_BOOK,
+    signer("market admin"),
+    readonly("rent sysvar"),
+]);
+
+impl MarketInstruction {
+    pub fn name(&self) -> &'static str {
+        use MarketInstruction::*;
+        match self {
+            InitializeMarket(_) => "InitializeMarket",
+            NewOrder(_) => "NewOrder",
+            MatchOrders(_) => "MatchOrders",
+            ConsumeEvents(_) => "ConsumeEvents",
+            CancelOrder(_) => "CancelOrder",
+            SettleFunds => "SettleFunds",
+            CancelOrderByClientId(_) => "CancelOrderByClientId",
+            DisableMarket => "DisableMarket",
+            SweepFees => "SweepFees",
+            NewOrderV2(_) => "NewOrderV2",
+            NewOrderV3(_) => "NewOrderV3",
+            CancelOrderV2(_) => "CancelOrderV2",
+            CancelOrderByClientIdV2(_) => "CancelOrderByClientIdV2",
+            SendTake(_) => "SendTake",
+            CloseOpenOrders => "CloseOpenOrders",
+            InitOpenOrders => "InitOpenOrders",
+            Prune(_) => "Prune",
+            ConsumeEventsPermissioned(_) => "ConsumeEventsPermissioned",
+            CancelOrdersByClientIds(_) => "CancelOrdersByClientIds",
+            ReplaceOrderByClientId(_) => "ReplaceOrderByClientId",
+            ReplaceOrdersByClientIds(_) => "ReplaceOrdersByClientIds",
+            NewTriggerOrder(_) => "NewTriggerOrder",
+            CancelTriggerOrder(_) => "CancelTriggerOrder",
+            PruneExpiredOrders(_) => "PruneExpiredOrders",
+            UpdateFeeSchedule(_) => "UpdateFeeSchedule",
+            NewIcebergOrder(_) => "NewIcebergOrder",
+            AmendOrder(_) => "AmendOrder",
+            CancelAllOrders(_) => "CancelAllOrders",
+            EnableFillDetails => "EnableFillDetails",
+            CloseMarket => "CloseMarket",
+            EnterCancelOnly => "EnterCancelOnly",
+            ExitCancelOnly => "ExitCancelOnly",
+            ProposeAuthority(_) => "ProposeAuthority",
+            AcceptAuthority(_) => "AcceptAuthority",
+            SetPriceBand(_) => "SetPriceBand",
+            ResetAuthority(_) => "ResetAuthority",
+            DisableFillDetails => "DisableFillDetails",
+            InitTriggerBook => "InitTriggerBook",
+            NewOrderWithExpiry(_) => "NewOrderWithExpiry",
+        }
+    }
+
+    /// The accounts the instruction takes, as documented on each variant.
+    pub fn account_roles(&self) -> AccountRoles {
+        use MarketInstruction::*;
+        match self {
+            InitializeMarket(_) => INITIALIZE_MARKET_ACCOUNTS,
+            NewOrder(_) | NewOrderV2(_) => NEW_ORDER_ACCOUNTS,
+            MatchOrders(_) => MATCH_ORDERS_ACCOUNTS,
+            ConsumeEvents(_) => CONSUME_EVENTS_ACCOUNTS,
+            CancelOrder(_) | CancelOrderByClientId(_) => CANCEL_ORDER_ACCOUNTS,
+            SettleFunds => SETTLE_FUNDS_ACCOUNTS,
+            DisableMarket => DISABLE_MARKET_ACCOUNTS,
+            SweepFees => SWEEP_FEES_ACCOUNTS,
+            NewOrderV3(_)
+            | ReplaceOrderByClientId(_)
+            | ReplaceOrdersByClientIds(_)
+            | NewIcebergOrder(_)
+            | AmendOrder(_)
+            | NewOrderWithExpiry(_) => NEW_ORDER_V3_ACCOUNTS,
+            CancelOrderV2(_)
+            | CancelOrderByClientIdV2(_)
+            | CancelOrdersByClientIds(_)
+            | CancelAllOrders(_) => CANCEL_ORDER_V2_ACCOUNTS,
+            SendTake(_) => SEND_TAKE_ACCOUNTS,
+            CloseOpenOrders => CLOSE_OPEN_ORDERS_ACCOUNTS,
+            InitOpenOrders => INIT_OPEN_ORDERS_ACCOUNTS,
+            Prune(_) => PRUNE_ACCOUNTS,
+            ConsumeEventsPermissioned(_) => CONSUME_EVENTS_PERMISSIONED_ACCOUNTS,
+            NewTriggerOrder(_) => NEW_TRIGGER_ORDER_ACCOUNTS,
+            CancelTriggerOrder(_) => CANCEL_TRIGGER_ORDER_ACCOUNTS,
+            PruneExpiredOrders(_) => PRUNE_EXPIRED_ORDERS_ACCOUNTS,
+            EnableFillDetails | DisableFillDetails => FILL_DETAILS_ACCOUNTS,
+            CloseMarket => CLOSE_MARKET_ACCOUNTS,
+            EnterCancelOnly | ExitCancelOnly => CANCEL_ONLY_ACCOUNTS,
+            UpdateFeeSchedule(_) => UPDATE_FEE_SCHEDULE_ACCOUNTS,
+            ProposeAuthority(_) | SetPriceBand(_) | ResetAuthority(_) => MARKET_ADMIN_ACCOUNTS,
+            AcceptAuthority(_) => ACCEPT_AUTHORITY_ACCOUNTS,
+            InitTriggerBook => INIT_TRIGGER_BOOK_ACCOUNTS,
+        }
+    }
+}
+
 pub fn initialize_market(
     market: &Pubkey,
     program_id: &Pubkey,
@@ -682,6 +1708,8 @@ pub fn initialize_market(
     pc_lot_size: u64,
     vault_signer_nonce: u64,
     pc_dust_threshold: u64,
+    tick_size: u64,
+    min_base_qty: u64,
 ) -> Result<solana_program::instruction::Instruction, DexError> {
     let data = MarketInstruction::InitializeMarket(InitializeMarketInstruction {
         coin_lot_size,
@@ -689,6 +1717,8 @@ pub fn initialize_market(
         fee_rate_bps: 0,
         vault_signer_nonce,
         pc_dust_threshold,
+        tick_size,
+        min_base_qty,
     })
     .pack();
 
@@ -811,10 +1841,11 @@ pub fn match_orders(
     event_queue: &Pubkey,
     coin_fee_receivable_account: &Pubkey,
     pc_fee_receivable_account: &Pubkey,
+    trigger_book: Option<&Pubkey>,
     limit: u16,
 ) -> Result<Instruction, DexError> {
     let data = MarketInstruction::MatchOrders(limit).pack();
-    let accounts: Vec<AccountMeta> = vec![
+    let mut accounts: Vec<AccountMeta> = vec![
         AccountMeta::new(*market, false),
         AccountMeta::new(*request_queue, false),
         AccountMeta::new(*event_queue, false),
@@ -823,6 +1854,9 @@ pub fn match_orders(
         AccountMeta::new(*coin_fee_receivable_account, false),
         AccountMeta::new(*pc_fee_receivable_account, false),
     ];
+    if let Some(key) = trigger_book {
+        accounts.push(AccountMeta::new(*key, false));
+    }
     Ok(Instruction {
         program_id: *program_id,
         data,
@@ -837,6 +1871,7 @@ pub fn consume_events(
     event_queue: &Pubkey,
     coin_fee_receivable_account: &Pubkey,
     pc_fee_receivable_account: &Pubkey,
+    trigger_book: Option<&Pubkey>,
     limit: u16,
 ) -> Result<Instruction, DexError> {
     let data = MarketInstruction::ConsumeEvents(limit).pack();
@@ -850,6 +1885,9 @@ pub fn consume_events(
         AccountMeta::new(*coin_fee_receivable_account, false),
         AccountMeta::new(*pc_fee_receivable_account, false),
     ]);
+    if let Some(key) = trigger_book {
+        accounts.push(AccountMeta::new(*key, false));
+    }
     Ok(Instruction {
         program_id: *program_id,
         data,
@@ -863,6 +1901,7 @@ pub fn consume_events_permissioned(
     market: &Pubkey,
     event_queue: &Pubkey,
     consume_events_authority: &Pubkey,
+    trigger_book: Option<&Pubkey>,
     limit: u16,
 ) -> Result<Instruction, DexError> {
     let data = MarketInstruction::ConsumeEventsPermissioned(limit).pack();
@@ -875,6 +1914,9 @@ pub fn consume_events_permissioned(
         AccountMeta::new(*event_queue, false),
         AccountMeta::new_readonly(*consume_events_authority, true),
     ]);
+    if let Some(key) = trigger_book {
+        accounts.push(AccountMeta::new(*key, false));
+    }
     Ok(Instruction {
         program_id: *program_id,
         data,
@@ -1168,62 +2210,640 @@ pub fn prune(
     })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+pub fn cancel_all_orders(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    market_bids: &Pubkey,
+    market_asks: &Pubkey,
+    open_orders_account: &Pubkey,
+    open_orders_account_owner: &Pubkey,
+    event_queue: &Pubkey,
+    side: Option<Side>,
+    limit: u16,
+) -> Result<Instruction, DexError> {
+    let data =
+        MarketInstruction::CancelAllOrders(CancelAllOrdersInstruction { side, limit }).pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*market_bids, false),
+        AccountMeta::new(*market_asks, false),
+        AccountMeta::new(*open_orders_account, false),
+        AccountMeta::new_readonly(*open_orders_account_owner, true),
+        AccountMeta::new(*event_queue, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
 
-    proptest! {
-        #[test]
-        fn test_pack_unpack_roundtrip(inst: MarketInstruction) {
-            let serialized = inst.pack();
-            let unpack_serde_result = MarketInstruction::unpack_serde(&serialized).ok();
-            let unpack_result = MarketInstruction::unpack(&serialized);
-            assert_eq!(unpack_result, Some(inst));
-            assert!(unpack_serde_result == unpack_result,
-                "Serialized:\n{:?}\nLeft:\n{:#?}\nRight:\n{:#?}",
-                serialized, unpack_serde_result, unpack_result
-            );
-        }
+pub fn new_trigger_order(
+    market: &Pubkey,
+    open_orders_account: &Pubkey,
+    request_queue: &Pubkey,
+    event_queue: &Pubkey,
+    trigger_book: &Pubkey,
+    order_payer: &Pubkey,
+    open_orders_account_owner: &Pubkey,
+    coin_vault: &Pubkey,
+    pc_vault: &Pubkey,
+    spl_token_program_id: &Pubkey,
+    rent_sysvar_id: &Pubkey,
+    srm_account_referral: Option<&Pubkey>,
+    program_id: &Pubkey,
+    side: Side,
+    trigger_price: NonZeroU64,
+    limit_price: NonZeroU64,
+    max_coin_qty: NonZeroU64,
+    order_type: OrderType,
+    client_order_id: u64,
+    self_trade_behavior: SelfTradeBehavior,
+    max_native_pc_qty_including_fees: NonZeroU64,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::NewTriggerOrder(NewTriggerOrderInstruction {
+        side,
+        trigger_price,
+        limit_price,
+        max_coin_qty,
+        max_native_pc_qty_including_fees,
+        self_trade_behavior,
+        order_type,
+        client_order_id,
+    })
+    .pack();
+    let mut accounts = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*open_orders_account, false),
+        AccountMeta::new(*request_queue, false),
+        AccountMeta::new_readonly(*event_queue, false),
+        AccountMeta::new(*trigger_book, false),
+        AccountMeta::new(*order_payer, false),
+        AccountMeta::new_readonly(*open_orders_account_owner, true),
+        AccountMeta::new(*coin_vault, false),
+        AccountMeta::new(*pc_vault, false),
+        AccountMeta::new_readonly(*spl_token_program_id, false),
+        AccountMeta::new_readonly(*rent_sysvar_id, false),
+    ];
+    if let Some(key) = srm_account_referral {
+        accounts.push(AccountMeta::new_readonly(*key, false))
     }
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
 }
 
-#[cfg(feature = "fuzz")]
-mod fuzzing {
-    use super::*;
-    use crate::matching::{OrderType, Side};
-    use arbitrary::Unstructured;
-    use std::convert::{TryFrom, TryInto};
+pub fn cancel_trigger_order(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    trigger_book: &Pubkey,
+    open_orders_account: &Pubkey,
+    open_orders_account_owner: &Pubkey,
+    order_id: u128,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::CancelTriggerOrder(order_id).pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*trigger_book, false),
+        AccountMeta::new(*open_orders_account, false),
+        AccountMeta::new_readonly(*open_orders_account_owner, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
 
-    #[derive(arbitrary::Arbitrary)]
-    struct NewOrderInstructionU64 {
-        pub side: Side,
-        pub limit_price: u64,
-        pub max_qty: u64,
-        pub order_type: OrderType,
-        pub client_id: u64,
-        pub self_trade_behavior: SelfTradeBehavior,
-    }
+pub fn prune_expired_orders(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bids: &Pubkey,
+    asks: &Pubkey,
+    event_q: &Pubkey,
+    limit: u16,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::PruneExpiredOrders(limit).pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*bids, false),
+        AccountMeta::new(*asks, false),
+        AccountMeta::new(*event_q, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
 
-    #[derive(arbitrary::Arbitrary)]
-    struct NewOrderInstructionV3U64 {
-        pub side: Side,
+pub fn update_fee_schedule(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    admin: &Pubkey,
+    fee_schedule: UpdateFeeScheduleInstruction,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::UpdateFeeSchedule(fee_schedule).pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
 
-        pub limit_price: u64,
-        pub max_coin_qty: u64,
-        pub max_native_pc_qty_including_fees: u64,
-        pub self_trade_behavior: SelfTradeBehavior,
-        pub order_type: OrderType,
-        pub client_order_id: u64,
-        pub limit: u16,
-    }
+pub fn enable_fill_details(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    event_queue: &Pubkey,
+    admin: &Pubkey,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::EnableFillDetails.pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new_readonly(*market, false),
+        AccountMeta::new(*event_queue, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
 
-    #[derive(arbitrary::Arbitrary)]
-    struct SendTakeInstructionU64 {
-        pub side: Side,
-        pub limit_price: u64,
-        pub max_coin_qty: u64,
-        pub max_native_pc_qty_including_fees: u64,
-        pub min_coin_qty: u64,
+pub fn disable_fill_details(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    event_queue: &Pubkey,
+    admin: &Pubkey,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::DisableFillDetails.pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new_readonly(*market, false),
+        AccountMeta::new(*event_queue, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn init_trigger_book(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    trigger_book: &Pubkey,
+    admin: &Pubkey,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::InitTriggerBook.pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*trigger_book, false),
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn close_market(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    request_queue: &Pubkey,
+    event_queue: &Pubkey,
+    market_bids: &Pubkey,
+    market_asks: &Pubkey,
+    authority: &Pubkey,
+    destination: &Pubkey,
+    trigger_book: Option<&Pubkey>,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::CloseMarket.pack();
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*request_queue, false),
+        AccountMeta::new(*event_queue, false),
+        AccountMeta::new(*market_bids, false),
+        AccountMeta::new(*market_asks, false),
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*destination, false),
+    ];
+    if let Some(trigger_book) = trigger_book {
+        accounts.push(AccountMeta::new(*trigger_book, false));
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn enter_cancel_only(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    authority: &Pubkey,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::EnterCancelOnly.pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new_readonly(*authority, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn exit_cancel_only(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    authority: &Pubkey,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::ExitCancelOnly.pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new_readonly(*authority, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn propose_authority(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    admin: &Pubkey,
+    kind: AuthorityKind,
+    new_authority: &Pubkey,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::ProposeAuthority(ProposeAuthorityInstruction {
+        kind,
+        new_authority: new_authority.to_bytes(),
+    })
+    .pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn accept_authority(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    new_authority: &Pubkey,
+    kind: AuthorityKind,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::AcceptAuthority(kind).pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new_readonly(*new_authority, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn reset_authority(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    admin: &Pubkey,
+    kind: AuthorityKind,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::ResetAuthority(kind).pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn set_price_band(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    admin: &Pubkey,
+    max_deviation_bps: u16,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::SetPriceBand(max_deviation_bps).pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
+pub fn new_iceberg_order(
+    market: &Pubkey,
+    open_orders_account: &Pubkey,
+    request_queue: &Pubkey,
+    event_queue: &Pubkey,
+    market_bids: &Pubkey,
+    market_asks: &Pubkey,
+    order_payer: &Pubkey,
+    open_orders_account_owner: &Pubkey,
+    coin_vault: &Pubkey,
+    pc_vault: &Pubkey,
+    spl_token_program_id: &Pubkey,
+    rent_sysvar_id: &Pubkey,
+    srm_account_referral: Option<&Pubkey>,
+    program_id: &Pubkey,
+    side: Side,
+    limit_price: NonZeroU64,
+    max_coin_qty: NonZeroU64,
+    display_qty: NonZeroU64,
+    order_type: OrderType,
+    client_order_id: u64,
+    self_trade_behavior: SelfTradeBehavior,
+    limit: u16,
+    max_native_pc_qty_including_fees: NonZeroU64,
+    max_ts: i64,
+) -> Result<Instruction, DexError> {
+    let mut instruction = new_order(
+        market,
+        open_orders_account,
+        request_queue,
+        event_queue,
+        market_bids,
+        market_asks,
+        order_payer,
+        open_orders_account_owner,
+        coin_vault,
+        pc_vault,
+        spl_token_program_id,
+        rent_sysvar_id,
+        srm_account_referral,
+        program_id,
+        side,
+        limit_price,
+        max_coin_qty,
+        order_type,
+        client_order_id,
+        self_trade_behavior,
+        limit,
+        max_native_pc_qty_including_fees,
+        max_ts,
+    )?;
+    instruction.data = MarketInstruction::NewIcebergOrder(NewIcebergOrderInstruction {
+        order: NewOrderInstructionV3 {
+            side,
+            limit_price,
+            max_coin_qty,
+            order_type,
+            client_order_id,
+            self_trade_behavior,
+            limit,
+            max_native_pc_qty_including_fees,
+            max_ts,
+        },
+        display_qty,
+    })
+    .pack();
+    Ok(instruction)
+}
+
+pub fn new_order_with_expiry(
+    market: &Pubkey,
+    open_orders_account: &Pubkey,
+    request_queue: &Pubkey,
+    event_queue: &Pubkey,
+    market_bids: &Pubkey,
+    market_asks: &Pubkey,
+    order_payer: &Pubkey,
+    open_orders_account_owner: &Pubkey,
+    coin_vault: &Pubkey,
+    pc_vault: &Pubkey,
+    spl_token_program_id: &Pubkey,
+    rent_sysvar_id: &Pubkey,
+    srm_account_referral: Option<&Pubkey>,
+    program_id: &Pubkey,
+    side: Side,
+    limit_price: NonZeroU64,
+    max_coin_qty: NonZeroU64,
+    order_type: OrderType,
+    client_order_id: u64,
+    self_trade_behavior: SelfTradeBehavior,
+    limit: u16,
+    max_native_pc_qty_including_fees: NonZeroU64,
+    max_ts: i64,
+    expiry_ts: i64,
+) -> Result<Instruction, DexError> {
+    let mut instruction = new_order(
+        market,
+        open_orders_account,
+        request_queue,
+        event_queue,
+        market_bids,
+        market_asks,
+        order_payer,
+        open_orders_account_owner,
+        coin_vault,
+        pc_vault,
+        spl_token_program_id,
+        rent_sysvar_id,
+        srm_account_referral,
+        program_id,
+        side,
+        limit_price,
+        max_coin_qty,
+        order_type,
+        client_order_id,
+        self_trade_behavior,
+        limit,
+        max_native_pc_qty_including_fees,
+        max_ts,
+    )?;
+    instruction.data = MarketInstruction::NewOrderWithExpiry(NewOrderWithExpiryInstruction {
+        order: NewOrderInstructionV3 {
+            side,
+            limit_price,
+            max_coin_qty,
+            order_type,
+            client_order_id,
+            self_trade_behavior,
+            limit,
+            max_native_pc_qty_including_fees,
+            max_ts,
+        },
+        expiry_ts,
+    })
+    .pack();
+    Ok(instruction)
+}
+
+pub fn amend_order(
+    market: &Pubkey,
+    open_orders_account: &Pubkey,
+    request_queue: &Pubkey,
+    event_queue: &Pubkey,
+    market_bids: &Pubkey,
+    market_asks: &Pubkey,
+    order_payer: &Pubkey,
+    open_orders_account_owner: &Pubkey,
+    coin_vault: &Pubkey,
+    pc_vault: &Pubkey,
+    spl_token_program_id: &Pubkey,
+    rent_sysvar_id: &Pubkey,
+    srm_account_referral: Option<&Pubkey>,
+    program_id: &Pubkey,
+    side: Side,
+    order_id: u128,
+    limit_price: NonZeroU64,
+    max_coin_qty: NonZeroU64,
+    order_type: OrderType,
+    self_trade_behavior: SelfTradeBehavior,
+    limit: u16,
+    max_native_pc_qty_including_fees: NonZeroU64,
+) -> Result<Instruction, DexError> {
+    let mut instruction = new_order(
+        market,
+        open_orders_account,
+        request_queue,
+        event_queue,
+        market_bids,
+        market_asks,
+        order_payer,
+        open_orders_account_owner,
+        coin_vault,
+        pc_vault,
+        spl_token_program_id,
+        rent_sysvar_id,
+        srm_account_referral,
+        program_id,
+        side,
+        limit_price,
+        max_coin_qty,
+        order_type,
+        0,
+        self_trade_behavior,
+        limit,
+        max_native_pc_qty_including_fees,
+        i64::MAX,
+    )?;
+    instruction.data = MarketInstruction::AmendOrder(AmendOrderInstruction {
+        side,
+        order_id,
+        limit_price,
+        max_coin_qty,
+        max_native_pc_qty_including_fees,
+        self_trade_behavior,
+        order_type,
+        limit,
+    })
+    .pack();
+    Ok(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_pack_unpack_roundtrip(inst: MarketInstruction) {
+            let serialized = inst.pack();
+            let unpack_serde_result = MarketInstruction::unpack_serde(&serialized).ok();
+            let unpack_result = MarketInstruction::unpack(&serialized);
+            assert_eq!(unpack_result, Some(inst));
+            assert!(unpack_serde_result == unpack_result,
+                "Serialized:\n{:?}\nLeft:\n{:#?}\nRight:\n{:#?}",
+                serialized, unpack_serde_result, unpack_result
+            );
+        }
+    }
+}
+
+#[cfg(feature = "fuzz")]
+mod fuzzing {
+    use super::*;
+    use crate::matching::{OrderType, Side};
+    use arbitrary::Unstructured;
+    use std::convert::{TryFrom, TryInto};
+
+    #[derive(arbitrary::Arbitrary)]
+    struct NewOrderInstructionU64 {
+        pub side: Side,
+        pub limit_price: u64,
+        pub max_qty: u64,
+        pub order_type: OrderType,
+        pub client_id: u64,
+        pub self_trade_behavior: SelfTradeBehavior,
+    }
+
+    #[derive(arbitrary::Arbitrary)]
+    struct NewOrderInstructionV3U64 {
+        pub side: Side,
+
+        pub limit_price: u64,
+        pub max_coin_qty: u64,
+        pub max_native_pc_qty_including_fees: u64,
+        pub self_trade_behavior: SelfTradeBehavior,
+        pub order_type: OrderType,
+        pub client_order_id: u64,
+        pub limit: u16,
+    }
+
+    #[derive(arbitrary::Arbitrary)]
+    struct NewIcebergOrderInstructionU64 {
+        pub order: NewOrderInstructionV3,
+        pub display_qty: u64,
+    }
+
+    #[derive(arbitrary::Arbitrary)]
+    struct AmendOrderInstructionU64 {
+        pub side: Side,
+        pub order_id: u128,
+        pub limit_price: u64,
+        pub max_coin_qty: u64,
+        pub max_native_pc_qty_including_fees: u64,
+        pub self_trade_behavior: SelfTradeBehavior,
+        pub order_type: OrderType,
+        pub limit: u16,
+    }
+
+    #[derive(arbitrary::Arbitrary)]
+    struct NewTriggerOrderInstructionU64 {
+        pub side: Side,
+        pub trigger_price: u64,
+        pub limit_price: u64,
+        pub max_coin_qty: u64,
+        pub max_native_pc_qty_including_fees: u64,
+        pub self_trade_behavior: SelfTradeBehavior,
+        pub order_type: OrderType,
+        pub client_order_id: u64,
+    }
+
+    #[derive(arbitrary::Arbitrary)]
+    struct SendTakeInstructionU64 {
+        pub side: Side,
+        pub limit_price: u64,
+        pub max_coin_qty: u64,
+        pub max_native_pc_qty_including_fees: u64,
+        pub min_coin_qty: u64,
         pub min_native_pc_qty: u64,
         pub limit: u16,
     }
@@ -1265,6 +2885,55 @@ mod fuzzing {
         }
     }
 
+    impl TryFrom<NewIcebergOrderInstructionU64> for NewIcebergOrderInstruction {
+        type Error = std::num::TryFromIntError;
+
+        fn try_from(value: NewIcebergOrderInstructionU64) -> Result<Self, Self::Error> {
+            Ok(Self {
+                order: value.order,
+                display_qty: value.display_qty.try_into()?,
+            })
+        }
+    }
+
+    impl TryFrom<AmendOrderInstructionU64> for AmendOrderInstruction {
+        type Error = std::num::TryFromIntError;
+
+        fn try_from(value: AmendOrderInstructionU64) -> Result<Self, Self::Error> {
+            Ok(Self {
+                side: value.side,
+                order_id: value.order_id,
+                limit_price: value.limit_price.try_into()?,
+                max_coin_qty: value.max_coin_qty.try_into()?,
+                max_native_pc_qty_including_fees: value
+                    .max_native_pc_qty_including_fees
+                    .try_into()?,
+                self_trade_behavior: value.self_trade_behavior,
+                order_type: value.order_type,
+                limit: value.limit,
+            })
+        }
+    }
+
+    impl TryFrom<NewTriggerOrderInstructionU64> for NewTriggerOrderInstruction {
+        type Error = std::num::TryFromIntError;
+
+        fn try_from(value: NewTriggerOrderInstructionU64) -> Result<Self, Self::Error> {
+            Ok(Self {
+                side: value.side,
+                trigger_price: value.trigger_price.try_into()?,
+                limit_price: value.limit_price.try_into()?,
+                max_coin_qty: value.max_coin_qty.try_into()?,
+                max_native_pc_qty_including_fees: value
+                    .max_native_pc_qty_including_fees
+                    .try_into()?,
+                self_trade_behavior: value.self_trade_behavior,
+                order_type: value.order_type,
+                client_order_id: value.client_order_id,
+            })
+        }
+    }
+
     impl TryFrom<NewOrderInstructionU64> for NewOrderInstructionV2 {
         type Error = std::num::TryFromIntError;
 
@@ -1323,6 +2992,45 @@ mod fuzzing {
         }
     }
 
+    impl From<&NewIcebergOrderInstruction> for NewIcebergOrderInstructionU64 {
+        fn from(value: &NewIcebergOrderInstruction) -> Self {
+            Self {
+                order: value.order.clone(),
+                display_qty: value.display_qty.get(),
+            }
+        }
+    }
+
+    impl From<&AmendOrderInstruction> for AmendOrderInstructionU64 {
+        fn from(value: &AmendOrderInstruction) -> Self {
+            Self {
+                side: value.side,
+                order_id: value.order_id,
+                limit_price: value.limit_price.get(),
+                max_coin_qty: value.max_coin_qty.get(),
+                max_native_pc_qty_including_fees: value.max_native_pc_qty_including_fees.get(),
+                self_trade_behavior: value.self_trade_behavior,
+                order_type: value.order_type,
+                limit: value.limit,
+            }
+        }
+    }
+
+    impl From<&NewTriggerOrderInstruction> for NewTriggerOrderInstructionU64 {
+        fn from(value: &NewTriggerOrderInstruction) -> Self {
+            Self {
+                side: value.side,
+                trigger_price: value.trigger_price.get(),
+                limit_price: value.limit_price.get(),
+                max_coin_qty: value.max_coin_qty.get(),
+                max_native_pc_qty_including_fees: value.max_native_pc_qty_including_fees.get(),
+                self_trade_behavior: value.self_trade_behavior,
+                order_type: value.order_type,
+                client_order_id: value.client_order_id,
+            }
+        }
+    }
+
     impl From<&NewOrderInstructionV2> for NewOrderInstructionU64 {
         fn from(value: &NewOrderInstructionV2) -> Self {
             Self {
@@ -1372,6 +3080,9 @@ mod fuzzing {
 
     arbitrary_impl!(SendTakeInstruction, SendTakeInstructionU64);
     arbitrary_impl!(NewOrderInstructionV3, NewOrderInstructionV3U64);
+    arbitrary_impl!(NewTriggerOrderInstruction, NewTriggerOrderInstructionU64);
+    arbitrary_impl!(NewIcebergOrderInstruction, NewIcebergOrderInstructionU64);
+    arbitrary_impl!(AmendOrderInstruction, AmendOrderInstructionU64);
     arbitrary_impl!(NewOrderInstructionV2, NewOrderInstructionU64);
     arbitrary_impl!(NewOrderInstructionV1, NewOrderInstructionU64);
 }
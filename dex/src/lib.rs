@@ -7,10 +7,20 @@ pub mod error;
 #[cfg(test)]
 mod tests;
 
+#[cfg(any(test, feature = "client"))]
+pub mod client;
 pub mod critbit;
-mod fees;
+pub mod fees;
+#[cfg(any(test, feature = "client"))]
+pub mod idl;
 pub mod instruction;
 pub mod matching;
+#[cfg(any(test, feature = "simulator"))]
+pub mod simulator;
+// The simulator swaps the token program CPI, the clock and the rent sysvar
+// for host-side stand-ins in `state`, so it must never reach the chain.
+#[cfg(all(feature = "simulator", target_arch = "bpf"))]
+compile_error!("the simulator feature can't be built for the on-chain program");
 pub mod state;
 
 #[cfg(all(feature = "program", not(feature = "no-entrypoint")))]
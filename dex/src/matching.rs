@@ -11,9 +11,12 @@ use solana_program::{msg, pubkey::Pubkey, system_program};
 use crate::critbit::SlabTreeError;
 use crate::error::{DexErrorCode, DexResult, SourceFileId};
 use crate::{
-    critbit::{LeafNode, NodeHandle, Slab, SlabView},
-    fees::{self, FeeTier},
-    state::{Event, EventQueue, EventView, MarketState, OpenOrders, RequestView},
+    critbit::{LeafNode, NodeHandle, ReserveNode, Slab, SlabView},
+    fees::{self, FeeSchedule, FeeTier},
+    state::{
+        Event, EventQueue, EventView, MarketState, MarketStats, OpenOrdersAccount, OutReason,
+        RequestQueue, RequestView,
+    },
 };
 
 use bytemuck::cast;
@@ -56,17 +59,38 @@ pub enum OrderType {
     Limit = 0,
     ImmediateOrCancel = 1,
     PostOnly = 2,
+    /// Fails the instruction unless the whole order fills. Self-trade
+    /// prevention that cancels any of the order counts as not filling it.
+    FillOrKill = 3,
+    /// Like PostOnly, but a crossing price is moved one tick behind the
+    /// opposite side's best price instead of the order being dropped.
+    PostOnlySlide = 4,
 }
 
 fn extract_price_from_order_id(order_id: u128) -> u64 {
     (order_id >> 64) as u64
 }
 
+/// The range of prices, in quote lots per base lot, that a taker may fill at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PriceBand {
+    pub min_price: u64,
+    pub max_price: u64,
+}
+
 pub struct OrderBookState<'a> {
     // first byte of a key is 0xaa or 0xbb, disambiguating bids and asks
     pub bids: &'a mut Slab,
     pub asks: &'a mut Slab,
     pub market_state: &'a mut MarketState,
+    // None on V1 markets, which have no room for trade statistics.
+    pub stats: Option<&'a mut MarketStats>,
+    pub price_band: Option<PriceBand>,
+    // Orders left with less than this after matching don't rest.
+    pub min_base_qty: u64,
+    pub fee_schedule: FeeSchedule,
+    // Other OpenOrders accounts of the wallet placing the order.
+    pub sibling_open_orders: &'a [[u64; 4]],
 }
 
 impl<'ob> OrderBookState<'ob> {
@@ -77,6 +101,26 @@ impl<'ob> OrderBookState<'ob> {
         }
     }
 
+    // A refilled iceberg slice rests under a new key, but its owner's
+    // OpenOrders only knows the id the order was placed with.
+    fn owner_order_id(&self, side: Side, key: u128) -> u128 {
+        let orders: &Slab = match side {
+            Side::Bid => self.bids,
+            Side::Ask => self.asks,
+        };
+        orders.find_reserve(key).map_or(key, ReserveNode::order_id)
+    }
+
+    // Unix timestamp after which the order at `key` can no longer be matched,
+    // or 0 if it never expires.
+    pub(crate) fn expiry_ts(&self, side: Side, key: u128) -> u32 {
+        let orders: &Slab = match side {
+            Side::Bid => self.bids,
+            Side::Ask => self.asks,
+        };
+        orders.find_reserve(key).map_or(0, ReserveNode::expiry_ts)
+    }
+
     fn find_bbo(&self, side: Side) -> Option<NodeHandle> {
         match side {
             Side::Bid => self.bids.find_max(),
@@ -84,9 +128,53 @@ impl<'ob> OrderBookState<'ob> {
         }
     }
 
+    // Whether an order at `limit_price` would trade against the opposite side.
+    pub(crate) fn would_cross(&self, side: Side, limit_price: NonZeroU64) -> bool {
+        let (opposite, book): (Side, &Slab) = match side {
+            Side::Bid => (Side::Ask, self.asks),
+            Side::Ask => (Side::Bid, self.bids),
+        };
+        let best_price = match self.find_bbo(opposite) {
+            None => return false,
+            Some(h) => book.get(h).unwrap().as_leaf().unwrap().price(),
+        };
+        match side {
+            Side::Bid => limit_price >= best_price,
+            Side::Ask => limit_price <= best_price,
+        }
+    }
+
+    // Price at which a PostOnlySlide order can rest without crossing: one tick
+    // behind the opposite side's best price when `limit_price` would cross it.
+    // If there is no such price the order keeps `limit_price` and is dropped
+    // like any other crossing PostOnly order.
+    pub(crate) fn slide_post_only_price(
+        &self,
+        side: Side,
+        limit_price: NonZeroU64,
+        tick_size: u64,
+    ) -> NonZeroU64 {
+        let (opposite, book): (Side, &Slab) = match side {
+            Side::Bid => (Side::Ask, self.asks),
+            Side::Ask => (Side::Bid, self.bids),
+        };
+        let best_price = match self.find_bbo(opposite) {
+            None => return limit_price,
+            Some(h) => book.get(h).unwrap().as_leaf().unwrap().price(),
+        };
+        let slid_price = match side {
+            Side::Bid if limit_price >= best_price => best_price.get().checked_sub(tick_size),
+            Side::Ask if limit_price <= best_price => best_price.get().checked_add(tick_size),
+            _ => return limit_price,
+        };
+        slid_price.and_then(NonZeroU64::new).unwrap_or(limit_price)
+    }
+
     pub(crate) fn process_orderbook_request(
         &mut self,
         request: &RequestView,
+        now: i64,
+        req_q: &mut RequestQueue,
         event_q: &mut EventQueue,
         proceeds: &mut RequestProceeds,
         limit: &mut u16,
@@ -103,6 +191,7 @@ impl<'ob> OrderBookState<'ob> {
                 native_pc_qty_locked,
                 client_order_id,
                 self_trade_behavior,
+                expiry_ts,
             } => self
                 .new_order(
                     NewOrderParams {
@@ -116,7 +205,11 @@ impl<'ob> OrderBookState<'ob> {
                         native_pc_qty_locked,
                         client_order_id: client_order_id.map_or(0, NonZeroU64::get),
                         self_trade_behavior,
+                        deferred_settlement: false,
+                        expiry_ts,
+                        now,
                     },
+                    req_q,
                     event_q,
                     proceeds,
                     limit,
@@ -132,6 +225,7 @@ impl<'ob> OrderBookState<'ob> {
                     native_pc_qty_locked: remaining.native_pc_qty_remaining,
                     client_order_id,
                     self_trade_behavior,
+                    expiry_ts,
                 }),
             RequestView::CancelOrder {
                 side,
@@ -155,18 +249,79 @@ impl<'ob> OrderBookState<'ob> {
         })
     }
 
+    // Matches a stop order released from the trigger book. Its OpenOrders
+    // account isn't part of the transaction, so every balance change is
+    // carried by events and settled when the queue is cranked.
+    pub(crate) fn process_triggered_order(
+        &mut self,
+        request: &RequestView,
+        now: i64,
+        req_q: &mut RequestQueue,
+        event_q: &mut EventQueue,
+        limit: &mut u16,
+    ) -> DexResult {
+        let mut proceeds = RequestProceeds::zero();
+        match *request {
+            RequestView::NewOrder {
+                side,
+                order_type,
+                order_id,
+                owner_slot,
+                fee_tier,
+                owner,
+                max_coin_qty,
+                native_pc_qty_locked,
+                client_order_id,
+                self_trade_behavior,
+                expiry_ts,
+            } => {
+                let remaining = self.new_order(
+                    NewOrderParams {
+                        side,
+                        order_type,
+                        order_id,
+                        owner,
+                        owner_slot,
+                        fee_tier,
+                        max_coin_qty,
+                        native_pc_qty_locked,
+                        client_order_id: client_order_id.map_or(0, NonZeroU64::get),
+                        self_trade_behavior,
+                        deferred_settlement: true,
+                        expiry_ts,
+                        now,
+                    },
+                    req_q,
+                    event_q,
+                    &mut proceeds,
+                    limit,
+                )?;
+                check_assert!(remaining.is_none())?;
+                Ok(())
+            }
+            RequestView::CancelOrder { .. } => check_unreachable!()?,
+        }
+    }
+
     // Removes all orders belonging to the given open orders account.
     pub fn remove_all(
         &mut self,
         open_orders: [u64; 4],
+        side: Option<Side>,
         mut limit: u16,
     ) -> DexResult<(Vec<LeafNode>, Vec<LeafNode>)> {
-        let asks_matching_open_orders = self
-            .asks
-            .find_by(&mut limit, |order| order.owner().eq(&open_orders));
-        let bids_matching_open_orders = self
-            .bids
-            .find_by(&mut limit, |order| order.owner().eq(&open_orders));
+        let asks_matching_open_orders = match side {
+            Some(Side::Bid) => Vec::new(),
+            _ => self
+                .asks
+                .find_by(&mut limit, |order| order.owner().eq(&open_orders)),
+        };
+        let bids_matching_open_orders = match side {
+            Some(Side::Ask) => Vec::new(),
+            _ => self
+                .bids
+                .find_by(&mut limit, |order| order.owner().eq(&open_orders)),
+        };
         let bids_removed: Vec<LeafNode> = bids_matching_open_orders
             .iter()
             .filter_map(|order_to_remove| self.bids.remove_by_key(*order_to_remove))
@@ -177,6 +332,208 @@ impl<'ob> OrderBookState<'ob> {
             .collect();
         Ok((bids_removed, asks_removed))
     }
+
+    // Removes orders whose expiry has passed, searching at most `limit`
+    // reserves, which is where expiries are kept.
+    pub(crate) fn prune_expired(
+        &mut self,
+        now: i64,
+        event_q: &mut EventQueue,
+        mut limit: u16,
+    ) -> DexResult<usize> {
+        let expired_asks = self
+            .asks
+            .find_reserves_by(&mut limit, |reserve| reserve.is_expired(now));
+        let expired_bids = self
+            .bids
+            .find_reserves_by(&mut limit, |reserve| reserve.is_expired(now));
+        for &order_id in expired_bids.iter() {
+            self.expire_order(Side::Bid, order_id, event_q)?;
+        }
+        for &order_id in expired_asks.iter() {
+            self.expire_order(Side::Ask, order_id, event_q)?;
+        }
+        Ok(expired_bids.len() + expired_asks.len())
+    }
+
+    // The owner's OpenOrders account isn't loaded, so the order's funds are
+    // released when the Out event is consumed.
+    fn expire_order(&mut self, side: Side, key: u128, event_q: &mut EventQueue) -> DexResult {
+        let order_id = self.owner_order_id(side, key);
+        let (leaf_node, quantity) = self.remove_order(side, key).unwrap();
+        let native_qty_unlocked = match side {
+            Side::Bid => quantity * leaf_node.price().get() * self.market_state.pc_lot_size,
+            Side::Ask => quantity * self.market_state.coin_lot_size,
+        };
+        event_q
+            .push_back(Event::new(EventView::Out {
+                side,
+                release_funds: true,
+                native_qty_unlocked,
+                native_qty_still_locked: 0,
+                order_id,
+                owner: leaf_node.owner(),
+                owner_slot: leaf_node.owner_slot(),
+                client_order_id: NonZeroU64::new(leaf_node.client_order_id()),
+                reason: OutReason::Expired,
+            }))
+            .map_err(|_| DexErrorCode::EventQueueFull)?;
+        Ok(())
+    }
+
+    // Removes a resting order along with its hidden reserve, returning the
+    // leaf and the order's total remaining quantity.
+    fn remove_order(&mut self, side: Side, key: u128) -> Option<(LeafNode, u64)> {
+        let orders = self.orders_mut(side);
+        let leaf_node = orders.remove_by_key(key)?;
+        let hidden_qty = orders
+            .remove_reserve(key)
+            .map_or(0, |reserve| reserve.quantity());
+        Some((leaf_node, leaf_node.quantity() + hidden_qty))
+    }
+
+    // Called once the visible part of the order at `key` has been taken. If it
+    // is an iceberg with quantity left in reserve, shows the next slice behind
+    // everything already resting at its price and returns the quantity the
+    // order still has on the book. Returns 0 if there was nothing to show.
+    fn replenish(&mut self, side: Side, key: u128, req_q: &mut RequestQueue) -> DexResult<u64> {
+        let orders = self.orders_mut(side);
+        let reserve = match orders.find_reserve(key) {
+            Some(reserve) if reserve.quantity() > 0 => *reserve,
+            _ => return Ok(0),
+        };
+        orders.remove_reserve(key);
+        let leaf_node = orders.remove_by_key(key).unwrap();
+
+        let new_key = req_q.gen_order_id(leaf_node.price().get(), side);
+        let shown_qty = reserve.display_quantity().min(reserve.quantity());
+        let new_leaf = LeafNode::new(
+            leaf_node.owner_slot(),
+            new_key,
+            leaf_node.owner(),
+            shown_qty,
+            leaf_node.fee_tier(),
+            leaf_node.client_order_id(),
+        );
+        // Removing the old leaf and reserve freed the nodes these need.
+        orders.insert_leaf(&new_leaf).or(check_unreachable!())?;
+        orders
+            .insert_reserve(&ReserveNode::new(
+                new_key,
+                reserve.order_id(),
+                reserve.display_quantity(),
+                reserve.quantity() - shown_qty,
+                reserve.expiry_ts(),
+            ))
+            .or(check_unreachable!())?;
+        Ok(reserve.quantity())
+    }
+
+    // Turns a freshly posted order into an iceberg that shows at most
+    // `display_qty` at a time.
+    pub(crate) fn hide_reserve(
+        &mut self,
+        side: Side,
+        order_id: u128,
+        display_qty: NonZeroU64,
+    ) -> DexResult {
+        let orders = self.orders_mut(side);
+        let mut leaf_node = match orders.remove_by_key(order_id) {
+            None => return Ok(()),
+            Some(leaf_node) => leaf_node,
+        };
+        let hidden_qty = leaf_node.quantity().saturating_sub(display_qty.get());
+        leaf_node.set_quantity(leaf_node.quantity() - hidden_qty);
+        orders.insert_leaf(&leaf_node).or(check_unreachable!())?;
+        if hidden_qty > 0 {
+            // The reserve takes over the order's expiry, if it has one.
+            let expiry_ts = orders
+                .remove_reserve(order_id)
+                .map_or(0, |reserve| reserve.expiry_ts());
+            orders
+                .insert_reserve(&ReserveNode::new(
+                    order_id,
+                    order_id,
+                    display_qty.get(),
+                    hidden_qty,
+                    expiry_ts,
+                ))
+                .map_err(|_| DexErrorCode::SlabTooSmall)?;
+        }
+        Ok(())
+    }
+
+    // Rests the unfilled part of an order, booting out the least aggressive
+    // order on its side if the book is full. An expiry goes in before the
+    // leaf, so making room for it can't boot out the order itself.
+    fn post_order(
+        &mut self,
+        side: Side,
+        leaf_node: &LeafNode,
+        expiry_ts: u32,
+        event_q: &mut EventQueue,
+    ) -> DexResult {
+        if expiry_ts != 0 {
+            let key = leaf_node.order_id();
+            let expiry = ReserveNode::new(key, key, 0, 0, expiry_ts);
+            match self.orders_mut(side).insert_reserve(&expiry) {
+                Ok(()) => (),
+                Err(SlabTreeError::OutOfSpace) => {
+                    self.evict_order(side, event_q)?;
+                    self.orders_mut(side)
+                        .insert_reserve(&expiry)
+                        .or(check_unreachable!())?;
+                }
+            }
+        }
+        let insert_result = self.orders_mut(side).insert_leaf(leaf_node);
+        if let Err(SlabTreeError::OutOfSpace) = insert_result {
+            self.evict_order(side, event_q)?;
+            self.orders_mut(side).insert_leaf(leaf_node).unwrap();
+        } else {
+            insert_result.unwrap();
+        }
+        Ok(())
+    }
+
+    fn evict_order(&mut self, side: Side, event_q: &mut EventQueue) -> DexResult {
+        let pc_lot_size = self.market_state.pc_lot_size;
+        let coin_lot_size = self.market_state.coin_lot_size;
+        let orders = self.orders_mut(side);
+        let order = match side {
+            Side::Bid => {
+                // boot out the least aggressive bid
+                msg!("bids full! booting...");
+                orders.remove_min().unwrap()
+            }
+            Side::Ask => {
+                // boot out the least aggressive offer
+                msg!("offers full! booting...");
+                orders.remove_max().unwrap()
+            }
+        };
+        let reserve = orders.remove_reserve(order.order_id());
+        let quantity = order.quantity() + reserve.map_or(0, |reserve| reserve.quantity());
+        let native_qty_unlocked = match side {
+            Side::Bid => quantity * order.price().get() * pc_lot_size,
+            Side::Ask => quantity * coin_lot_size,
+        };
+        let out = Event::new(EventView::Out {
+            side,
+            release_funds: true,
+            native_qty_unlocked,
+            native_qty_still_locked: 0,
+            order_id: reserve.map_or(order.order_id(), |reserve| reserve.order_id()),
+            owner: order.owner(),
+            owner_slot: order.owner_slot(),
+            client_order_id: NonZeroU64::new(order.client_order_id()),
+            reason: OutReason::Evicted,
+        });
+        event_q
+            .push_back(out)
+            .map_err(|_| DexErrorCode::EventQueueFull)?;
+        Ok(())
+    }
 }
 
 pub(crate) struct RequestProceeds {
@@ -223,12 +580,15 @@ pub(crate) struct NewOrderParams {
     order_type: OrderType,
     order_id: u128,
     owner: [u64; 4],
-    owner_slot: u8,
+    owner_slot: u16,
     fee_tier: FeeTier,
     max_coin_qty: NonZeroU64,
     native_pc_qty_locked: Option<NonZeroU64>,
     client_order_id: u64,
     self_trade_behavior: SelfTradeBehavior,
+    deferred_settlement: bool,
+    expiry_ts: u32,
+    now: i64,
 }
 
 struct OrderRemaining {
@@ -240,6 +600,7 @@ impl<'ob> OrderBookState<'ob> {
     fn new_order(
         &mut self,
         params: NewOrderParams,
+        req_q: &mut RequestQueue,
         event_q: &mut EventQueue,
         proceeds: &mut RequestProceeds,
         limit: &mut u16,
@@ -255,12 +616,19 @@ impl<'ob> OrderBookState<'ob> {
             mut native_pc_qty_locked,
             client_order_id,
             self_trade_behavior,
+            deferred_settlement,
+            expiry_ts,
+            now,
         } = params;
         let (mut post_only, post_allowed) = match order_type {
             OrderType::Limit => (false, true),
-            OrderType::ImmediateOrCancel => (false, false),
-            OrderType::PostOnly => (true, true),
+            OrderType::ImmediateOrCancel | OrderType::FillOrKill => (false, false),
+            OrderType::PostOnly | OrderType::PostOnlySlide => (true, true),
         };
+        // Running out of cycles below also sets `post_only`, but only orders
+        // placed as post-only are cancelled for crossing the book.
+        let post_only_order = post_only;
+        let fill_or_kill = order_type == OrderType::FillOrKill;
         let limit_price = extract_price_from_order_id(order_id);
         loop {
             if *limit == 0 {
@@ -282,10 +650,16 @@ impl<'ob> OrderBookState<'ob> {
                         owner_slot,
                         fee_tier,
                         post_only,
+                        post_only_order,
                         post_allowed,
+                        fill_or_kill,
                         client_order_id,
                         self_trade_behavior,
+                        deferred_settlement,
+                        expiry_ts,
+                        now,
                     },
+                    req_q,
                     event_q,
                     proceeds,
                 ),
@@ -300,10 +674,16 @@ impl<'ob> OrderBookState<'ob> {
                             owner_slot,
                             fee_tier,
                             post_only,
+                            post_only_order,
                             post_allowed,
+                            fill_or_kill,
                             client_order_id,
                             self_trade_behavior,
+                            deferred_settlement,
+                            expiry_ts,
+                            now,
                         },
+                        req_q,
                         event_q,
                         proceeds,
                     )
@@ -329,18 +709,24 @@ struct NewAskParams {
     limit_price: NonZeroU64,
     order_id: u128,
     owner: [u64; 4],
-    owner_slot: u8,
+    owner_slot: u16,
     fee_tier: FeeTier,
     post_only: bool,
+    post_only_order: bool,
     post_allowed: bool,
+    fill_or_kill: bool,
     client_order_id: u64,
     self_trade_behavior: SelfTradeBehavior,
+    deferred_settlement: bool,
+    expiry_ts: u32,
+    now: i64,
 }
 
 impl<'ob> OrderBookState<'ob> {
     fn new_ask(
         &mut self,
         params: NewAskParams,
+        req_q: &mut RequestQueue,
         event_q: &mut EventQueue,
         to_release: &mut RequestProceeds,
     ) -> DexResult<Option<OrderRemaining>> {
@@ -352,9 +738,14 @@ impl<'ob> OrderBookState<'ob> {
             owner_slot,
             fee_tier,
             post_only,
+            post_only_order,
             post_allowed,
+            fill_or_kill,
             client_order_id,
             self_trade_behavior,
+            deferred_settlement,
+            expiry_ts,
+            now,
         } = params;
 
         let is_send_take = system_program::ID.to_aligned_bytes() == owner;
@@ -363,8 +754,13 @@ impl<'ob> OrderBookState<'ob> {
 
         let pc_lot_size = self.market_state.pc_lot_size;
         let coin_lot_size = self.market_state.coin_lot_size;
+        let fee_schedule = self.fee_schedule;
+        let sibling_open_orders = self.sibling_open_orders;
+        let price_band = self.price_band;
+        let min_base_qty = self.min_base_qty;
 
         let mut accum_maker_rebates = 0;
+        let mut outside_band = false;
         let crossed;
         let done = loop {
             let best_bid_h = match self.find_bbo(Side::Bid) {
@@ -374,6 +770,16 @@ impl<'ob> OrderBookState<'ob> {
                 }
                 Some(h) => h,
             };
+            let best_bid_key = self
+                .bids
+                .get(best_bid_h)
+                .unwrap()
+                .as_leaf()
+                .unwrap()
+                .order_id();
+            let best_bid_reserve = self.bids.find_reserve(best_bid_key).copied();
+            let best_bid_order_id =
+                best_bid_reserve.map_or(best_bid_key, |reserve| reserve.order_id());
 
             let best_bid_ref = self
                 .orders_mut(Side::Bid)
@@ -385,6 +791,12 @@ impl<'ob> OrderBookState<'ob> {
             let trade_price = best_bid_ref.price();
             crossed = limit_price <= trade_price;
 
+            if crossed && matches!(best_bid_reserve, Some(reserve) if reserve.is_expired(now)) {
+                let best_bid_id = best_bid_ref.order_id();
+                self.expire_order(Side::Bid, best_bid_id, event_q)?;
+                break false;
+            }
+
             if !crossed || post_only {
                 break true;
             }
@@ -396,9 +808,15 @@ impl<'ob> OrderBookState<'ob> {
                 break true;
             }
 
-            let order_would_self_trade = owner == best_bid_ref.owner();
+            let order_would_self_trade = owner == best_bid_ref.owner()
+                || sibling_open_orders.contains(&best_bid_ref.owner());
             if order_would_self_trade {
-                let best_bid_id = best_bid_ref.order_id();
+                let best_bid = *best_bid_ref;
+                let best_bid_id = best_bid.order_id();
+                let hidden_provide_size = self
+                    .orders_mut(Side::Bid)
+                    .find_reserve(best_bid_id)
+                    .map_or(0, ReserveNode::quantity);
                 let cancelled_provide_qty;
                 let cancelled_take_qty;
 
@@ -408,48 +826,73 @@ impl<'ob> OrderBookState<'ob> {
                         cancelled_take_qty = trade_qty;
                     }
                     SelfTradeBehavior::CancelProvide => {
-                        cancelled_provide_qty = best_bid_ref.quantity();
+                        cancelled_provide_qty = bid_size + hidden_provide_size;
                         cancelled_take_qty = 0;
                     }
+                    SelfTradeBehavior::CancelTake => {
+                        cancelled_provide_qty = 0;
+                        cancelled_take_qty = unfilled_qty;
+                    }
+                    SelfTradeBehavior::CancelBoth => {
+                        cancelled_provide_qty = bid_size + hidden_provide_size;
+                        cancelled_take_qty = unfilled_qty;
+                    }
                     SelfTradeBehavior::AbortTransaction => {
                         return Err(DexErrorCode::WouldSelfTrade.into())
                     }
                 };
+                // Quantity cancelled to avoid trading with oneself is never
+                // filled.
+                if fill_or_kill && cancelled_take_qty > 0 {
+                    return Err(DexErrorCode::FillOrKillNotFilled.into());
+                }
 
-                let remaining_provide_size = bid_size - cancelled_provide_qty;
-                let provide_out = Event::new(EventView::Out {
-                    side: Side::Bid,
-                    release_funds: true,
-                    native_qty_unlocked: cancelled_provide_qty * trade_price.get() * pc_lot_size,
-                    native_qty_still_locked: remaining_provide_size
-                        * trade_price.get()
-                        * pc_lot_size,
-                    order_id: best_bid_id,
-                    owner: best_bid_ref.owner(),
-                    owner_slot: best_bid_ref.owner_slot(),
-                    client_order_id: NonZeroU64::new(best_bid_ref.client_order_id()),
-                });
-                event_q
-                    .push_back(provide_out)
-                    .map_err(|_| DexErrorCode::EventQueueFull)?;
-                if remaining_provide_size == 0 {
-                    self.orders_mut(Side::Bid)
-                        .remove_by_key(best_bid_id)
-                        .unwrap();
-                } else {
-                    best_bid_ref.set_quantity(remaining_provide_size);
+                let remaining_provide_size = bid_size + hidden_provide_size - cancelled_provide_qty;
+                // CancelTake leaves the resting order untouched.
+                if cancelled_provide_qty > 0 {
+                    let provide_out = Event::new(EventView::Out {
+                        side: Side::Bid,
+                        release_funds: true,
+                        native_qty_unlocked: cancelled_provide_qty
+                            * trade_price.get()
+                            * pc_lot_size,
+                        native_qty_still_locked: remaining_provide_size
+                            * trade_price.get()
+                            * pc_lot_size,
+                        order_id: best_bid_order_id,
+                        owner: best_bid.owner(),
+                        owner_slot: best_bid.owner_slot(),
+                        client_order_id: NonZeroU64::new(best_bid.client_order_id()),
+                        reason: OutReason::SelfTrade,
+                    });
+                    event_q
+                        .push_back(provide_out)
+                        .map_err(|_| DexErrorCode::EventQueueFull)?;
+                    if remaining_provide_size == 0 {
+                        self.remove_order(Side::Bid, best_bid_id).unwrap();
+                    } else if cancelled_provide_qty < bid_size {
+                        self.orders_mut(Side::Bid)
+                            .get_mut(best_bid_h)
+                            .unwrap()
+                            .as_leaf_mut()
+                            .unwrap()
+                            .set_quantity(bid_size - cancelled_provide_qty);
+                    } else {
+                        self.replenish(Side::Bid, best_bid_id, req_q)?;
+                    }
                 }
 
                 unfilled_qty -= cancelled_take_qty;
                 let take_out = Event::new(EventView::Out {
                     side: Side::Ask,
-                    release_funds: false,
+                    release_funds: deferred_settlement,
                     native_qty_unlocked: cancelled_take_qty * coin_lot_size,
                     native_qty_still_locked: unfilled_qty,
                     order_id,
                     owner,
                     owner_slot,
                     client_order_id: NonZeroU64::new(client_order_id),
+                    reason: OutReason::SelfTrade,
                 });
                 event_q
                     .push_back(take_out)
@@ -464,26 +907,36 @@ impl<'ob> OrderBookState<'ob> {
                 return Ok(order_remaining);
             }
 
+            if matches!(price_band, Some(band) if trade_price.get() < band.min_price) {
+                // A triggered order can't fail the transaction that released
+                // it, so it stops at the band and the rest is cancelled.
+                if !deferred_settlement {
+                    return Err(DexErrorCode::PriceBandExceeded.into());
+                }
+                outside_band = true;
+                break true;
+            }
+
             let maker_fee_tier = best_bid_ref.fee_tier();
             let native_maker_pc_qty = trade_qty * trade_price.get() * pc_lot_size;
-            let native_maker_rebate = maker_fee_tier.maker_rebate(native_maker_pc_qty);
+            let native_maker_rebate =
+                fee_schedule.maker_rebate(maker_fee_tier, native_maker_pc_qty);
             accum_maker_rebates += native_maker_rebate;
 
-            let maker_fill = Event::new(EventView::Fill {
+            let maker_fill = EventView::Fill {
                 side: Side::Bid,
                 maker: true,
+                release_funds: false,
                 native_qty_paid: native_maker_pc_qty - native_maker_rebate,
                 native_qty_received: trade_qty * coin_lot_size,
                 native_fee_or_rebate: native_maker_rebate,
-                order_id: best_bid_ref.order_id(),
+                order_id: best_bid_order_id,
                 owner: best_bid_ref.owner(),
                 owner_slot: best_bid_ref.owner_slot(),
                 fee_tier: maker_fee_tier,
                 client_order_id: NonZeroU64::new(best_bid_ref.client_order_id()),
-            });
-            event_q
-                .push_back(maker_fill)
-                .map_err(|_| DexErrorCode::EventQueueFull)?;
+            };
+            event_q.push_maker_fill(maker_fill, trade_price, order_id, now)?;
 
             best_bid_ref.set_quantity(best_bid_ref.quantity() - trade_qty);
             unfilled_qty -= trade_qty;
@@ -491,28 +944,39 @@ impl<'ob> OrderBookState<'ob> {
 
             if best_bid_ref.quantity() == 0 {
                 let best_bid_id = best_bid_ref.order_id();
-                event_q
-                    .push_back(Event::new(EventView::Out {
-                        side: Side::Bid,
-                        release_funds: true,
-                        native_qty_unlocked: 0,
-                        native_qty_still_locked: 0,
-                        order_id: best_bid_id,
-                        owner: best_bid_ref.owner(),
-                        owner_slot: best_bid_ref.owner_slot(),
-                        client_order_id: NonZeroU64::new(best_bid_ref.client_order_id()),
-                    }))
-                    .map_err(|_| DexErrorCode::EventQueueFull)?;
-                self.orders_mut(Side::Bid)
-                    .remove_by_key(best_bid_id)
-                    .unwrap();
+                let out = Event::new(EventView::Out {
+                    side: Side::Bid,
+                    release_funds: true,
+                    native_qty_unlocked: 0,
+                    native_qty_still_locked: 0,
+                    order_id: best_bid_order_id,
+                    owner: best_bid_ref.owner(),
+                    owner_slot: best_bid_ref.owner_slot(),
+                    client_order_id: NonZeroU64::new(best_bid_ref.client_order_id()),
+                    reason: OutReason::Filled,
+                });
+                if self.replenish(Side::Bid, best_bid_id, req_q)? == 0 {
+                    event_q
+                        .push_back(out)
+                        .map_err(|_| DexErrorCode::EventQueueFull)?;
+                    self.remove_order(Side::Bid, best_bid_id).unwrap();
+                }
+            }
+
+            if let Some(stats) = self.stats.as_deref_mut() {
+                stats.record_trade(
+                    trade_price.get(),
+                    trade_qty * coin_lot_size,
+                    native_maker_pc_qty,
+                    now,
+                );
             }
 
             break false;
         };
 
         let native_taker_pc_qty = accum_fill_price * pc_lot_size;
-        let native_taker_fee = fee_tier.taker_fee(native_taker_pc_qty);
+        let native_taker_fee = fee_schedule.taker_fee(fee_tier, native_taker_pc_qty);
 
         {
             let net_taker_pc_qty = native_taker_pc_qty - native_taker_fee;
@@ -525,6 +989,7 @@ impl<'ob> OrderBookState<'ob> {
                 let taker_fill = Event::new(EventView::Fill {
                     side: Side::Ask,
                     maker: false,
+                    release_funds: deferred_settlement,
                     native_qty_paid: coin_lots_traded * coin_lot_size,
                     native_qty_received: net_taker_pc_qty,
                     native_fee_or_rebate: native_taker_fee,
@@ -558,9 +1023,13 @@ impl<'ob> OrderBookState<'ob> {
             }
         }
 
-        if post_allowed && !crossed && unfilled_qty > 0 {
+        if fill_or_kill && unfilled_qty > 0 {
+            return Err(DexErrorCode::FillOrKillNotFilled.into());
+        }
+
+        let rests = post_allowed && !crossed && unfilled_qty > 0;
+        if rests && unfilled_qty >= min_base_qty {
             check_assert!(!is_send_take)?;
-            let offers = self.orders_mut(Side::Ask);
             let new_order = LeafNode::new(
                 owner_slot,
                 order_id,
@@ -569,39 +1038,30 @@ impl<'ob> OrderBookState<'ob> {
                 fee_tier,
                 client_order_id,
             );
-            let insert_result = offers.insert_leaf(&new_order);
-            if let Err(SlabTreeError::OutOfSpace) = insert_result {
-                // boot out the least aggressive offer
-                msg!("offers full! booting...");
-                let order = offers.remove_max().unwrap();
-                let out = Event::new(EventView::Out {
-                    side: Side::Ask,
-                    release_funds: true,
-                    native_qty_unlocked: order.quantity() * coin_lot_size,
-                    native_qty_still_locked: 0,
-                    order_id: order.order_id(),
-                    owner: order.owner(),
-                    owner_slot: order.owner_slot(),
-                    client_order_id: NonZeroU64::new(order.client_order_id()),
-                });
-                event_q
-                    .push_back(out)
-                    .map_err(|_| DexErrorCode::EventQueueFull)?;
-                offers.insert_leaf(&new_order).unwrap();
-            } else {
-                insert_result.unwrap();
-            }
+            self.post_order(Side::Ask, &new_order, expiry_ts, event_q)?;
         } else if !is_send_take {
             to_release.unlock_coin(unfilled_qty);
+            let reason = if unfilled_qty == 0 {
+                OutReason::Filled
+            } else if post_only_order && crossed {
+                OutReason::PostOnly
+            } else if outside_band {
+                OutReason::PriceBand
+            } else if rests {
+                OutReason::BelowMinimum
+            } else {
+                OutReason::ImmediateOrCancel
+            };
             let out = Event::new(EventView::Out {
                 side: Side::Ask,
-                release_funds: false,
+                release_funds: deferred_settlement,
                 native_qty_unlocked: unfilled_qty * coin_lot_size,
                 native_qty_still_locked: 0,
                 order_id,
                 owner,
                 owner_slot,
                 client_order_id: NonZeroU64::new(client_order_id),
+                reason,
             });
             event_q
                 .push_back(out)
@@ -618,18 +1078,24 @@ struct NewBidParams {
     limit_price: Option<NonZeroU64>,
     order_id: u128,
     owner: [u64; 4],
-    owner_slot: u8,
+    owner_slot: u16,
     fee_tier: FeeTier,
     post_only: bool,
+    post_only_order: bool,
     post_allowed: bool,
+    fill_or_kill: bool,
     client_order_id: u64,
     self_trade_behavior: SelfTradeBehavior,
+    deferred_settlement: bool,
+    expiry_ts: u32,
+    now: i64,
 }
 
 impl<'ob> OrderBookState<'ob> {
     fn new_bid(
         &mut self,
         params: NewBidParams,
+        req_q: &mut RequestQueue,
         event_q: &mut EventQueue,
         to_release: &mut RequestProceeds,
     ) -> DexResult<Option<OrderRemaining>> {
@@ -642,9 +1108,14 @@ impl<'ob> OrderBookState<'ob> {
             owner_slot,
             fee_tier,
             post_only,
+            post_only_order,
             post_allowed,
+            fill_or_kill,
             client_order_id,
             self_trade_behavior,
+            deferred_settlement,
+            expiry_ts,
+            now,
         } = params;
         if post_allowed {
             check_assert!(limit_price.is_some())?;
@@ -654,12 +1125,18 @@ impl<'ob> OrderBookState<'ob> {
 
         let pc_lot_size = self.market_state.pc_lot_size;
         let coin_lot_size = self.market_state.coin_lot_size;
+        let fee_schedule = self.fee_schedule;
+        let sibling_open_orders = self.sibling_open_orders;
+        let price_band = self.price_band;
+        let min_base_qty = self.min_base_qty;
 
-        let max_pc_qty = fee_tier.remove_taker_fee(native_pc_qty_locked.get()) / pc_lot_size;
+        let max_pc_qty =
+            fee_schedule.remove_taker_fee(fee_tier, native_pc_qty_locked.get()) / pc_lot_size;
 
         let mut coin_qty_remaining = max_coin_qty.get();
         let mut pc_qty_remaining = max_pc_qty;
         let mut accum_maker_rebates = 0;
+        let mut outside_band = false;
 
         let crossed;
         let done = loop {
@@ -670,6 +1147,16 @@ impl<'ob> OrderBookState<'ob> {
                 }
                 Some(h) => h,
             };
+            let best_offer_key = self
+                .asks
+                .get(best_offer_h)
+                .unwrap()
+                .as_leaf()
+                .unwrap()
+                .order_id();
+            let best_offer_reserve = self.asks.find_reserve(best_offer_key).copied();
+            let best_offer_order_id =
+                best_offer_reserve.map_or(best_offer_key, |reserve| reserve.order_id());
 
             let best_offer_ref = self
                 .orders_mut(Side::Ask)
@@ -682,6 +1169,12 @@ impl<'ob> OrderBookState<'ob> {
             crossed = limit_price
                 .map(|limit_price| limit_price >= trade_price)
                 .unwrap_or(true);
+
+            if crossed && matches!(best_offer_reserve, Some(reserve) if reserve.is_expired(now)) {
+                let best_offer_id = best_offer_ref.order_id();
+                self.expire_order(Side::Ask, best_offer_id, event_q)?;
+                break false;
+            }
             if !crossed || post_only {
                 break true;
             }
@@ -695,9 +1188,15 @@ impl<'ob> OrderBookState<'ob> {
                 break true;
             }
 
-            let order_would_self_trade = owner == best_offer_ref.owner();
+            let order_would_self_trade = owner == best_offer_ref.owner()
+                || sibling_open_orders.contains(&best_offer_ref.owner());
             if order_would_self_trade {
-                let best_offer_id = best_offer_ref.order_id();
+                let best_offer = *best_offer_ref;
+                let best_offer_id = best_offer.order_id();
+                let hidden_provide_qty = self
+                    .orders_mut(Side::Ask)
+                    .find_reserve(best_offer_id)
+                    .map_or(0, ReserveNode::quantity);
 
                 let cancelled_take_qty;
                 let cancelled_provide_qty;
@@ -705,42 +1204,65 @@ impl<'ob> OrderBookState<'ob> {
                 match self_trade_behavior {
                     SelfTradeBehavior::CancelProvide => {
                         cancelled_take_qty = 0;
-                        cancelled_provide_qty = best_offer_ref.quantity();
+                        cancelled_provide_qty = offer_size + hidden_provide_qty;
                     }
                     SelfTradeBehavior::DecrementTake => {
                         cancelled_take_qty = trade_qty;
                         cancelled_provide_qty = trade_qty;
                     }
+                    SelfTradeBehavior::CancelTake => {
+                        cancelled_take_qty = coin_qty_remaining;
+                        cancelled_provide_qty = 0;
+                    }
+                    SelfTradeBehavior::CancelBoth => {
+                        cancelled_take_qty = coin_qty_remaining;
+                        cancelled_provide_qty = offer_size + hidden_provide_qty;
+                    }
                     SelfTradeBehavior::AbortTransaction => {
                         return Err(DexErrorCode::WouldSelfTrade.into())
                     }
                 };
+                // Quantity cancelled to avoid trading with oneself is never
+                // filled.
+                if fill_or_kill && cancelled_take_qty > 0 {
+                    return Err(DexErrorCode::FillOrKillNotFilled.into());
+                }
 
-                let remaining_provide_qty = best_offer_ref.quantity() - cancelled_provide_qty;
-                let provide_out = Event::new(EventView::Out {
-                    side: Side::Ask,
-                    release_funds: true,
-                    native_qty_unlocked: cancelled_provide_qty * coin_lot_size,
-                    native_qty_still_locked: remaining_provide_qty * coin_lot_size,
-                    order_id: best_offer_id,
-                    owner: best_offer_ref.owner(),
-                    owner_slot: best_offer_ref.owner_slot(),
-                    client_order_id: NonZeroU64::new(best_offer_ref.client_order_id()),
-                });
-                event_q
-                    .push_back(provide_out)
-                    .map_err(|_| DexErrorCode::EventQueueFull)?;
-                if remaining_provide_qty == 0 {
-                    self.orders_mut(Side::Ask)
-                        .remove_by_key(best_offer_id)
-                        .unwrap();
-                } else {
-                    best_offer_ref.set_quantity(remaining_provide_qty);
+                let remaining_provide_qty = offer_size + hidden_provide_qty - cancelled_provide_qty;
+                // CancelTake leaves the resting order untouched.
+                if cancelled_provide_qty > 0 {
+                    let provide_out = Event::new(EventView::Out {
+                        side: Side::Ask,
+                        release_funds: true,
+                        native_qty_unlocked: cancelled_provide_qty * coin_lot_size,
+                        native_qty_still_locked: remaining_provide_qty * coin_lot_size,
+                        order_id: best_offer_order_id,
+                        owner: best_offer.owner(),
+                        owner_slot: best_offer.owner_slot(),
+                        client_order_id: NonZeroU64::new(best_offer.client_order_id()),
+                        reason: OutReason::SelfTrade,
+                    });
+                    event_q
+                        .push_back(provide_out)
+                        .map_err(|_| DexErrorCode::EventQueueFull)?;
+                    if remaining_provide_qty == 0 {
+                        self.remove_order(Side::Ask, best_offer_id).unwrap();
+                    } else if cancelled_provide_qty < offer_size {
+                        self.orders_mut(Side::Ask)
+                            .get_mut(best_offer_h)
+                            .unwrap()
+                            .as_leaf_mut()
+                            .unwrap()
+                            .set_quantity(offer_size - cancelled_provide_qty);
+                    } else {
+                        self.replenish(Side::Ask, best_offer_id, req_q)?;
+                    }
                 }
 
                 let native_taker_pc_unlocked = cancelled_take_qty * trade_price.get() * pc_lot_size;
-                let native_taker_pc_still_locked =
-                    native_pc_qty_locked.get() - native_taker_pc_unlocked;
+                let native_taker_pc_still_locked = native_pc_qty_locked
+                    .get()
+                    .saturating_sub(native_taker_pc_unlocked);
 
                 let order_remaining = (|| {
                     Some(OrderRemaining {
@@ -769,13 +1291,14 @@ impl<'ob> OrderBookState<'ob> {
                     to_release.unlock_native_pc(native_qty_unlocked);
                     let take_out = Event::new(EventView::Out {
                         side: Side::Bid,
-                        release_funds: false,
+                        release_funds: deferred_settlement,
                         native_qty_unlocked,
                         native_qty_still_locked,
                         order_id,
                         owner,
                         owner_slot,
                         client_order_id: NonZeroU64::new(client_order_id),
+                        reason: OutReason::SelfTrade,
                     });
                     event_q
                         .push_back(take_out)
@@ -784,26 +1307,34 @@ impl<'ob> OrderBookState<'ob> {
 
                 return Ok(order_remaining);
             }
+            if matches!(price_band, Some(band) if trade_price.get() > band.max_price) {
+                // See new_ask.
+                if !deferred_settlement {
+                    return Err(DexErrorCode::PriceBandExceeded.into());
+                }
+                outside_band = true;
+                break true;
+            }
             let maker_fee_tier = best_offer_ref.fee_tier();
             let native_maker_pc_qty = trade_qty * trade_price.get() * pc_lot_size;
-            let native_maker_rebate = maker_fee_tier.maker_rebate(native_maker_pc_qty);
+            let native_maker_rebate =
+                fee_schedule.maker_rebate(maker_fee_tier, native_maker_pc_qty);
             accum_maker_rebates += native_maker_rebate;
 
-            let maker_fill = Event::new(EventView::Fill {
+            let maker_fill = EventView::Fill {
                 side: Side::Ask,
                 maker: true,
+                release_funds: false,
                 native_qty_paid: trade_qty * coin_lot_size,
                 native_qty_received: native_maker_pc_qty + native_maker_rebate,
                 native_fee_or_rebate: native_maker_rebate,
-                order_id: best_offer_ref.order_id(),
+                order_id: best_offer_order_id,
                 owner: best_offer_ref.owner(),
                 owner_slot: best_offer_ref.owner_slot(),
                 fee_tier: maker_fee_tier,
                 client_order_id: NonZeroU64::new(best_offer_ref.client_order_id()),
-            });
-            event_q
-                .push_back(maker_fill)
-                .map_err(|_| DexErrorCode::EventQueueFull)?;
+            };
+            event_q.push_maker_fill(maker_fill, trade_price, order_id, now)?;
 
             best_offer_ref.set_quantity(best_offer_ref.quantity() - trade_qty);
             coin_qty_remaining -= trade_qty;
@@ -811,28 +1342,39 @@ impl<'ob> OrderBookState<'ob> {
 
             if best_offer_ref.quantity() == 0 {
                 let best_offer_id = best_offer_ref.order_id();
-                event_q
-                    .push_back(Event::new(EventView::Out {
-                        side: Side::Ask,
-                        release_funds: true,
-                        native_qty_unlocked: 0,
-                        native_qty_still_locked: 0,
-                        order_id: best_offer_id,
-                        owner: best_offer_ref.owner(),
-                        owner_slot: best_offer_ref.owner_slot(),
-                        client_order_id: NonZeroU64::new(best_offer_ref.client_order_id()),
-                    }))
-                    .map_err(|_| DexErrorCode::EventQueueFull)?;
-                self.orders_mut(Side::Ask)
-                    .remove_by_key(best_offer_id)
-                    .unwrap();
+                let out = Event::new(EventView::Out {
+                    side: Side::Ask,
+                    release_funds: true,
+                    native_qty_unlocked: 0,
+                    native_qty_still_locked: 0,
+                    order_id: best_offer_order_id,
+                    owner: best_offer_ref.owner(),
+                    owner_slot: best_offer_ref.owner_slot(),
+                    client_order_id: NonZeroU64::new(best_offer_ref.client_order_id()),
+                    reason: OutReason::Filled,
+                });
+                if self.replenish(Side::Ask, best_offer_id, req_q)? == 0 {
+                    event_q
+                        .push_back(out)
+                        .map_err(|_| DexErrorCode::EventQueueFull)?;
+                    self.remove_order(Side::Ask, best_offer_id).unwrap();
+                }
+            }
+
+            if let Some(stats) = self.stats.as_deref_mut() {
+                stats.record_trade(
+                    trade_price.get(),
+                    trade_qty * coin_lot_size,
+                    native_maker_pc_qty,
+                    now,
+                );
             }
 
             break false;
         };
 
         let native_accum_fill_price = (max_pc_qty - pc_qty_remaining) * pc_lot_size;
-        let native_taker_fee = fee_tier.taker_fee(native_accum_fill_price);
+        let native_taker_fee = fee_schedule.taker_fee(fee_tier, native_accum_fill_price);
         let native_pc_qty_remaining =
             native_pc_qty_locked.get() - native_accum_fill_price - native_taker_fee;
 
@@ -847,6 +1389,7 @@ impl<'ob> OrderBookState<'ob> {
                 let taker_fill = Event::new(EventView::Fill {
                     side: Side::Bid,
                     maker: false,
+                    release_funds: deferred_settlement,
                     native_qty_paid: native_pc_paid,
                     native_qty_received: coin_lots_received * coin_lot_size,
                     native_fee_or_rebate: native_taker_fee,
@@ -881,12 +1424,24 @@ impl<'ob> OrderBookState<'ob> {
             }
         }
 
-        let (coin_qty_to_post, pc_qty_to_keep_locked) = match limit_price {
+        // A bid is filled once it gets its coin or can't afford another lot.
+        if fill_or_kill
+            && coin_qty_remaining > 0
+            && pc_qty_remaining >= limit_price.map_or(1, NonZeroU64::get)
+        {
+            return Err(DexErrorCode::FillOrKillNotFilled.into());
+        }
+
+        let affordable_qty = match limit_price {
             Some(price) if post_allowed && !crossed => {
-                let coin_qty_to_post =
-                    coin_qty_remaining.min(native_pc_qty_remaining / pc_lot_size / price.get());
-                (coin_qty_to_post, coin_qty_to_post * price.get())
+                coin_qty_remaining.min(native_pc_qty_remaining / pc_lot_size / price.get())
             }
+            _ => 0,
+        };
+        // What is left of the bid may be too small to rest.
+        let below_minimum = affordable_qty > 0 && affordable_qty < min_base_qty;
+        let (coin_qty_to_post, pc_qty_to_keep_locked) = match limit_price {
+            Some(price) if !below_minimum => (affordable_qty, affordable_qty * price.get()),
             _ => (0, 0),
         };
 
@@ -897,15 +1452,31 @@ impl<'ob> OrderBookState<'ob> {
 
                 to_release.unlock_native_pc(native_qty_unlocked);
 
+                let reason = if coin_qty_to_post > 0 {
+                    OutReason::Posted
+                } else if below_minimum {
+                    OutReason::BelowMinimum
+                } else if coin_qty_remaining == 0
+                    || pc_qty_remaining < limit_price.map_or(1, NonZeroU64::get)
+                {
+                    OutReason::Filled
+                } else if post_only_order && crossed {
+                    OutReason::PostOnly
+                } else if outside_band {
+                    OutReason::PriceBand
+                } else {
+                    OutReason::ImmediateOrCancel
+                };
                 Event::new(EventView::Out {
                     side: Side::Bid,
-                    release_funds: false,
+                    release_funds: deferred_settlement,
                     native_qty_unlocked,
                     native_qty_still_locked,
                     order_id,
                     owner,
                     owner_slot,
                     client_order_id: NonZeroU64::new(client_order_id),
+                    reason,
                 })
             };
             event_q
@@ -914,7 +1485,6 @@ impl<'ob> OrderBookState<'ob> {
         }
 
         if pc_qty_to_keep_locked > 0 {
-            let bids = self.orders_mut(Side::Bid);
             let new_leaf = LeafNode::new(
                 owner_slot,
                 order_id,
@@ -923,28 +1493,7 @@ impl<'ob> OrderBookState<'ob> {
                 fee_tier,
                 client_order_id,
             );
-            let insert_result = bids.insert_leaf(&new_leaf);
-            if let Err(SlabTreeError::OutOfSpace) = insert_result {
-                // boot out the least aggressive bid
-                msg!("bids full! booting...");
-                let order = bids.remove_min().unwrap();
-                let out = Event::new(EventView::Out {
-                    side: Side::Bid,
-                    release_funds: true,
-                    native_qty_unlocked: order.quantity() * order.price().get() * pc_lot_size,
-                    native_qty_still_locked: 0,
-                    order_id: order.order_id(),
-                    owner: order.owner(),
-                    owner_slot: order.owner_slot(),
-                    client_order_id: NonZeroU64::new(order.client_order_id()),
-                });
-                event_q
-                    .push_back(out)
-                    .map_err(|_| DexErrorCode::EventQueueFull)?;
-                bids.insert_leaf(&new_leaf).unwrap();
-            } else {
-                insert_result.unwrap();
-            }
+            self.post_order(Side::Bid, &new_leaf, expiry_ts, event_q)?;
         }
 
         Ok(None)
@@ -954,13 +1503,18 @@ impl<'ob> OrderBookState<'ob> {
         &mut self,
         side: Side,
         open_orders_address: [u64; 4],
-        open_orders: &mut OpenOrders,
+        open_orders: &mut OpenOrdersAccount,
         order_id: u128,
         event_q: &mut EventQueue,
     ) -> DexResult {
+        // A replenished iceberg rests under a newer key than the id its owner knows.
+        let key = self
+            .orders_mut(side)
+            .find_reserve_by_order_id(order_id)
+            .map_or(order_id, ReserveNode::key);
         let leaf_node = self
             .orders_mut(side)
-            .remove_by_key(order_id)
+            .remove_by_key(key)
             .ok_or(DexErrorCode::OrderNotFound)?;
         self.cancel_leaf_node(
             leaf_node,
@@ -968,35 +1522,107 @@ impl<'ob> OrderBookState<'ob> {
             open_orders,
             open_orders_address,
             order_id,
+            OutReason::Cancelled,
             event_q,
         )
     }
 
+    // Shrinks a resting order in place, keeping its time priority. Returns the
+    // untouched leaf if the amend needs the order to be requeued instead.
+    pub(crate) fn amend_order_in_place(
+        &mut self,
+        side: Side,
+        open_orders_address: [u64; 4],
+        open_orders: &mut OpenOrdersAccount,
+        order_id: u128,
+        limit_price: NonZeroU64,
+        max_coin_qty: NonZeroU64,
+        event_q: &mut EventQueue,
+    ) -> DexResult<Option<LeafNode>> {
+        let pc_lot_size = self.market_state.pc_lot_size;
+        let coin_lot_size = self.market_state.coin_lot_size;
+        let orders = self.orders_mut(side);
+        // An iceberg is re-keyed as it refills, so it has no one leaf to amend.
+        let reserve = orders.find_reserve_by_order_id(order_id);
+        if matches!(reserve, Some(reserve) if reserve.is_iceberg()) {
+            return Err(DexErrorCode::InvalidIcebergOrder.into());
+        }
+        let leaf_h = orders
+            .find_by_key(order_id)
+            .ok_or(DexErrorCode::OrderNotFound)?;
+        let leaf_node = orders.get_mut(leaf_h).unwrap().as_leaf_mut().unwrap();
+        check_assert_eq!(leaf_node.owner(), open_orders_address)
+            .or(Err(DexErrorCode::OrderNotYours))?;
+
+        let owner_slot = leaf_node.owner_slot();
+        check_assert_eq!(order_id, open_orders.order_id(owner_slot))?;
+        check_assert_eq!(Some(side), open_orders.slot_side(owner_slot))?;
+
+        let quantity = leaf_node.quantity();
+        if leaf_node.price() != limit_price || max_coin_qty.get() > quantity {
+            return Ok(Some(*leaf_node));
+        }
+        if max_coin_qty.get() == quantity {
+            return Err(DexErrorCode::InvalidAmendOrder.into());
+        }
+        leaf_node.set_quantity(max_coin_qty.get());
+
+        let native_lot_size = match side {
+            Side::Bid => limit_price.get() * pc_lot_size,
+            Side::Ask => coin_lot_size,
+        };
+        let native_qty_unlocked = (quantity - max_coin_qty.get()) * native_lot_size;
+        match side {
+            Side::Bid => open_orders.unlock_pc(native_qty_unlocked),
+            Side::Ask => open_orders.unlock_coin(native_qty_unlocked),
+        };
+        event_q
+            .push_back(Event::new(EventView::Out {
+                side,
+                release_funds: false,
+                native_qty_unlocked,
+                native_qty_still_locked: max_coin_qty.get() * native_lot_size,
+                order_id,
+                owner: open_orders_address,
+                owner_slot,
+                client_order_id: NonZeroU64::new(leaf_node.client_order_id()),
+                reason: OutReason::Amended,
+            }))
+            .map_err(|_| DexErrorCode::EventQueueFull)?;
+        Ok(None)
+    }
+
     pub(crate) fn cancel_leaf_node(
         &mut self,
         leaf_node: LeafNode,
         side: Side,
-        open_orders: &mut OpenOrders,
+        open_orders: &mut OpenOrdersAccount,
         open_orders_address: [u64; 4],
         order_id: u128,
+        reason: OutReason,
         event_q: &mut EventQueue,
     ) -> DexResult {
         check_assert_eq!(leaf_node.owner(), open_orders_address)
             .or(Err(DexErrorCode::OrderNotYours))?;
 
         let open_orders_slot = leaf_node.owner_slot();
-        check_assert_eq!(order_id, open_orders.orders[open_orders_slot as usize])?;
+        check_assert_eq!(order_id, open_orders.order_id(open_orders_slot))?;
         check_assert_eq!(Some(side), open_orders.slot_side(open_orders_slot))?;
 
+        let quantity = leaf_node.quantity()
+            + self
+                .orders_mut(side)
+                .remove_reserve(leaf_node.order_id())
+                .map_or(0, |reserve| reserve.quantity());
         let native_qty_unlocked;
         match side {
             Side::Bid => {
                 native_qty_unlocked =
-                    leaf_node.quantity() * leaf_node.price().get() * self.market_state.pc_lot_size;
+                    quantity * leaf_node.price().get() * self.market_state.pc_lot_size;
                 open_orders.unlock_pc(native_qty_unlocked);
             }
             Side::Ask => {
-                native_qty_unlocked = leaf_node.quantity() * self.market_state.coin_lot_size;
+                native_qty_unlocked = quantity * self.market_state.coin_lot_size;
                 open_orders.unlock_coin(native_qty_unlocked);
             }
         }
@@ -1010,6 +1636,7 @@ impl<'ob> OrderBookState<'ob> {
                 owner: open_orders_address,
                 owner_slot: open_orders_slot,
                 client_order_id: NonZeroU64::new(leaf_node.client_order_id()),
+                reason,
             }))
             .map_err(|_| DexErrorCode::EventQueueFull)?;
         Ok(())
@@ -1020,23 +1647,28 @@ impl<'ob> OrderBookState<'ob> {
         side: Side,
         order_id: u128,
         expected_owner: [u64; 4],
-        expected_owner_slot: u8,
+        expected_owner_slot: u16,
         client_order_id: Option<NonZeroU64>,
         event_q: &mut EventQueue,
     ) -> DexResult<()> {
-        if let Some(leaf_node) = self.orders_mut(side).remove_by_key(order_id) {
+        let key = self
+            .orders_mut(side)
+            .find_reserve_by_order_id(order_id)
+            .map_or(order_id, ReserveNode::key);
+        if let Some(leaf_node) = self.orders_mut(side).remove_by_key(key) {
             if leaf_node.owner() == expected_owner && leaf_node.owner_slot() == expected_owner_slot
             {
                 if let Some(client_id) = client_order_id {
                     debug_assert_eq!(client_id.get(), leaf_node.client_order_id());
                 }
+                let quantity = leaf_node.quantity()
+                    + self
+                        .orders_mut(side)
+                        .remove_reserve(key)
+                        .map_or(0, |reserve| reserve.quantity());
                 let native_qty_unlocked = match side {
-                    Side::Bid => {
-                        leaf_node.quantity()
-                            * leaf_node.price().get()
-                            * self.market_state.pc_lot_size
-                    }
-                    Side::Ask => leaf_node.quantity() * self.market_state.coin_lot_size,
+                    Side::Bid => quantity * leaf_node.price().get() * self.market_state.pc_lot_size,
+                    Side::Ask => quantity * self.market_state.coin_lot_size,
                 };
                 event_q
                     .push_back(Event::new(EventView::Out {
@@ -1048,6 +1680,7 @@ impl<'ob> OrderBookState<'ob> {
                         owner: expected_owner,
                         owner_slot: expected_owner_slot,
                         client_order_id: NonZeroU64::new(leaf_node.client_order_id()),
+                        reason: OutReason::Cancelled,
                     }))
                     .map_err(|_| DexErrorCode::EventQueueFull)?;
             } else {
@@ -0,0 +1,562 @@
+//! Runs the program against heap-allocated accounts, so strategies can be
+//! backtested on the production matching engine without a validator.
+//!
+//! A `Simulator` owns one permissioned market, its vaults and any number of
+//! users, each with funded token wallets and an OpenOrders account. Signer
+//! flags are taken from the instruction's `AccountMeta`s rather than checked
+//! against signatures, and a failed instruction leaves every account as it
+//! was, as it would on chain.
+
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use solana_program::account_info::AccountInfo;
+use solana_program::bpf_loader;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::program_error::ProgramError;
+use solana_program::program_pack::Pack;
+use solana_program::pubkey::Pubkey;
+use solana_program::rent::Rent;
+use solana_program::{system_program, sysvar};
+use spl_token::state::{Account, AccountState, Mint};
+
+use crate::critbit::L2Level;
+use crate::error::{DexErrorCode, DexResult};
+use crate::instruction::{self, MarketInstruction};
+use crate::matching::Side;
+use crate::state::{
+    gen_vault_signer_key, load_book_side, EventQueue, EventView, Market, MarketStateV2, OpenOrders,
+    State, ToAlignedBytes, UNIX_TIMESTAMP,
+};
+
+#[derive(Copy, Clone, Debug)]
+pub struct SimulatorConfig {
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+    pub pc_dust_threshold: u64,
+    pub tick_size: u64,
+    pub min_base_qty: u64,
+    /// Sizes in bytes of each side of the book and of the event queue.
+    pub book_side_size: usize,
+    pub event_queue_size: usize,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig {
+            coin_lot_size: 1_000,
+            pc_lot_size: 1,
+            pc_dust_threshold: 5,
+            tick_size: 1,
+            min_base_qty: 1,
+            book_side_size: 1 << 20,
+            event_queue_size: 1 << 16,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UserId(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UserAccounts {
+    pub owner: Pubkey,
+    pub open_orders: Pubkey,
+    pub coin_wallet: Pubkey,
+    pub pc_wallet: Pubkey,
+}
+
+/// A user's native token amounts, in their wallets and in their OpenOrders
+/// account. Whatever isn't free in the OpenOrders account backs resting
+/// orders.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Balances {
+    pub coin_wallet: u64,
+    pub pc_wallet: u64,
+    pub coin_free: u64,
+    pub coin_total: u64,
+    pub pc_free: u64,
+    pub pc_total: u64,
+}
+
+#[derive(Debug)]
+pub struct Outcome {
+    /// The events the instruction pushed, in queue order. The simulator
+    /// consumes them before returning, like an ideal crank.
+    pub events: Vec<EventView>,
+    pub balances: Balances,
+}
+
+#[derive(Clone)]
+struct SimAccount {
+    lamports: u64,
+    // Backed by u64s so DEX account payloads are 8-byte aligned, as they are
+    // in the runtime.
+    data: Vec<u64>,
+    offset: usize,
+    len: usize,
+    owner: Pubkey,
+}
+
+impl SimAccount {
+    fn new(data: &[u8], owner: Pubkey) -> Self {
+        let mut account = SimAccount {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data: vec![0; data.len().div_ceil(8)],
+            offset: 0,
+            len: data.len(),
+            owner,
+        };
+        account.bytes_mut().copy_from_slice(data);
+        account
+    }
+
+    fn dex_owned(unpadded_len: usize, program_id: Pubkey) -> Self {
+        let len = unpadded_len + 12;
+        SimAccount {
+            lamports: Rent::default().minimum_balance(len),
+            data: vec![0; (len + 3).div_ceil(8)],
+            offset: 3,
+            len,
+            owner: program_id,
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &bytemuck::cast_slice(&self.data)[self.offset..self.offset + self.len]
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut bytemuck::cast_slice_mut(&mut self.data)[self.offset..self.offset + self.len]
+    }
+}
+
+pub struct Simulator {
+    program_id: Pubkey,
+    accounts: HashMap<Pubkey, SimAccount>,
+    market: Pubkey,
+    req_q: Pubkey,
+    event_q: Pubkey,
+    bids: Pubkey,
+    asks: Pubkey,
+    coin_vault: Pubkey,
+    pc_vault: Pubkey,
+    coin_mint: Pubkey,
+    pc_mint: Pubkey,
+    vault_signer: Pubkey,
+    authority: Pubkey,
+    users: Vec<UserAccounts>,
+    now: i64,
+}
+
+impl Simulator {
+    pub fn new(config: SimulatorConfig) -> DexResult<Self> {
+        let program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let (vault_signer_nonce, vault_signer) = (0..)
+            .find_map(|nonce| {
+                gen_vault_signer_key(nonce, &market, &program_id)
+                    .ok()
+                    .map(|key| (nonce, key))
+            })
+            .unwrap();
+
+        let mut sim = Simulator {
+            program_id,
+            accounts: HashMap::new(),
+            market,
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            vault_signer,
+            authority: Pubkey::new_unique(),
+            users: Vec::new(),
+            now: 0,
+        };
+
+        sim.accounts
+            .insert(spl_token::ID, SimAccount::new(&[], bpf_loader::ID));
+        let rent = bincode::serialize(&Rent::default()).unwrap();
+        sim.accounts
+            .insert(sysvar::rent::ID, SimAccount::new(&rent, sysvar::ID));
+        for key in [vault_signer, sim.authority] {
+            sim.accounts
+                .insert(key, SimAccount::new(&[], system_program::ID));
+        }
+        for mint in [sim.coin_mint, sim.pc_mint] {
+            let mut data = [0; Mint::LEN];
+            Mint::pack(
+                Mint {
+                    is_initialized: true,
+                    ..Mint::default()
+                },
+                &mut data,
+            )?;
+            sim.accounts
+                .insert(mint, SimAccount::new(&data, spl_token::ID));
+        }
+        sim.insert_token_account(sim.coin_vault, sim.coin_mint, vault_signer, 0)?;
+        sim.insert_token_account(sim.pc_vault, sim.pc_mint, vault_signer, 0)?;
+        for (key, len) in [
+            (market, size_of::<MarketStateV2>()),
+            (sim.req_q, 640),
+            (sim.event_q, config.event_queue_size),
+            (sim.bids, config.book_side_size),
+            (sim.asks, config.book_side_size),
+        ] {
+            sim.accounts
+                .insert(key, SimAccount::dex_owned(len, program_id));
+        }
+
+        let initialize = instruction::initialize_market(
+            &market,
+            &program_id,
+            &sim.coin_mint,
+            &sim.pc_mint,
+            &sim.coin_vault,
+            &sim.pc_vault,
+            Some(&sim.authority),
+            None,
+            None,
+            &sim.bids,
+            &sim.asks,
+            &sim.req_q,
+            &sim.event_q,
+            config.coin_lot_size,
+            config.pc_lot_size,
+            vault_signer_nonce,
+            config.pc_dust_threshold,
+            config.tick_size,
+            config.min_base_qty,
+        )?;
+        sim.process(&initialize)?;
+        Ok(sim)
+    }
+
+    pub fn program_id(&self) -> Pubkey {
+        self.program_id
+    }
+
+    pub fn market(&self) -> Pubkey {
+        self.market
+    }
+
+    pub fn req_q(&self) -> Pubkey {
+        self.req_q
+    }
+
+    pub fn event_q(&self) -> Pubkey {
+        self.event_q
+    }
+
+    pub fn bids(&self) -> Pubkey {
+        self.bids
+    }
+
+    pub fn asks(&self) -> Pubkey {
+        self.asks
+    }
+
+    /// The current data of a simulated account, as a client would fetch it.
+    pub fn account_data(&self, key: &Pubkey) -> Option<&[u8]> {
+        self.accounts.get(key).map(SimAccount::bytes)
+    }
+
+    /// The key that signs for the market's open orders authority and admin.
+    pub fn authority(&self) -> Pubkey {
+        self.authority
+    }
+
+    /// Sets the clock seen by instructions, for order expiry and trade
+    /// statistics.
+    pub fn set_timestamp(&mut self, now: i64) {
+        self.now = now;
+    }
+
+    /// Creates a user holding the given native amounts in their wallets.
+    pub fn add_user(&mut self, native_coin: u64, native_pc: u64) -> DexResult<UserId> {
+        let user = UserAccounts {
+            owner: Pubkey::new_unique(),
+            open_orders: Pubkey::new_unique(),
+            coin_wallet: Pubkey::new_unique(),
+            pc_wallet: Pubkey::new_unique(),
+        };
+        self.accounts
+            .insert(user.owner, SimAccount::new(&[], system_program::ID));
+        self.insert_token_account(user.coin_wallet, self.coin_mint, user.owner, native_coin)?;
+        self.insert_token_account(user.pc_wallet, self.pc_mint, user.owner, native_pc)?;
+        self.accounts.insert(
+            user.open_orders,
+            SimAccount::dex_owned(size_of::<OpenOrders>(), self.program_id),
+        );
+        let init_open_orders = instruction::init_open_orders(
+            &self.program_id,
+            &user.open_orders,
+            &user.owner,
+            &self.market,
+            Some(&self.authority),
+        )?;
+        self.process(&init_open_orders)?;
+        self.users.push(user);
+        Ok(UserId(self.users.len() - 1))
+    }
+
+    pub fn user_accounts(&self, user: UserId) -> UserAccounts {
+        self.users[user.0]
+    }
+
+    /// Runs an instruction that `user` signs, then consumes the events it
+    /// produced. Order placement, cancels, SendTake and SettleFunds are
+    /// supported; anything else fails with `DexErrorCode::Unknown` and can be
+    /// built with the `instruction` module and run with `process`.
+    pub fn execute(&mut self, user: UserId, instruction: MarketInstruction) -> DexResult<Outcome> {
+        let keys = self.users[user.0];
+        let accounts = match &instruction {
+            MarketInstruction::NewOrderV3(instruction::NewOrderInstructionV3 { side, .. })
+            | MarketInstruction::AmendOrder(instruction::AmendOrderInstruction { side, .. }) => {
+                self.new_order_accounts(&keys, *side)
+            }
+            MarketInstruction::NewIcebergOrder(iceberg) => {
+                self.new_order_accounts(&keys, iceberg.order.side)
+            }
+            MarketInstruction::CancelOrderV2(_)
+            | MarketInstruction::CancelOrderByClientIdV2(_)
+            | MarketInstruction::CancelOrdersByClientIds(_)
+            | MarketInstruction::CancelAllOrders(_) => vec![
+                AccountMeta::new(self.market, false),
+                AccountMeta::new(self.bids, false),
+                AccountMeta::new(self.asks, false),
+                AccountMeta::new(keys.open_orders, false),
+                AccountMeta::new_readonly(keys.owner, true),
+                AccountMeta::new(self.event_q, false),
+            ],
+            MarketInstruction::SettleFunds => vec![
+                AccountMeta::new(self.market, false),
+                AccountMeta::new(keys.open_orders, false),
+                AccountMeta::new_readonly(keys.owner, true),
+                AccountMeta::new(self.coin_vault, false),
+                AccountMeta::new(self.pc_vault, false),
+                AccountMeta::new(keys.coin_wallet, false),
+                AccountMeta::new(keys.pc_wallet, false),
+                AccountMeta::new_readonly(self.vault_signer, false),
+                AccountMeta::new_readonly(spl_token::ID, false),
+            ],
+            MarketInstruction::SendTake(_) => vec![
+                AccountMeta::new(self.market, false),
+                AccountMeta::new(self.req_q, false),
+                AccountMeta::new(self.event_q, false),
+                AccountMeta::new(self.bids, false),
+                AccountMeta::new(self.asks, false),
+                AccountMeta::new(keys.coin_wallet, false),
+                AccountMeta::new(keys.pc_wallet, false),
+                AccountMeta::new_readonly(keys.owner, true),
+                AccountMeta::new(self.coin_vault, false),
+                AccountMeta::new(self.pc_vault, false),
+                AccountMeta::new_readonly(spl_token::ID, false),
+                AccountMeta::new_readonly(self.vault_signer, false),
+            ],
+            _ => return Err(DexErrorCode::Unknown.into()),
+        };
+        self.process(&Instruction {
+            program_id: self.program_id,
+            accounts,
+            data: instruction.pack(),
+        })?;
+        let events = self.crank()?;
+        Ok(Outcome {
+            events,
+            balances: self.balances(user)?,
+        })
+    }
+
+    /// Runs one of the market admin's instructions: UpdateFeeSchedule,
+    /// SetPriceBand, EnterCancelOnly or ExitCancelOnly.
+    pub fn execute_as_admin(&mut self, instruction: MarketInstruction) -> DexResult {
+        match instruction {
+            MarketInstruction::UpdateFeeSchedule(_)
+            | MarketInstruction::SetPriceBand(_)
+            | MarketInstruction::EnterCancelOnly
+            | MarketInstruction::ExitCancelOnly => {}
+            _ => return Err(DexErrorCode::Unknown.into()),
+        }
+        self.process(&Instruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new(self.market, false),
+                AccountMeta::new_readonly(self.authority, true),
+            ],
+            data: instruction.pack(),
+        })
+    }
+
+    /// Runs any instruction against the simulated accounts. Every account
+    /// it names must belong to the simulator.
+    pub fn process(&mut self, instruction: &Instruction) -> DexResult {
+        let snapshot: Vec<(Pubkey, SimAccount)> = instruction
+            .accounts
+            .iter()
+            .filter(|meta| meta.is_writable)
+            .filter_map(|meta| Some((meta.pubkey, self.accounts.get(&meta.pubkey)?.clone())))
+            .collect();
+        let now = self.now;
+        UNIX_TIMESTAMP.with(|ts| ts.set(now));
+        let program_id = instruction.program_id;
+        let result = self.with_accounts(&instruction.accounts, |accounts| {
+            State::process(&program_id, accounts, &instruction.data)
+        });
+        if result.is_err() {
+            self.accounts.extend(snapshot);
+        }
+        result
+    }
+
+    pub fn balances(&self, user: UserId) -> DexResult<Balances> {
+        let keys = &self.users[user.0];
+        let wallet_amount = |key: &Pubkey| -> DexResult<u64> {
+            Ok(Account::unpack(self.accounts[key].bytes())?.amount)
+        };
+        let open_orders: OpenOrders = *bytemuck::from_bytes(
+            &self.accounts[&keys.open_orders].bytes()[5..5 + size_of::<OpenOrders>()],
+        );
+        Ok(Balances {
+            coin_wallet: wallet_amount(&keys.coin_wallet)?,
+            pc_wallet: wallet_amount(&keys.pc_wallet)?,
+            coin_free: open_orders.native_coin_free,
+            coin_total: open_orders.native_coin_total,
+            pc_free: open_orders.native_pc_free,
+            pc_total: open_orders.native_pc_total,
+        })
+    }
+
+    pub fn l2_depth(&self, side: Side, max_levels: Option<usize>) -> DexResult<Vec<L2Level>> {
+        let key = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let slab = load_book_side(self.accounts[key].bytes(), side)?;
+        Ok(slab.l2_depth(side == Side::Bid, max_levels, None))
+    }
+
+    fn new_order_accounts(&self, keys: &UserAccounts, side: Side) -> Vec<AccountMeta> {
+        let payer = match side {
+            Side::Bid => keys.pc_wallet,
+            Side::Ask => keys.coin_wallet,
+        };
+        vec![
+            AccountMeta::new(self.market, false),
+            AccountMeta::new(keys.open_orders, false),
+            AccountMeta::new(self.req_q, false),
+            AccountMeta::new(self.event_q, false),
+            AccountMeta::new(self.bids, false),
+            AccountMeta::new(self.asks, false),
+            AccountMeta::new(payer, false),
+            AccountMeta::new_readonly(keys.owner, true),
+            AccountMeta::new(self.coin_vault, false),
+            AccountMeta::new(self.pc_vault, false),
+            AccountMeta::new_readonly(spl_token::ID, false),
+            AccountMeta::new_readonly(sysvar::rent::ID, false),
+        ]
+    }
+
+    fn crank(&mut self) -> DexResult<Vec<EventView>> {
+        let events =
+            self.with_event_queue(|event_q| event_q.iter().map(|event| event.as_view()).collect())?;
+
+        let mut open_orders: Vec<Pubkey> = self.users.iter().map(|user| user.open_orders).collect();
+        open_orders.sort_by_key(|key| key.to_aligned_bytes());
+        let mut accounts: Vec<AccountMeta> = open_orders
+            .into_iter()
+            .map(|key| AccountMeta::new(key, false))
+            .collect();
+        accounts.extend_from_slice(&[
+            AccountMeta::new(self.market, false),
+            AccountMeta::new(self.event_q, false),
+            // Unused fee receivable accounts.
+            AccountMeta::new(self.coin_vault, false),
+            AccountMeta::new(self.pc_vault, false),
+        ]);
+        let consume_events = Instruction {
+            program_id: self.program_id,
+            accounts,
+            data: MarketInstruction::ConsumeEvents(u16::MAX).pack(),
+        };
+        while !self.with_event_queue(|event_q| Ok(event_q.empty()))? {
+            self.process(&consume_events)?;
+        }
+        Ok(events)
+    }
+
+    fn with_event_queue<T>(&mut self, f: impl FnOnce(&EventQueue) -> DexResult<T>) -> DexResult<T> {
+        let program_id = self.program_id;
+        let accounts = [
+            AccountMeta::new(self.market, false),
+            AccountMeta::new(self.event_q, false),
+        ];
+        self.with_accounts(&accounts, |accounts| {
+            let market = Market::load(&accounts[0], &program_id, true)?;
+            let event_q = market.load_event_queue_mut(&accounts[1])?;
+            f(&event_q)
+        })
+    }
+
+    fn insert_token_account(
+        &mut self,
+        key: Pubkey,
+        mint: Pubkey,
+        owner: Pubkey,
+        amount: u64,
+    ) -> DexResult {
+        let mut data = [0; Account::LEN];
+        Account::pack(
+            Account {
+                mint,
+                owner,
+                amount,
+                state: AccountState::Initialized,
+                ..Account::default()
+            },
+            &mut data,
+        )?;
+        self.accounts
+            .insert(key, SimAccount::new(&data, spl_token::ID));
+        Ok(())
+    }
+
+    fn with_accounts<T>(
+        &mut self,
+        metas: &[AccountMeta],
+        f: impl FnOnce(&[AccountInfo]) -> DexResult<T>,
+    ) -> DexResult<T> {
+        let mut infos: HashMap<Pubkey, AccountInfo> = HashMap::new();
+        for (key, account) in self.accounts.iter_mut() {
+            let SimAccount {
+                lamports,
+                data,
+                offset,
+                len,
+                owner,
+            } = account;
+            let data = &mut bytemuck::cast_slice_mut(data)[*offset..*offset + *len];
+            let info = AccountInfo::new(key, false, false, lamports, data, owner, false, 0);
+            infos.insert(*key, info);
+        }
+        let accounts = metas
+            .iter()
+            .map(|meta| {
+                let mut info = infos
+                    .get(&meta.pubkey)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?
+                    .clone();
+                info.is_signer = meta.is_signer;
+                info.is_writable = meta.is_writable;
+                Ok(info)
+            })
+            .collect::<DexResult<Vec<_>>>()?;
+        f(&accounts)
+    }
+}
@@ -1,8 +1,8 @@
 #![cfg_attr(not(feature = "program"), allow(unused))]
-use num_enum::TryFromPrimitive;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
 use std::{
-    cell::RefMut, convert::identity, convert::TryInto, mem::size_of, num::NonZeroU64, ops::Deref,
-    ops::DerefMut,
+    cell::RefMut, cmp::Reverse, convert::identity, convert::TryInto, mem::size_of, num::NonZeroU64,
+    ops::Deref, ops::DerefMut,
 };
 
 use arrayref::{array_ref, array_refs, mut_array_refs};
@@ -22,15 +22,17 @@ use solana_program::{
 use spl_token::error::TokenError;
 
 use crate::{
-    critbit::Slab,
+    critbit::{join_owner_slot, split_owner_slot, LeafNode, Slab},
     error::{DexErrorCode, DexResult, SourceFileId, DexError},
-    fees::{self, FeeTier},
+    fees::{self, FeeSchedule, FeeTier},
     instruction::{
-        disable_authority, fee_sweeper, msrm_token, srm_token, CancelOrderInstructionV2,
-        InitializeMarketInstruction, MarketInstruction, NewOrderInstructionV3, SelfTradeBehavior,
-        SendTakeInstruction,
+        disable_authority, fee_sweeper, msrm_token, srm_token, AmendOrderInstruction,
+        AuthorityKind, CancelAllOrdersInstruction, CancelOrderInstructionV2,
+        InitializeMarketInstruction, MarketInstruction, NewOrderInstructionV3,
+        NewTriggerOrderInstruction, ProposeAuthorityInstruction, SelfTradeBehavior,
+        SendTakeInstruction, UpdateFeeScheduleInstruction,
     },
-    matching::{OrderBookState, OrderType, RequestProceeds, Side},
+    matching::{OrderBookState, OrderType, PriceBand, RequestProceeds, Side},
 };
 
 declare_check_assert_macros!(SourceFileId::State);
@@ -60,6 +62,9 @@ pub enum AccountFlag {
     Closed = 1u64 << 8,
     Permissioned = 1u64 << 9,
     CrankAuthorityRequired = 1u64 << 10,
+    Triggers = 1u64 << 11,
+    FillDetails = 1u64 << 12,
+    CancelOnly = 1u64 << 13,
 }
 
 // Versioned frontend for market accounts.
@@ -89,6 +94,18 @@ impl<'a> DerefMut for Market<'a> {
 }
 
 impl<'a> Market<'a> {
+    // Matching updates the trade statistics alongside the V1 state, so it
+    // needs both borrowed at once.
+    pub(crate) fn state_and_stats_mut(&mut self) -> (&mut MarketState, Option<&mut MarketStats>) {
+        match self {
+            Market::V1(v1) => (v1.deref_mut(), None),
+            Market::V2(v2) => {
+                let v2: &mut MarketStateV2 = v2;
+                (&mut v2.inner, Some(&mut v2.stats))
+            }
+        }
+    }
+
     #[inline]
     pub fn load(
         market_account: &'a AccountInfo,
@@ -132,6 +149,48 @@ impl<'a> Market<'a> {
         }
     }
 
+    pub fn admin(&self) -> Option<&Pubkey> {
+        match &self {
+            Market::V1(_) => None,
+            Market::V2(state) if state.admin == Pubkey::default() => {
+                Some(&state.open_orders_authority)
+            }
+            Market::V2(state) => Some(&state.admin),
+        }
+    }
+
+    /// The key that may replace the market's fee schedule: its admin, or the
+    /// global disable authority on markets that have none.
+    pub fn fee_schedule_authority(&self) -> &Pubkey {
+        match self.admin() {
+            Some(admin) if admin != &Pubkey::default() => admin,
+            _ => &disable_authority::ID,
+        }
+    }
+
+    /// The global disable authority can always disable the market, so that
+    /// it keeps working as a kill switch; a market's own disable authority
+    /// can too.
+    pub fn is_disable_authority(&self, key: &Pubkey) -> bool {
+        match &self {
+            Market::V2(state) if state.disable_authority != Pubkey::default() => {
+                key == &disable_authority::ID || key == &state.disable_authority
+            }
+            _ => key == &disable_authority::ID,
+        }
+    }
+
+    /// Like the disable authority, a market's own fee sweeper works alongside
+    /// the global one rather than replacing it.
+    pub fn is_fee_sweeper(&self, key: &Pubkey) -> bool {
+        match &self {
+            Market::V2(state) if state.fee_sweeper != Pubkey::default() => {
+                key == &fee_sweeper::ID || key == &state.fee_sweeper
+            }
+            _ => key == &fee_sweeper::ID,
+        }
+    }
+
     pub fn prune_authority(&self) -> Option<&Pubkey> {
         match &self {
             Market::V1(_) => None,
@@ -153,6 +212,86 @@ impl<'a> Market<'a> {
         }
     }
 
+    pub fn fee_schedule(&self) -> FeeSchedule {
+        match &self {
+            Market::V1(_) => FeeSchedule::DEFAULT,
+            Market::V2(state) => state.effective_fee_schedule(),
+        }
+    }
+
+    /// The smallest price increment, in quote lots per base lot.
+    pub fn tick_size(&self) -> u64 {
+        match &self {
+            Market::V1(_) => 1,
+            Market::V2(state) => state.tick_size.max(1),
+        }
+    }
+
+    pub fn stats(&self) -> Option<MarketStats> {
+        match &self {
+            Market::V1(_) => None,
+            Market::V2(state) => Some(state.stats),
+        }
+    }
+
+    /// Prices takers may fill at, snapshotted before matching so a single
+    /// instruction can't walk the reference price along with it. None until
+    /// the market's first trade records a reference price.
+    pub fn price_band(&self) -> Option<PriceBand> {
+        match &self {
+            Market::V1(_) => None,
+            Market::V2(state) => state.price_band(),
+        }
+    }
+
+    /// The smallest order size, in base lots.
+    pub fn min_base_qty(&self) -> u64 {
+        match &self {
+            Market::V1(_) => 1,
+            Market::V2(state) => state.min_base_qty.max(1),
+        }
+    }
+
+    pub fn check_order_size(&self, limit_price: NonZeroU64, max_coin_qty: NonZeroU64) -> DexResult {
+        if limit_price.get() % self.tick_size() != 0 {
+            Err(DexErrorCode::PriceNotOnTick)?
+        }
+        if max_coin_qty.get() < self.min_base_qty() {
+            Err(DexErrorCode::OrderSizeBelowMinimum)?
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn load_fee_tier(
+        &self,
+        expected_owner: &[u64; 4],
+        srm_or_msrm_account: Option<account_parser::TokenAccount>,
+    ) -> DexResult<FeeTier> {
+        let market_addr = self.pubkey();
+        let fee_schedule = self.fee_schedule();
+        let srm_or_msrm_account = match srm_or_msrm_account {
+            Some(a) => a,
+            None => return Ok(fee_schedule.fee_tier(&market_addr, 0, 0)),
+        };
+        let data = srm_or_msrm_account.inner().try_borrow_data()?;
+
+        let mut aligned_data: [u64; 9] = Zeroable::zeroed();
+        bytes_of_mut(&mut aligned_data).copy_from_slice(&data[..72]);
+        let (mint, owner, &[balance]) = array_refs![&aligned_data, 4, 4, 1];
+
+        check_assert_eq!(owner, expected_owner)?;
+        if mint == &srm_token::ID.to_aligned_bytes() {
+            return Ok(fee_schedule.fee_tier(&market_addr, balance, 0));
+        }
+
+        if mint == &msrm_token::ID.to_aligned_bytes() {
+            return Ok(fee_schedule.fee_tier(&market_addr, 0, balance));
+        }
+
+        Ok(fee_schedule.fee_tier(&market_addr, 0, 0))
+    }
+
     pub fn load_orders_mut(
         &self,
         orders_account: &'a AccountInfo,
@@ -160,14 +299,13 @@ impl<'a> Market<'a> {
         program_id: &Pubkey,
         rent: Option<Rent>,
         open_orders_authority: Option<account_parser::SignerAccount>,
-    ) -> DexResult<RefMut<'a, OpenOrders>> {
+    ) -> DexResult<OpenOrdersAccount<'a>> {
         check_assert_eq!(orders_account.owner, program_id)?;
-        let mut open_orders: RefMut<'a, OpenOrders>;
 
         let open_orders_data_len = orders_account.data_len();
         let open_orders_lamports = orders_account.lamports();
         let (_, data) = strip_header::<[u8; 0], u8>(orders_account, true)?;
-        open_orders = RefMut::map(data, |data| from_bytes_mut(data));
+        let mut open_orders = OpenOrdersAccount::new(data)?;
 
         if open_orders.account_flags == 0 {
             let oo_authority = open_orders_authority.map(|a| a.inner().key);
@@ -206,8 +344,24 @@ pub struct MarketStateV2 {
     pub open_orders_authority: Pubkey,
     pub prune_authority: Pubkey,
     pub consume_events_authority: Pubkey,
+    pub fee_schedule: FeeSchedule,
+    pub tick_size: u64,
+    pub min_base_qty: u64,
+    // Signs authority transfers. Zero on markets initialized before it
+    // existed, which fall back to the open orders authority.
+    pub admin: Pubkey,
+    // Work alongside the global disable authority and fee sweeper unless
+    // zero.
+    pub disable_authority: Pubkey,
+    pub fee_sweeper: Pubkey,
+    // A transfer proposed by the admin that the new key hasn't accepted yet.
+    pub pending_authority: Pubkey,
+    pub pending_authority_kind: u64,
+    pub stats: MarketStats,
+    // Basis points takers may trade away from the last trade price.
+    pub price_band_bps: u64,
     // Unused bytes for future upgrades.
-    padding: [u8; 992],
+    padding: [u8; 672],
 }
 
 impl Deref for MarketStateV2 {
@@ -225,6 +379,27 @@ impl DerefMut for MarketStateV2 {
 }
 
 impl MarketStateV2 {
+    fn effective_fee_schedule(&self) -> FeeSchedule {
+        if self.fee_schedule.is_configured() {
+            self.fee_schedule
+        } else {
+            FeeSchedule::DEFAULT
+        }
+    }
+
+    // There's no band until the market's first trade sets a reference price.
+    fn price_band(&self) -> Option<PriceBand> {
+        if self.price_band_bps == 0 || self.stats.last_trade_price == 0 {
+            return None;
+        }
+        let reference = self.stats.last_trade_price as u128;
+        let offset = reference * self.price_band_bps as u128 / 10_000;
+        Some(PriceBand {
+            min_price: (reference - offset.min(reference)) as u64,
+            max_price: (reference + offset).min(u64::MAX as u128) as u64,
+        })
+    }
+
     #[inline]
     pub fn load<'a>(
         market_account: &'a AccountInfo,
@@ -251,8 +426,12 @@ impl MarketStateV2 {
 
     #[inline]
     pub fn check_flags(&self, allow_disabled: bool) -> DexResult {
-        let flags = BitFlags::from_bits(self.account_flags)
+        let mut flags = BitFlags::from_bits(self.account_flags)
             .map_err(|_| DexErrorCode::InvalidMarketFlags)?;
+        // Cancel-only markets load as usual; placing orders is refused by
+        // check_enabled. Triggers only records that the market has a trigger
+        // book.
+        flags.remove(AccountFlag::CancelOnly | AccountFlag::Triggers);
 
         let required_flags =
             AccountFlag::Initialized | AccountFlag::Market | AccountFlag::Permissioned;
@@ -284,9 +463,80 @@ unsafe impl Zeroable for MarketStateV2 {}
 #[cfg(target_endian = "little")]
 unsafe impl Pod for MarketStateV2 {}
 
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(MarketStateV2 {
+    inner,
+    open_orders_authority,
+    prune_authority,
+    consume_events_authority,
+    fee_schedule,
+    tick_size,
+    min_base_qty,
+    admin,
+    disable_authority,
+    fee_sweeper,
+    pending_authority,
+    pending_authority_kind,
+    stats,
+    price_band_bps,
+    padding,
+});
+
 #[cfg(target_endian = "little")]
 unsafe impl TriviallyTransmutable for MarketStateV2 {}
 
+/// Trade statistics of a V2 market, updated on every fill.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(packed)]
+pub struct MarketStats {
+    /// Price of the most recent fill, in quote lots per base lot.
+    pub last_trade_price: u64,
+    pub last_trade_ts: i64,
+    /// Native base and quote quantities filled since the market was created.
+    pub base_volume: u128,
+    pub quote_volume: u128,
+    pub trade_count: u64,
+    price_cumulative: u128,
+}
+unsafe impl Zeroable for MarketStats {}
+unsafe impl Pod for MarketStats {}
+
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(MarketStats {
+    last_trade_price,
+    last_trade_ts,
+    base_volume,
+    quote_volume,
+    trade_count,
+    price_cumulative,
+});
+
+impl MarketStats {
+    /// The sum of the last trade price over every second up to `now`.
+    /// Sampling this twice and dividing the difference by the seconds in
+    /// between gives the time-weighted average price over that window.
+    pub fn price_cumulative(&self, now: i64) -> u128 {
+        let elapsed = now.saturating_sub(self.last_trade_ts).max(0) as u128;
+        self.price_cumulative
+            .wrapping_add(elapsed.wrapping_mul(self.last_trade_price as u128))
+    }
+
+    pub(crate) fn record_trade(
+        &mut self,
+        price: u64,
+        native_base_qty: u64,
+        native_quote_qty: u64,
+        now: i64,
+    ) {
+        self.price_cumulative = self.price_cumulative(now);
+        self.last_trade_price = price;
+        self.last_trade_ts = now;
+        self.base_volume = self.base_volume.wrapping_add(native_base_qty as u128);
+        self.quote_volume = self.quote_volume.wrapping_add(native_quote_qty as u128);
+        self.trade_count = self.trade_count.wrapping_add(1);
+    }
+}
+
 #[derive(Copy, Clone)]
 #[cfg_attr(target_endian = "little", derive(Debug))]
 #[repr(packed)]
@@ -348,6 +598,30 @@ unsafe impl Pod for MarketState {}
 #[cfg(target_endian = "little")]
 unsafe impl TriviallyTransmutable for MarketState {}
 
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(MarketState {
+    account_flags,
+    own_address,
+    vault_signer_nonce,
+    coin_mint,
+    pc_mint,
+    coin_vault,
+    coin_deposits_total,
+    coin_fees_accrued,
+    pc_vault,
+    pc_deposits_total,
+    pc_fees_accrued,
+    pc_dust_threshold,
+    req_q,
+    event_q,
+    bids,
+    asks,
+    coin_lot_size,
+    pc_lot_size,
+    fee_rate_bps,
+    referrer_rebates_accrued,
+});
+
 pub const ACCOUNT_HEAD_PADDING: &[u8; 5] = b"serum";
 pub const ACCOUNT_TAIL_PADDING: &[u8; 7] = b"padding";
 
@@ -413,6 +687,195 @@ pub fn strip_header<'a, H: Pod, D: Pod>(
     Ok((header, inner))
 }
 
+/// Reads the bids or asks of a market from raw account data, e.g. as fetched
+/// over RPC, without needing an `AccountInfo`.
+#[cfg(any(test, feature = "client", feature = "simulator"))]
+pub fn load_book_side(account_data: &[u8], side: Side) -> DexResult<&Slab> {
+    check_assert!(account_data.len() >= 12 + size_of::<OrderBookStateHeader>())?;
+    let (head, data, tail) = array_refs![account_data, 5; ..; 7];
+    check_assert_eq!(head, ACCOUNT_HEAD_PADDING)?;
+    check_assert_eq!(tail, ACCOUNT_TAIL_PADDING)?;
+
+    let (flag_bytes, slab_bytes) = array_refs![data, 8; ..;];
+    let (required_flag, error) = match side {
+        Side::Bid => (AccountFlag::Bids, DexErrorCode::InvalidBidFlags),
+        Side::Ask => (AccountFlag::Asks, DexErrorCode::InvalidAskFlags),
+    };
+    let flags = BitFlags::from_bits(u64::from_le_bytes(*flag_bytes)).map_err(|_| error)?;
+    if flags != AccountFlag::Initialized | required_flag {
+        Err(error)?
+    }
+    Slab::from_bytes(slab_bytes)
+}
+
+/// The fill a SendTake would get against the book as it stands. For a bid
+/// the taker pays `native_pc_qty`, fees included, for `native_coin_qty`; for
+/// an ask it pays `native_coin_qty` for `native_pc_qty`, net of fees.
+#[cfg(any(test, feature = "client"))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SendTakeQuote {
+    pub native_coin_qty: u64,
+    pub native_pc_qty: u64,
+    pub native_taker_fee: u64,
+    /// The price levels filled against, best first, with the base lots and
+    /// number of maker orders filled at each.
+    pub levels: Vec<crate::critbit::L2Level>,
+}
+
+/// Quotes `instruction` from raw account data, e.g. as fetched over RPC, by
+/// running the matching a SendTake does on copies of the accounts. Fails
+/// where the SendTake would, e.g. when it can't meet its minimums or the
+/// event queue has no room. Stop orders the take would set off aren't run;
+/// they don't change what the taker gets. An ImmediateOrCancel NewOrderV3
+/// with the same limits fills the same way unless it would trade against
+/// its owner.
+#[cfg(any(test, feature = "client"))]
+pub fn quote_send_take(
+    program_id: &Pubkey,
+    market_data: &[u8],
+    req_q_data: &[u8],
+    event_q_data: &[u8],
+    bids_data: &[u8],
+    asks_data: &[u8],
+    instruction: &SendTakeInstruction,
+    fee_tier: FeeTier,
+    now: i64,
+) -> DexResult<SendTakeQuote> {
+    let market_end = ACCOUNT_HEAD_PADDING.len() + size_of::<MarketState>();
+    check_assert!(market_data.len() >= market_end)?;
+    let state: &MarketState =
+        bytemuck::from_bytes(&market_data[ACCOUNT_HEAD_PADDING.len()..market_end]);
+    let key = |words: [u64; 4]| Pubkey::new(cast_slice(&words));
+    let keys = [
+        key(state.own_address),
+        key(state.req_q),
+        key(state.event_q),
+        key(state.bids),
+        key(state.asks),
+    ];
+    let mut data =
+        [market_data, req_q_data, event_q_data, bids_data, asks_data].map(<[u8]>::to_vec);
+    let mut lamports = [0u64; 5];
+    let accounts: Vec<AccountInfo> = keys
+        .iter()
+        .zip(data.iter_mut())
+        .zip(lamports.iter_mut())
+        .map(|((key, data), lamports)| {
+            AccountInfo::new(key, false, true, lamports, data, program_id, false, 0)
+        })
+        .collect();
+    let (market_acc, req_q_acc, event_q_acc, bids_acc, asks_acc) = (
+        &accounts[0],
+        &accounts[1],
+        &accounts[2],
+        &accounts[3],
+        &accounts[4],
+    );
+
+    let mut market = Market::load(market_acc, program_id, false)?;
+    market.check_enabled()?;
+    market.check_order_size(instruction.limit_price, instruction.max_coin_qty)?;
+    let mut req_q = market.load_request_queue_mut(req_q_acc)?;
+    let mut event_q = market.load_event_queue_mut(event_q_acc)?;
+    let mut bids = market.load_bids_mut(bids_acc)?;
+    let mut asks = market.load_asks_mut(asks_acc)?;
+    let coin_lot_size = market.coin_lot_size;
+    let fee_schedule = market.fee_schedule();
+    let price_band = market.price_band();
+    let min_base_qty = market.min_base_qty();
+    let (market_state, stats) = market.state_and_stats_mut();
+    let mut order_book_state = OrderBookState {
+        bids: bids.deref_mut(),
+        asks: asks.deref_mut(),
+        market_state,
+        stats,
+        price_band,
+        min_base_qty,
+        fee_schedule,
+        sibling_open_orders: &[],
+    };
+
+    let events_before = event_q.len();
+    let proceeds = State::match_send_take(
+        instruction,
+        fee_tier,
+        &mut order_book_state,
+        &mut req_q,
+        &mut event_q,
+        now,
+    )?;
+    State::check_send_take_minimums(instruction, &proceeds)?;
+
+    // The taker gets no events of its own; the makers' fills say what it
+    // traded against, and the difference from its proceeds is its fee.
+    let mut native_pc_traded = 0;
+    let mut levels: Vec<crate::critbit::L2Level> = Vec::new();
+    for (_, event) in event_q.events_since(events_before) {
+        let (coin_qty, pc_qty, order_id) = match event.as_view()? {
+            EventView::Fill {
+                side: Side::Bid,
+                maker: true,
+                native_qty_paid,
+                native_qty_received,
+                native_fee_or_rebate,
+                order_id,
+                ..
+            } => (
+                native_qty_received,
+                native_qty_paid + native_fee_or_rebate,
+                order_id,
+            ),
+            EventView::Fill {
+                side: Side::Ask,
+                maker: true,
+                native_qty_paid,
+                native_qty_received,
+                native_fee_or_rebate,
+                order_id,
+                ..
+            } => (
+                native_qty_paid,
+                native_qty_received - native_fee_or_rebate,
+                order_id,
+            ),
+            _ => continue,
+        };
+        native_pc_traded += pc_qty;
+        let price = (order_id >> 64) as u64;
+        let quantity = coin_qty / coin_lot_size;
+        match levels.last_mut() {
+            Some(last) if last.price == price => {
+                last.quantity += quantity;
+                last.order_count += 1;
+            }
+            _ => levels.push(crate::critbit::L2Level {
+                price,
+                quantity,
+                order_count: 1,
+            }),
+        }
+    }
+
+    let (native_coin_qty, native_pc_qty, native_taker_fee) = match instruction.side {
+        Side::Bid => (
+            proceeds.coin_credit * coin_lot_size,
+            proceeds.native_pc_debit,
+            proceeds.native_pc_debit - native_pc_traded,
+        ),
+        Side::Ask => (
+            proceeds.coin_debit * coin_lot_size,
+            proceeds.native_pc_credit,
+            native_pc_traded - proceeds.native_pc_credit,
+        ),
+    };
+    Ok(SendTakeQuote {
+        native_coin_qty,
+        native_pc_qty,
+        native_taker_fee,
+        levels,
+    })
+}
+
 impl MarketState {
     #[inline]
     pub fn load<'a>(
@@ -438,8 +901,12 @@ impl MarketState {
 
     #[inline]
     pub fn check_flags(&self, allow_disabled: bool) -> DexResult {
-        let flags = BitFlags::from_bits(self.account_flags)
+        let mut flags = BitFlags::from_bits(self.account_flags)
             .map_err(|_| DexErrorCode::InvalidMarketFlags)?;
+        // Cancel-only markets load as usual; placing orders is refused by
+        // check_enabled. Triggers only records that the market has a trigger
+        // book.
+        flags.remove(AccountFlag::CancelOnly | AccountFlag::Triggers);
         let required_flags = AccountFlag::Initialized | AccountFlag::Market;
         if allow_disabled {
             let disabled_flags = required_flags | AccountFlag::Disabled;
@@ -460,7 +927,8 @@ impl MarketState {
         let (header, buf) = strip_header::<OrderBookStateHeader, u8>(bids, false)?;
         let flags = BitFlags::from_bits(header.account_flags).unwrap();
         check_assert_eq!(&flags, &(AccountFlag::Initialized | AccountFlag::Bids))?;
-        Ok(RefMut::map(buf, Slab::new))
+        RefMut::filter_map(buf, |bytes| Slab::new(bytes).ok())
+            .map_err(|_| DexErrorCode::SlabTooSmall.into())
     }
 
     pub fn load_asks_mut<'a>(&self, asks: &'a AccountInfo) -> DexResult<RefMut<'a, Slab>> {
@@ -469,7 +937,8 @@ impl MarketState {
         let (header, buf) = strip_header::<OrderBookStateHeader, u8>(asks, false)?;
         let flags = BitFlags::from_bits(header.account_flags).unwrap();
         check_assert_eq!(&flags, &(AccountFlag::Initialized | AccountFlag::Asks))?;
-        Ok(RefMut::map(buf, Slab::new))
+        RefMut::filter_map(buf, |bytes| Slab::new(bytes).ok())
+            .map_err(|_| DexErrorCode::SlabTooSmall.into())
     }
 
     fn load_request_queue_mut<'a>(&self, queue: &'a AccountInfo) -> DexResult<RequestQueue<'a>> {
@@ -485,16 +954,74 @@ impl MarketState {
         Ok(Queue { header, buf })
     }
 
-    fn load_event_queue_mut<'a>(&self, queue: &'a AccountInfo) -> DexResult<EventQueue<'a>> {
+    fn load_trigger_book_mut<'a>(
+        &self,
+        book: &'a AccountInfo,
+        program_id: &Pubkey,
+    ) -> DexResult<TriggerBook<'a>> {
+        check_assert_eq!(book.owner, program_id)
+            .map_err(|_| DexErrorCode::WrongTriggerBookAccount)?;
+        check_assert!(book.data_len() >= 12 + size_of::<TriggerBookHeader>())?;
+
+        let (header, orders) = strip_header::<TriggerBookHeader, TriggerOrder>(book, false)?;
+        let flags = BitFlags::from_bits(header.account_flags)
+            .map_err(|_| DexErrorCode::WrongTriggerBookAccount)?;
+        if flags != AccountFlag::Initialized | AccountFlag::Triggers
+            || identity(header.market) != identity(self.own_address)
+        {
+            return Err(DexErrorCode::WrongTriggerBookAccount.into());
+        }
+        Ok(TriggerBook { orders })
+    }
+
+    // A market has at most one trigger book, since ConsumeEvents has to arm
+    // its stops; the market's Triggers flag records that it exists. Its
+    // capacity is fixed so that arming stays cheap enough for ConsumeEvents.
+    fn init_trigger_book(
+        &mut self,
+        book: &AccountInfo,
+        program_id: &Pubkey,
+        rent: Rent,
+    ) -> DexResult {
+        check_assert_eq!(book.owner, program_id)
+            .map_err(|_| DexErrorCode::WrongTriggerBookAccount)?;
+        if self.has_trigger_book() {
+            return Err(DexErrorCode::WrongTriggerBookAccount.into());
+        }
+        if book.data_len() != TriggerBook::DATA_LEN + 12 {
+            return Err(DexErrorCode::WrongTriggerBookAccount.into());
+        }
+        if !rent.is_exempt(book.lamports(), book.data_len()) {
+            return Err(DexErrorCode::OrdersNotRentExempt)?;
+        }
+
+        let (mut header, _) = strip_header::<TriggerBookHeader, TriggerOrder>(book, true)?;
+        if header.account_flags != 0 {
+            return Err(DexErrorCode::WrongTriggerBookAccount.into());
+        }
+        header.account_flags = (AccountFlag::Initialized | AccountFlag::Triggers).bits();
+        header.market = self.own_address;
+        self.account_flags |= AccountFlag::Triggers as u64;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn has_trigger_book(&self) -> bool {
+        BitFlags::<AccountFlag>::from_bits_truncate(self.account_flags)
+            .contains(AccountFlag::Triggers)
+    }
+
+    pub(crate) fn load_event_queue_mut<'a>(
+        &self,
+        queue: &'a AccountInfo,
+    ) -> DexResult<EventQueue<'a>> {
         check_assert_eq!(&queue.key.to_aligned_bytes(), &identity(self.event_q))
             .map_err(|_| DexErrorCode::WrongEventQueueAccount)?;
         let (header, buf) = strip_header::<EventQueueHeader, Event>(queue, false)?;
 
         let flags = BitFlags::from_bits(header.account_flags).unwrap();
-        check_assert_eq!(
-            &flags,
-            &(AccountFlag::Initialized | AccountFlag::EventQueue)
-        )?;
+        let event_queue = AccountFlag::Initialized | AccountFlag::EventQueue;
+        check_assert!(flags == event_queue || flags == event_queue | AccountFlag::FillDetails)?;
         Ok(Queue { header, buf })
     }
 
@@ -531,48 +1058,14 @@ impl MarketState {
         Ok(())
     }
 
-    #[inline]
-    fn load_fee_tier(
-        &self,
-        expected_owner: &[u64; 4],
-        srm_or_msrm_account: Option<account_parser::TokenAccount>,
-    ) -> DexResult<FeeTier> {
-        let market_addr = self.pubkey();
-        let srm_or_msrm_account = match srm_or_msrm_account {
-            Some(a) => a,
-            None => return Ok(FeeTier::from_srm_and_msrm_balances(&market_addr, 0, 0)),
-        };
-        let data = srm_or_msrm_account.inner().try_borrow_data()?;
-
-        let mut aligned_data: [u64; 9] = Zeroable::zeroed();
-        bytes_of_mut(&mut aligned_data).copy_from_slice(&data[..72]);
-        let (mint, owner, &[balance]) = array_refs![&aligned_data, 4, 4, 1];
-
-        check_assert_eq!(owner, expected_owner)?;
-        if mint == &srm_token::ID.to_aligned_bytes() {
-            return Ok(FeeTier::from_srm_and_msrm_balances(
-                &market_addr,
-                balance,
-                0,
-            ));
-        }
-
-        if mint == &msrm_token::ID.to_aligned_bytes() {
-            return Ok(FeeTier::from_srm_and_msrm_balances(
-                &market_addr,
-                0,
-                balance,
-            ));
-        }
-
-        Ok(FeeTier::from_srm_and_msrm_balances(&market_addr, 0, 0))
-    }
-
     fn check_enabled(&self) -> DexResult {
         let flags = BitFlags::from_bits(self.account_flags).unwrap();
         if flags.contains(AccountFlag::Disabled) {
             return Err(DexErrorCode::MarketIsDisabled.into());
         }
+        if flags.contains(AccountFlag::CancelOnly) {
+            return Err(DexErrorCode::MarketIsCancelOnly.into());
+        }
         Ok(())
     }
 
@@ -605,6 +1098,22 @@ pub struct OpenOrders {
 unsafe impl Pod for OpenOrders {}
 unsafe impl Zeroable for OpenOrders {}
 
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(OpenOrders {
+    account_flags,
+    market,
+    owner,
+    native_coin_free,
+    native_coin_total,
+    native_pc_free,
+    native_pc_total,
+    free_slot_bits,
+    is_bid_bits,
+    orders,
+    client_order_ids,
+    referrer_rebates_accrued,
+});
+
 impl OpenOrders {
     fn check_flags(&self) -> DexResult {
         let flags = BitFlags::from_bits(self.account_flags)
@@ -664,46 +1173,12 @@ impl OpenOrders {
         assert!(self.native_pc_free <= self.native_pc_total);
     }
 
-    fn slot_is_free(&self, slot: u8) -> bool {
-        let slot_mask = 1u128 << slot;
-        self.free_slot_bits & slot_mask != 0
-    }
-
-    #[inline]
-    fn iter_filled_slots(&self) -> impl Iterator<Item = u8> {
-        struct Iter {
-            bits: u128,
-        }
-        impl Iterator for Iter {
-            type Item = u8;
-            #[inline(always)]
-            fn next(&mut self) -> Option<Self::Item> {
-                if self.bits == 0 {
-                    None
-                } else {
-                    let next = self.bits.trailing_zeros();
-                    let mask = 1u128 << next;
-                    self.bits &= !mask;
-                    Some(next as u8)
-                }
-            }
-        }
-        Iter {
-            bits: !self.free_slot_bits,
+    /// Only covers the first 128 slots; `OpenOrdersAccount::slot_side` also
+    /// covers the extra slots of an `OpenOrdersV2`.
+    pub fn slot_side(&self, slot: u16) -> Option<Side> {
+        if slot >= 128 {
+            return None;
         }
-    }
-
-    #[inline]
-    fn orders_with_client_ids(&self) -> impl Iterator<Item = (NonZeroU64, u128, Side)> + '_ {
-        self.iter_filled_slots().filter_map(move |slot| {
-            let client_order_id = NonZeroU64::new(self.client_order_ids[slot as usize])?;
-            let order_id = self.orders[slot as usize];
-            let side = self.slot_side(slot).unwrap();
-            Some((client_order_id, order_id, side))
-        })
-    }
-
-    pub fn slot_side(&self, slot: u8) -> Option<Side> {
         let slot_mask = 1u128 << slot;
         if self.free_slot_bits & slot_mask != 0 {
             None
@@ -713,61 +1188,291 @@ impl OpenOrders {
             Some(Side::Ask)
         }
     }
+}
 
-    pub fn remove_order(&mut self, slot: u8) -> DexResult {
-        check_assert!(slot < 128)?;
-        check_assert!(!self.slot_is_free(slot))?;
-
-        let slot_mask = 1u128 << slot;
-        self.orders[slot as usize] = 0;
-        self.client_order_ids[slot as usize] = 0;
-        self.free_slot_bits |= slot_mask;
-        self.is_bid_bits &= !slot_mask;
-
-        Ok(())
-    }
-
-    fn add_order(&mut self, id: u128, side: Side) -> DexResult<u8> {
-        if self.free_slot_bits == 0 {
-            Err(DexErrorCode::TooManyOpenOrders)?;
-        }
-        let slot = self.free_slot_bits.trailing_zeros();
-        check_assert!(self.slot_is_free(slot as u8))?;
-        let slot_mask = 1u128 << slot;
-        self.free_slot_bits &= !slot_mask;
-        match side {
-            Side::Bid => {
-                self.is_bid_bits |= slot_mask;
-            }
-            Side::Ask => {
-                self.is_bid_bits &= !slot_mask;
-            }
-        };
-        self.orders[slot as usize] = id;
-        Ok(slot as u8)
+/// An OpenOrders account with room for more than 128 orders. The slots past
+/// the first 128 follow this header as `OpenOrdersSlot`s; how many is decided
+/// by the account's size when it is initialized. Their free and bid bits are
+/// kept 128 slots to a word, with word `i` covering slots `128 * (i + 1)` on.
+#[repr(packed)]
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "fuzz", derive(Debug))]
+pub struct OpenOrdersV2 {
+    pub inner: OpenOrders,
+    pub slot_capacity: u64,
+    pub extra_free_slot_bits: [u128; OpenOrdersV2::EXTRA_SLOT_WORDS],
+    pub extra_is_bid_bits: [u128; OpenOrdersV2::EXTRA_SLOT_WORDS],
+}
+unsafe impl Pod for OpenOrdersV2 {}
+unsafe impl Zeroable for OpenOrdersV2 {}
+
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(OpenOrdersV2 {
+    inner,
+    slot_capacity,
+    extra_free_slot_bits,
+    extra_is_bid_bits,
+});
+
+impl OpenOrdersV2 {
+    pub const MAX_SLOTS: usize = 1024;
+    const EXTRA_SLOT_WORDS: usize = (Self::MAX_SLOTS - 128) / 128;
+
+    /// The unpadded account size needed for `slot_capacity` slots.
+    pub fn data_len(slot_capacity: usize) -> usize {
+        let extra_slots = slot_capacity.saturating_sub(128);
+        size_of::<OpenOrdersV2>() + extra_slots * size_of::<OpenOrdersSlot>()
     }
 }
 
-pub trait QueueHeader: Pod {
-    type Item: Pod + Copy;
+#[repr(packed)]
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "fuzz", derive(Debug))]
+pub struct OpenOrdersSlot {
+    pub order_id: u128,
+    pub client_order_id: u64,
+}
+unsafe impl Pod for OpenOrdersSlot {}
+unsafe impl Zeroable for OpenOrdersSlot {}
+
+// Versioned frontend for OpenOrders accounts. Both layouts keep the balances
+// and the first 128 slots in `OpenOrders`.
+pub enum OpenOrdersAccount<'a> {
+    V1(RefMut<'a, OpenOrders>),
+    V2(RefMut<'a, OpenOrdersV2>, RefMut<'a, [OpenOrdersSlot]>),
+}
 
-    fn head(&self) -> u64;
-    fn set_head(&mut self, value: u64);
-    fn count(&self) -> u64;
-    fn set_count(&mut self, value: u64);
+impl<'a> Deref for OpenOrdersAccount<'a> {
+    type Target = OpenOrders;
 
-    fn incr_event_id(&mut self);
-    fn decr_event_id(&mut self, n: u64);
+    fn deref(&self) -> &Self::Target {
+        match self {
+            OpenOrdersAccount::V1(v1) => v1.deref(),
+            OpenOrdersAccount::V2(v2, _) => &v2.inner,
+        }
+    }
 }
 
-pub struct Queue<'a, H: QueueHeader> {
-    header: RefMut<'a, H>,
-    buf: RefMut<'a, [H::Item]>,
+impl<'a> DerefMut for OpenOrdersAccount<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            OpenOrdersAccount::V1(v1) => v1.deref_mut(),
+            OpenOrdersAccount::V2(v2, _) => &mut v2.inner,
+        }
+    }
 }
 
-impl<'a, H: QueueHeader> Queue<'a, H> {
-    pub fn new(header: RefMut<'a, H>, buf: RefMut<'a, [H::Item]>) -> Self {
-        Self { header, buf }
+impl<'a> OpenOrdersAccount<'a> {
+    pub(crate) fn new(data: RefMut<'a, [u8]>) -> DexResult<Self> {
+        if data.len() == size_of::<OpenOrders>() {
+            return Ok(OpenOrdersAccount::V1(RefMut::map(data, |data| {
+                from_bytes_mut(data)
+            })));
+        }
+        let extra_len = data
+            .len()
+            .checked_sub(size_of::<OpenOrdersV2>())
+            .ok_or(DexErrorCode::WrongOrdersAccount)?;
+        if extra_len % size_of::<OpenOrdersSlot>() != 0
+            || 128 + extra_len / size_of::<OpenOrdersSlot>() > OpenOrdersV2::MAX_SLOTS
+        {
+            return Err(DexErrorCode::WrongOrdersAccount.into());
+        }
+        let (header, slots) = RefMut::map_split(data, |data| {
+            let (header, slots) = data.split_at_mut(size_of::<OpenOrdersV2>());
+            (from_bytes_mut(header), cast_slice_mut(slots))
+        });
+        Ok(OpenOrdersAccount::V2(header, slots))
+    }
+
+    fn init(&mut self, market: &[u64; 4], owner: &[u64; 4]) -> DexResult<()> {
+        self.deref_mut().init(market, owner)?;
+        if let OpenOrdersAccount::V2(header, slots) = self {
+            header.slot_capacity = (128 + slots.len()) as u64;
+            header.extra_free_slot_bits = [std::u128::MAX; OpenOrdersV2::EXTRA_SLOT_WORDS];
+            header.extra_is_bid_bits = [0; OpenOrdersV2::EXTRA_SLOT_WORDS];
+        }
+        Ok(())
+    }
+
+    fn check_flags(&self) -> DexResult {
+        self.deref().check_flags()?;
+        if let OpenOrdersAccount::V2(header, slots) = self {
+            check_assert_eq!(identity(header.slot_capacity), (128 + slots.len()) as u64)
+                .map_err(|_| DexErrorCode::WrongOrdersAccount)?;
+        }
+        Ok(())
+    }
+
+    pub fn slot_capacity(&self) -> usize {
+        match self {
+            OpenOrdersAccount::V1(_) => 128,
+            OpenOrdersAccount::V2(_, slots) => 128 + slots.len(),
+        }
+    }
+
+    // The free and bid bitmaps of the 128 slots that `slot` belongs to.
+    fn slot_bits(&self, slot: u16) -> (u128, u128) {
+        match self {
+            OpenOrdersAccount::V2(header, _) if slot >= 128 => {
+                let word = slot as usize / 128 - 1;
+                (
+                    header.extra_free_slot_bits[word],
+                    header.extra_is_bid_bits[word],
+                )
+            }
+            _ => (self.free_slot_bits, self.is_bid_bits),
+        }
+    }
+
+    fn set_slot_bits(&mut self, slot: u16, free_slot_bits: u128, is_bid_bits: u128) {
+        match self {
+            OpenOrdersAccount::V2(header, _) if slot >= 128 => {
+                let word = slot as usize / 128 - 1;
+                header.extra_free_slot_bits[word] = free_slot_bits;
+                header.extra_is_bid_bits[word] = is_bid_bits;
+            }
+            _ => {
+                self.free_slot_bits = free_slot_bits;
+                self.is_bid_bits = is_bid_bits;
+            }
+        }
+    }
+
+    fn set_slot(&mut self, slot: u16, order_id: u128, client_order_id: u64) {
+        match self {
+            OpenOrdersAccount::V2(_, slots) if slot >= 128 => {
+                slots[slot as usize - 128] = OpenOrdersSlot {
+                    order_id,
+                    client_order_id,
+                };
+            }
+            _ => {
+                self.orders[slot as usize] = order_id;
+                self.client_order_ids[slot as usize] = client_order_id;
+            }
+        }
+    }
+
+    pub fn order_id(&self, slot: u16) -> u128 {
+        match self {
+            OpenOrdersAccount::V2(_, slots) if slot >= 128 => slots[slot as usize - 128].order_id,
+            _ => self.orders[slot as usize],
+        }
+    }
+
+    pub fn client_order_id(&self, slot: u16) -> u64 {
+        match self {
+            OpenOrdersAccount::V2(_, slots) if slot >= 128 => {
+                slots[slot as usize - 128].client_order_id
+            }
+            _ => self.client_order_ids[slot as usize],
+        }
+    }
+
+    fn set_client_order_id(&mut self, slot: u16, client_order_id: u64) {
+        self.set_slot(slot, self.order_id(slot), client_order_id);
+    }
+
+    #[inline]
+    fn iter_filled_slots(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..self.slot_capacity())
+            .map(|slot| slot as u16)
+            .filter(move |&slot| self.slot_side(slot).is_some())
+    }
+
+    #[inline]
+    fn orders_with_client_ids(&self) -> impl Iterator<Item = (NonZeroU64, u128, Side)> + '_ {
+        self.iter_filled_slots().filter_map(move |slot| {
+            let client_order_id = NonZeroU64::new(self.client_order_id(slot))?;
+            let order_id = self.order_id(slot);
+            let side = self.slot_side(slot).unwrap();
+            Some((client_order_id, order_id, side))
+        })
+    }
+
+    pub fn has_open_orders(&self) -> bool {
+        self.iter_filled_slots().next().is_some()
+    }
+
+    pub fn slot_side(&self, slot: u16) -> Option<Side> {
+        if slot as usize >= self.slot_capacity() {
+            return None;
+        }
+        let (free_slot_bits, is_bid_bits) = self.slot_bits(slot);
+        let slot_mask = 1u128 << (slot % 128);
+        if free_slot_bits & slot_mask != 0 {
+            None
+        } else if is_bid_bits & slot_mask != 0 {
+            Some(Side::Bid)
+        } else {
+            Some(Side::Ask)
+        }
+    }
+
+    pub fn remove_order(&mut self, slot: u16) -> DexResult {
+        check_assert!(self.slot_side(slot).is_some())?;
+
+        let (free_slot_bits, is_bid_bits) = self.slot_bits(slot);
+        let slot_mask = 1u128 << (slot % 128);
+        self.set_slot(slot, 0, 0);
+        self.set_slot_bits(slot, free_slot_bits | slot_mask, is_bid_bits & !slot_mask);
+
+        Ok(())
+    }
+
+    fn add_order(&mut self, id: u128, side: Side) -> DexResult<u16> {
+        let free_slot_bits = self.free_slot_bits;
+        let slot = match self {
+            _ if free_slot_bits != 0 => free_slot_bits.trailing_zeros() as usize,
+            OpenOrdersAccount::V2(header, _) => {
+                let extra_free_slot_bits = header.extra_free_slot_bits;
+                extra_free_slot_bits
+                    .iter()
+                    .position(|&bits| bits != 0)
+                    .map_or(OpenOrdersV2::MAX_SLOTS, |word| {
+                        128 * (word + 1) + extra_free_slot_bits[word].trailing_zeros() as usize
+                    })
+            }
+            OpenOrdersAccount::V1(_) => 128,
+        };
+        if slot >= self.slot_capacity() {
+            Err(DexErrorCode::TooManyOpenOrders)?;
+        }
+        let slot = slot as u16;
+        let (free_slot_bits, is_bid_bits) = self.slot_bits(slot);
+        let slot_mask = 1u128 << (slot % 128);
+        check_assert!(free_slot_bits & slot_mask != 0)?;
+        let is_bid_bits = match side {
+            Side::Bid => is_bid_bits | slot_mask,
+            Side::Ask => is_bid_bits & !slot_mask,
+        };
+        self.set_slot_bits(slot, free_slot_bits & !slot_mask, is_bid_bits);
+        self.set_slot(slot, id, 0);
+        Ok(slot)
+    }
+}
+
+pub trait QueueHeader: Pod {
+    type Item: Pod + Copy;
+
+    fn head(&self) -> u64;
+    fn set_head(&mut self, value: u64);
+    fn count(&self) -> u64;
+    fn set_count(&mut self, value: u64);
+
+    fn incr_event_id(&mut self);
+    fn decr_event_id(&mut self, n: u64);
+}
+
+pub struct Queue<'a, H: QueueHeader> {
+    header: RefMut<'a, H>,
+    buf: RefMut<'a, [H::Item]>,
+}
+
+impl<'a, H: QueueHeader> Queue<'a, H> {
+    pub fn new(header: RefMut<'a, H>, buf: RefMut<'a, [H::Item]>) -> Self {
+        Self { header, buf }
     }
 
     #[inline]
@@ -879,6 +1584,14 @@ pub struct RequestQueueHeader {
 unsafe impl Zeroable for RequestQueueHeader {}
 unsafe impl Pod for RequestQueueHeader {}
 
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(RequestQueueHeader {
+    account_flags,
+    head,
+    count,
+    next_seq_num,
+});
+
 impl QueueHeader for RequestQueueHeader {
     type Item = Request;
 
@@ -903,7 +1616,7 @@ impl QueueHeader for RequestQueueHeader {
 pub type RequestQueue<'a> = Queue<'a, RequestQueueHeader>;
 
 impl RequestQueue<'_> {
-    fn gen_order_id(&mut self, limit_price: u64, side: Side) -> u128 {
+    pub(crate) fn gen_order_id(&mut self, limit_price: u64, side: Side) -> u128 {
         let seq_num = self.gen_seq_num();
         let upper = (limit_price as u128) << 64;
         let lower = match side {
@@ -922,15 +1635,18 @@ impl RequestQueue<'_> {
 
 #[derive(Copy, Clone, BitFlags, Debug)]
 #[repr(u8)]
-enum RequestFlag {
+pub(crate) enum RequestFlag {
     NewOrder = 0x01,
     CancelOrder = 0x02,
     Bid = 0x04,
     PostOnly = 0x08,
     ImmediateOrCancel = 0x10,
     DecrementTakeOnSelfTrade = 0x20,
+    FillOrKill = 0x40,
 }
 
+// As in a `LeafNode`, the high nibble of `fee_tier` holds bits 8 and up of
+// the owner slot.
 #[derive(Copy, Clone, Debug)]
 #[repr(packed)]
 pub struct Request {
@@ -938,7 +1654,7 @@ pub struct Request {
     owner_slot: u8,
     fee_tier: u8,
     self_trade_behavior: u8,
-    padding: [u8; 4],
+    expiry_ts: u32,
     max_coin_qty_or_cancel_id: u64,
     native_pc_qty_locked: u64,
     order_id: u128,
@@ -948,12 +1664,26 @@ pub struct Request {
 unsafe impl Zeroable for Request {}
 unsafe impl Pod for Request {}
 
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(Request {
+    request_flags,
+    owner_slot,
+    fee_tier,
+    self_trade_behavior,
+    expiry_ts,
+    max_coin_qty_or_cancel_id,
+    native_pc_qty_locked,
+    order_id,
+    owner,
+    client_order_id,
+});
+
 #[derive(Debug)]
 pub enum RequestView {
     NewOrder {
         side: Side,
         order_type: OrderType,
-        owner_slot: u8,
+        owner_slot: u16,
         fee_tier: FeeTier,
         order_id: u128,
         max_coin_qty: NonZeroU64,
@@ -961,12 +1691,13 @@ pub enum RequestView {
         owner: [u64; 4],
         client_order_id: Option<NonZeroU64>,
         self_trade_behavior: SelfTradeBehavior,
+        expiry_ts: u32,
     },
     CancelOrder {
         side: Side,
         order_id: u128,
         cancel_id: u64,
-        expected_owner_slot: u8,
+        expected_owner_slot: u16,
         expected_owner: [u64; 4],
         client_order_id: Option<NonZeroU64>,
     },
@@ -987,23 +1718,28 @@ impl Request {
                 native_pc_qty_locked,
                 client_order_id,
                 self_trade_behavior,
+                expiry_ts,
             } => {
                 let mut flags = BitFlags::from_flag(RequestFlag::NewOrder);
                 if side == Side::Bid {
                     flags.insert(RequestFlag::Bid);
                 }
                 match order_type {
-                    OrderType::PostOnly => flags |= RequestFlag::PostOnly,
+                    OrderType::PostOnly | OrderType::PostOnlySlide => {
+                        flags |= RequestFlag::PostOnly
+                    }
                     OrderType::ImmediateOrCancel => flags |= RequestFlag::ImmediateOrCancel,
+                    OrderType::FillOrKill => flags |= RequestFlag::FillOrKill,
                     OrderType::Limit => (),
                 };
 
+                let (owner_slot, fee_tier) = split_owner_slot(owner_slot, fee_tier.into());
                 Request {
                     request_flags: flags.bits(),
                     owner_slot,
-                    fee_tier: fee_tier.into(),
+                    fee_tier,
                     self_trade_behavior: self_trade_behavior.into(),
-                    padding: Zeroable::zeroed(),
+                    expiry_ts,
                     order_id,
                     owner,
                     max_coin_qty_or_cancel_id: max_coin_qty.get(),
@@ -1023,16 +1759,17 @@ impl Request {
                 if side == Side::Bid {
                     flags.insert(RequestFlag::Bid);
                 }
+                let (owner_slot, fee_tier) = split_owner_slot(expected_owner_slot, 0);
                 Request {
                     request_flags: flags.bits(),
                     max_coin_qty_or_cancel_id: cancel_id,
                     order_id,
-                    owner_slot: expected_owner_slot,
-                    fee_tier: 0,
+                    owner_slot,
+                    fee_tier,
                     self_trade_behavior: 0,
                     owner: expected_owner,
                     native_pc_qty_locked: 0,
-                    padding: Zeroable::zeroed(),
+                    expiry_ts: 0,
                     client_order_id: client_order_id.map_or(0, NonZeroU64::get),
                 }
             }
@@ -1050,25 +1787,28 @@ impl Request {
         if flags.contains(RequestFlag::NewOrder) {
             let allowed_flags = {
                 use RequestFlag::*;
-                NewOrder | Bid | PostOnly | ImmediateOrCancel
+                NewOrder | Bid | PostOnly | ImmediateOrCancel | FillOrKill
             };
             check_assert!(allowed_flags.contains(flags))?;
             let post_only = flags.contains(RequestFlag::PostOnly);
             let ioc = flags.contains(RequestFlag::ImmediateOrCancel);
-            let order_type = match (post_only, ioc) {
-                (true, false) => OrderType::PostOnly,
-                (false, true) => OrderType::ImmediateOrCancel,
-                (false, false) => OrderType::Limit,
-                (true, true) => unreachable!(),
+            let fok = flags.contains(RequestFlag::FillOrKill);
+            let order_type = match (post_only, ioc, fok) {
+                (true, false, false) => OrderType::PostOnly,
+                (false, true, false) => OrderType::ImmediateOrCancel,
+                (false, false, true) => OrderType::FillOrKill,
+                (false, false, false) => OrderType::Limit,
+                _ => check_unreachable!()?,
             };
-            let fee_tier = FeeTier::try_from_primitive(self.fee_tier).or(check_unreachable!())?;
+            let fee_tier =
+                FeeTier::try_from_primitive(self.fee_tier & 0xf).or(check_unreachable!())?;
             let self_trade_behavior =
                 SelfTradeBehavior::try_from_primitive(self.self_trade_behavior)
                     .or(check_unreachable!())?;
             Ok(RequestView::NewOrder {
                 side,
                 order_type,
-                owner_slot: self.owner_slot,
+                owner_slot: join_owner_slot(self.owner_slot, self.fee_tier),
                 fee_tier,
                 self_trade_behavior,
                 order_id: self.order_id,
@@ -1076,6 +1816,7 @@ impl Request {
                 max_coin_qty: NonZeroU64::new(self.max_coin_qty_or_cancel_id).unwrap(),
                 native_pc_qty_locked: NonZeroU64::new(self.native_pc_qty_locked),
                 client_order_id: NonZeroU64::new(self.client_order_id),
+                expiry_ts: self.expiry_ts,
             })
         } else {
             check_assert!(flags.contains(RequestFlag::CancelOrder))?;
@@ -1088,7 +1829,7 @@ impl Request {
                 side,
                 cancel_id: self.max_coin_qty_or_cancel_id,
                 order_id: self.order_id,
-                expected_owner_slot: self.owner_slot,
+                expected_owner_slot: join_owner_slot(self.owner_slot, self.fee_tier),
                 expected_owner: self.owner,
                 client_order_id: NonZeroU64::new(self.client_order_id),
             })
@@ -1107,6 +1848,14 @@ pub struct EventQueueHeader {
 unsafe impl Zeroable for EventQueueHeader {}
 unsafe impl Pod for EventQueueHeader {}
 
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(EventQueueHeader {
+    account_flags,
+    head,
+    count,
+    seq_num,
+});
+
 unsafe impl TriviallyTransmutable for EventQueueHeader {}
 unsafe impl TriviallyTransmutable for RequestQueueHeader {}
 
@@ -1135,14 +1884,103 @@ impl QueueHeader for EventQueueHeader {
 
 pub type EventQueue<'a> = Queue<'a, EventQueueHeader>;
 
+impl EventQueue<'_> {
+    #[inline]
+    fn has_fill_details(&self) -> bool {
+        BitFlags::<AccountFlag>::from_bits_truncate(self.header.account_flags)
+            .contains(AccountFlag::FillDetails)
+    }
+
+    // Pushes a maker's Fill, followed by a FillDetail describing the trade on
+    // queues that opted in to fill details.
+    pub(crate) fn push_maker_fill(
+        &mut self,
+        fill: EventView,
+        price: NonZeroU64,
+        taker_order_id: u128,
+        now: i64,
+    ) -> DexResult {
+        let seq_num = self.header.seq_num;
+        let detail = match fill {
+            EventView::Fill {
+                side,
+                maker: true,
+                order_id,
+                owner,
+                owner_slot,
+                ..
+            } => EventView::FillDetail {
+                side,
+                seq_num,
+                timestamp: now.try_into().unwrap_or(0),
+                price: price.get(),
+                order_id,
+                owner,
+                owner_slot,
+                taker_order_id,
+            },
+            _ => check_unreachable!()?,
+        };
+        self.push_back(Event::new(fill))
+            .map_err(|_| DexErrorCode::EventQueueFull)?;
+        if self.has_fill_details() {
+            self.push_back(Event::new(detail))
+                .map_err(|_| DexErrorCode::EventQueueFull)?;
+        }
+        Ok(())
+    }
+
+    // The events pushed since the queue held `start` events, along with
+    // their sequence numbers.
+    fn events_since(&self, start: u64) -> impl Iterator<Item = (u64, &Event)> + '_ {
+        let head = self.header.head();
+        let first_seq_num = self.header.seq_num - self.len();
+        (start..self.len()).map(move |index| {
+            let event = &self.buf[((head + index) as usize) % self.buf.len()];
+            (first_seq_num + index, event)
+        })
+    }
+}
+
 #[derive(Copy, Clone, BitFlags, Debug)]
 #[repr(u8)]
-enum EventFlag {
+pub(crate) enum EventFlag {
     Fill = 0x1,
     Out = 0x2,
     Bid = 0x4,
     Maker = 0x8,
     ReleaseFunds = 0x10,
+    Detail = 0x20,
+}
+
+/// Why an order, or part of one, left the book.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum OutReason {
+    /// Written before Out events recorded a reason.
+    Unknown = 0,
+    Cancelled = 1,
+    Filled = 2,
+    /// The unfilled part of an order that wasn't allowed to rest.
+    ImmediateOrCancel = 3,
+    SelfTrade = 4,
+    Pruned = 5,
+    Expired = 6,
+    /// Booted to make room for a more aggressive order on a full book.
+    Evicted = 7,
+    /// The order rests on the book and only releases funds it doesn't need.
+    Posted = 8,
+    /// The rest of a triggered order that would have filled outside the
+    /// price band.
+    PriceBand = 9,
+    /// A post-only order that would have crossed the book.
+    PostOnly = 10,
+    /// The unfilled part of an order that was smaller than the market's
+    /// minimum order size, so it couldn't rest.
+    BelowMinimum = 11,
+    /// What an amend released when it shrank the order in place. The rest
+    /// stays on the book.
+    Amended = 12,
 }
 
 impl EventFlag {
@@ -1164,6 +2002,8 @@ impl EventFlag {
     }
 }
 
+// As in a `LeafNode`, the high nibble of `fee_tier` holds bits 8 and up of
+// the owner slot, in Out events too.
 #[derive(Copy, Clone, Debug)]
 #[repr(packed)]
 pub struct Event {
@@ -1171,8 +2011,9 @@ pub struct Event {
     owner_slot: u8,
 
     fee_tier: u8,
+    out_reason: u8,
 
-    _padding: [u8; 5],
+    _padding: [u8; 4],
 
     native_qty_released: u64,
     native_qty_paid: u64,
@@ -1185,9 +2026,76 @@ pub struct Event {
 unsafe impl Zeroable for Event {}
 unsafe impl Pod for Event {}
 
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(Event {
+    event_flags,
+    owner_slot,
+    fee_tier,
+    out_reason,
+    _padding,
+    native_qty_released,
+    native_qty_paid,
+    native_fee_or_rebate,
+    order_id,
+    owner,
+    client_order_id,
+});
+
 unsafe impl TriviallyTransmutable for Event {}
 unsafe impl TriviallyTransmutable for Request {}
 
+// How a FillDetail occupies an event queue slot. The maker's slot, order id
+// and owner sit where every other event keeps them, so consumers that look
+// up OpenOrders accounts by `Event::owner` don't need to tell them apart.
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub(crate) struct FillDetail {
+    event_flags: u8,
+    owner_slot: u8,
+    // Where other events keep their fee tier; only the high nibble is used.
+    owner_slot_hi: u8,
+    _padding: u8,
+    timestamp: u32,
+    taker_order_id: u128,
+    price: u64,
+    order_id: u128,
+    owner: [u64; 4],
+    seq_num: u64,
+}
+unsafe impl Zeroable for FillDetail {}
+unsafe impl Pod for FillDetail {}
+
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(FillDetail {
+    event_flags,
+    owner_slot,
+    owner_slot_hi,
+    _padding,
+    timestamp,
+    taker_order_id,
+    price,
+    order_id,
+    owner,
+    seq_num,
+});
+
+impl FillDetail {
+    #[inline]
+    fn as_view(&self) -> EventView {
+        let flags = BitFlags::<EventFlag>::from_bits_truncate(self.event_flags);
+        EventView::FillDetail {
+            side: EventFlag::flags_to_side(flags),
+            seq_num: self.seq_num,
+            timestamp: self.timestamp,
+            price: self.price,
+            order_id: self.order_id,
+            owner: self.owner,
+            owner_slot: join_owner_slot(self.owner_slot, self.owner_slot_hi),
+            taker_order_id: self.taker_order_id,
+        }
+    }
+}
+
 impl Event {
     #[inline(always)]
     pub fn new(view: EventView) -> Self {
@@ -1195,6 +2103,7 @@ impl Event {
             EventView::Fill {
                 side,
                 maker,
+                release_funds,
                 native_qty_paid,
                 native_qty_received,
                 native_fee_or_rebate,
@@ -1209,12 +2118,20 @@ impl Event {
                 } else {
                     0
                 };
-                let event_flags =
-                    (EventFlag::from_side(side) | EventFlag::Fill).bits() | maker_flag;
+                let release_funds_flag = if release_funds {
+                    BitFlags::from_flag(EventFlag::ReleaseFunds).bits()
+                } else {
+                    0
+                };
+                let event_flags = (EventFlag::from_side(side) | EventFlag::Fill).bits()
+                    | maker_flag
+                    | release_funds_flag;
+                let (owner_slot, fee_tier) = split_owner_slot(owner_slot, fee_tier.into());
                 Event {
                     event_flags,
                     owner_slot,
-                    fee_tier: fee_tier.into(),
+                    fee_tier,
+                    out_reason: 0,
 
                     _padding: Zeroable::zeroed(),
 
@@ -1238,6 +2155,7 @@ impl Event {
                 owner,
                 owner_slot,
                 client_order_id,
+                reason,
             } => {
                 let release_funds_flag = if release_funds {
                     BitFlags::from_flag(EventFlag::ReleaseFunds).bits()
@@ -1246,10 +2164,12 @@ impl Event {
                 };
                 let event_flags =
                     (EventFlag::from_side(side) | EventFlag::Out).bits() | release_funds_flag;
+                let (owner_slot, fee_tier) = split_owner_slot(owner_slot, 0);
                 Event {
                     event_flags,
                     owner_slot,
-                    fee_tier: 0,
+                    fee_tier,
+                    out_reason: reason.into(),
 
                     _padding: Zeroable::zeroed(),
 
@@ -1262,24 +2182,66 @@ impl Event {
                     client_order_id: client_order_id.map_or(0, NonZeroU64::get),
                 }
             }
+
+            EventView::FillDetail {
+                side,
+                seq_num,
+                timestamp,
+                price,
+                order_id,
+                owner,
+                owner_slot,
+                taker_order_id,
+            } => {
+                let (owner_slot, owner_slot_hi) = split_owner_slot(owner_slot, 0);
+                cast(FillDetail {
+                    event_flags: (EventFlag::from_side(side) | EventFlag::Detail).bits(),
+                    owner_slot,
+                    owner_slot_hi,
+                    _padding: 0,
+                    timestamp,
+                    taker_order_id,
+                    price,
+                    order_id,
+                    owner,
+                    seq_num,
+                })
+            }
         }
     }
 
+    // FillDetails keep the high bits of the slot in the same byte.
+    #[inline]
+    fn owner_slot(&self) -> u16 {
+        join_owner_slot(self.owner_slot, self.fee_tier)
+    }
+
     #[inline(always)]
     pub fn as_view(&self) -> DexResult<EventView> {
         let flags = BitFlags::from_bits(self.event_flags).unwrap();
         let side = EventFlag::flags_to_side(flags);
         let client_order_id = NonZeroU64::new(self.client_order_id);
+        if flags.contains(EventFlag::Detail) {
+            let allowed_flags = {
+                use EventFlag::*;
+                Detail | Bid
+            };
+            check_assert!(allowed_flags.contains(flags))?;
+
+            let detail: FillDetail = cast(*self);
+            return Ok(detail.as_view());
+        }
         if flags.contains(EventFlag::Fill) {
             let allowed_flags = {
                 use EventFlag::*;
-                Fill | Bid | Maker
+                Fill | Bid | Maker | ReleaseFunds
             };
             check_assert!(allowed_flags.contains(flags))?;
 
             return Ok(EventView::Fill {
                 side,
                 maker: flags.contains(EventFlag::Maker),
+                release_funds: flags.contains(EventFlag::ReleaseFunds),
                 native_qty_paid: self.native_qty_paid,
                 native_qty_received: self.native_qty_released,
                 native_fee_or_rebate: self.native_fee_or_rebate,
@@ -1287,8 +2249,8 @@ impl Event {
                 order_id: self.order_id,
                 owner: self.owner,
 
-                owner_slot: self.owner_slot,
-                fee_tier: self.fee_tier.try_into().or(check_unreachable!())?,
+                owner_slot: self.owner_slot(),
+                fee_tier: (self.fee_tier & 0xf).try_into().or(check_unreachable!())?,
                 client_order_id,
             });
         }
@@ -1306,8 +2268,9 @@ impl Event {
             order_id: self.order_id,
             owner: self.owner,
 
-            owner_slot: self.owner_slot,
+            owner_slot: self.owner_slot(),
             client_order_id,
+            reason: OutReason::try_from_primitive(self.out_reason).or(check_unreachable!())?,
         })
     }
 }
@@ -1317,12 +2280,13 @@ pub enum EventView {
     Fill {
         side: Side,
         maker: bool,
+        release_funds: bool,
         native_qty_paid: u64,
         native_qty_received: u64,
         native_fee_or_rebate: u64,
         order_id: u128,
         owner: [u64; 4],
-        owner_slot: u8,
+        owner_slot: u16,
         fee_tier: FeeTier,
         client_order_id: Option<NonZeroU64>,
     },
@@ -1333,77 +2297,410 @@ pub enum EventView {
         native_qty_still_locked: u64,
         order_id: u128,
         owner: [u64; 4],
-        owner_slot: u8,
+        owner_slot: u16,
         client_order_id: Option<NonZeroU64>,
+        reason: OutReason,
+    },
+    // Follows a maker's Fill on event queues with fill details. `seq_num` is
+    // the Fill's sequence number, `price` is in quote lots per base lot and
+    // `timestamp` is the unix time of the trade.
+    FillDetail {
+        side: Side,
+        seq_num: u64,
+        timestamp: u32,
+        price: u64,
+        order_id: u128,
+        owner: [u64; 4],
+        owner_slot: u16,
+        taker_order_id: u128,
     },
 }
 
 impl EventView {
     fn side(&self) -> Side {
         match self {
-            &EventView::Fill { side, .. } | &EventView::Out { side, .. } => side,
+            &EventView::Fill { side, .. }
+            | &EventView::Out { side, .. }
+            | &EventView::FillDetail { side, .. } => side,
         }
     }
 }
 
 #[derive(Copy, Clone)]
 #[repr(packed)]
-struct OrderBookStateHeader {
-    account_flags: u64, // Initialized, (Bids or Asks)
+pub struct TriggerBookHeader {
+    account_flags: u64, // Initialized, Triggers
+    market: [u64; 4],
 }
-unsafe impl Zeroable for OrderBookStateHeader {}
-unsafe impl Pod for OrderBookStateHeader {}
+unsafe impl Zeroable for TriggerBookHeader {}
+unsafe impl Pod for TriggerBookHeader {}
 
-pub enum State {}
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(TriggerBookHeader {
+    account_flags,
+    market,
+});
 
-fn gen_vault_signer_seeds<'a>(nonce: &'a u64, market: &'a Pubkey) -> [&'a [u8]; 2] {
-    [market.as_ref(), bytes_of(nonce)]
+#[derive(Copy, Clone, BitFlags, Debug)]
+#[repr(u8)]
+pub(crate) enum TriggerFlag {
+    InUse = 0x1,
+    Bid = 0x2,
+    ImmediateOrCancel = 0x4,
+    Armed = 0x8,
 }
 
-#[inline]
-pub fn gen_vault_signer_key(
-    nonce: u64,
-    market: &Pubkey,
-    program_id: &Pubkey,
-) -> Result<Pubkey, ProgramError> {
-    let seeds = gen_vault_signer_seeds(&nonce, market);
-    Ok(Pubkey::create_program_address(&seeds, program_id)?)
+// As in a `LeafNode`, the high nibble of `fee_tier` holds bits 8 and up of
+// the owner slot. `armed_seq_num` and `armed_price` record the fill that
+// armed the order.
+#[derive(Copy, Clone, Debug)]
+#[repr(packed)]
+pub struct TriggerOrder {
+    owner: [u64; 4],
+    order_id: u128,
+    trigger_price: u64,
+    max_coin_qty: u64,
+    native_pc_qty_locked: u64,
+    client_order_id: u64,
+    event_seq_num: u64,
+    armed_seq_num: u64,
+    armed_price: u64,
+    trigger_flags: u8,
+    owner_slot: u8,
+    fee_tier: u8,
+    self_trade_behavior: u8,
+    padding: [u8; 4],
 }
+unsafe impl Zeroable for TriggerOrder {}
+unsafe impl Pod for TriggerOrder {}
+
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(TriggerOrder {
+    owner,
+    order_id,
+    trigger_price,
+    max_coin_qty,
+    native_pc_qty_locked,
+    client_order_id,
+    event_seq_num,
+    armed_seq_num,
+    armed_price,
+    trigger_flags,
+    owner_slot,
+    fee_tier,
+    self_trade_behavior,
+    padding,
+});
+
+impl TriggerOrder {
+    fn new(
+        instruction: &NewTriggerOrderInstruction,
+        order_id: u128,
+        owner: [u64; 4],
+        owner_slot: u16,
+        fee_tier: FeeTier,
+        native_pc_qty_locked: Option<NonZeroU64>,
+        event_seq_num: u64,
+    ) -> DexResult<Self> {
+        let mut flags = BitFlags::from_flag(TriggerFlag::InUse);
+        if instruction.side == Side::Bid {
+            flags.insert(TriggerFlag::Bid);
+        }
+        match instruction.order_type {
+            OrderType::Limit => (),
+            OrderType::ImmediateOrCancel => flags.insert(TriggerFlag::ImmediateOrCancel),
+            OrderType::PostOnly | OrderType::PostOnlySlide | OrderType::FillOrKill => {
+                return Err(DexErrorCode::InvalidTriggerOrder.into())
+            }
+        };
+        let (owner_slot, fee_tier) = split_owner_slot(owner_slot, fee_tier.into());
+        Ok(TriggerOrder {
+            owner,
+            order_id,
+            trigger_price: instruction.trigger_price.get(),
+            max_coin_qty: instruction.max_coin_qty.get(),
+            native_pc_qty_locked: native_pc_qty_locked.map_or(0, NonZeroU64::get),
+            client_order_id: instruction.client_order_id,
+            event_seq_num,
+            armed_seq_num: 0,
+            armed_price: 0,
+            trigger_flags: flags.bits(),
+            owner_slot,
+            fee_tier,
+            self_trade_behavior: instruction.self_trade_behavior.into(),
+            padding: Zeroable::zeroed(),
+        })
+    }
 
-#[cfg(not(any(test, feature = "fuzz")))]
-fn invoke_spl_token(
-    instruction: &solana_program::instruction::Instruction,
-    account_infos: &[AccountInfo],
-    signers_seeds: &[&[&[u8]]],
-) -> solana_program::entrypoint::ProgramResult {
-    solana_program::program::invoke_signed(instruction, account_infos, signers_seeds)
-}
+    #[inline]
+    fn flags(&self) -> BitFlags<TriggerFlag> {
+        BitFlags::from_bits_truncate(self.trigger_flags)
+    }
 
-#[cfg(any(test, feature = "fuzz"))]
-fn invoke_spl_token(
-    instruction: &solana_program::instruction::Instruction,
-    account_infos: &[AccountInfo],
-    _signers_seeds: &[&[&[u8]]],
-) -> solana_program::entrypoint::ProgramResult {
-    assert_eq!(instruction.program_id, spl_token::ID);
-    let account_infos: Vec<AccountInfo> = instruction
-        .accounts
-        .iter()
-        .map(|meta| {
-            account_infos
-                .iter()
-                .find(|info| *info.key == meta.pubkey)
-                .unwrap()
-                .clone()
-        })
-        .collect();
-    spl_token::processor::Processor::process(
-        &instruction.program_id,
-        &account_infos,
-        &instruction.data,
-    )?;
-    Ok(())
-}
+    #[inline]
+    fn owner_slot(&self) -> u16 {
+        join_owner_slot(self.owner_slot, self.fee_tier)
+    }
+
+    #[inline]
+    fn side(&self) -> Side {
+        if self.flags().contains(TriggerFlag::Bid) {
+            Side::Bid
+        } else {
+            Side::Ask
+        }
+    }
+
+    // The order's place in the market's sequence, undoing the inversion
+    // `gen_order_id` applies to bids.
+    #[inline]
+    fn seq_num(&self) -> u64 {
+        let lower = self.order_id as u64;
+        match self.side() {
+            Side::Bid => !lower,
+            Side::Ask => lower,
+        }
+    }
+
+    // Stops armed by earlier fills go first. Among those armed by the same
+    // fill, the stop whose trigger price lies furthest behind the fill's
+    // price was crossed first, and ties go to the stop placed first.
+    #[inline]
+    fn release_key(&self) -> (u64, Reverse<u64>, u64) {
+        let trigger_price = self.trigger_price;
+        let armed_price = self.armed_price;
+        let distance = match self.side() {
+            Side::Bid => armed_price.saturating_sub(trigger_price),
+            Side::Ask => trigger_price.saturating_sub(armed_price),
+        };
+        (self.armed_seq_num, Reverse(distance), self.seq_num())
+    }
+
+    fn as_request_view(&self) -> DexResult<RequestView> {
+        let order_type = if self.flags().contains(TriggerFlag::ImmediateOrCancel) {
+            OrderType::ImmediateOrCancel
+        } else {
+            OrderType::Limit
+        };
+        Ok(RequestView::NewOrder {
+            side: self.side(),
+            order_type,
+            owner_slot: self.owner_slot(),
+            fee_tier: FeeTier::try_from_primitive(self.fee_tier & 0xf).or(check_unreachable!())?,
+            order_id: self.order_id,
+            max_coin_qty: NonZeroU64::new(self.max_coin_qty).ok_or(assertion_error!())?,
+            native_pc_qty_locked: NonZeroU64::new(self.native_pc_qty_locked),
+            owner: self.owner,
+            client_order_id: NonZeroU64::new(self.client_order_id),
+            self_trade_behavior: SelfTradeBehavior::try_from_primitive(self.self_trade_behavior)
+                .or(check_unreachable!())?,
+            expiry_ts: 0,
+        })
+    }
+}
+
+// Matching limit of each triggered order, however much of its limit the
+// instruction that releases it has used up.
+const TRIGGERED_ORDER_MATCH_LIMIT: u16 = 16;
+// Armed stops matched by an order instruction that brings the trigger book.
+const TRIGGERED_ORDERS_PER_ORDER: u16 = 2;
+
+pub struct TriggerBook<'a> {
+    orders: RefMut<'a, [TriggerOrder]>,
+}
+
+impl TriggerBook<'_> {
+    pub const CAPACITY: usize = 64;
+    /// The unpadded size of a trigger book account.
+    pub const DATA_LEN: usize =
+        size_of::<TriggerBookHeader>() + Self::CAPACITY * size_of::<TriggerOrder>();
+
+    fn insert(&mut self, order: TriggerOrder) -> DexResult {
+        let slot = self
+            .orders
+            .iter_mut()
+            .find(|slot| slot.trigger_flags == 0)
+            .ok_or(DexErrorCode::TriggerBookFull)?;
+        *slot = order;
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.orders.iter().all(|slot| slot.trigger_flags == 0)
+    }
+
+    fn remove(&mut self, owner: [u64; 4], order_id: u128) -> DexResult<TriggerOrder> {
+        let slot = self
+            .orders
+            .iter_mut()
+            .find(|slot| {
+                slot.trigger_flags != 0
+                    && identity(slot.owner) == owner
+                    && identity(slot.order_id) == order_id
+            })
+            .ok_or(DexErrorCode::TriggerOrderNotFound)?;
+        let order = *slot;
+        *slot = Zeroable::zeroed();
+        Ok(order)
+    }
+
+    // A buy stop arms once the market trades at or above its trigger price,
+    // a sell stop once it trades at or below. Only fills that happened after
+    // the stop was placed count.
+    fn arm(&mut self, seq_num: u64, event: &Event) {
+        let maker_fill = EventFlag::Fill | EventFlag::Maker;
+        if !BitFlags::from_bits_truncate(event.event_flags).contains(maker_fill) {
+            return;
+        }
+        let price = (event.order_id >> 64) as u64;
+        for order in self.orders.iter_mut() {
+            let mut flags = order.flags();
+            if !flags.contains(TriggerFlag::InUse)
+                || flags.contains(TriggerFlag::Armed)
+                || seq_num < order.event_seq_num
+            {
+                continue;
+            }
+            let trigger_price = order.trigger_price;
+            let triggered = match order.side() {
+                Side::Bid => price >= trigger_price,
+                Side::Ask => price <= trigger_price,
+            };
+            if triggered {
+                flags.insert(TriggerFlag::Armed);
+                order.trigger_flags = flags.bits();
+                order.armed_seq_num = seq_num;
+                order.armed_price = price;
+            }
+        }
+    }
+
+    // Releases armed stops in price/time order rather than slot order.
+    fn pop_armed(&mut self) -> Option<TriggerOrder> {
+        let slot = self
+            .orders
+            .iter_mut()
+            .filter(|slot| slot.flags().contains(TriggerFlag::Armed))
+            .min_by_key(|slot| slot.release_key())?;
+        let order = *slot;
+        *slot = Zeroable::zeroed();
+        Some(order)
+    }
+
+    // Arms every stop crossed by the fills pushed since `events_before` and
+    // matches up to `max_orders` armed stops one at a time, so that a stop's
+    // own fills can arm further stops. Each stop gets its own match limit.
+    // Armed stops left over are kept for MatchOrders or the next order that
+    // brings the trigger book.
+    fn execute(
+        &mut self,
+        order_book_state: &mut OrderBookState,
+        now: i64,
+        req_q: &mut RequestQueue,
+        event_q: &mut EventQueue,
+        mut events_before: u64,
+        max_orders: u16,
+    ) -> DexResult {
+        for _ in 0..max_orders {
+            for (seq_num, event) in event_q.events_since(events_before) {
+                self.arm(seq_num, event);
+            }
+            let order = match self.pop_armed() {
+                None => break,
+                Some(order) => order,
+            };
+            events_before = event_q.len();
+            let mut limit = TRIGGERED_ORDER_MATCH_LIMIT;
+            order_book_state.process_triggered_order(
+                &order.as_request_view()?,
+                now,
+                req_q,
+                event_q,
+                &mut limit,
+            )?;
+        }
+        for (seq_num, event) in event_q.events_since(events_before) {
+            self.arm(seq_num, event);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone)]
+#[repr(packed)]
+pub(crate) struct OrderBookStateHeader {
+    account_flags: u64, // Initialized, (Bids or Asks)
+}
+unsafe impl Zeroable for OrderBookStateHeader {}
+unsafe impl Pod for OrderBookStateHeader {}
+
+#[cfg(any(test, feature = "client"))]
+crate::idl::impl_idl_layout!(OrderBookStateHeader { account_flags });
+
+pub enum State {}
+
+fn gen_vault_signer_seeds<'a>(nonce: &'a u64, market: &'a Pubkey) -> [&'a [u8]; 2] {
+    [market.as_ref(), bytes_of(nonce)]
+}
+
+#[inline]
+pub fn gen_vault_signer_key(
+    nonce: u64,
+    market: &Pubkey,
+    program_id: &Pubkey,
+) -> Result<Pubkey, ProgramError> {
+    let seeds = gen_vault_signer_seeds(&nonce, market);
+    Ok(Pubkey::create_program_address(&seeds, program_id)?)
+}
+
+// Signs for the address `seeds` derive under `program_id`, as
+// `invoke_signed` does for the running program.
+type PdaSigner<'a> = (&'a [&'a [u8]], &'a Pubkey);
+
+#[cfg(not(any(test, feature = "fuzz", feature = "simulator")))]
+fn invoke_spl_token(
+    instruction: &solana_program::instruction::Instruction,
+    account_infos: &[AccountInfo],
+    signer: Option<PdaSigner>,
+) -> solana_program::entrypoint::ProgramResult {
+    match signer {
+        Some((seeds, _)) => {
+            solana_program::program::invoke_signed(instruction, account_infos, &[seeds])
+        }
+        None => solana_program::program::invoke(instruction, account_infos),
+    }
+}
+
+#[cfg(any(test, feature = "fuzz", feature = "simulator"))]
+fn invoke_spl_token(
+    instruction: &solana_program::instruction::Instruction,
+    account_infos: &[AccountInfo],
+    signer: Option<PdaSigner>,
+) -> solana_program::entrypoint::ProgramResult {
+    assert_eq!(instruction.program_id, spl_token::ID);
+    let signer_key = signer
+        .map(|(seeds, program_id)| Pubkey::create_program_address(seeds, program_id))
+        .transpose()?;
+    let account_infos: Vec<AccountInfo> = instruction
+        .accounts
+        .iter()
+        .map(|meta| {
+            let mut info = account_infos
+                .iter()
+                .find(|info| *info.key == meta.pubkey)
+                .unwrap()
+                .clone();
+            info.is_signer |= Some(meta.pubkey) == signer_key;
+            info
+        })
+        .collect();
+    spl_token::processor::Processor::process(
+        &instruction.program_id,
+        &account_infos,
+        &instruction.data,
+    )?;
+    Ok(())
+}
 
 #[cfg(feature = "program")]
 fn send_from_vault<'a, 'b: 'a>(
@@ -1428,11 +2725,121 @@ fn send_from_vault<'a, 'b: 'a>(
         vault_signer.inner().clone(),
         spl_token_program.inner().clone(),
     ];
-    invoke_spl_token(&deposit_instruction, &accounts[..], &[vault_signer_seeds])
-        .map_err(|_| DexErrorCode::TransferFailed)?;
+    invoke_spl_token(
+        &deposit_instruction,
+        &accounts[..],
+        Some((vault_signer_seeds, vault_signer.program_id())),
+    )
+    .map_err(|_| DexErrorCode::TransferFailed)?;
+    Ok(())
+}
+
+#[cfg(any(test, feature = "fuzz", feature = "simulator"))]
+thread_local! {
+    // Dynamic sysvars don't work in unit tests, so they set the clock here.
+    pub(crate) static UNIX_TIMESTAMP: std::cell::Cell<i64> =
+        const { std::cell::Cell::new(1_650_000_000) };
+}
+
+fn current_timestamp() -> DexResult<i64> {
+    #[cfg(any(test, feature = "fuzz", feature = "simulator"))]
+    let now = UNIX_TIMESTAMP.with(|ts| ts.get());
+    #[cfg(not(any(test, feature = "fuzz", feature = "simulator")))]
+    let now = solana_program::clock::Clock::get()?.unix_timestamp;
+    Ok(now)
+}
+
+#[cfg(feature = "program")]
+fn deposit_into_vault<'a, 'b: 'a>(
+    native_amount: u64,
+    source: account_parser::TokenAccount<'a, 'b>,
+    vault: account_parser::TokenAccount<'a, 'b>,
+    authority: account_parser::SignerAccount<'a, 'b>,
+    spl_token_program: account_parser::SplTokenProgram<'a, 'b>,
+) -> DexResult {
+    if native_amount == 0 {
+        return Ok(());
+    }
+    let balance_before = vault.balance()?;
+    let deposit_instruction = spl_token::instruction::transfer(
+        &spl_token::ID,
+        source.inner().key,
+        vault.inner().key,
+        authority.inner().key,
+        &[],
+        native_amount,
+    )
+    .unwrap();
+    invoke_spl_token(
+        &deposit_instruction,
+        &[
+            source.inner().clone(),
+            vault.inner().clone(),
+            authority.inner().clone(),
+            spl_token_program.inner().clone(),
+        ],
+        None,
+    )
+    .map_err(|err| match err {
+        ProgramError::Custom(i) => match TokenError::from_u32(i) {
+            Some(TokenError::InsufficientFunds) => DexErrorCode::InsufficientFunds,
+            _ => DexErrorCode::TransferFailed,
+        },
+        _ => DexErrorCode::TransferFailed,
+    })?;
+    let balance_after = vault.balance()?;
+    let balance_change = balance_after.checked_sub(balance_before);
+    check_assert_eq!(Some(native_amount), balance_change)?;
     Ok(())
 }
 
+// Locks the funds backing a new order in the OpenOrders account, using its
+// free balance first. Returns the amount the payer still has to deposit.
+fn lock_order_funds(
+    open_orders: &mut OpenOrders,
+    market_state: &mut MarketState,
+    payer: account_parser::TokenAccount,
+    side: Side,
+    max_coin_qty: NonZeroU64,
+    max_native_pc_qty_including_fees: NonZeroU64,
+) -> DexResult<u64> {
+    let deposit_amount;
+    match side {
+        Side::Bid => {
+            let lock_qty_native = max_native_pc_qty_including_fees.get();
+            let free_qty_to_lock = lock_qty_native.min(open_orders.native_pc_free);
+            deposit_amount = lock_qty_native - free_qty_to_lock;
+            if payer.balance()? < deposit_amount {
+                return Err(DexErrorCode::InsufficientFunds.into());
+            }
+            open_orders.lock_free_pc(free_qty_to_lock);
+            open_orders.credit_locked_pc(deposit_amount);
+            market_state.pc_deposits_total = market_state
+                .pc_deposits_total
+                .checked_add(deposit_amount)
+                .unwrap();
+        }
+        Side::Ask => {
+            let lock_qty_native = max_coin_qty
+                .get()
+                .checked_mul(market_state.coin_lot_size)
+                .ok_or(DexErrorCode::InsufficientFunds)?;
+            let free_qty_to_lock = lock_qty_native.min(open_orders.native_coin_free);
+            deposit_amount = lock_qty_native - free_qty_to_lock;
+            if payer.balance()? < deposit_amount {
+                return Err(DexErrorCode::InsufficientFunds.into());
+            }
+            open_orders.lock_free_coin(free_qty_to_lock);
+            open_orders.credit_locked_coin(deposit_amount);
+            market_state.coin_deposits_total = market_state
+                .coin_deposits_total
+                .checked_add(deposit_amount)
+                .unwrap();
+        }
+    };
+    Ok(deposit_amount)
+}
+
 #[cfg(feature = "fuzz")]
 pub mod fuzz_account_parser {
     pub use super::account_parser::SignerAccount;
@@ -1521,17 +2928,49 @@ pub(crate) mod account_parser {
         Ok(())
     });
 
-    declare_validated_account_wrapper!(SigningFeeSweeper, |account: &AccountInfo| {
-        check_assert!(account.is_signer)?;
-        check_assert_eq!(account.key, &fee_sweeper::ID)?;
-        Ok(())
-    });
+    declare_validated_account_wrapper!(
+        SigningFeeSweeper,
+        |account: &AccountInfo, market: &Market| {
+            check_assert!(account.is_signer)?;
+            check_assert!(market.is_fee_sweeper(account.key))?;
+            Ok(())
+        },
+        market: &Market
+    );
 
-    declare_validated_account_wrapper!(SigningDisableAuthority, |account: &AccountInfo| {
-        check_assert!(account.is_signer)?;
-        check_assert_eq!(account.key, &disable_authority::ID)?;
-        Ok(())
-    });
+    declare_validated_account_wrapper!(
+        SigningDisableAuthority,
+        |account: &AccountInfo, market: &Market| {
+            check_assert!(account.is_signer)?;
+            check_assert!(market.is_disable_authority(account.key))?;
+            Ok(())
+        },
+        market: &Market
+    );
+
+    declare_validated_account_wrapper!(
+        SigningMarketAdmin,
+        |account: &AccountInfo, market: &Market| {
+            check_assert!(account.is_signer)?;
+            if market.admin() != Some(account.key) {
+                return Err(DexErrorCode::WrongSigner.into());
+            }
+            Ok(())
+        },
+        market: &Market
+    );
+
+    declare_validated_account_wrapper!(
+        SigningAdminOrDisableAuthority,
+        |account: &AccountInfo, market: &Market| {
+            check_assert!(account.is_signer)?;
+            if !market.is_disable_authority(account.key) && market.admin() != Some(account.key) {
+                return Err(DexErrorCode::WrongSigner.into());
+            }
+            Ok(())
+        },
+        market: &Market
+    );
 
     declare_validated_token_account_wrapper!(
         CoinVault,
@@ -1557,16 +2996,39 @@ pub(crate) mod account_parser {
         market: &Market
     );
 
-    declare_validated_account_wrapper!(
-        VaultSigner,
-        |account: &AccountInfo, market: &Market, program_id: &Pubkey| {
+    // Keeps the program id the key was derived under, since transfers out
+    // of the vaults sign with the same seeds.
+    #[derive(Copy, Clone)]
+    pub struct VaultSigner<'a, 'b: 'a> {
+        account: &'a AccountInfo<'b>,
+        program_id: &'a Pubkey,
+    }
+
+    impl<'a, 'b: 'a> VaultSigner<'a, 'b> {
+        pub fn new(
+            account: &'a AccountInfo<'b>,
+            market: &Market,
+            program_id: &'a Pubkey,
+        ) -> DexResult<Self> {
             let vault_signer_key =
                 gen_vault_signer_key(market.vault_signer_nonce, &market.pubkey(), program_id)?;
-            Ok(check_assert_eq!(&vault_signer_key, account.key)?)
-        },
-        market: &Market,
-        program_id: &Pubkey
-    );
+            check_assert_eq!(&vault_signer_key, account.key)?;
+            Ok(VaultSigner {
+                account,
+                program_id,
+            })
+        }
+
+        #[inline(always)]
+        pub fn inner(self) -> &'a AccountInfo<'b> {
+            self.account
+        }
+
+        #[inline(always)]
+        pub fn program_id(self) -> &'a Pubkey {
+            self.program_id
+        }
+    }
 
     impl<'a, 'b: 'a> TokenAccount<'a, 'b> {
         pub fn balance(self) -> DexResult<u64> {
@@ -1597,6 +3059,71 @@ pub(crate) mod account_parser {
         }
     }
 
+    // Order instructions take an optional (M)SRM account followed by an
+    // optional trigger book; only the latter is owned by the dex program.
+    fn parse_optional_accounts<'a, 'b: 'a>(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> DexResult<(
+        Option<TokenAccount<'a, 'b>>,
+        Option<&'a AccountInfo<'b>>,
+        &'a [AccountInfo<'b>],
+    )> {
+        let (srm_or_msrm_account, accounts) = match accounts {
+            [account, rest @ ..] if account.owner != program_id => {
+                (Some(TokenAccount::new(account)?), rest)
+            }
+            _ => (None, accounts),
+        };
+        let (trigger_book, sibling_open_orders) = match accounts {
+            [account, rest @ ..] if is_trigger_book(account, program_id) => (Some(account), rest),
+            _ => (None, accounts),
+        };
+        check_assert!(sibling_open_orders
+            .iter()
+            .all(|account| account.owner == program_id && is_open_orders(account)))?;
+        Ok((srm_or_msrm_account, trigger_book, sibling_open_orders))
+    }
+
+    fn is_open_orders(account: &AccountInfo) -> bool {
+        let data = match account.try_borrow_data() {
+            Ok(data) => data,
+            Err(_) => return false,
+        };
+        let flags = match data.get(5..13) {
+            Some(flags) => u64::from_le_bytes(*array_ref![flags, 0, 8]),
+            None => return false,
+        };
+        flags & (AccountFlag::OpenOrders as u64) != 0
+    }
+
+    fn is_trigger_book(account: &AccountInfo, program_id: &Pubkey) -> bool {
+        if account.owner != program_id {
+            return false;
+        }
+        let data = match account.try_borrow_data() {
+            Ok(data) => data,
+            Err(_) => return false,
+        };
+        let flags = match data.get(5..13) {
+            Some(flags) => u64::from_le_bytes(*array_ref![flags, 0, 8]),
+            None => return false,
+        };
+        flags == (AccountFlag::Initialized | AccountFlag::Triggers).bits()
+    }
+
+    // ConsumeEvents takes the market's trigger book, if it has one, after
+    // its other accounts so that it can arm stops as it consumes fills.
+    fn split_trigger_book<'a, 'b: 'a>(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> (&'a [AccountInfo<'b>], Option<&'a AccountInfo<'b>>) {
+        match accounts {
+            [rest @ .., last] if is_trigger_book(last, program_id) => (rest, Some(last)),
+            _ => (accounts, None),
+        }
+    }
+
     pub struct InitializeMarketArgs<'a, 'b: 'a> {
         pub program_id: &'a Pubkey,
         pub instruction: &'a InitializeMarketInstruction,
@@ -1630,9 +3157,9 @@ pub(crate) mod account_parser {
 
             {
                 // Dynamic sysvars don't work in unit tests.
-                #[cfg(any(test, feature = "fuzz"))]
+                #[cfg(any(test, feature = "fuzz", feature = "simulator"))]
                 let rent = Rent::from_account_info(&unchecked_rent[0])?;
-                #[cfg(not(any(test, feature = "fuzz")))]
+                #[cfg(not(any(test, feature = "fuzz", feature = "simulator")))]
                 let rent = Rent::get()?;
 
                 let end_idx = accounts.len() - remaining_accounts.len() - 1;
@@ -1731,6 +3258,7 @@ pub(crate) mod account_parser {
         pub spl_token_program: SplTokenProgram<'a, 'b>,
         pub fee_tier: FeeTier,
         pub vault_signer: VaultSigner<'a, 'b>,
+        pub trigger_book: Option<TriggerBook<'a>>,
     }
     impl<'a, 'b: 'a> SendTakeArgs<'a, 'b> {
         pub fn with_parsed_args<T>(
@@ -1740,8 +3268,12 @@ pub(crate) mod account_parser {
             f: impl FnOnce(SendTakeArgs) -> DexResult<T>,
         ) -> DexResult<T> {
             const MIN_ACCOUNTS: usize = 12;
-            check_assert!(accounts.len() == MIN_ACCOUNTS || accounts.len() == MIN_ACCOUNTS + 1)?;
-            let (fixed_accounts, fee_discount_account): (
+            check_assert!(
+                accounts.len() == MIN_ACCOUNTS
+                    || accounts.len() == MIN_ACCOUNTS + 1
+                    || accounts.len() == MIN_ACCOUNTS + 2
+            )?;
+            let (fixed_accounts, optional_accounts): (
                 &'a [AccountInfo<'b>; MIN_ACCOUNTS],
                 &'a [AccountInfo<'b>],
             ) = array_refs![accounts, MIN_ACCOUNTS; .. ;];
@@ -1759,13 +3291,13 @@ pub(crate) mod account_parser {
                 ref spl_token_program_acc,
                 ref vault_signer_acc,
             ]: &'a [AccountInfo<'b>; MIN_ACCOUNTS] = fixed_accounts;
-            let srm_or_msrm_account = match fee_discount_account {
-                &[] => None,
-                &[ref account] => Some(TokenAccount::new(account)?),
-                _ => check_unreachable!()?,
-            };
+            let (srm_or_msrm_account, trigger_book_acc, sibling_open_orders) =
+                parse_optional_accounts(program_id, optional_accounts)?;
+            check_assert!(sibling_open_orders.is_empty())?;
 
             let mut market = Market::load(market_acc, program_id, false)?;
+            market.check_enabled()?;
+            market.check_order_size(instruction.limit_price, instruction.max_coin_qty)?;
 
             let signer = SignerAccount::new(signer_acc)?;
             let fee_tier = market
@@ -1784,13 +3316,26 @@ pub(crate) mod account_parser {
 
             let vault_signer = VaultSigner::new(vault_signer_acc, &market, program_id)?;
 
+            let trigger_book = trigger_book_acc
+                .map(|acc| market.load_trigger_book_mut(acc, program_id))
+                .transpose()?;
+
             let mut bids = market.load_bids_mut(bids_acc).or(check_unreachable!())?;
             let mut asks = market.load_asks_mut(asks_acc).or(check_unreachable!())?;
 
+            let fee_schedule = market.fee_schedule();
+            let price_band = market.price_band();
+            let min_base_qty = market.min_base_qty();
+            let (market_state, stats) = market.state_and_stats_mut();
             let order_book_state = OrderBookState {
                 bids: bids.deref_mut(),
                 asks: asks.deref_mut(),
-                market_state: market.deref_mut(),
+                market_state,
+                stats,
+                price_band,
+                min_base_qty,
+                fee_schedule,
+                sibling_open_orders: &[],
             };
 
             let args = SendTakeArgs {
@@ -1806,6 +3351,7 @@ pub(crate) mod account_parser {
                 order_book_state,
                 spl_token_program,
                 vault_signer,
+                trigger_book,
             };
             f(args)
         }
@@ -1813,7 +3359,7 @@ pub(crate) mod account_parser {
 
     pub struct NewOrderV3Args<'a, 'b: 'a> {
         pub instruction: &'a NewOrderInstructionV3,
-        pub open_orders: RefMut<'a, OpenOrders>,
+        pub open_orders: OpenOrdersAccount<'a>,
         pub open_orders_address: [u64; 4],
         pub owner: SignerAccount<'a, 'b>,
         pub req_q: RequestQueue<'a>,
@@ -1824,6 +3370,8 @@ pub(crate) mod account_parser {
         pub pc_vault: PcVault<'a, 'b>,
         pub spl_token_program: SplTokenProgram<'a, 'b>,
         pub fee_tier: FeeTier,
+        pub trigger_book: Option<TriggerBook<'a>>,
+        pub tick_size: u64,
     }
     impl<'a, 'b: 'a> NewOrderV3Args<'a, 'b> {
         pub fn with_parsed_args<T>(
@@ -1833,12 +3381,8 @@ pub(crate) mod account_parser {
             f: impl FnOnce(NewOrderV3Args) -> DexResult<T>,
         ) -> DexResult<T> {
             const MIN_ACCOUNTS: usize = 12;
-            check_assert!(
-                accounts.len() == MIN_ACCOUNTS
-                    || accounts.len() == MIN_ACCOUNTS + 1
-                    || accounts.len() == MIN_ACCOUNTS + 2
-            )?;
-            let (fixed_accounts, fee_discount_account): (
+            check_assert!(accounts.len() >= MIN_ACCOUNTS)?;
+            let (fixed_accounts, optional_accounts): (
                 &'a [AccountInfo<'b>; MIN_ACCOUNTS],
                 &'a [AccountInfo<'b>],
             ) = array_refs![accounts, MIN_ACCOUNTS; .. ;];
@@ -1856,18 +3400,15 @@ pub(crate) mod account_parser {
                 ref spl_token_program_acc,
                 ref rent_sysvar_acc,
             ]: &'a [AccountInfo<'b>; MIN_ACCOUNTS] = fixed_accounts;
-            let srm_or_msrm_account = match fee_discount_account {
-                &[] => None,
-                &[ref account] => Some(TokenAccount::new(account)?),
-                _ => check_unreachable!()?,
-            };
+            let (srm_or_msrm_account, trigger_book_acc, sibling_open_orders_accs) =
+                parse_optional_accounts(program_id, optional_accounts)?;
 
             let mut market = Market::load(market_acc, program_id, false)?;
 
             // Dynamic sysvars don't work in unit tests.
-            #[cfg(any(test, feature = "fuzz"))]
+            #[cfg(any(test, feature = "fuzz", feature = "simulator"))]
             let rent = Rent::from_account_info(rent_sysvar_acc)?;
-            #[cfg(not(any(test, feature = "fuzz")))]
+            #[cfg(not(any(test, feature = "fuzz", feature = "simulator")))]
             let rent = Rent::get()?;
 
             let owner = SignerAccount::new(owner_acc)?;
@@ -1885,11 +3426,17 @@ pub(crate) mod account_parser {
             let coin_vault = CoinVault::from_account(coin_vault_acc, &market)?;
             let pc_vault = PcVault::from_account(pc_vault_acc, &market)?;
             market.check_enabled()?;
+            market.check_order_size(instruction.limit_price, instruction.max_coin_qty)?;
             let spl_token_program = SplTokenProgram::new(spl_token_program_acc)?;
 
             let mut bids = market.load_bids_mut(bids_acc).or(check_unreachable!())?;
             let mut asks = market.load_asks_mut(asks_acc).or(check_unreachable!())?;
 
+            let mut sibling_open_orders = Vec::with_capacity(sibling_open_orders_accs.len());
+            for sibling_acc in sibling_open_orders_accs {
+                market.load_orders_mut(sibling_acc, Some(owner.inner()), program_id, None, None)?;
+                sibling_open_orders.push(sibling_acc.key.to_aligned_bytes());
+            }
             let open_orders = market.load_orders_mut(
                 open_orders_acc,
                 Some(owner.inner()),
@@ -1898,10 +3445,23 @@ pub(crate) mod account_parser {
                 None, // To use an open orders authority, explicitly use the
                       // InitOpenOrders instruction.
             )?;
+            let trigger_book = trigger_book_acc
+                .map(|acc| market.load_trigger_book_mut(acc, program_id))
+                .transpose()?;
+            let fee_schedule = market.fee_schedule();
+            let tick_size = market.tick_size();
+            let price_band = market.price_band();
+            let min_base_qty = market.min_base_qty();
+            let (market_state, stats) = market.state_and_stats_mut();
             let order_book_state = OrderBookState {
                 bids: bids.deref_mut(),
                 asks: asks.deref_mut(),
-                market_state: market.deref_mut(),
+                market_state,
+                stats,
+                price_band,
+                min_base_qty,
+                fee_schedule,
+                sibling_open_orders: &sibling_open_orders,
             };
 
             let args = NewOrderV3Args {
@@ -1917,32 +3477,182 @@ pub(crate) mod account_parser {
                 pc_vault,
                 spl_token_program,
                 fee_tier,
+                trigger_book,
+                tick_size,
             };
             f(args)
         }
     }
 
-    pub struct ReplaceOrdersByClientIdsArgs<'a, 'b: 'a> {
-        pub program_id: &'a Pubkey,
-        pub cancel_accounts: &'a [AccountInfo<'b>],
-        pub client_order_ids: [u64; 8],
-        pub instructions: &'a Vec<NewOrderInstructionV3>,
-        pub accounts: &'a [AccountInfo<'b>],
+    pub struct NewTriggerOrderArgs<'a, 'b: 'a> {
+        pub instruction: &'a NewTriggerOrderInstruction,
+        pub market: Market<'a>,
+        pub open_orders: OpenOrdersAccount<'a>,
+        pub open_orders_address: [u64; 4],
+        pub owner: SignerAccount<'a, 'b>,
+        pub req_q: RequestQueue<'a>,
+        pub event_seq_num: u64,
+        pub trigger_book: TriggerBook<'a>,
+        pub payer: TokenAccount<'a, 'b>,
+        pub coin_vault: CoinVault<'a, 'b>,
+        pub pc_vault: PcVault<'a, 'b>,
+        pub spl_token_program: SplTokenProgram<'a, 'b>,
+        pub fee_tier: FeeTier,
     }
-    impl<'a, 'b: 'a> ReplaceOrdersByClientIdsArgs<'a, 'b> {
+    impl<'a, 'b: 'a> NewTriggerOrderArgs<'a, 'b> {
         pub fn with_parsed_args<T>(
             program_id: &'a Pubkey,
+            instruction: &'a NewTriggerOrderInstruction,
             accounts: &'a [AccountInfo<'b>],
-            instructions: &'a Vec<NewOrderInstructionV3>,
-            f: impl FnOnce(ReplaceOrdersByClientIdsArgs) -> DexResult<T>,
+            f: impl FnOnce(NewTriggerOrderArgs) -> DexResult<T>,
         ) -> DexResult<T> {
-            // Account indices for market, bids, asks, OpenOrders, owner, event_q
-            let cancel_accounts = [0, 4, 5, 1, 7, 3]
-                .iter()
-                .map(|i| accounts[*i].clone())
-                .collect::<Vec<_>>();
-            let mut client_order_ids = [0; 8];
-            for (instruction, client_order_id) in
+            const MIN_ACCOUNTS: usize = 11;
+            check_assert!(accounts.len() == MIN_ACCOUNTS || accounts.len() == MIN_ACCOUNTS + 1)?;
+            let (fixed_accounts, fee_discount_account): (
+                &'a [AccountInfo<'b>; MIN_ACCOUNTS],
+                &'a [AccountInfo<'b>],
+            ) = array_refs![accounts, MIN_ACCOUNTS; .. ;];
+            let &[
+                ref market_acc,
+                ref open_orders_acc,
+                ref req_q_acc,
+                ref event_q_acc,
+                ref trigger_book_acc,
+                ref payer_acc,
+                ref owner_acc,
+                ref coin_vault_acc,
+                ref pc_vault_acc,
+                ref spl_token_program_acc,
+                ref rent_sysvar_acc,
+            ]: &'a [AccountInfo<'b>; MIN_ACCOUNTS] = fixed_accounts;
+            let srm_or_msrm_account = match fee_discount_account {
+                &[] => None,
+                &[ref account] => Some(TokenAccount::new(account)?),
+                _ => check_unreachable!()?,
+            };
+
+            let market = Market::load(market_acc, program_id, false)?;
+            market.check_enabled()?;
+            market.check_order_size(instruction.limit_price, instruction.max_coin_qty)?;
+
+            // Dynamic sysvars don't work in unit tests.
+            #[cfg(any(test, feature = "fuzz", feature = "simulator"))]
+            let rent = Rent::from_account_info(rent_sysvar_acc)?;
+            #[cfg(not(any(test, feature = "fuzz", feature = "simulator")))]
+            let rent = Rent::get()?;
+
+            let owner = SignerAccount::new(owner_acc)?;
+            let fee_tier =
+                market.load_fee_tier(&owner.inner().key.to_aligned_bytes(), srm_or_msrm_account)?;
+            let open_orders_address = open_orders_acc.key.to_aligned_bytes();
+            let req_q = market.load_request_queue_mut(req_q_acc)?;
+            // Only fills after this one can trigger the order.
+            let event_seq_num = market.load_event_queue_mut(event_q_acc)?.header.seq_num;
+            let trigger_book = market.load_trigger_book_mut(trigger_book_acc, program_id)?;
+
+            let payer = TokenAccount::new(payer_acc)?;
+            match instruction.side {
+                Side::Bid => market.check_pc_payer(payer).or(check_unreachable!())?,
+                Side::Ask => market.check_coin_payer(payer).or(check_unreachable!())?,
+            };
+            let coin_vault = CoinVault::from_account(coin_vault_acc, &market)?;
+            let pc_vault = PcVault::from_account(pc_vault_acc, &market)?;
+            let spl_token_program = SplTokenProgram::new(spl_token_program_acc)?;
+
+            let open_orders = market.load_orders_mut(
+                open_orders_acc,
+                Some(owner.inner()),
+                program_id,
+                Some(rent),
+                None,
+            )?;
+
+            let args = NewTriggerOrderArgs {
+                instruction,
+                market,
+                open_orders,
+                open_orders_address,
+                owner,
+                req_q,
+                event_seq_num,
+                trigger_book,
+                payer,
+                coin_vault,
+                pc_vault,
+                spl_token_program,
+                fee_tier,
+            };
+            f(args)
+        }
+    }
+
+    pub struct CancelTriggerOrderArgs<'a> {
+        pub order_id: u128,
+        pub market: Market<'a>,
+        pub trigger_book: TriggerBook<'a>,
+        pub open_orders: OpenOrdersAccount<'a>,
+        pub open_orders_address: [u64; 4],
+    }
+    impl<'a> CancelTriggerOrderArgs<'a> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo],
+            order_id: u128,
+            f: impl FnOnce(CancelTriggerOrderArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert_eq!(accounts.len(), 4)?;
+            #[rustfmt::skip]
+            let &[
+                ref market_acc,
+                ref trigger_book_acc,
+                ref open_orders_acc,
+                ref open_orders_signer_acc,
+            ] = array_ref![accounts, 0, 4];
+
+            let market = Market::load(market_acc, program_id, true)?;
+            let trigger_book = market.load_trigger_book_mut(trigger_book_acc, program_id)?;
+            let open_orders_signer = SignerAccount::new(open_orders_signer_acc)?;
+            let open_orders = market.load_orders_mut(
+                open_orders_acc,
+                Some(open_orders_signer.inner()),
+                program_id,
+                None,
+                None,
+            )?;
+            let open_orders_address = open_orders_acc.key.to_aligned_bytes();
+
+            let args = CancelTriggerOrderArgs {
+                order_id,
+                market,
+                trigger_book,
+                open_orders,
+                open_orders_address,
+            };
+            f(args)
+        }
+    }
+
+    pub struct ReplaceOrdersByClientIdsArgs<'a, 'b: 'a> {
+        pub program_id: &'a Pubkey,
+        pub cancel_accounts: &'a [AccountInfo<'b>],
+        pub client_order_ids: [u64; 8],
+        pub instructions: &'a Vec<NewOrderInstructionV3>,
+        pub accounts: &'a [AccountInfo<'b>],
+    }
+    impl<'a, 'b: 'a> ReplaceOrdersByClientIdsArgs<'a, 'b> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo<'b>],
+            instructions: &'a Vec<NewOrderInstructionV3>,
+            f: impl FnOnce(ReplaceOrdersByClientIdsArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            // Account indices for market, bids, asks, OpenOrders, owner, event_q
+            let cancel_accounts = [0, 4, 5, 1, 7, 3]
+                .iter()
+                .map(|i| accounts[*i].clone())
+                .collect::<Vec<_>>();
+            let mut client_order_ids = [0; 8];
+            for (instruction, client_order_id) in
                 instructions.iter().zip(client_order_ids.iter_mut())
             {
                 *client_order_id = instruction.client_order_id;
@@ -1958,12 +3668,49 @@ pub(crate) mod account_parser {
         }
     }
 
+    pub struct AmendOrderArgs<'a, 'b: 'a> {
+        pub program_id: &'a Pubkey,
+        pub instruction: &'a AmendOrderInstruction,
+        pub cancel_accounts: &'a [AccountInfo<'b>],
+        pub accounts: &'a [AccountInfo<'b>],
+    }
+    impl<'a, 'b: 'a> AmendOrderArgs<'a, 'b> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo<'b>],
+            instruction: &'a AmendOrderInstruction,
+            f: impl FnOnce(AmendOrderArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert!(accounts.len() >= 12)?;
+            match instruction.order_type {
+                OrderType::Limit | OrderType::PostOnly | OrderType::PostOnlySlide => {}
+                _ => return Err(DexErrorCode::InvalidAmendOrder.into()),
+            }
+            // Shrinking in place skips NewOrderV3's checks, so check here.
+            Market::load(&accounts[0], program_id, false)?
+                .check_order_size(instruction.limit_price, instruction.max_coin_qty)?;
+            // Account indices for market, bids, asks, OpenOrders, owner, event_q
+            let cancel_accounts = [0, 4, 5, 1, 7, 3]
+                .iter()
+                .map(|i| accounts[*i].clone())
+                .collect::<Vec<_>>();
+
+            f(AmendOrderArgs {
+                program_id,
+                instruction,
+                cancel_accounts: cancel_accounts.as_slice(),
+                accounts,
+            })
+        }
+    }
+
     pub struct ConsumeEventsArgs<'a, 'b: 'a> {
         pub limit: u16,
         pub program_id: &'a Pubkey,
         pub open_orders_accounts: &'a [AccountInfo<'b>],
         pub market: Market<'a>,
         pub event_q: EventQueue<'a>,
+        pub trigger_book: Option<TriggerBook<'a>>,
     }
     impl<'a, 'b: 'a> ConsumeEventsArgs<'a, 'b> {
         pub fn with_parsed_args<T>(
@@ -1972,6 +3719,7 @@ pub(crate) mod account_parser {
             limit: u16,
             f: impl FnOnce(ConsumeEventsArgs) -> DexResult<T>,
         ) -> DexResult<T> {
+            let (accounts, trigger_book_acc) = split_trigger_book(program_id, accounts);
             check_assert!(accounts.len() >= 5)?;
             #[rustfmt::skip]
             let (
@@ -1986,12 +3734,18 @@ pub(crate) mod account_parser {
             let market = Market::load(market_acc, program_id, true)?;
             check_assert!(market.consume_events_authority().is_none())?;
             let event_q = market.load_event_queue_mut(event_q_acc)?;
+            // Without the trigger book the fills consumed here don't arm any
+            // stops, so that cranks that don't know about it keep working.
+            let trigger_book = trigger_book_acc
+                .map(|acc| market.load_trigger_book_mut(acc, program_id))
+                .transpose()?;
             let args = ConsumeEventsArgs {
                 limit,
                 program_id,
                 open_orders_accounts,
                 market,
                 event_q,
+                trigger_book,
             };
             f(args)
         }
@@ -2002,6 +3756,7 @@ pub(crate) mod account_parser {
             limit: u16,
             f: impl FnOnce(ConsumeEventsArgs) -> DexResult<T>,
         ) -> DexResult<T> {
+            let (accounts, trigger_book_acc) = split_trigger_book(program_id, accounts);
             check_assert!(accounts.len() >= 4)?;
             #[rustfmt::skip]
             let (
@@ -2020,12 +3775,81 @@ pub(crate) mod account_parser {
                 market.consume_events_authority()
             )?;
             let event_q = market.load_event_queue_mut(event_q_acc)?;
+            // Without the trigger book the fills consumed here don't arm any
+            // stops, so that cranks that don't know about it keep working.
+            let trigger_book = trigger_book_acc
+                .map(|acc| market.load_trigger_book_mut(acc, program_id))
+                .transpose()?;
             let args = ConsumeEventsArgs {
                 limit,
                 program_id,
                 open_orders_accounts,
                 market,
                 event_q,
+                trigger_book,
+            };
+            f(args)
+        }
+    }
+
+    pub struct MatchOrdersArgs<'a> {
+        pub limit: u16,
+        pub order_book_state: OrderBookState<'a>,
+        pub req_q: RequestQueue<'a>,
+        pub event_q: EventQueue<'a>,
+        pub trigger_book: Option<TriggerBook<'a>>,
+    }
+    impl<'a> MatchOrdersArgs<'a> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo],
+            limit: u16,
+            f: impl FnOnce(MatchOrdersArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            let (accounts, trigger_book_acc) = split_trigger_book(program_id, accounts);
+            check_assert!(accounts.len() >= 5)?;
+            #[rustfmt::skip]
+            let &[
+                ref market_acc,
+                ref req_q_acc,
+                ref event_q_acc,
+                ref bids_acc,
+                ref asks_acc,
+            ] = array_ref![accounts, 0, 5];
+
+            let mut market = Market::load(market_acc, program_id, true)?;
+            let trigger_book = trigger_book_acc
+                .map(|acc| market.load_trigger_book_mut(acc, program_id))
+                .transpose()?;
+            if trigger_book.is_some() {
+                market.check_enabled()?;
+            }
+            let req_q = market.load_request_queue_mut(req_q_acc)?;
+            let event_q = market.load_event_queue_mut(event_q_acc)?;
+            let mut bids = market.load_bids_mut(bids_acc).or(check_unreachable!())?;
+            let mut asks = market.load_asks_mut(asks_acc).or(check_unreachable!())?;
+
+            let fee_schedule = market.fee_schedule();
+            let price_band = market.price_band();
+            let min_base_qty = market.min_base_qty();
+            let (market_state, stats) = market.state_and_stats_mut();
+            let order_book_state = OrderBookState {
+                bids: bids.deref_mut(),
+                asks: asks.deref_mut(),
+                market_state,
+                stats,
+                price_band,
+                min_base_qty,
+                fee_schedule,
+                sibling_open_orders: &[],
+            };
+
+            let args = MatchOrdersArgs {
+                limit,
+                order_book_state,
+                req_q,
+                event_q,
+                trigger_book,
             };
             f(args)
         }
@@ -2034,7 +3858,7 @@ pub(crate) mod account_parser {
     pub struct CancelOrderV2Args<'a, 'b: 'a> {
         pub instruction: &'a CancelOrderInstructionV2,
         pub open_orders_address: [u64; 4],
-        pub open_orders: &'a mut OpenOrders,
+        pub open_orders: OpenOrdersAccount<'a>,
         pub open_orders_signer: SignerAccount<'a, 'b>,
         pub order_book_state: OrderBookState<'a>,
         pub event_q: EventQueue<'a>,
@@ -2060,7 +3884,7 @@ pub(crate) mod account_parser {
             let mut market = Market::load(market_acc, program_id, true).or(check_unreachable!())?;
 
             let open_orders_signer = SignerAccount::new(open_orders_signer_acc)?;
-            let mut open_orders = market.load_orders_mut(
+            let open_orders = market.load_orders_mut(
                 open_orders_acc,
                 Some(open_orders_signer.inner()),
                 program_id,
@@ -2074,16 +3898,25 @@ pub(crate) mod account_parser {
 
             let event_q = market.load_event_queue_mut(event_q_acc)?;
 
+            let fee_schedule = market.fee_schedule();
+            let price_band = market.price_band();
+            let min_base_qty = market.min_base_qty();
+            let (market_state, stats) = market.state_and_stats_mut();
             let order_book_state = OrderBookState {
                 bids: bids.deref_mut(),
                 asks: asks.deref_mut(),
-                market_state: market.deref_mut(),
+                market_state,
+                stats,
+                price_band,
+                min_base_qty,
+                fee_schedule,
+                sibling_open_orders: &[],
             };
 
             let args = CancelOrderV2Args {
                 instruction,
                 open_orders_address,
-                open_orders: open_orders.deref_mut(),
+                open_orders,
                 open_orders_signer,
                 order_book_state,
                 event_q,
@@ -2095,7 +3928,7 @@ pub(crate) mod account_parser {
     pub struct CancelOrderByClientIdV2Args<'a, 'b: 'a> {
         pub client_order_id: NonZeroU64,
         pub open_orders_address: [u64; 4],
-        pub open_orders: &'a mut OpenOrders,
+        pub open_orders: OpenOrdersAccount<'a>,
         pub open_orders_signer: SignerAccount<'a, 'b>,
         pub order_book_state: OrderBookState<'a>,
         pub event_q: EventQueue<'a>,
@@ -2123,7 +3956,7 @@ pub(crate) mod account_parser {
             let mut market = Market::load(market_acc, program_id, true).or(check_unreachable!())?;
 
             let open_orders_signer = SignerAccount::new(open_orders_signer_acc)?;
-            let mut open_orders = market.load_orders_mut(
+            let open_orders = market.load_orders_mut(
                 open_orders_acc,
                 Some(open_orders_signer.inner()),
                 program_id,
@@ -2137,16 +3970,25 @@ pub(crate) mod account_parser {
 
             let event_q = market.load_event_queue_mut(event_q_acc)?;
 
+            let fee_schedule = market.fee_schedule();
+            let price_band = market.price_band();
+            let min_base_qty = market.min_base_qty();
+            let (market_state, stats) = market.state_and_stats_mut();
             let order_book_state = OrderBookState {
                 bids: bids.deref_mut(),
                 asks: asks.deref_mut(),
-                market_state: market.deref_mut(),
+                market_state,
+                stats,
+                price_band,
+                min_base_qty,
+                fee_schedule,
+                sibling_open_orders: &[],
             };
 
             let args = CancelOrderByClientIdV2Args {
                 client_order_id,
                 open_orders_address,
-                open_orders: open_orders.deref_mut(),
+                open_orders,
                 open_orders_signer,
                 order_book_state,
                 event_q,
@@ -2158,7 +4000,7 @@ pub(crate) mod account_parser {
     pub struct CancelOrdersByClientIdsArgs<'a, 'b: 'a> {
         pub client_order_ids: &'a [NonZeroU64],
         pub open_orders_address: [u64; 4],
-        pub open_orders: &'a mut OpenOrders,
+        pub open_orders: OpenOrdersAccount<'a>,
         pub open_orders_signer: SignerAccount<'a, 'b>,
         pub order_book_state: OrderBookState<'a>,
         pub event_q: EventQueue<'a>,
@@ -2190,7 +4032,7 @@ pub(crate) mod account_parser {
             let mut market = Market::load(market_acc, program_id, true).or(check_unreachable!())?;
 
             let open_orders_signer = SignerAccount::new(open_orders_signer_acc)?;
-            let mut open_orders = market.load_orders_mut(
+            let open_orders = market.load_orders_mut(
                 open_orders_acc,
                 Some(open_orders_signer.inner()),
                 program_id,
@@ -2204,16 +4046,25 @@ pub(crate) mod account_parser {
 
             let event_q = market.load_event_queue_mut(event_q_acc)?;
 
+            let fee_schedule = market.fee_schedule();
+            let price_band = market.price_band();
+            let min_base_qty = market.min_base_qty();
+            let (market_state, stats) = market.state_and_stats_mut();
             let order_book_state = OrderBookState {
                 bids: bids.deref_mut(),
                 asks: asks.deref_mut(),
-                market_state: market.deref_mut(),
+                market_state,
+                stats,
+                price_band,
+                min_base_qty,
+                fee_schedule,
+                sibling_open_orders: &[],
             };
 
             let args = CancelOrdersByClientIdsArgs {
                 client_order_ids: client_order_ids.as_slice(),
                 open_orders_address,
-                open_orders: open_orders.deref_mut(),
+                open_orders,
                 open_orders_signer,
                 order_book_state,
                 event_q,
@@ -2224,7 +4075,7 @@ pub(crate) mod account_parser {
 
     pub struct SettleFundsArgs<'a, 'b: 'a> {
         pub market: Market<'a>,
-        pub open_orders: &'a mut OpenOrders,
+        pub open_orders: OpenOrdersAccount<'a>,
         pub coin_vault: CoinVault<'a, 'b>,
         pub pc_vault: PcVault<'a, 'b>,
         pub coin_wallet: CoinWallet<'a, 'b>,
@@ -2274,7 +4125,7 @@ pub(crate) mod account_parser {
 
             let vault_signer = VaultSigner::new(vault_signer_acc, &market, program_id)?;
 
-            let mut open_orders = market.load_orders_mut(
+            let open_orders = market.load_orders_mut(
                 open_orders_acc,
                 Some(owner.inner()),
                 program_id,
@@ -2284,7 +4135,7 @@ pub(crate) mod account_parser {
 
             let args = SettleFundsArgs {
                 market,
-                open_orders: open_orders.deref_mut(),
+                open_orders,
                 coin_vault,
                 pc_vault,
                 coin_wallet,
@@ -2310,7 +4161,7 @@ pub(crate) mod account_parser {
             check_assert_eq!(accounts.len(), 2)?;
             let &[ref market_acc, ref signer_acc] = array_ref![accounts, 0, 2];
             let mut market = Market::load(market_acc, program_id, false)?;
-            let authorization = SigningDisableAuthority::new(signer_acc)?;
+            let authorization = SigningDisableAuthority::new(signer_acc, &market)?;
 
             let args = DisableMarketArgs {
                 market: market.deref_mut(),
@@ -2350,7 +4201,7 @@ pub(crate) mod account_parser {
             let fee_receiver = PcWallet::from_account(pc_wallet_acc, &market)?;
             let vault_signer = VaultSigner::new(vault_signer_acc, &market, program_id)?;
             let spl_token_program = SplTokenProgram::new(spl_token_program)?;
-            let authorization = SigningFeeSweeper::new(sweep_authority_acc)?;
+            let authorization = SigningFeeSweeper::new(sweep_authority_acc, &market)?;
 
             let args = SweepFeesArgs {
                 market,
@@ -2365,7 +4216,7 @@ pub(crate) mod account_parser {
     }
 
     pub struct CloseOpenOrdersArgs<'a, 'b: 'a> {
-        pub open_orders: &'a mut OpenOrders,
+        pub open_orders: OpenOrdersAccount<'a>,
         pub open_orders_acc: &'a AccountInfo<'b>,
         pub dest_acc: &'a AccountInfo<'b>,
     }
@@ -2389,7 +4240,7 @@ pub(crate) mod account_parser {
             // Validate the accounts given are valid.
             let owner = SignerAccount::new(owner_acc)?;
             let market = Market::load(market_acc, program_id, true)?;
-            let mut open_orders = market.load_orders_mut(
+            let open_orders = market.load_orders_mut(
                 open_orders_acc,
                 Some(owner.inner()),
                 program_id,
@@ -2398,7 +4249,7 @@ pub(crate) mod account_parser {
             )?;
 
             // Only accounts with no funds associated with them can be closed.
-            if open_orders.free_slot_bits != std::u128::MAX {
+            if open_orders.has_open_orders() {
                 return Err(DexErrorCode::TooManyOpenOrders.into());
             }
             if open_orders.native_coin_total != 0 {
@@ -2413,10 +4264,9 @@ pub(crate) mod account_parser {
                 );
                 return Err(DexErrorCode::TooManyOpenOrders.into());
             }
-
             // Invoke processor.
             f(CloseOpenOrdersArgs {
-                open_orders: open_orders.deref_mut(),
+                open_orders,
                 open_orders_acc,
                 dest_acc,
             })
@@ -2447,9 +4297,9 @@ pub(crate) mod account_parser {
                 .transpose()?;
 
             // Dynamic sysvars don't work in unit tests.
-            #[cfg(any(test, feature = "fuzz"))]
+            #[cfg(any(test, feature = "fuzz", feature = "simulator"))]
             let rent = Rent::from_account_info(rent_acc)?;
-            #[cfg(not(any(test, feature = "fuzz")))]
+            #[cfg(not(any(test, feature = "fuzz", feature = "simulator")))]
             let rent = Rent::get()?;
 
             // Validate the accounts given are valid.
@@ -2472,7 +4322,7 @@ pub(crate) mod account_parser {
 
     pub struct PruneArgs<'a> {
         pub order_book_state: OrderBookState<'a>,
-        pub open_orders: &'a mut OpenOrders,
+        pub open_orders: OpenOrdersAccount<'a>,
         pub open_orders_address: &'a Pubkey,
         pub event_q: EventQueue<'a>,
         pub limit: u16,
@@ -2502,7 +4352,7 @@ pub(crate) mod account_parser {
             let mut market = Market::load(market_acc, program_id, false)?;
             check_assert!(market.prune_authority() == Some(prune_auth_acc.key))?;
             let open_orders_address = open_orders_acc.key;
-            let mut open_orders = market.load_orders_mut(
+            let open_orders = market.load_orders_mut(
                 open_orders_acc,
                 Some(open_orders_owner_acc),
                 program_id,
@@ -2512,60 +4362,514 @@ pub(crate) mod account_parser {
             let mut bids = market.load_bids_mut(bids_acc).or(check_unreachable!())?;
             let mut asks = market.load_asks_mut(asks_acc).or(check_unreachable!())?;
             let event_q = market.load_event_queue_mut(event_q_acc)?;
+            let fee_schedule = market.fee_schedule();
+            let price_band = market.price_band();
+            let min_base_qty = market.min_base_qty();
+            let (market_state, stats) = market.state_and_stats_mut();
             let order_book_state = OrderBookState {
                 bids: bids.deref_mut(),
                 asks: asks.deref_mut(),
-                market_state: market.deref_mut(),
+                market_state,
+                stats,
+                price_band,
+                min_base_qty,
+                fee_schedule,
+                sibling_open_orders: &[],
             };
 
             let args = PruneArgs {
                 order_book_state,
                 open_orders_address,
-                open_orders: open_orders.deref_mut(),
+                open_orders,
                 event_q,
                 limit,
             };
             f(args)
         }
     }
-}
 
-#[inline]
-fn remove_slop<T: Pod>(bytes: &[u8]) -> &[T] {
-    let slop = bytes.len() % size_of::<T>();
-    let new_len = bytes.len() - slop;
-    cast_slice(&bytes[..new_len])
-}
+    pub struct CancelAllOrdersArgs<'a, 'b: 'a> {
+        pub instruction: &'a CancelAllOrdersInstruction,
+        pub open_orders_address: [u64; 4],
+        pub open_orders: OpenOrdersAccount<'a>,
+        pub open_orders_signer: SignerAccount<'a, 'b>,
+        pub order_book_state: OrderBookState<'a>,
+        pub event_q: EventQueue<'a>,
+    }
+    impl<'a, 'b: 'a> CancelAllOrdersArgs<'a, 'b> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo<'b>],
+            instruction: &'a CancelAllOrdersInstruction,
+            f: impl FnOnce(CancelAllOrdersArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert!(accounts.len() >= 6)?;
+            #[rustfmt::skip]
+            let &[
+                ref market_acc,
+                ref bids_acc,
+                ref asks_acc,
+                ref open_orders_acc,
+                ref open_orders_signer_acc,
+                ref event_q_acc,
+            ] = array_ref![accounts, 0, 6];
 
-#[inline]
-fn remove_slop_mut<T: Pod>(bytes: &mut [u8]) -> &mut [T] {
-    let slop = bytes.len() % size_of::<T>();
-    let new_len = bytes.len() - slop;
-    cast_slice_mut(&mut bytes[..new_len])
-}
+            let mut market = Market::load(market_acc, program_id, true).or(check_unreachable!())?;
 
-#[cfg_attr(not(feature = "program"), allow(unused))]
-impl State {
-    #[cfg(feature = "program")]
-    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> DexResult {
-        let instruction = MarketInstruction::unpack(input).ok_or(ProgramError::InvalidArgument)?;
-        match instruction {
-            MarketInstruction::InitializeMarket(ref inner) => Self::process_initialize_market(
-                account_parser::InitializeMarketArgs::new(program_id, inner, accounts)?,
-            )?,
-            MarketInstruction::NewOrder(_inner) => {
-                unimplemented!()
-            }
-            MarketInstruction::NewOrderV2(_inner) => {
-                unimplemented!()
-            }
-            MarketInstruction::NewOrderV3(ref inner) => {
-                account_parser::NewOrderV3Args::with_parsed_args(
-                    program_id,
-                    inner,
-                    accounts,
-                    Self::process_new_order_v3,
-                )?
+            let open_orders_signer = SignerAccount::new(open_orders_signer_acc)?;
+            let open_orders = market.load_orders_mut(
+                open_orders_acc,
+                Some(open_orders_signer.inner()),
+                program_id,
+                None,
+                None,
+            )?;
+            let open_orders_address = open_orders_acc.key.to_aligned_bytes();
+
+            let mut bids = market.load_bids_mut(bids_acc).or(check_unreachable!())?;
+            let mut asks = market.load_asks_mut(asks_acc).or(check_unreachable!())?;
+
+            let event_q = market.load_event_queue_mut(event_q_acc)?;
+
+            let fee_schedule = market.fee_schedule();
+            let price_band = market.price_band();
+            let min_base_qty = market.min_base_qty();
+            let (market_state, stats) = market.state_and_stats_mut();
+            let order_book_state = OrderBookState {
+                bids: bids.deref_mut(),
+                asks: asks.deref_mut(),
+                market_state,
+                stats,
+                price_band,
+                min_base_qty,
+                fee_schedule,
+                sibling_open_orders: &[],
+            };
+
+            let args = CancelAllOrdersArgs {
+                instruction,
+                open_orders_address,
+                open_orders,
+                open_orders_signer,
+                order_book_state,
+                event_q,
+            };
+            f(args)
+        }
+    }
+
+    pub struct PruneExpiredOrdersArgs<'a> {
+        pub order_book_state: OrderBookState<'a>,
+        pub event_q: EventQueue<'a>,
+        pub limit: u16,
+    }
+
+    impl<'a> PruneExpiredOrdersArgs<'a> {
+        pub fn with_parsed_args<T>(
+            program_id: &Pubkey,
+            accounts: &[AccountInfo],
+            limit: u16,
+            f: impl FnOnce(PruneExpiredOrdersArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert!(accounts.len() == 4)?;
+            #[rustfmt::skip]
+            let &[
+                ref market_acc,
+                ref bids_acc,
+                ref asks_acc,
+                ref event_q_acc,
+            ] = array_ref![accounts, 0, 4];
+
+            let mut market = Market::load(market_acc, program_id, false)?;
+            let mut bids = market.load_bids_mut(bids_acc).or(check_unreachable!())?;
+            let mut asks = market.load_asks_mut(asks_acc).or(check_unreachable!())?;
+            let event_q = market.load_event_queue_mut(event_q_acc)?;
+            let fee_schedule = market.fee_schedule();
+            let price_band = market.price_band();
+            let min_base_qty = market.min_base_qty();
+            let (market_state, stats) = market.state_and_stats_mut();
+            let order_book_state = OrderBookState {
+                bids: bids.deref_mut(),
+                asks: asks.deref_mut(),
+                market_state,
+                stats,
+                price_band,
+                min_base_qty,
+                fee_schedule,
+                sibling_open_orders: &[],
+            };
+
+            let args = PruneExpiredOrdersArgs {
+                order_book_state,
+                event_q,
+                limit,
+            };
+            f(args)
+        }
+    }
+
+    pub struct UpdateFeeScheduleArgs<'a> {
+        pub instruction: &'a UpdateFeeScheduleInstruction,
+        pub market: Market<'a>,
+    }
+
+    impl<'a> UpdateFeeScheduleArgs<'a> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            instruction: &'a UpdateFeeScheduleInstruction,
+            accounts: &'a [AccountInfo],
+            f: impl FnOnce(UpdateFeeScheduleArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert_eq!(accounts.len(), 2)?;
+            #[rustfmt::skip]
+            let &[
+                ref market_acc,
+                ref admin_acc,
+            ] = array_ref![accounts, 0, 2];
+
+            let market = Market::load(market_acc, program_id, true)?;
+            check_assert!(admin_acc.is_signer)?;
+            if admin_acc.key != market.fee_schedule_authority() {
+                return Err(DexErrorCode::WrongSigner.into());
+            }
+            // V1 markets have no room to store a schedule and always use the
+            // default one.
+            if let Market::V1(_) = market {
+                return Err(DexErrorCode::InvalidMarketFlags.into());
+            }
+
+            let args = UpdateFeeScheduleArgs {
+                instruction,
+                market,
+            };
+            f(args)
+        }
+    }
+
+    pub struct InitTriggerBookArgs<'a, 'b: 'a> {
+        pub program_id: &'a Pubkey,
+        pub market: Market<'a>,
+        pub trigger_book_acc: &'a AccountInfo<'b>,
+        pub rent: Rent,
+    }
+
+    impl<'a, 'b: 'a> InitTriggerBookArgs<'a, 'b> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo<'b>],
+            f: impl FnOnce(InitTriggerBookArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert_eq!(accounts.len(), 4)?;
+            #[rustfmt::skip]
+            let &[
+                ref market_acc,
+                ref trigger_book_acc,
+                ref admin_acc,
+                ref rent_sysvar_acc,
+            ] = array_ref![accounts, 0, 4];
+
+            let market = Market::load(market_acc, program_id, true)?;
+            SigningMarketAdmin::new(admin_acc, &market)?;
+
+            // Dynamic sysvars don't work in unit tests.
+            #[cfg(any(test, feature = "fuzz", feature = "simulator"))]
+            let rent = Rent::from_account_info(rent_sysvar_acc)?;
+            #[cfg(not(any(test, feature = "fuzz", feature = "simulator")))]
+            let rent = Rent::get()?;
+
+            let args = InitTriggerBookArgs {
+                program_id,
+                market,
+                trigger_book_acc,
+                rent,
+            };
+            f(args)
+        }
+    }
+
+    pub struct FillDetailsArgs<'a> {
+        pub event_q: EventQueue<'a>,
+    }
+
+    impl<'a> FillDetailsArgs<'a> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo],
+            f: impl FnOnce(FillDetailsArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert_eq!(accounts.len(), 3)?;
+            #[rustfmt::skip]
+            let &[
+                ref market_acc,
+                ref event_q_acc,
+                ref admin_acc,
+            ] = array_ref![accounts, 0, 3];
+
+            let market = Market::load(market_acc, program_id, true)?;
+            SigningMarketAdmin::new(admin_acc, &market)?;
+            let event_q = market.load_event_queue_mut(event_q_acc)?;
+
+            let args = FillDetailsArgs { event_q };
+            f(args)
+        }
+    }
+
+    pub struct CancelOnlyArgs<'a, 'b: 'a> {
+        pub market: Market<'a>,
+        pub authorization: SigningAdminOrDisableAuthority<'a, 'b>,
+    }
+
+    impl<'a, 'b: 'a> CancelOnlyArgs<'a, 'b> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo<'b>],
+            f: impl FnOnce(CancelOnlyArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert_eq!(accounts.len(), 2)?;
+            let &[ref market_acc, ref signer_acc] = array_ref![accounts, 0, 2];
+            let market = Market::load(market_acc, program_id, false)?;
+            let authorization = SigningAdminOrDisableAuthority::new(signer_acc, &market)?;
+
+            let args = CancelOnlyArgs {
+                market,
+                authorization,
+            };
+            f(args)
+        }
+    }
+
+    pub struct ProposeAuthorityArgs<'a, 'b: 'a> {
+        pub instruction: &'a ProposeAuthorityInstruction,
+        pub market: Market<'a>,
+        pub authorization: SigningMarketAdmin<'a, 'b>,
+    }
+
+    impl<'a, 'b: 'a> ProposeAuthorityArgs<'a, 'b> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo<'b>],
+            instruction: &'a ProposeAuthorityInstruction,
+            f: impl FnOnce(ProposeAuthorityArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert_eq!(accounts.len(), 2)?;
+            let &[ref market_acc, ref admin_acc] = array_ref![accounts, 0, 2];
+            let market = Market::load(market_acc, program_id, true)?;
+            let authorization = SigningMarketAdmin::new(admin_acc, &market)?;
+
+            let args = ProposeAuthorityArgs {
+                instruction,
+                market,
+                authorization,
+            };
+            f(args)
+        }
+    }
+
+    pub struct ResetAuthorityArgs<'a, 'b: 'a> {
+        pub kind: AuthorityKind,
+        pub market: Market<'a>,
+        pub authorization: SigningMarketAdmin<'a, 'b>,
+    }
+
+    impl<'a, 'b: 'a> ResetAuthorityArgs<'a, 'b> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo<'b>],
+            kind: AuthorityKind,
+            f: impl FnOnce(ResetAuthorityArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert_eq!(accounts.len(), 2)?;
+            let &[ref market_acc, ref admin_acc] = array_ref![accounts, 0, 2];
+            let market = Market::load(market_acc, program_id, true)?;
+            let authorization = SigningMarketAdmin::new(admin_acc, &market)?;
+
+            let args = ResetAuthorityArgs {
+                kind,
+                market,
+                authorization,
+            };
+            f(args)
+        }
+    }
+
+    pub struct AcceptAuthorityArgs<'a> {
+        pub market: Market<'a>,
+        pub kind: AuthorityKind,
+        pub new_authority: Pubkey,
+    }
+
+    impl<'a> AcceptAuthorityArgs<'a> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo],
+            kind: AuthorityKind,
+            f: impl FnOnce(AcceptAuthorityArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert_eq!(accounts.len(), 2)?;
+            let &[ref market_acc, ref new_authority_acc] = array_ref![accounts, 0, 2];
+            let new_authority = SignerAccount::new(new_authority_acc)?;
+            let market = Market::load(market_acc, program_id, true)?;
+            match &market {
+                Market::V2(state)
+                    if state.pending_authority == *new_authority.inner().key
+                        && state.pending_authority_kind == u8::from(kind) as u64 => {}
+                _ => return Err(DexErrorCode::NoPendingAuthority.into()),
+            }
+
+            let args = AcceptAuthorityArgs {
+                market,
+                kind,
+                new_authority: *new_authority.inner().key,
+            };
+            f(args)
+        }
+    }
+
+    pub struct SetPriceBandArgs<'a, 'b: 'a> {
+        pub max_deviation_bps: u16,
+        pub market: Market<'a>,
+        pub authorization: SigningMarketAdmin<'a, 'b>,
+    }
+
+    impl<'a, 'b: 'a> SetPriceBandArgs<'a, 'b> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo<'b>],
+            max_deviation_bps: u16,
+            f: impl FnOnce(SetPriceBandArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert_eq!(accounts.len(), 2)?;
+            let &[ref market_acc, ref admin_acc] = array_ref![accounts, 0, 2];
+            let market = Market::load(market_acc, program_id, true)?;
+            let authorization = SigningMarketAdmin::new(admin_acc, &market)?;
+
+            let args = SetPriceBandArgs {
+                max_deviation_bps,
+                market,
+                authorization,
+            };
+            f(args)
+        }
+    }
+
+    pub struct CloseMarketArgs<'a, 'b: 'a> {
+        pub market_accounts: &'a [AccountInfo<'b>; 5],
+        pub trigger_book_acc: Option<&'a AccountInfo<'b>>,
+        pub dest_acc: &'a AccountInfo<'b>,
+    }
+
+    impl<'a, 'b: 'a> CloseMarketArgs<'a, 'b> {
+        pub fn with_parsed_args<T>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo<'b>],
+            f: impl FnOnce(CloseMarketArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            check_assert!(accounts.len() == 7 || accounts.len() == 8)?;
+            let (accounts, trigger_book_acc) = accounts.split_at(7);
+            let accounts = array_ref![accounts, 0, 7];
+            let (market_accounts, &[ref authority_acc, ref dest_acc]) = array_refs![accounts, 5, 2];
+            #[rustfmt::skip]
+            let &[
+                ref market_acc,
+                ref req_q_acc,
+                ref event_q_acc,
+                ref bids_acc,
+                ref asks_acc,
+            ] = market_accounts;
+            let trigger_book_acc = trigger_book_acc.first();
+            // The rent would be zeroed along with the account it was sent to.
+            if market_accounts
+                .iter()
+                .chain(trigger_book_acc)
+                .any(|account| account.key == dest_acc.key)
+            {
+                return Err(DexErrorCode::InvalidRentDestination.into());
+            }
+
+            {
+                let market = Market::load(market_acc, program_id, true)?;
+                SigningAdminOrDisableAuthority::new(authority_acc, &market)?;
+
+                let flags = BitFlags::from_bits(market.account_flags).unwrap();
+                if !flags.contains(AccountFlag::Disabled) {
+                    return Err(DexErrorCode::MarketNotDisabled.into());
+                }
+                if market.has_trigger_book() != trigger_book_acc.is_some() {
+                    return Err(DexErrorCode::WrongTriggerBookAccount.into());
+                }
+
+                let req_q = market.load_request_queue_mut(req_q_acc)?;
+                let event_q = market.load_event_queue_mut(event_q_acc)?;
+                let bids = market.load_bids_mut(bids_acc)?;
+                let asks = market.load_asks_mut(asks_acc)?;
+                let trigger_book = trigger_book_acc
+                    .map(|acc| market.load_trigger_book_mut(acc, program_id))
+                    .transpose()?;
+                if !req_q.empty()
+                    || !event_q.empty()
+                    || bids.find_min().is_some()
+                    || asks.find_min().is_some()
+                    || matches!(trigger_book, Some(book) if !book.is_empty())
+                {
+                    solana_program::msg!("The books and queues must be empty to close the market");
+                    return Err(DexErrorCode::MarketNotEmpty.into());
+                }
+                if market.coin_deposits_total != 0
+                    || market.pc_deposits_total != 0
+                    || market.pc_fees_accrued != 0
+                {
+                    solana_program::msg!(
+                        "Deposits must be settled and fees swept to close the market"
+                    );
+                    return Err(DexErrorCode::MarketNotEmpty.into());
+                }
+            }
+
+            f(CloseMarketArgs {
+                market_accounts,
+                trigger_book_acc,
+                dest_acc,
+            })
+        }
+    }
+}
+
+#[inline]
+fn remove_slop<T: Pod>(bytes: &[u8]) -> &[T] {
+    let slop = bytes.len() % size_of::<T>();
+    let new_len = bytes.len() - slop;
+    cast_slice(&bytes[..new_len])
+}
+
+#[inline]
+fn remove_slop_mut<T: Pod>(bytes: &mut [u8]) -> &mut [T] {
+    let slop = bytes.len() % size_of::<T>();
+    let new_len = bytes.len() - slop;
+    cast_slice_mut(&mut bytes[..new_len])
+}
+
+#[cfg_attr(not(feature = "program"), allow(unused))]
+impl State {
+    #[cfg(feature = "program")]
+    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> DexResult {
+        let instruction = MarketInstruction::unpack(input).ok_or(ProgramError::InvalidArgument)?;
+        match instruction {
+            MarketInstruction::InitializeMarket(ref inner) => Self::process_initialize_market(
+                account_parser::InitializeMarketArgs::new(program_id, inner, accounts)?,
+            )?,
+            MarketInstruction::NewOrder(_inner) => {
+                unimplemented!()
+            }
+            MarketInstruction::NewOrderV2(_inner) => {
+                unimplemented!()
+            }
+            MarketInstruction::NewOrderV3(ref inner) => {
+                account_parser::NewOrderV3Args::with_parsed_args(
+                    program_id,
+                    inner,
+                    accounts,
+                    Self::process_new_order_v3,
+                )?
             }
             MarketInstruction::ReplaceOrderByClientId(instruction) => {
                 account_parser::ReplaceOrdersByClientIdsArgs::with_parsed_args(
@@ -2583,119 +4887,252 @@ impl State {
                     Self::process_replace_orders_by_client_ids,
                 )?
             }
-            MarketInstruction::MatchOrders(_limit) => {}
+            MarketInstruction::MatchOrders(limit) => {
+                account_parser::MatchOrdersArgs::with_parsed_args(
+                    program_id,
+                    accounts,
+                    limit,
+                    Self::process_match_orders,
+                )?
+            }
             MarketInstruction::ConsumeEvents(limit) => {
                 account_parser::ConsumeEventsArgs::with_parsed_args(
                     program_id,
                     accounts,
-                    limit,
-                    Self::process_consume_events,
+                    limit,
+                    Self::process_consume_events,
+                )?
+            }
+            MarketInstruction::ConsumeEventsPermissioned(limit) => {
+                account_parser::ConsumeEventsArgs::with_parsed_args_permissioned(
+                    program_id,
+                    accounts,
+                    limit,
+                    Self::process_consume_events,
+                )?
+            }
+            MarketInstruction::CancelOrder(_inner) => {
+                unimplemented!()
+            }
+            MarketInstruction::CancelOrderV2(ref inner) => {
+                account_parser::CancelOrderV2Args::with_parsed_args(
+                    program_id,
+                    accounts,
+                    inner,
+                    Self::process_cancel_order_v2,
+                )?
+            }
+            MarketInstruction::SettleFunds => account_parser::SettleFundsArgs::with_parsed_args(
+                program_id,
+                accounts,
+                Self::process_settle_funds,
+            )?,
+            MarketInstruction::CancelOrderByClientId(_client_id) => {
+                unimplemented!()
+            }
+            MarketInstruction::CancelOrderByClientIdV2(client_id) => {
+                account_parser::CancelOrderByClientIdV2Args::with_parsed_args(
+                    program_id,
+                    accounts,
+                    client_id,
+                    Self::process_cancel_order_by_client_id_v2,
+                )?
+            }
+            MarketInstruction::CancelOrdersByClientIds(client_ids) => {
+                account_parser::CancelOrdersByClientIdsArgs::with_parsed_args(
+                    program_id,
+                    accounts,
+                    client_ids,
+                    Self::process_cancel_orders_by_client_ids,
+                )?
+            }
+            MarketInstruction::DisableMarket => {
+                account_parser::DisableMarketArgs::with_parsed_args(
+                    program_id,
+                    accounts,
+                    Self::process_disable_market,
+                )?
+            }
+            MarketInstruction::SweepFees => account_parser::SweepFeesArgs::with_parsed_args(
+                program_id,
+                accounts,
+                Self::process_sweep_fees,
+            )?,
+            MarketInstruction::SendTake(ref inner) => {
+                account_parser::SendTakeArgs::with_parsed_args(
+                    program_id,
+                    inner,
+                    accounts,
+                    Self::process_send_take,
+                )?
+            }
+            MarketInstruction::CloseOpenOrders => {
+                account_parser::CloseOpenOrdersArgs::with_parsed_args(
+                    program_id,
+                    accounts,
+                    Self::process_close_open_orders,
+                )?
+            }
+            MarketInstruction::InitOpenOrders => {
+                account_parser::InitOpenOrdersArgs::with_parsed_args(
+                    program_id,
+                    accounts,
+                    Self::process_init_open_orders,
+                )?
+            }
+            MarketInstruction::Prune(limit) => account_parser::PruneArgs::with_parsed_args(
+                program_id,
+                accounts,
+                limit,
+                Self::process_prune,
+            )?,
+            MarketInstruction::NewTriggerOrder(ref inner) => {
+                account_parser::NewTriggerOrderArgs::with_parsed_args(
+                    program_id,
+                    inner,
+                    accounts,
+                    Self::process_new_trigger_order,
+                )?
+            }
+            MarketInstruction::CancelTriggerOrder(order_id) => {
+                account_parser::CancelTriggerOrderArgs::with_parsed_args(
+                    program_id,
+                    accounts,
+                    order_id,
+                    Self::process_cancel_trigger_order,
+                )?
+            }
+            MarketInstruction::PruneExpiredOrders(limit) => {
+                account_parser::PruneExpiredOrdersArgs::with_parsed_args(
+                    program_id,
+                    accounts,
+                    limit,
+                    Self::process_prune_expired_orders,
+                )?
+            }
+            MarketInstruction::UpdateFeeSchedule(ref inner) => {
+                account_parser::UpdateFeeScheduleArgs::with_parsed_args(
+                    program_id,
+                    inner,
+                    accounts,
+                    Self::process_update_fee_schedule,
+                )?
+            }
+            MarketInstruction::NewIcebergOrder(ref inner) => {
+                account_parser::NewOrderV3Args::with_parsed_args(
+                    program_id,
+                    &inner.order,
+                    accounts,
+                    |args| Self::process_new_iceberg_order(args, inner.display_qty),
                 )?
             }
-            MarketInstruction::ConsumeEventsPermissioned(limit) => {
-                account_parser::ConsumeEventsArgs::with_parsed_args_permissioned(
+            MarketInstruction::NewOrderWithExpiry(ref inner) => {
+                account_parser::NewOrderV3Args::with_parsed_args(
                     program_id,
+                    &inner.order,
                     accounts,
-                    limit,
-                    Self::process_consume_events,
+                    |args| Self::process_new_order_with_expiry(args, inner.expiry_ts),
                 )?
             }
-            MarketInstruction::CancelOrder(_inner) => {
-                unimplemented!()
-            }
-            MarketInstruction::CancelOrderV2(ref inner) => {
-                account_parser::CancelOrderV2Args::with_parsed_args(
+            MarketInstruction::CancelAllOrders(ref inner) => {
+                account_parser::CancelAllOrdersArgs::with_parsed_args(
                     program_id,
                     accounts,
                     inner,
-                    Self::process_cancel_order_v2,
+                    Self::process_cancel_all_orders,
                 )?
             }
-            MarketInstruction::SettleFunds => account_parser::SettleFundsArgs::with_parsed_args(
-                program_id,
-                accounts,
-                Self::process_settle_funds,
-            )?,
-            MarketInstruction::CancelOrderByClientId(_client_id) => {
-                unimplemented!()
-            }
-            MarketInstruction::CancelOrderByClientIdV2(client_id) => {
-                account_parser::CancelOrderByClientIdV2Args::with_parsed_args(
+            MarketInstruction::EnableFillDetails => {
+                account_parser::FillDetailsArgs::with_parsed_args(
                     program_id,
                     accounts,
-                    client_id,
-                    Self::process_cancel_order_by_client_id_v2,
+                    Self::process_enable_fill_details,
                 )?
             }
-            MarketInstruction::CancelOrdersByClientIds(client_ids) => {
-                account_parser::CancelOrdersByClientIdsArgs::with_parsed_args(
+            MarketInstruction::DisableFillDetails => {
+                account_parser::FillDetailsArgs::with_parsed_args(
                     program_id,
                     accounts,
-                    client_ids,
-                    Self::process_cancel_orders_by_client_ids,
+                    Self::process_disable_fill_details,
                 )?
             }
-            MarketInstruction::DisableMarket => {
-                account_parser::DisableMarketArgs::with_parsed_args(
+            MarketInstruction::InitTriggerBook => {
+                account_parser::InitTriggerBookArgs::with_parsed_args(
                     program_id,
                     accounts,
-                    Self::process_disable_market,
+                    Self::process_init_trigger_book,
                 )?
             }
-            MarketInstruction::SweepFees => account_parser::SweepFeesArgs::with_parsed_args(
+            MarketInstruction::EnterCancelOnly => account_parser::CancelOnlyArgs::with_parsed_args(
                 program_id,
                 accounts,
-                Self::process_sweep_fees,
+                Self::process_enter_cancel_only,
             )?,
-            MarketInstruction::SendTake(ref inner) => {
-                account_parser::SendTakeArgs::with_parsed_args(
+            MarketInstruction::ExitCancelOnly => account_parser::CancelOnlyArgs::with_parsed_args(
+                program_id,
+                accounts,
+                Self::process_exit_cancel_only,
+            )?,
+            MarketInstruction::ProposeAuthority(ref inner) => {
+                account_parser::ProposeAuthorityArgs::with_parsed_args(
                     program_id,
+                    accounts,
                     inner,
+                    Self::process_propose_authority,
+                )?
+            }
+            MarketInstruction::AcceptAuthority(kind) => {
+                account_parser::AcceptAuthorityArgs::with_parsed_args(
+                    program_id,
                     accounts,
-                    Self::process_send_take,
+                    kind,
+                    Self::process_accept_authority,
                 )?
             }
-            MarketInstruction::CloseOpenOrders => {
-                account_parser::CloseOpenOrdersArgs::with_parsed_args(
+            MarketInstruction::ResetAuthority(kind) => {
+                account_parser::ResetAuthorityArgs::with_parsed_args(
                     program_id,
                     accounts,
-                    Self::process_close_open_orders,
+                    kind,
+                    Self::process_reset_authority,
                 )?
             }
-            MarketInstruction::InitOpenOrders => {
-                account_parser::InitOpenOrdersArgs::with_parsed_args(
+            MarketInstruction::SetPriceBand(max_deviation_bps) => {
+                account_parser::SetPriceBandArgs::with_parsed_args(
                     program_id,
                     accounts,
-                    Self::process_init_open_orders,
+                    max_deviation_bps,
+                    Self::process_set_price_band,
                 )?
             }
-            MarketInstruction::Prune(limit) => account_parser::PruneArgs::with_parsed_args(
+            MarketInstruction::CloseMarket => account_parser::CloseMarketArgs::with_parsed_args(
                 program_id,
                 accounts,
-                limit,
-                Self::process_prune,
+                Self::process_close_market,
             )?,
+            MarketInstruction::AmendOrder(ref inner) => {
+                account_parser::AmendOrderArgs::with_parsed_args(
+                    program_id,
+                    accounts,
+                    inner,
+                    Self::process_amend_order,
+                )?
+            }
         };
         Ok(())
     }
 
     #[cfg(feature = "program")]
-    fn process_send_take(args: account_parser::SendTakeArgs) -> DexResult {
-        let account_parser::SendTakeArgs {
-            instruction,
-            signer,
-            mut event_q,
-            mut req_q,
-            mut order_book_state,
-            coin_wallet,
-            pc_wallet,
-            coin_vault,
-            pc_vault,
-            spl_token_program,
-            fee_tier,
-            vault_signer,
-        } = args;
-
+    // Matches a SendTake against the book. The taker has no OpenOrders
+    // account, so everything it trades ends up in the returned proceeds.
+    fn match_send_take(
+        instruction: &SendTakeInstruction,
+        fee_tier: FeeTier,
+        order_book_state: &mut OrderBookState,
+        req_q: &mut RequestQueue,
+        event_q: &mut EventQueue,
+        now: i64,
+    ) -> DexResult<RequestProceeds> {
         let order_id = req_q.gen_order_id(instruction.limit_price.get(), instruction.side);
         let native_pc_qty_locked = match instruction.side {
             Side::Bid => {
@@ -2718,16 +5155,78 @@ impl State {
             max_coin_qty: instruction.max_coin_qty,
             native_pc_qty_locked,
             client_order_id: None,
+            expiry_ts: 0,
         };
 
         let mut limit = instruction.limit;
         let mut proceeds = RequestProceeds::zero();
         let _unfilled_portion = order_book_state.process_orderbook_request(
             &request,
-            &mut event_q,
+            now,
+            req_q,
+            event_q,
             &mut proceeds,
             &mut limit,
         )?;
+        Ok(proceeds)
+    }
+
+    fn check_send_take_minimums(
+        instruction: &SendTakeInstruction,
+        proceeds: &RequestProceeds,
+    ) -> DexResult {
+        let abort = match instruction.side {
+            Side::Bid if proceeds.coin_credit < instruction.min_coin_qty => true,
+            Side::Ask if proceeds.native_pc_credit < instruction.min_native_pc_qty => true,
+            _ => false,
+        };
+
+        if abort {
+            solana_program::msg!("Min amount requested not met! Aborting");
+            return Err(DexErrorCode::MinAmountNotMet.into());
+        };
+        Ok(())
+    }
+
+    fn process_send_take(args: account_parser::SendTakeArgs) -> DexResult {
+        let account_parser::SendTakeArgs {
+            instruction,
+            signer,
+            mut event_q,
+            mut req_q,
+            mut order_book_state,
+            coin_wallet,
+            pc_wallet,
+            coin_vault,
+            pc_vault,
+            spl_token_program,
+            fee_tier,
+            vault_signer,
+            mut trigger_book,
+        } = args;
+
+        let now = current_timestamp()?;
+        let events_before = event_q.len();
+        let proceeds = Self::match_send_take(
+            instruction,
+            fee_tier,
+            &mut order_book_state,
+            &mut req_q,
+            &mut event_q,
+            now,
+        )?;
+
+        if let Some(trigger_book) = trigger_book.as_mut() {
+            trigger_book.execute(
+                &mut order_book_state,
+                now,
+                &mut req_q,
+                &mut event_q,
+                events_before,
+                TRIGGERED_ORDERS_PER_ORDER,
+            )?;
+        }
+        Self::check_send_take_minimums(instruction, &proceeds)?;
 
         let market_state = order_book_state.market_state;
 
@@ -2744,17 +5243,6 @@ impl State {
             native_pc_debit,
         } = proceeds;
 
-        let abort = match instruction.side {
-            Side::Bid if coin_credit < instruction.min_coin_qty => true,
-            Side::Ask if native_pc_credit < instruction.min_native_pc_qty => true,
-            _ => false,
-        };
-
-        if abort {
-            solana_program::msg!("Min amount requested not met! Aborting");
-            return Err(DexErrorCode::MinAmountNotMet.into());
-        };
-
         // Amount that user deposits into the program
         let deposit_amount;
         // Amount that user receives after the exchange 
@@ -2787,103 +5275,306 @@ impl State {
                 withdraw_destination = pc_wallet.token_account();
                 withdraw_vault = pc_vault.token_account();
 
-                market_state.coin_deposits_total += deposit_amount;
-                check_assert!(market_state.pc_deposits_total >= withdraw_amount)?;
-                market_state.pc_deposits_total -= withdraw_amount;
-            }
+                market_state.coin_deposits_total += deposit_amount;
+                check_assert!(market_state.pc_deposits_total >= withdraw_amount)?;
+                market_state.pc_deposits_total -= withdraw_amount;
+            }
+        };
+
+        deposit_into_vault(
+            deposit_amount,
+            deposit_source,
+            deposit_vault,
+            signer,
+            spl_token_program,
+        )?;
+
+        let nonce = market_state.vault_signer_nonce;
+        let market_pubkey = market_state.pubkey();
+        let vault_signer_seeds = gen_vault_signer_seeds(&nonce, &market_pubkey);
+
+        if withdraw_amount != 0 {
+            send_from_vault(
+                withdraw_amount,
+                withdraw_destination,
+                withdraw_vault,
+                spl_token_program,
+                vault_signer,
+                &vault_signer_seeds,
+            )?;
+        };
+
+        Ok(())
+    }
+
+    fn process_prune(args: account_parser::PruneArgs) -> DexResult {
+        let account_parser::PruneArgs {
+            mut order_book_state,
+            mut open_orders,
+            open_orders_address,
+            mut event_q,
+            limit,
+        } = args;
+        let open_orders_addr_bytes = open_orders_address.to_aligned_bytes();
+        let (bids_removed, asks_removed) =
+            order_book_state.remove_all(open_orders_addr_bytes, None, limit)?;
+
+        solana_program::msg!(
+            "Pruned {:?} bids and {:?} asks",
+            bids_removed.len(),
+            asks_removed.len()
+        );
+
+        Self::cancel_removed_orders(
+            &mut order_book_state,
+            &mut open_orders,
+            open_orders_addr_bytes,
+            bids_removed,
+            asks_removed,
+            OutReason::Pruned,
+            &mut event_q,
+        )
+    }
+
+    fn process_cancel_all_orders(args: account_parser::CancelAllOrdersArgs) -> DexResult {
+        let account_parser::CancelAllOrdersArgs {
+            instruction: &CancelAllOrdersInstruction { side, limit },
+
+            open_orders_address,
+            mut open_orders,
+            open_orders_signer: _,
+
+            mut order_book_state,
+            mut event_q,
+        } = args;
+        let (bids_removed, asks_removed) =
+            order_book_state.remove_all(open_orders_address, side, limit)?;
+
+        solana_program::msg!(
+            "Cancelled {:?} bids and {:?} asks",
+            bids_removed.len(),
+            asks_removed.len()
+        );
+
+        Self::cancel_removed_orders(
+            &mut order_book_state,
+            &mut open_orders,
+            open_orders_address,
+            bids_removed,
+            asks_removed,
+            OutReason::Cancelled,
+            &mut event_q,
+        )
+    }
+
+    // Unlocks the funds of orders already taken off the book by `remove_all`.
+    fn cancel_removed_orders(
+        order_book_state: &mut OrderBookState,
+        open_orders: &mut OpenOrdersAccount,
+        open_orders_address: [u64; 4],
+        bids_removed: Vec<LeafNode>,
+        asks_removed: Vec<LeafNode>,
+        reason: OutReason,
+        event_q: &mut EventQueue,
+    ) -> DexResult {
+        for bid in bids_removed {
+            let order_id = open_orders.order_id(bid.owner_slot());
+            order_book_state.cancel_leaf_node(
+                bid,
+                Side::Bid,
+                open_orders,
+                open_orders_address,
+                order_id,
+                reason,
+                event_q,
+            )?;
+        }
+
+        for ask in asks_removed {
+            let order_id = open_orders.order_id(ask.owner_slot());
+            order_book_state.cancel_leaf_node(
+                ask,
+                Side::Ask,
+                open_orders,
+                open_orders_address,
+                order_id,
+                reason,
+                event_q,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn process_prune_expired_orders(args: account_parser::PruneExpiredOrdersArgs) -> DexResult {
+        let account_parser::PruneExpiredOrdersArgs {
+            mut order_book_state,
+            mut event_q,
+            limit,
+        } = args;
+        let now = current_timestamp()?;
+        let pruned = order_book_state.prune_expired(now, &mut event_q, limit)?;
+        solana_program::msg!("Pruned {:?} expired orders", pruned);
+        Ok(())
+    }
+
+    fn process_update_fee_schedule(args: account_parser::UpdateFeeScheduleArgs) -> DexResult {
+        let account_parser::UpdateFeeScheduleArgs {
+            instruction,
+            mut market,
+        } = args;
+        let fee_schedule = FeeSchedule::new(
+            instruction.taker_fee_tenth_of_bps,
+            instruction.maker_rebate_tenth_of_bps,
+            instruction.srm_thresholds,
+            instruction.msrm_threshold,
+            instruction.stable,
+        )
+        .ok_or(DexErrorCode::InvalidFeeSchedule)?;
+        match market {
+            Market::V2(ref mut state) => state.fee_schedule = fee_schedule,
+            Market::V1(_) => check_unreachable!()?,
+        }
+        Ok(())
+    }
+
+    fn process_init_trigger_book(args: account_parser::InitTriggerBookArgs) -> DexResult {
+        let account_parser::InitTriggerBookArgs {
+            program_id,
+            mut market,
+            trigger_book_acc,
+            rent,
+        } = args;
+        market.init_trigger_book(trigger_book_acc, program_id, rent)
+    }
+
+    fn process_enable_fill_details(args: account_parser::FillDetailsArgs) -> DexResult {
+        let account_parser::FillDetailsArgs { mut event_q } = args;
+        let flags = BitFlags::from_bits(event_q.header.account_flags).unwrap();
+        event_q.header.account_flags = (flags | AccountFlag::FillDetails).bits();
+        Ok(())
+    }
+
+    fn process_disable_fill_details(args: account_parser::FillDetailsArgs) -> DexResult {
+        let account_parser::FillDetailsArgs { mut event_q } = args;
+        let flags = BitFlags::from_bits(event_q.header.account_flags).unwrap();
+        event_q.header.account_flags = (flags & !AccountFlag::FillDetails).bits();
+        Ok(())
+    }
+
+    fn process_enter_cancel_only(args: account_parser::CancelOnlyArgs) -> DexResult {
+        let account_parser::CancelOnlyArgs {
+            mut market,
+            authorization: _,
+        } = args;
+        market.account_flags |= AccountFlag::CancelOnly as u64;
+        Ok(())
+    }
+
+    fn process_exit_cancel_only(args: account_parser::CancelOnlyArgs) -> DexResult {
+        let account_parser::CancelOnlyArgs {
+            mut market,
+            authorization: _,
+        } = args;
+        market.account_flags &= !(AccountFlag::CancelOnly as u64);
+        Ok(())
+    }
+
+    fn process_propose_authority(args: account_parser::ProposeAuthorityArgs) -> DexResult {
+        let account_parser::ProposeAuthorityArgs {
+            instruction,
+            mut market,
+            authorization: _,
+        } = args;
+        let state = match &mut market {
+            Market::V2(state) => state,
+            Market::V1(_) => check_unreachable!()?,
         };
+        state.pending_authority = Pubkey::new_from_array(instruction.new_authority);
+        state.pending_authority_kind = u8::from(instruction.kind) as u64;
+        Ok(())
+    }
 
-        if deposit_amount != 0 {
-            let balance_before = deposit_vault.balance()?;
-            let deposit_instruction = spl_token::instruction::transfer(
-                &spl_token::ID,
-                deposit_source.inner().key,
-                deposit_vault.inner().key,
-                signer.inner().key,
-                &[],
-                deposit_amount,
-            )
-            .unwrap();
-            invoke_spl_token(
-                &deposit_instruction,
-                &[
-                    deposit_source.inner().clone(),
-                    deposit_vault.inner().clone(),
-                    signer.inner().clone(),
-                    spl_token_program.inner().clone(),
-                ],
-                &[],
-            )
-            .map_err(|err| match err {
-                ProgramError::Custom(i) => match TokenError::from_u32(i) {
-                    Some(TokenError::InsufficientFunds) => DexErrorCode::InsufficientFunds,
-                    _ => DexErrorCode::TransferFailed,
-                },
-                _ => DexErrorCode::TransferFailed,
-            })?;
-            let balance_after = deposit_vault.balance()?;
-            let balance_change = balance_after.checked_sub(balance_before);
-            check_assert_eq!(Some(deposit_amount), balance_change)?;
+    fn process_accept_authority(args: account_parser::AcceptAuthorityArgs) -> DexResult {
+        let account_parser::AcceptAuthorityArgs {
+            mut market,
+            kind,
+            new_authority,
+        } = args;
+        let state = match &mut market {
+            Market::V2(state) => state,
+            Market::V1(_) => check_unreachable!()?,
+        };
+        match kind {
+            AuthorityKind::Admin => state.admin = new_authority,
+            AuthorityKind::OpenOrders => state.open_orders_authority = new_authority,
+            AuthorityKind::Prune => state.prune_authority = new_authority,
+            AuthorityKind::ConsumeEvents => state.consume_events_authority = new_authority,
+            AuthorityKind::Disable => state.disable_authority = new_authority,
+            AuthorityKind::FeeSweeper => state.fee_sweeper = new_authority,
         }
+        state.pending_authority = Pubkey::default();
+        state.pending_authority_kind = 0;
+        Ok(())
+    }
 
-        let nonce = market_state.vault_signer_nonce;
-        let market_pubkey = market_state.pubkey();
-        let vault_signer_seeds = gen_vault_signer_seeds(&nonce, &market_pubkey);
-
-        if withdraw_amount != 0 {
-            send_from_vault(
-                withdraw_amount,
-                withdraw_destination,
-                withdraw_vault,
-                spl_token_program,
-                vault_signer,
-                &vault_signer_seeds,
-            )?;
+    fn process_reset_authority(args: account_parser::ResetAuthorityArgs) -> DexResult {
+        let account_parser::ResetAuthorityArgs {
+            kind,
+            mut market,
+            authorization: _,
+        } = args;
+        let state = match &mut market {
+            Market::V2(state) => state,
+            Market::V1(_) => check_unreachable!()?,
         };
-
+        match kind {
+            AuthorityKind::Disable => state.disable_authority = Pubkey::default(),
+            AuthorityKind::FeeSweeper => state.fee_sweeper = Pubkey::default(),
+            // A default admin reads as the open orders authority, so dropping
+            // the admin would hand its powers to whoever holds that key.
+            AuthorityKind::Admin
+            | AuthorityKind::OpenOrders
+            | AuthorityKind::Prune
+            | AuthorityKind::ConsumeEvents => {
+                return Err(DexErrorCode::AuthorityHasNoDefault.into())
+            }
+        }
         Ok(())
     }
 
-    fn process_prune(args: account_parser::PruneArgs) -> DexResult {
-        let account_parser::PruneArgs {
-            mut order_book_state,
-            open_orders,
-            open_orders_address,
-            mut event_q,
-            limit,
+    fn process_set_price_band(args: account_parser::SetPriceBandArgs) -> DexResult {
+        let account_parser::SetPriceBandArgs {
+            max_deviation_bps,
+            mut market,
+            authorization: _,
         } = args;
-        let open_orders_addr_bytes = open_orders_address.to_aligned_bytes();
-        let (bids_removed, asks_removed) =
-            order_book_state.remove_all(open_orders_addr_bytes, limit)?;
+        match market {
+            Market::V2(ref mut state) => state.price_band_bps = max_deviation_bps as u64,
+            Market::V1(_) => check_unreachable!()?,
+        }
+        Ok(())
+    }
 
-        solana_program::msg!(
-            "Pruned {:?} bids and {:?} asks",
-            bids_removed.len(),
-            asks_removed.len()
-        );
+    fn process_close_market(args: account_parser::CloseMarketArgs) -> DexResult {
+        let account_parser::CloseMarketArgs {
+            market_accounts,
+            trigger_book_acc,
+            dest_acc,
+        } = args;
 
-        for bid in bids_removed {
-            let order_id = open_orders.orders[bid.owner_slot() as usize];
-            order_book_state.cancel_leaf_node(
-                bid,
-                Side::Bid,
-                open_orders,
-                open_orders_addr_bytes,
-                order_id,
-                &mut event_q,
-            )?;
-        }
+        for account in market_accounts.iter().chain(trigger_book_acc) {
+            let dest_starting_lamports = dest_acc.lamports();
+            **dest_acc.lamports.borrow_mut() = dest_starting_lamports
+                .checked_add(account.lamports())
+                .unwrap();
+            **account.lamports.borrow_mut() = 0;
 
-        for ask in asks_removed {
-            let order_id = open_orders.orders[ask.owner_slot() as usize];
-            order_book_state.cancel_leaf_node(
-                ask,
-                Side::Ask,
-                open_orders,
-                open_orders_addr_bytes,
-                order_id,
-                &mut event_q,
-            )?;
+            // Zeroing the data also removes the account padding, so nothing
+            // can load the account again before it is garbage collected.
+            for byte in account.try_borrow_mut_data()?.iter_mut() {
+                *byte = 0;
+            }
         }
 
         Ok(())
@@ -2895,7 +5586,7 @@ impl State {
 
     fn process_close_open_orders(args: account_parser::CloseOpenOrdersArgs) -> DexResult {
         let account_parser::CloseOpenOrdersArgs {
-            open_orders,
+            mut open_orders,
             open_orders_acc,
             dest_acc,
         } = args;
@@ -3005,7 +5696,7 @@ impl State {
         let account_parser::CancelOrderByClientIdV2Args {
             client_order_id,
             open_orders_address,
-            open_orders,
+            mut open_orders,
             open_orders_signer: _,
 
             mut order_book_state,
@@ -3019,7 +5710,7 @@ impl State {
         order_book_state.cancel_order_v2(
             side,
             open_orders_address,
-            open_orders,
+            &mut open_orders,
             order_id,
             &mut event_q,
         )
@@ -3031,7 +5722,7 @@ impl State {
         let account_parser::CancelOrdersByClientIdsArgs {
             client_order_ids,
             open_orders_address,
-            open_orders,
+            mut open_orders,
             open_orders_signer: _,
 
             mut order_book_state,
@@ -3049,7 +5740,7 @@ impl State {
             if let Err(err) = order_book_state.cancel_order_v2(
                 side,
                 open_orders_address,
-                open_orders,
+                &mut open_orders,
                 order_id,
                 &mut event_q,
             ) {
@@ -3079,7 +5770,7 @@ impl State {
             instruction: &CancelOrderInstructionV2 { side, order_id },
 
             open_orders_address,
-            open_orders,
+            mut open_orders,
             open_orders_signer: _,
 
             mut order_book_state,
@@ -3089,12 +5780,38 @@ impl State {
         order_book_state.cancel_order_v2(
             side,
             open_orders_address,
-            open_orders,
+            &mut open_orders,
             order_id,
             &mut event_q,
         )
     }
 
+    // Orders are matched as they are placed, so all that's left to match are
+    // stops armed by fills that ConsumeEvents has seen.
+    fn process_match_orders(args: account_parser::MatchOrdersArgs) -> DexResult {
+        let account_parser::MatchOrdersArgs {
+            limit,
+            mut order_book_state,
+            mut req_q,
+            mut event_q,
+            trigger_book,
+        } = args;
+
+        let mut trigger_book = match trigger_book {
+            None => return Ok(()),
+            Some(trigger_book) => trigger_book,
+        };
+        let events_before = event_q.len();
+        trigger_book.execute(
+            &mut order_book_state,
+            current_timestamp()?,
+            &mut req_q,
+            &mut event_q,
+            events_before,
+            limit,
+        )
+    }
+
     fn process_consume_events(args: account_parser::ConsumeEventsArgs) -> DexResult {
         let account_parser::ConsumeEventsArgs {
             limit,
@@ -3102,6 +5819,7 @@ impl State {
             open_orders_accounts,
             market,
             mut event_q,
+            mut trigger_book,
         } = args;
 
         for _i in 0u16..limit {
@@ -3114,7 +5832,7 @@ impl State {
             let owner: [u64; 4] = event.owner;
             let owner_index: Result<usize, usize> = open_orders_accounts
                 .binary_search_by_key(&owner, |account_info| account_info.key.to_aligned_bytes());
-            let mut open_orders: RefMut<OpenOrders> = match owner_index {
+            let mut open_orders = match owner_index {
                 Err(_) => break,
                 Ok(i) => market.load_orders_mut(
                     &open_orders_accounts[i],
@@ -3125,17 +5843,21 @@ impl State {
                 )?,
             };
 
-            check_assert!(event.owner_slot < 128)?;
-            check_assert_eq!(&open_orders.slot_side(event.owner_slot), &Some(view.side()))?;
-            check_assert_eq!(
-                open_orders.orders[event.owner_slot as usize],
-                event.order_id
-            )?;
+            let owner_slot = event.owner_slot();
+            check_assert!((owner_slot as usize) < open_orders.slot_capacity())?;
+            check_assert_eq!(&open_orders.slot_side(owner_slot), &Some(view.side()))?;
+            check_assert_eq!(open_orders.order_id(owner_slot), event.order_id)?;
+
+            if let Some(trigger_book) = trigger_book.as_mut() {
+                let (seq_num, _) = event_q.events_since(0).next().ok_or(assertion_error!())?;
+                trigger_book.arm(seq_num, event);
+            }
 
             match event.as_view()? {
                 EventView::Fill {
                     side,
                     maker,
+                    release_funds,
                     native_qty_paid,
                     native_qty_received,
                     native_fee_or_rebate,
@@ -3157,6 +5879,17 @@ impl State {
                             open_orders.native_pc_total += native_qty_received;
                             open_orders.native_pc_free += native_qty_received;
                         }
+                        // Takers settled through the queue, i.e. triggered orders.
+                        Side::Bid if release_funds => {
+                            open_orders.native_pc_total -= native_qty_paid;
+                            open_orders.native_coin_total += native_qty_received;
+                            open_orders.native_coin_free += native_qty_received;
+                        }
+                        Side::Ask if release_funds => {
+                            open_orders.native_coin_total -= native_qty_paid;
+                            open_orders.native_pc_total += native_qty_received;
+                            open_orders.native_pc_free += native_qty_received;
+                        }
                         _ => (),
                     };
                     if !maker {
@@ -3164,10 +5897,7 @@ impl State {
                         open_orders.referrer_rebates_accrued += referrer_rebate;
                     }
                     if let Some(client_id) = client_order_id {
-                        debug_assert_eq!(
-                            client_id.get(),
-                            identity(open_orders.client_order_ids[owner_slot as usize])
-                        );
+                        debug_assert_eq!(client_id.get(), open_orders.client_order_id(owner_slot));
                     }
                 }
                 EventView::Out {
@@ -3179,6 +5909,7 @@ impl State {
                     owner: _,
                     owner_slot,
                     client_order_id,
+                    reason: _,
                 } => {
                     let fully_out = native_qty_still_locked == 0;
 
@@ -3201,15 +5932,13 @@ impl State {
                         }
                     };
                     if let Some(client_id) = client_order_id {
-                        debug_assert_eq!(
-                            client_id.get(),
-                            identity(open_orders.client_order_ids[owner_slot as usize])
-                        );
+                        debug_assert_eq!(client_id.get(), open_orders.client_order_id(owner_slot));
                     }
                     if fully_out {
                         open_orders.remove_order(owner_slot)?;
                     }
                 }
+                EventView::FillDetail { .. } => (),
             };
 
             event_q
@@ -3221,6 +5950,41 @@ impl State {
 
     #[cfg(feature = "program")]
     fn process_new_order_v3(args: account_parser::NewOrderV3Args) -> DexResult {
+        Self::new_order_v3(args, None, 0)
+    }
+
+    #[cfg(feature = "program")]
+    fn process_new_iceberg_order(
+        args: account_parser::NewOrderV3Args,
+        display_qty: NonZeroU64,
+    ) -> DexResult {
+        match args.instruction.order_type {
+            OrderType::Limit | OrderType::PostOnly | OrderType::PostOnlySlide => (),
+            _ => return Err(DexErrorCode::InvalidIcebergOrder.into()),
+        }
+        Self::new_order_v3(args, Some(display_qty), 0)
+    }
+
+    #[cfg(feature = "program")]
+    fn process_new_order_with_expiry(
+        args: account_parser::NewOrderV3Args,
+        expiry_ts: i64,
+    ) -> DexResult {
+        // 0 means the order never expires.
+        let expiry_ts = match expiry_ts.try_into() {
+            Ok(0) | Err(_) => return Err(DexErrorCode::InvalidOrderExpiry.into()),
+            Ok(expiry_ts) => expiry_ts,
+        };
+        Self::new_order_v3(args, None, expiry_ts)
+    }
+
+    // A nonzero `expiry_ts` is when the order's resting part expires.
+    #[cfg(feature = "program")]
+    fn new_order_v3(
+        args: account_parser::NewOrderV3Args,
+        display_qty: Option<NonZeroU64>,
+        expiry_ts: u32,
+    ) -> DexResult {
         let account_parser::NewOrderV3Args {
             instruction,
             mut order_book_state,
@@ -3234,71 +5998,46 @@ impl State {
             pc_vault,
             spl_token_program,
             fee_tier,
+            mut trigger_book,
+            tick_size,
         } = args;
 
-        if instruction.max_ts < i64::MAX {
-            // Dynamic sysvars don't work in unit tests.
-            #[cfg(any(test, feature = "fuzz"))]
-            let cur_ts = 1_650_000_000;
-            #[cfg(not(any(test, feature = "fuzz")))]
-            let cur_ts = Clock::get()?.unix_timestamp;
-            if cur_ts > instruction.max_ts {
-                return Err(DexErrorCode::OrderMaxTimestampExceeded.into());
-            }
+        let now = current_timestamp()?;
+        if now > instruction.max_ts || (expiry_ts != 0 && now > i64::from(expiry_ts)) {
+            return Err(DexErrorCode::OrderMaxTimestampExceeded.into());
         }
 
-        let open_orders_mut = open_orders.deref_mut();
+        let open_orders_mut = &mut open_orders;
 
         check_assert_eq!(req_q.header.count(), 0)?;
 
-        let deposit_amount;
-        let deposit_vault;
-
-        let native_pc_qty_locked;
-        match instruction.side {
-            Side::Bid => {
-                let lock_qty_native = instruction.max_native_pc_qty_including_fees;
-                native_pc_qty_locked = Some(lock_qty_native);
-                let free_qty_to_lock = lock_qty_native.get().min(open_orders_mut.native_pc_free);
-                deposit_amount = lock_qty_native.get() - free_qty_to_lock;
-                deposit_vault = pc_vault.token_account();
-                if payer.balance()? < deposit_amount {
-                    return Err(DexErrorCode::InsufficientFunds.into());
-                }
-                open_orders_mut.lock_free_pc(free_qty_to_lock);
-                open_orders_mut.credit_locked_pc(deposit_amount);
-                order_book_state.market_state.pc_deposits_total = order_book_state
-                    .market_state
-                    .pc_deposits_total
-                    .checked_add(deposit_amount)
-                    .unwrap();
-            }
-            Side::Ask => {
-                native_pc_qty_locked = None;
-                let lock_qty_native = instruction
-                    .max_coin_qty
-                    .get()
-                    .checked_mul(order_book_state.market_state.coin_lot_size)
-                    .ok_or(DexErrorCode::InsufficientFunds)?;
-                let free_qty_to_lock = lock_qty_native.min(open_orders_mut.native_coin_free);
-                deposit_amount = lock_qty_native - free_qty_to_lock;
-                deposit_vault = coin_vault.token_account();
-                if payer.balance()? < deposit_amount {
-                    return Err(DexErrorCode::InsufficientFunds.into());
-                }
-                open_orders_mut.lock_free_coin(free_qty_to_lock);
-                open_orders_mut.credit_locked_coin(deposit_amount);
-                order_book_state.market_state.coin_deposits_total = order_book_state
-                    .market_state
-                    .coin_deposits_total
-                    .checked_add(deposit_amount)
-                    .unwrap();
-            }
+        let (deposit_vault, native_pc_qty_locked) = match instruction.side {
+            Side::Bid => (
+                pc_vault.token_account(),
+                Some(instruction.max_native_pc_qty_including_fees),
+            ),
+            Side::Ask => (coin_vault.token_account(), None),
         };
+        let deposit_amount = lock_order_funds(
+            open_orders_mut,
+            order_book_state.market_state,
+            payer,
+            instruction.side,
+            instruction.max_coin_qty,
+            instruction.max_native_pc_qty_including_fees,
+        )?;
 
-        let order_id = req_q.gen_order_id(instruction.limit_price.get(), instruction.side);
+        let limit_price = match instruction.order_type {
+            OrderType::PostOnlySlide => order_book_state.slide_post_only_price(
+                instruction.side,
+                instruction.limit_price,
+                tick_size,
+            ),
+            _ => instruction.limit_price,
+        };
+        let order_id = req_q.gen_order_id(limit_price.get(), instruction.side);
         let owner_slot = open_orders_mut.add_order(order_id, instruction.side)?;
-        open_orders_mut.client_order_ids[owner_slot as usize] = instruction.client_order_id;
+        open_orders_mut.set_client_order_id(owner_slot, instruction.client_order_id);
 
         let mut proceeds = RequestProceeds::zero();
 
@@ -3313,10 +6052,14 @@ impl State {
             max_coin_qty: instruction.max_coin_qty,
             native_pc_qty_locked,
             client_order_id: NonZeroU64::new(instruction.client_order_id),
+            expiry_ts,
         };
+        let events_before = event_q.len();
         let mut limit = instruction.limit;
         let unfilled_portion = order_book_state.process_orderbook_request(
             &request,
+            now,
+            &mut req_q,
             &mut event_q,
             &mut proceeds,
             &mut limit,
@@ -3324,6 +6067,21 @@ impl State {
 
         check_assert!(unfilled_portion.is_none())?;
 
+        if let Some(display_qty) = display_qty {
+            order_book_state.hide_reserve(instruction.side, order_id, display_qty)?;
+        }
+
+        if let Some(trigger_book) = trigger_book.as_mut() {
+            trigger_book.execute(
+                &mut order_book_state,
+                now,
+                &mut req_q,
+                &mut event_q,
+                events_before,
+                TRIGGERED_ORDERS_PER_ORDER,
+            )?;
+        }
+
         {
             let coin_lot_size = order_book_state.market_state.coin_lot_size;
 
@@ -3369,43 +6127,117 @@ impl State {
         // which would cause an error (as there would be two borrows while
         // one of them is mutable).
 
-        use solana_program::clock::Clock;
-
         drop(open_orders);
 
-        if deposit_amount != 0 {
-            let balance_before = deposit_vault.balance()?;
-            let deposit_instruction = spl_token::instruction::transfer(
-                &spl_token::ID,
-                payer.inner().key,
-                deposit_vault.inner().key,
-                owner.inner().key,
-                &[],
-                deposit_amount,
-            )
-            .unwrap();
-            invoke_spl_token(
-                &deposit_instruction,
-                &[
-                    payer.inner().clone(),
-                    deposit_vault.inner().clone(),
-                    owner.inner().clone(),
-                    spl_token_program.inner().clone(),
-                ],
-                &[],
-            )
-            .map_err(|err| match err {
-                ProgramError::Custom(i) => match TokenError::from_u32(i) {
-                    Some(TokenError::InsufficientFunds) => DexErrorCode::InsufficientFunds,
-                    _ => DexErrorCode::TransferFailed,
-                },
-                _ => DexErrorCode::TransferFailed,
-            })?;
-            let balance_after = deposit_vault.balance()?;
-            let balance_change = balance_after.checked_sub(balance_before);
-            check_assert_eq!(Some(deposit_amount), balance_change)?;
+        deposit_into_vault(
+            deposit_amount,
+            payer,
+            deposit_vault,
+            owner,
+            spl_token_program,
+        )?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "program")]
+    fn process_new_trigger_order(args: account_parser::NewTriggerOrderArgs) -> DexResult {
+        let account_parser::NewTriggerOrderArgs {
+            instruction,
+            mut market,
+            mut open_orders,
+            open_orders_address,
+            owner,
+            mut req_q,
+            event_seq_num,
+            mut trigger_book,
+            payer,
+            coin_vault,
+            pc_vault,
+            spl_token_program,
+            fee_tier,
+        } = args;
+
+        // A triggered order is matched inside someone else's transaction,
+        // so it must neither rest without taking nor be able to abort it.
+        if instruction.order_type == OrderType::PostOnly
+            || instruction.order_type == OrderType::PostOnlySlide
+            || instruction.order_type == OrderType::FillOrKill
+            || instruction.self_trade_behavior == SelfTradeBehavior::AbortTransaction
+        {
+            return Err(DexErrorCode::InvalidTriggerOrder.into());
         }
 
+        let open_orders_mut = &mut open_orders;
+
+        let (deposit_vault, native_pc_qty_locked) = match instruction.side {
+            Side::Bid => (
+                pc_vault.token_account(),
+                Some(instruction.max_native_pc_qty_including_fees),
+            ),
+            Side::Ask => (coin_vault.token_account(), None),
+        };
+        let deposit_amount = lock_order_funds(
+            open_orders_mut,
+            market.deref_mut(),
+            payer,
+            instruction.side,
+            instruction.max_coin_qty,
+            instruction.max_native_pc_qty_including_fees,
+        )?;
+
+        let order_id = req_q.gen_order_id(instruction.limit_price.get(), instruction.side);
+        let owner_slot = open_orders_mut.add_order(order_id, instruction.side)?;
+        open_orders_mut.set_client_order_id(owner_slot, instruction.client_order_id);
+
+        trigger_book.insert(TriggerOrder::new(
+            instruction,
+            order_id,
+            open_orders_address,
+            owner_slot,
+            fee_tier,
+            native_pc_qty_locked,
+            event_seq_num,
+        )?)?;
+
+        drop(open_orders);
+
+        deposit_into_vault(
+            deposit_amount,
+            payer,
+            deposit_vault,
+            owner,
+            spl_token_program,
+        )?;
+
+        Ok(())
+    }
+
+    fn process_cancel_trigger_order(args: account_parser::CancelTriggerOrderArgs) -> DexResult {
+        let account_parser::CancelTriggerOrderArgs {
+            order_id,
+            market,
+            mut trigger_book,
+            mut open_orders,
+            open_orders_address,
+        } = args;
+
+        let order = trigger_book.remove(open_orders_address, order_id)?;
+        let owner_slot = order.owner_slot();
+        check_assert_eq!(identity(open_orders.order_id(owner_slot)), order_id)?;
+        check_assert_eq!(open_orders.slot_side(owner_slot), Some(order.side()))?;
+
+        match order.side() {
+            Side::Bid => open_orders.unlock_pc(order.native_pc_qty_locked),
+            Side::Ask => open_orders.unlock_coin(
+                order
+                    .max_coin_qty
+                    .checked_mul(market.coin_lot_size)
+                    .ok_or(assertion_error!())?,
+            ),
+        };
+        open_orders.remove_order(owner_slot)?;
+
         Ok(())
     }
 
@@ -3439,6 +6271,91 @@ impl State {
         Ok(())
     }
 
+    #[cfg(feature = "program")]
+    fn process_amend_order(args: account_parser::AmendOrderArgs) -> DexResult {
+        let account_parser::AmendOrderArgs {
+            program_id,
+            instruction,
+            cancel_accounts,
+            accounts,
+        } = args;
+        let &AmendOrderInstruction {
+            side,
+            order_id,
+            limit_price,
+            max_coin_qty,
+            max_native_pc_qty_including_fees,
+            self_trade_behavior,
+            order_type,
+            limit,
+        } = instruction;
+
+        let cancel_instruction = CancelOrderInstructionV2 { side, order_id };
+        let requeued = account_parser::CancelOrderV2Args::with_parsed_args(
+            program_id,
+            cancel_accounts,
+            &cancel_instruction,
+            |args| {
+                let account_parser::CancelOrderV2Args {
+                    open_orders_address,
+                    mut open_orders,
+                    mut order_book_state,
+                    mut event_q,
+                    ..
+                } = args;
+                let requeued = order_book_state.amend_order_in_place(
+                    side,
+                    open_orders_address,
+                    &mut open_orders,
+                    order_id,
+                    limit_price,
+                    max_coin_qty,
+                    &mut event_q,
+                )?;
+                let requeued = requeued
+                    .map(|leaf_node| (leaf_node, order_book_state.expiry_ts(side, order_id)));
+                if requeued.is_some() {
+                    // Fail rather than drop the order, so it keeps resting.
+                    if order_type == OrderType::PostOnly
+                        && order_book_state.would_cross(side, limit_price)
+                    {
+                        return Err(DexErrorCode::InvalidAmendOrder.into());
+                    }
+                    order_book_state.cancel_order_v2(
+                        side,
+                        open_orders_address,
+                        &mut open_orders,
+                        order_id,
+                        &mut event_q,
+                    )?;
+                }
+                Ok(requeued)
+            },
+        )?;
+
+        if let Some((leaf_node, expiry_ts)) = requeued {
+            let new_order = NewOrderInstructionV3 {
+                side,
+                limit_price,
+                max_coin_qty,
+                max_native_pc_qty_including_fees,
+                order_type,
+                client_order_id: leaf_node.client_order_id(),
+                self_trade_behavior,
+                limit,
+                max_ts: i64::MAX,
+            };
+            account_parser::NewOrderV3Args::with_parsed_args(
+                program_id,
+                &new_order,
+                accounts,
+                |args| Self::new_order_v3(args, None, expiry_ts),
+            )?;
+        }
+
+        Ok(())
+    }
+
     fn process_disable_market(args: account_parser::DisableMarketArgs) -> DexResult {
         let account_parser::DisableMarketArgs {
             market,
@@ -3481,6 +6398,8 @@ impl State {
             fee_rate_bps,
             vault_signer_nonce,
             pc_dust_threshold,
+            tick_size,
+            min_base_qty,
         } = args.instruction;
 
         let market = args.get_market();
@@ -3496,6 +6415,14 @@ impl State {
         let prune_authority = args.prune_authority;
         let consume_events_authority = args.consume_events_authority;
 
+        // Only permissioned markets have room to store order size limits.
+        if tick_size == 0
+            || min_base_qty == 0
+            || (market_authority.is_none() && (tick_size != 1 || min_base_qty != 1))
+        {
+            Err(DexErrorCode::InvalidMarketParameters)?
+        }
+
         // initialize request queue
         let mut rq_data = req_q.try_borrow_mut_data()?;
         const RQ_HEADER_WORDS: usize = size_of::<RequestQueueHeader>() / size_of::<u64>();
@@ -3542,7 +6469,7 @@ impl State {
             *ob_hdr = OrderBookStateHeader {
                 account_flags: (AccountFlag::Initialized | *flag).bits(),
             };
-            let slab = Slab::new(cast_slice_mut(slab_words));
+            let slab = Slab::new(cast_slice_mut(slab_words))?;
             slab.assert_minimum_capacity(100)?;
         }
         // initialize market
@@ -3592,8 +6519,11 @@ impl State {
                     try_from_bytes_mut(cast_slice_mut(market_view)).or(check_unreachable!())?;
                 market_hdr.inner = market_state;
                 market_hdr.open_orders_authority = *oo_auth.key;
+                market_hdr.admin = *oo_auth.key;
                 market_hdr.prune_authority =
                     prune_authority.map(|p| *p.key).unwrap_or(Pubkey::default());
+                market_hdr.tick_size = tick_size;
+                market_hdr.min_base_qty = min_base_qty;
 
                 if let Some(consume_events_authority) = consume_events_authority {
                     market_hdr.consume_events_authority = *consume_events_authority.key;
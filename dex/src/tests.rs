@@ -1,13 +1,16 @@
-use std::convert::identity;
+use std::convert::{identity, TryInto};
 use std::mem::size_of;
 use std::num::NonZeroU64;
 
 use bumpalo::{collections::Vec as BumpVec, vec as bump_vec, Bump};
+use bytemuck::cast_slice;
 use rand::prelude::*;
 use safe_transmute::to_bytes::{transmute_to_bytes, transmute_to_bytes_mut};
 use solana_program::account_info::AccountInfo;
 use solana_program::bpf_loader;
 use solana_program::clock::Epoch;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::program_error::ProgramError;
 use solana_program::program_pack::Pack;
 use solana_program::pubkey::Pubkey;
 use solana_program::rent::Rent;
@@ -16,13 +19,24 @@ use solana_program::sysvar;
 use solana_program::sysvar::Sysvar;
 use spl_token::state::{Account, AccountState, Mint};
 
-use instruction::{initialize_market, MarketInstruction, NewOrderInstructionV3, SelfTradeBehavior};
-use matching::{OrderType, Side};
-use state::{Market, MarketState, OpenOrders, State, ToAlignedBytes};
+use fees::FeeTier;
+use instruction::{
+    disable_authority, initialize_market, AmendOrderInstruction, AuthorityKind,
+    CancelAllOrdersInstruction, CancelOrderInstructionV2, MarketInstruction,
+    NewIcebergOrderInstruction, NewOrderInstructionV3, NewOrderWithExpiryInstruction,
+    NewTriggerOrderInstruction, SelfTradeBehavior, UpdateFeeScheduleInstruction,
+};
+use matching::{OrderType, PriceBand, Side};
+use state::{
+    load_book_side, quote_send_take, AccountFlag, Event, EventQueueHeader, EventView, Market,
+    MarketState, MarketStateV2, OpenOrders, OpenOrdersV2, OutReason, State, ToAlignedBytes,
+    TriggerBook, TriggerBookHeader, TriggerOrder,
+};
 
-use crate::critbit::SlabView;
-use crate::error::DexErrorCode;
-use crate::instruction::SendTakeInstruction;
+use crate::critbit::{L2Level, LeafNode, SlabView};
+use crate::error::{AssertionError, DecodedDexError, DexError, DexErrorCode, SourceFileId};
+use crate::instruction::{ProposeAuthorityInstruction, SendTakeInstruction};
+use crate::simulator::{Simulator, SimulatorConfig};
 use crate::state::account_parser::TokenAccount;
 
 use super::*;
@@ -169,6 +183,14 @@ fn new_spl_token_program<'bump>(bump: &'bump Bump) -> AccountInfo<'bump> {
 }
 
 fn setup_market<'bump, R: Rng>(rng: &mut R, bump: &'bump Bump) -> MarketAccounts<'bump> {
+    setup_market_with_authority(rng, bump, None)
+}
+
+fn setup_market_with_authority<'bump, R: Rng>(
+    rng: &mut R,
+    bump: &'bump Bump,
+    market_authority: Option<&AccountInfo<'bump>>,
+) -> MarketAccounts<'bump> {
     let program_id = random_pubkey(rng, bump);
 
     let mut i: u64 = 0;
@@ -186,7 +208,7 @@ fn setup_market<'bump, R: Rng>(rng: &mut R, bump: &'bump Bump) -> MarketAccounts
         };
         let vault_signer = AccountInfo::new(
             bump.alloc(vault_signer_pk),
-            true,
+            false,
             false,
             bump.alloc(1000000),
             &mut [],
@@ -197,12 +219,16 @@ fn setup_market<'bump, R: Rng>(rng: &mut R, bump: &'bump Bump) -> MarketAccounts
         break (market, i, vault_signer);
     };
 
+    let market_size = match market_authority {
+        None => size_of::<MarketState>(),
+        Some(_) => size_of::<MarketStateV2>(),
+    };
     let market = AccountInfo::new(
         bump.alloc(market_key),
         false,
         true,
         bump.alloc(60_000_000_000),
-        allocate_dex_owned_account(size_of::<MarketState>(), bump),
+        allocate_dex_owned_account(market_size, bump),
         program_id,
         false,
         Epoch::default(),
@@ -232,7 +258,7 @@ fn setup_market<'bump, R: Rng>(rng: &mut R, bump: &'bump Bump) -> MarketAccounts
         &pc_mint.key,
         &coin_vault.key,
         &pc_vault.key,
-        None,
+        market_authority.map(|authority| authority.key),
         None,
         None,
         &bids.key,
@@ -243,11 +269,13 @@ fn setup_market<'bump, R: Rng>(rng: &mut R, bump: &'bump Bump) -> MarketAccounts
         pc_lot_size,
         vault_signer_nonce,
         pc_dust_threshold,
+        1,
+        1,
     )
     .unwrap();
 
     {
-        let accounts: &'bump [AccountInfo<'bump>] = bump_vec![in bump;
+        let mut accounts = bump_vec![in bump;
             market.clone(),
             req_q.clone(),
             event_q.clone(),
@@ -258,9 +286,9 @@ fn setup_market<'bump, R: Rng>(rng: &mut R, bump: &'bump Bump) -> MarketAccounts
             coin_mint.clone(),
             pc_mint.clone(),
             rent_sysvar.clone(),
-        ]
-        .into_bump_slice_mut();
-        State::process(&program_id, accounts, &init_instruction.data).unwrap();
+        ];
+        accounts.extend(market_authority.cloned());
+        State::process(&program_id, accounts.as_slice(), &init_instruction.data).unwrap();
     }
 
     MarketAccounts {
@@ -368,7 +396,7 @@ fn get_bbo(
     bids_a: &AccountInfo,
     asks_a: &AccountInfo,
 ) -> BBO {
-    let mkt = MarketState::load(market, program_id, false).unwrap();
+    let mkt = Market::load(market, program_id, false).unwrap();
     let bids = mkt.load_bids_mut(bids_a).unwrap();
     let asks = mkt.load_asks_mut(asks_a).unwrap();
     let (ask, nask, seller) = match asks.find_min() {
@@ -395,6 +423,26 @@ fn get_bbo(
     }
 }
 
+fn new_trigger_book<'bump, R: Rng>(
+    rng: &mut R,
+    accounts: &MarketAccounts<'bump>,
+    admin: &AccountInfo<'bump>,
+    bump: &'bump Bump,
+) -> AccountInfo<'bump> {
+    let program_id = accounts.market.owner;
+    let trigger_book = new_dex_owned_account(rng, TriggerBook::DATA_LEN, program_id, bump);
+    let init_accounts: &[AccountInfo] = bump_vec![in bump;
+        accounts.market.clone(),
+        trigger_book.clone(),
+        admin.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::InitTriggerBook.pack();
+    State::process(program_id, init_accounts, &instruction_data).unwrap();
+    trigger_book
+}
+
 #[test]
 fn test_initialize_market() {
     let mut rng = StdRng::seed_from_u64(0);
@@ -1428,3 +1476,4214 @@ fn test_replace_orders() {
         assert_eq!(identity(open_orders.native_pc_total), 260_000);
     }
 }
+
+#[test]
+fn test_trigger_orders() {
+    let mut rng = StdRng::seed_from_u64(4);
+    let bump = Bump::new();
+
+    let market_authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let accounts = setup_market_with_authority(&mut rng, &bump, Some(&market_authority));
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let maker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_maker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let maker_coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, maker.key, 25_000, &bump);
+    let stop_owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_stop =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let taker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_taker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    for (open_orders, owner) in [
+        (&orders_account_maker, &maker),
+        (&orders_account_stop, &stop_owner),
+        (&orders_account_taker, &taker),
+    ] {
+        let init_accounts: &[AccountInfo] = bump_vec![in &bump;
+            open_orders.clone(),
+            owner.clone(),
+            accounts.market.clone(),
+            accounts.rent_sysvar.clone(),
+            market_authority.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::InitOpenOrders.pack();
+        State::process(dex_program_id, init_accounts, &instruction_data).unwrap();
+    }
+    let instruction_accounts = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_maker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        maker_coin_account.clone(),
+        maker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ];
+    layer_orders(
+        dex_program_id,
+        10_100,
+        11_100,
+        200,
+        1,
+        1,
+        Side::Ask,
+        instruction_accounts.as_slice(),
+    );
+
+    let trigger_book = new_trigger_book(&mut rng, &accounts, &market_authority, &bump);
+
+    // Stop-buy 2 lots up to 10_500 once the market trades at 10_100.
+    let stop_pc_account = new_token_account(
+        &mut rng,
+        accounts.pc_mint.key,
+        stop_owner.key,
+        100_000,
+        &bump,
+    );
+    let stop_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_stop.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        trigger_book.clone(),
+        stop_pc_account.clone(),
+        stop_owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let stop_instruction = |trigger_price: u64| {
+        MarketInstruction::NewTriggerOrder(NewTriggerOrderInstruction {
+            side: Side::Bid,
+            trigger_price: NonZeroU64::new(trigger_price).unwrap(),
+            limit_price: NonZeroU64::new(10_500).unwrap(),
+            max_coin_qty: NonZeroU64::new(2).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(30_000).unwrap(),
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            order_type: OrderType::ImmediateOrCancel,
+            client_order_id: 0xface,
+        })
+        .pack()
+    };
+    State::process(dex_program_id, stop_accounts, &stop_instruction(10_100)).unwrap();
+    let stop_pc_balance = TokenAccount::new(&stop_pc_account)
+        .unwrap()
+        .balance()
+        .unwrap();
+    assert_eq!(stop_pc_balance, 70_000);
+
+    // A taker lifting the 10_100 ask arms the stop, which then sweeps the
+    // next level in the same transaction.
+    let taker_pc_account =
+        new_token_account(&mut rng, accounts.pc_mint.key, taker.key, 100_000, &bump);
+    let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+        side: Side::Bid,
+        limit_price: NonZeroU64::new(10_100).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(20_000).unwrap(),
+        order_type: OrderType::ImmediateOrCancel,
+        client_order_id: 0,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        limit: 5,
+        max_ts: i64::MAX,
+    })
+    .pack();
+    let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_taker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        taker_pc_account.clone(),
+        taker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+        trigger_book.clone(),
+    ]
+    .into_bump_slice();
+    State::process(dex_program_id, instruction_accounts, &instruction_data).unwrap();
+
+    let BBO { ask, nask, .. } = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!(ask, 10_500);
+    assert_eq!(nask, 3);
+
+    {
+        let crank_accounts = bump_vec![in &bump;
+            orders_account_maker.clone(),
+            orders_account_taker.clone(),
+            orders_account_stop.clone(),
+            accounts.market.clone(),
+            accounts.event_q.clone(),
+            taker_pc_account.clone(),
+            taker_pc_account.clone(),
+            trigger_book.clone(),
+        ]
+        .into_bump_slice_mut();
+        crank_accounts[0..3].sort_by_key(|account_info| account_info.key.to_aligned_bytes());
+        let instruction_data = MarketInstruction::ConsumeEvents(200).pack();
+        State::process(dex_program_id, crank_accounts, &instruction_data).unwrap();
+    }
+
+    {
+        let open_orders_stop = Market::load(&accounts.market, dex_program_id, false)
+            .unwrap()
+            .load_orders_mut(&orders_account_stop, None, dex_program_id, None, None)
+            .unwrap();
+        assert_eq!(identity(open_orders_stop.native_coin_free), 2_000);
+        assert_eq!(identity(open_orders_stop.native_coin_total), 2_000);
+        assert_eq!(identity(open_orders_stop.native_pc_free), 9_391);
+        assert_eq!(identity(open_orders_stop.native_pc_total), 9_391);
+        assert_eq!(identity(open_orders_stop.free_slot_bits), u128::MAX);
+    }
+
+    // A stop that never triggers can be cancelled to release its funds.
+    State::process(dex_program_id, stop_accounts, &stop_instruction(20_000)).unwrap();
+    let order_id = {
+        let open_orders_stop = Market::load(&accounts.market, dex_program_id, false)
+            .unwrap()
+            .load_orders_mut(&orders_account_stop, None, dex_program_id, None, None)
+            .unwrap();
+        assert_eq!(identity(open_orders_stop.native_pc_free), 0);
+        assert_eq!(identity(open_orders_stop.native_pc_total), 30_000);
+        identity(open_orders_stop.orders[0])
+    };
+    let cancel_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        trigger_book.clone(),
+        orders_account_stop.clone(),
+        stop_owner.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::CancelTriggerOrder(order_id).pack();
+    State::process(dex_program_id, cancel_accounts, &instruction_data).unwrap();
+    assert_eq!(
+        State::process(dex_program_id, cancel_accounts, &instruction_data),
+        Err(DexErrorCode::TriggerOrderNotFound.into())
+    );
+    {
+        let open_orders_stop = Market::load(&accounts.market, dex_program_id, false)
+            .unwrap()
+            .load_orders_mut(&orders_account_stop, None, dex_program_id, None, None)
+            .unwrap();
+        assert_eq!(identity(open_orders_stop.native_pc_free), 30_000);
+        assert_eq!(identity(open_orders_stop.native_pc_total), 30_000);
+        assert_eq!(identity(open_orders_stop.free_slot_bits), u128::MAX);
+    }
+
+    // Stops armed by the same fill are released in price order, not in the
+    // order of their slots: the stop with the lower trigger price goes
+    // first and takes both lots left at 10_500.
+    let stop_instruction = |trigger_price: u64, limit_price: u64| {
+        MarketInstruction::NewTriggerOrder(NewTriggerOrderInstruction {
+            side: Side::Bid,
+            trigger_price: NonZeroU64::new(trigger_price).unwrap(),
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(3).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(33_000).unwrap(),
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            order_type: OrderType::ImmediateOrCancel,
+            client_order_id: 0,
+        })
+        .pack()
+    };
+    State::process(
+        dex_program_id,
+        stop_accounts,
+        &stop_instruction(10_500, 10_500),
+    )
+    .unwrap();
+    State::process(
+        dex_program_id,
+        stop_accounts,
+        &stop_instruction(10_400, 10_700),
+    )
+    .unwrap();
+    let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+        side: Side::Bid,
+        limit_price: NonZeroU64::new(10_500).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(20_000).unwrap(),
+        order_type: OrderType::ImmediateOrCancel,
+        client_order_id: 0,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        limit: 5,
+        max_ts: i64::MAX,
+    })
+    .pack();
+    State::process(dex_program_id, instruction_accounts, &instruction_data).unwrap();
+    let BBO { ask, nask, .. } = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!(ask, 10_700);
+    assert_eq!(nask, 3);
+}
+
+#[test]
+fn test_trigger_orders_armed_by_consume_events() {
+    let mut rng = StdRng::seed_from_u64(4);
+    let bump = Bump::new();
+
+    let market_authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let accounts = setup_market_with_authority(&mut rng, &bump, Some(&market_authority));
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let maker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_maker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let maker_coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, maker.key, 25_000, &bump);
+    let stop_owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_stop =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let taker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_taker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    for (open_orders, owner) in [
+        (&orders_account_maker, &maker),
+        (&orders_account_stop, &stop_owner),
+        (&orders_account_taker, &taker),
+    ] {
+        let init_accounts: &[AccountInfo] = bump_vec![in &bump;
+            open_orders.clone(),
+            owner.clone(),
+            accounts.market.clone(),
+            accounts.rent_sysvar.clone(),
+            market_authority.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::InitOpenOrders.pack();
+        State::process(dex_program_id, init_accounts, &instruction_data).unwrap();
+    }
+    let instruction_accounts = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_maker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        maker_coin_account.clone(),
+        maker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ];
+    layer_orders(
+        dex_program_id,
+        10_100,
+        11_100,
+        200,
+        1,
+        1,
+        Side::Ask,
+        instruction_accounts.as_slice(),
+    );
+
+    // Only the market admin can create the trigger book, and only one of
+    // the fixed size.
+    let init_trigger_book = |trigger_book, admin| {
+        let init_accounts = [
+            accounts.market.clone(),
+            AccountInfo::clone(trigger_book),
+            AccountInfo::clone(admin),
+            accounts.rent_sysvar.clone(),
+        ];
+        let instruction_data = MarketInstruction::InitTriggerBook.pack();
+        State::process(dex_program_id, &init_accounts, &instruction_data)
+    };
+    let small_trigger_book = new_dex_owned_account(
+        &mut rng,
+        size_of::<TriggerBookHeader>() + 4 * size_of::<TriggerOrder>(),
+        dex_program_id,
+        &bump,
+    );
+    assert_eq!(
+        init_trigger_book(&small_trigger_book, &market_authority),
+        Err(DexErrorCode::WrongTriggerBookAccount.into())
+    );
+    let trigger_book =
+        new_dex_owned_account(&mut rng, TriggerBook::DATA_LEN, dex_program_id, &bump);
+    assert_eq!(
+        init_trigger_book(&trigger_book, &stop_owner),
+        Err(DexErrorCode::WrongSigner.into())
+    );
+    init_trigger_book(&trigger_book, &market_authority).unwrap();
+    let other_trigger_book =
+        new_dex_owned_account(&mut rng, TriggerBook::DATA_LEN, dex_program_id, &bump);
+    assert_eq!(
+        init_trigger_book(&other_trigger_book, &market_authority),
+        Err(DexErrorCode::WrongTriggerBookAccount.into())
+    );
+
+    let stop_pc_account = new_token_account(
+        &mut rng,
+        accounts.pc_mint.key,
+        stop_owner.key,
+        100_000,
+        &bump,
+    );
+    let stop_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_stop.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        trigger_book.clone(),
+        stop_pc_account.clone(),
+        stop_owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let stop_instruction = |trigger_price: u64| {
+        MarketInstruction::NewTriggerOrder(NewTriggerOrderInstruction {
+            side: Side::Bid,
+            trigger_price: NonZeroU64::new(trigger_price).unwrap(),
+            limit_price: NonZeroU64::new(10_500).unwrap(),
+            max_coin_qty: NonZeroU64::new(2).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(30_000).unwrap(),
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            order_type: OrderType::ImmediateOrCancel,
+            client_order_id: 0xface,
+        })
+        .pack()
+    };
+    State::process(dex_program_id, stop_accounts, &stop_instruction(10_100)).unwrap();
+
+    // The taker crosses the stop's trigger price without the trigger book.
+    let taker_pc_account =
+        new_token_account(&mut rng, accounts.pc_mint.key, taker.key, 100_000, &bump);
+    let taker_order = |limit_price: u64| {
+        MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side: Side::Bid,
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(1).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(20_000).unwrap(),
+            order_type: OrderType::ImmediateOrCancel,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack()
+    };
+    let instruction_data = taker_order(10_100);
+    let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_taker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        taker_pc_account.clone(),
+        taker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    State::process(dex_program_id, instruction_accounts, &instruction_data).unwrap();
+
+    let match_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        trigger_book.clone(),
+    ]
+    .into_bump_slice();
+    let match_orders = MarketInstruction::MatchOrders(5).pack();
+    let best_ask = || {
+        get_bbo(
+            dex_program_id,
+            &accounts.market,
+            &accounts.bids,
+            &accounts.asks,
+        )
+        .ask
+    };
+
+    // Nothing is armed until the crank consumes the fill.
+    State::process(dex_program_id, match_accounts, &match_orders).unwrap();
+    assert_eq!(best_ask(), 10_300);
+
+    let crank_accounts = bump_vec![in &bump;
+        orders_account_maker.clone(),
+        orders_account_taker.clone(),
+        orders_account_stop.clone(),
+        accounts.market.clone(),
+        accounts.event_q.clone(),
+        taker_pc_account.clone(),
+        taker_pc_account.clone(),
+        trigger_book.clone(),
+    ]
+    .into_bump_slice_mut();
+    crank_accounts[0..3].sort_by_key(|account_info| account_info.key.to_aligned_bytes());
+    let consume_events = MarketInstruction::ConsumeEvents(200).pack();
+    State::process(dex_program_id, crank_accounts, &consume_events).unwrap();
+
+    State::process(dex_program_id, match_accounts, &match_orders).unwrap();
+    let BBO { ask, nask, .. } = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!(ask, 10_500);
+    assert_eq!(nask, 3);
+
+    State::process(dex_program_id, crank_accounts, &consume_events).unwrap();
+    {
+        let open_orders_stop = Market::load(&accounts.market, dex_program_id, false)
+            .unwrap()
+            .load_orders_mut(&orders_account_stop, None, dex_program_id, None, None)
+            .unwrap();
+        assert_eq!(identity(open_orders_stop.native_coin_free), 2_000);
+        assert_eq!(identity(open_orders_stop.native_pc_free), 9_391);
+        assert_eq!(identity(open_orders_stop.free_slot_bits), u128::MAX);
+    }
+
+    // A crank that doesn't pass the trigger book still consumes fills, but
+    // they don't arm any stops.
+    State::process(dex_program_id, stop_accounts, &stop_instruction(10_500)).unwrap();
+    let order_id = Market::load(&accounts.market, dex_program_id, false)
+        .unwrap()
+        .load_orders_mut(&orders_account_stop, None, dex_program_id, None, None)
+        .unwrap()
+        .orders[0];
+    let instruction_data = taker_order(10_500);
+    State::process(dex_program_id, instruction_accounts, &instruction_data).unwrap();
+    State::process(
+        dex_program_id,
+        &crank_accounts[..crank_accounts.len() - 1],
+        &consume_events,
+    )
+    .unwrap();
+    State::process(dex_program_id, match_accounts, &match_orders).unwrap();
+    let BBO { ask, nask, .. } = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!(ask, 10_500);
+    assert_eq!(nask, 2);
+    let cancel_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        trigger_book.clone(),
+        orders_account_stop.clone(),
+        stop_owner.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::CancelTriggerOrder(order_id).pack();
+    State::process(dex_program_id, cancel_accounts, &instruction_data).unwrap();
+}
+
+#[test]
+fn test_fill_or_kill_order() {
+    let mut rng = StdRng::seed_from_u64(5);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let maker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_maker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let maker_coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, maker.key, 25_000, &bump);
+    let instruction_accounts = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_maker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        maker_coin_account.clone(),
+        maker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ];
+    layer_orders(
+        dex_program_id,
+        10_100,
+        11_100,
+        200,
+        1,
+        1,
+        Side::Ask,
+        instruction_accounts.as_slice(),
+    );
+
+    let taker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_taker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let taker_pc_account =
+        new_token_account(&mut rng, accounts.pc_mint.key, taker.key, 100_000, &bump);
+    let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_taker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        taker_pc_account.clone(),
+        taker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let fok_bid = |limit_price: u64, max_coin_qty: u64| {
+        MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side: Side::Bid,
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(max_coin_qty).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(50_000).unwrap(),
+            order_type: OrderType::FillOrKill,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack()
+    };
+
+    // Three lots rest at or below 10_300, so this sweeps both levels.
+    State::process(dex_program_id, instruction_accounts, &fok_bid(10_300, 3)).unwrap();
+    let BBO { ask, nask, bid, .. } = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!(ask, 10_500);
+    assert_eq!(nask, 3);
+    assert_eq!(bid, 0);
+
+    // Only three lots rest at 10_500, so a fourth can't be filled.
+    assert_eq!(
+        State::process(dex_program_id, instruction_accounts, &fok_bid(10_500, 4)),
+        Err(DexErrorCode::FillOrKillNotFilled.into())
+    );
+}
+
+#[test]
+fn test_fill_or_kill_self_trade() {
+    let mut rng = StdRng::seed_from_u64(24);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let maker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_maker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let maker_coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, maker.key, 10_000, &bump);
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let orders_account_sibling =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 10_000, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 1_000_000, &bump);
+
+    let mut order_accounts = Vec::new();
+    for (open_orders, payer, owner) in [
+        (&orders_account_maker, &maker_coin_account, &maker),
+        (&orders_account, &coin_account, &owner),
+        (&orders_account, &pc_account, &owner),
+    ] {
+        let accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            open_orders.clone(),
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            payer.clone(),
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+        ]
+        .into_bump_slice();
+        order_accounts.push(accounts);
+    }
+    let (maker_accounts, ask_accounts, bid_accounts) =
+        (order_accounts[0], order_accounts[1], order_accounts[2]);
+    let order = |side, limit_price, max_coin_qty, order_type, self_trade_behavior| {
+        MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(max_coin_qty).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(30_000).unwrap(),
+            order_type,
+            client_order_id: 0,
+            self_trade_behavior,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack()
+    };
+
+    // The owner's own ask sits in front of enough outside liquidity to fill
+    // the FOK bid.
+    let instruction_data = order(
+        Side::Ask,
+        10_300,
+        2,
+        OrderType::Limit,
+        SelfTradeBehavior::AbortTransaction,
+    );
+    State::process(dex_program_id, maker_accounts, &instruction_data).unwrap();
+    let instruction_data = order(
+        Side::Ask,
+        10_100,
+        1,
+        OrderType::Limit,
+        SelfTradeBehavior::AbortTransaction,
+    );
+    State::process(dex_program_id, ask_accounts, &instruction_data).unwrap();
+
+    let fok_bid = |self_trade_behavior| {
+        order(
+            Side::Bid,
+            10_300,
+            1,
+            OrderType::FillOrKill,
+            self_trade_behavior,
+        )
+    };
+    for self_trade_behavior in [
+        SelfTradeBehavior::DecrementTake,
+        SelfTradeBehavior::CancelTake,
+        SelfTradeBehavior::CancelBoth,
+    ] {
+        assert_eq!(
+            State::process(dex_program_id, bid_accounts, &fok_bid(self_trade_behavior)),
+            Err(DexErrorCode::FillOrKillNotFilled.into())
+        );
+    }
+    assert_eq!(
+        State::process(
+            dex_program_id,
+            bid_accounts,
+            &fok_bid(SelfTradeBehavior::AbortTransaction)
+        ),
+        Err(DexErrorCode::WouldSelfTrade.into())
+    );
+
+    // Cancelling the resting ask instead lets the bid fill further up.
+    State::process(
+        dex_program_id,
+        bid_accounts,
+        &fok_bid(SelfTradeBehavior::CancelProvide),
+    )
+    .unwrap();
+    let BBO { ask, nask, bid, .. } = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!((ask, nask, bid), (10_300, 1, 0));
+
+    // The same goes for an ask resting in another of the owner's OpenOrders
+    // accounts, as long as it is passed along.
+    let instruction_data = order(
+        Side::Ask,
+        10_100,
+        1,
+        OrderType::Limit,
+        SelfTradeBehavior::AbortTransaction,
+    );
+    let mut sibling_ask_accounts = ask_accounts.to_vec();
+    sibling_ask_accounts[1] = orders_account_sibling.clone();
+    State::process(dex_program_id, &sibling_ask_accounts, &instruction_data).unwrap();
+    let mut sibling_bid_accounts = bid_accounts.to_vec();
+    sibling_bid_accounts.push(orders_account_sibling.clone());
+    assert_eq!(
+        State::process(
+            dex_program_id,
+            &sibling_bid_accounts,
+            &fok_bid(SelfTradeBehavior::DecrementTake)
+        ),
+        Err(DexErrorCode::FillOrKillNotFilled.into())
+    );
+}
+
+#[test]
+fn test_order_expiry() {
+    let mut rng = StdRng::seed_from_u64(6);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let maker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_maker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let maker_coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, maker.key, 10_000, &bump);
+    let maker_pc_account =
+        new_token_account(&mut rng, accounts.pc_mint.key, maker.key, 10_000, &bump);
+    let ask_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_maker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        maker_coin_account.clone(),
+        maker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let bid_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_maker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        maker_pc_account.clone(),
+        maker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let maker_order =
+        |side: Side, limit_price: u64, max_coin_qty: u64, max_ts: i64| NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(max_coin_qty).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(limit_price * max_coin_qty).unwrap(),
+            order_type: OrderType::Limit,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts,
+        };
+    let with_expiry = |order: NewOrderInstructionV3, expiry_ts: i64| {
+        MarketInstruction::NewOrderWithExpiry(NewOrderWithExpiryInstruction { order, expiry_ts })
+            .pack()
+    };
+    let expiry_ts = 1_650_000_100;
+    for (bad_expiry_ts, error) in [
+        (0, DexErrorCode::InvalidOrderExpiry),
+        (i64::from(u32::MAX) + 1, DexErrorCode::InvalidOrderExpiry),
+        (1_649_999_999, DexErrorCode::OrderMaxTimestampExceeded),
+    ] {
+        assert_eq!(
+            State::process(
+                dex_program_id,
+                ask_accounts,
+                &with_expiry(maker_order(Side::Ask, 10_000, 2, i64::MAX), bad_expiry_ts),
+            ),
+            Err(error.into())
+        );
+    }
+    State::process(
+        dex_program_id,
+        ask_accounts,
+        &with_expiry(maker_order(Side::Ask, 10_000, 2, i64::MAX), expiry_ts),
+    )
+    .unwrap();
+    // `max_ts` only bounds when an order may be placed, so this one never
+    // expires.
+    State::process(
+        dex_program_id,
+        ask_accounts,
+        &MarketInstruction::NewOrderV3(maker_order(Side::Ask, 10_100, 2, expiry_ts)).pack(),
+    )
+    .unwrap();
+    State::process(
+        dex_program_id,
+        bid_accounts,
+        &with_expiry(maker_order(Side::Bid, 9_000, 1, i64::MAX), expiry_ts),
+    )
+    .unwrap();
+
+    state::UNIX_TIMESTAMP.with(|ts| ts.set(expiry_ts + 1));
+
+    // The expired ask at 10_000 is skipped and the taker fills at 10_100.
+    let taker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_taker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let taker_pc_account =
+        new_token_account(&mut rng, accounts.pc_mint.key, taker.key, 100_000, &bump);
+    let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+        side: Side::Bid,
+        limit_price: NonZeroU64::new(10_100).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(20_000).unwrap(),
+        order_type: OrderType::ImmediateOrCancel,
+        client_order_id: 0,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        limit: 5,
+        max_ts: i64::MAX,
+    })
+    .pack();
+    let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_taker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        taker_pc_account.clone(),
+        taker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    State::process(dex_program_id, instruction_accounts, &instruction_data).unwrap();
+
+    let BBO { ask, nask, bid, .. } = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!(ask, 10_100);
+    assert_eq!(nask, 1);
+    assert_eq!(bid, 9_000);
+
+    // Nobody would match the expired bid, so it has to be pruned.
+    let prune_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        accounts.event_q.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::PruneExpiredOrders(10).pack();
+    State::process(dex_program_id, prune_accounts, &instruction_data).unwrap();
+
+    let BBO { ask, bid, .. } = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!(ask, 10_100);
+    assert_eq!(bid, 0);
+
+    {
+        let crank_accounts = bump_vec![in &bump;
+            orders_account_maker.clone(),
+            orders_account_taker.clone(),
+            accounts.market.clone(),
+            accounts.event_q.clone(),
+            taker_pc_account.clone(),
+            taker_pc_account.clone(),
+        ]
+        .into_bump_slice_mut();
+        crank_accounts[0..2].sort_by_key(|account_info| account_info.key.to_aligned_bytes());
+        let instruction_data = MarketInstruction::ConsumeEvents(200).pack();
+        State::process(dex_program_id, crank_accounts, &instruction_data).unwrap();
+    }
+
+    {
+        let open_orders_maker = Market::load(&accounts.market, dex_program_id, false)
+            .unwrap()
+            .load_orders_mut(&orders_account_maker, None, dex_program_id, None, None)
+            .unwrap();
+        assert_eq!(identity(open_orders_maker.native_coin_free), 2_000);
+        assert_eq!(identity(open_orders_maker.native_coin_total), 3_000);
+        assert_eq!(
+            identity(open_orders_maker.native_pc_free),
+            identity(open_orders_maker.native_pc_total)
+        );
+        assert_eq!(identity(open_orders_maker.free_slot_bits).count_zeros(), 1);
+    }
+}
+
+#[test]
+fn test_fee_schedule() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let bump = Bump::new();
+
+    let market_authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let accounts = setup_market_with_authority(&mut rng, &bump, Some(&market_authority));
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let fee_schedule = |maker_rebate: u16| {
+        MarketInstruction::UpdateFeeSchedule(UpdateFeeScheduleInstruction {
+            taker_fee_tenth_of_bps: [20; 8],
+            maker_rebate_tenth_of_bps: [maker_rebate; 8],
+            srm_thresholds: [100, 200, 300, 400, 500],
+            msrm_threshold: 1,
+            stable: false,
+        })
+        .pack()
+    };
+    let impostor = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let impostor_accounts: &[AccountInfo] =
+        bump_vec![in &bump; accounts.market.clone(), impostor.clone()].into_bump_slice();
+    assert_eq!(
+        State::process(dex_program_id, impostor_accounts, &fee_schedule(5)),
+        Err(DexErrorCode::WrongSigner.into())
+    );
+    // The global disable authority only stands in on markets without an admin.
+    let disable_authority = AccountInfo::new(
+        bump.alloc(disable_authority::ID),
+        true,
+        false,
+        bump.alloc(0),
+        &mut [],
+        &system_program::ID,
+        false,
+        Epoch::default(),
+    );
+    let disable_accounts: &[AccountInfo] =
+        bump_vec![in &bump; accounts.market.clone(), disable_authority.clone()].into_bump_slice();
+    assert_eq!(
+        State::process(dex_program_id, disable_accounts, &fee_schedule(5)),
+        Err(DexErrorCode::WrongSigner.into())
+    );
+    let update_accounts: &[AccountInfo] =
+        bump_vec![in &bump; accounts.market.clone(), market_authority.clone()].into_bump_slice();
+    // Rebates above four fifths of the taker fee would eat into the referrer's share.
+    assert_eq!(
+        State::process(dex_program_id, update_accounts, &fee_schedule(17)),
+        Err(DexErrorCode::InvalidFeeSchedule.into())
+    );
+    State::process(dex_program_id, update_accounts, &fee_schedule(5)).unwrap();
+    {
+        let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+        let fee_schedule = market.fee_schedule();
+        assert!(matches!(
+            fee_schedule.fee_tier(accounts.market.key, 250, 0),
+            FeeTier::SRM3
+        ));
+        assert!(matches!(
+            fee_schedule.fee_tier(accounts.market.key, 250, 1),
+            FeeTier::MSRM
+        ));
+    }
+
+    let maker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_maker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let maker_coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, maker.key, 1_000, &bump);
+    let taker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_taker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let taker_pc_account =
+        new_token_account(&mut rng, accounts.pc_mint.key, taker.key, 200_000, &bump);
+    for (open_orders, owner) in [
+        (&orders_account_maker, &maker),
+        (&orders_account_taker, &taker),
+    ] {
+        let init_accounts: &[AccountInfo] = bump_vec![in &bump;
+            open_orders.clone(),
+            owner.clone(),
+            accounts.market.clone(),
+            accounts.rent_sysvar.clone(),
+            market_authority.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::InitOpenOrders.pack();
+        State::process(dex_program_id, init_accounts, &instruction_data).unwrap();
+    }
+
+    let order = |side: Side, order_type: OrderType, max_native_pc_qty: u64| {
+        MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(100_000).unwrap(),
+            max_coin_qty: NonZeroU64::new(1).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(max_native_pc_qty).unwrap(),
+            order_type,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack()
+    };
+    let maker_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_maker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        maker_coin_account.clone(),
+        maker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    State::process(
+        dex_program_id,
+        maker_accounts,
+        &order(Side::Ask, OrderType::Limit, 100_000),
+    )
+    .unwrap();
+    let taker_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_taker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        taker_pc_account.clone(),
+        taker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    State::process(
+        dex_program_id,
+        taker_accounts,
+        &order(Side::Bid, OrderType::ImmediateOrCancel, 200_000),
+    )
+    .unwrap();
+
+    {
+        let crank_accounts = bump_vec![in &bump;
+            orders_account_maker.clone(),
+            orders_account_taker.clone(),
+            accounts.market.clone(),
+            accounts.event_q.clone(),
+            taker_pc_account.clone(),
+            taker_pc_account.clone(),
+        ]
+        .into_bump_slice_mut();
+        crank_accounts[0..2].sort_by_key(|account_info| account_info.key.to_aligned_bytes());
+        let instruction_data = MarketInstruction::ConsumeEvents(200).pack();
+        State::process(dex_program_id, crank_accounts, &instruction_data).unwrap();
+    }
+
+    // A 2 bps taker fee and a 0.5 bps maker rebate on 100_000 pc.
+    let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+    let open_orders_taker = market
+        .load_orders_mut(&orders_account_taker, None, dex_program_id, None, None)
+        .unwrap();
+    assert_eq!(identity(open_orders_taker.native_pc_free), 99_980);
+    assert_eq!(identity(open_orders_taker.native_coin_free), 1_000);
+    let open_orders_maker = market
+        .load_orders_mut(&orders_account_maker, None, dex_program_id, None, None)
+        .unwrap();
+    assert_eq!(identity(open_orders_maker.native_pc_free), 100_005);
+}
+
+#[test]
+fn test_fee_schedule_without_admin() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let bump = Bump::new();
+
+    let fee_schedule = MarketInstruction::UpdateFeeSchedule(UpdateFeeScheduleInstruction {
+        taker_fee_tenth_of_bps: [20; 8],
+        maker_rebate_tenth_of_bps: [5; 8],
+        srm_thresholds: [100, 200, 300, 400, 500],
+        msrm_threshold: 1,
+        stable: false,
+    })
+    .pack();
+    let disable_authority = AccountInfo::new(
+        bump.alloc(disable_authority::ID),
+        true,
+        false,
+        bump.alloc(0),
+        &mut [],
+        &system_program::ID,
+        false,
+        Epoch::default(),
+    );
+
+    // A market whose authority is the default key has no admin, so the
+    // global disable authority sets its schedule.
+    let no_authority = AccountInfo::new(
+        bump.alloc(Pubkey::default()),
+        false,
+        false,
+        bump.alloc(0),
+        &mut [],
+        &system_program::ID,
+        false,
+        Epoch::default(),
+    );
+    let accounts = setup_market_with_authority(&mut rng, &bump, Some(&no_authority));
+    let dex_program_id = accounts.market.owner;
+    let update_accounts: &[AccountInfo] =
+        bump_vec![in &bump; accounts.market.clone(), disable_authority.clone()].into_bump_slice();
+    State::process(dex_program_id, update_accounts, &fee_schedule).unwrap();
+    {
+        let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+        assert!(matches!(
+            market.fee_schedule().fee_tier(accounts.market.key, 250, 0),
+            FeeTier::SRM3
+        ));
+    }
+
+    // V1 markets have nowhere to keep one.
+    let accounts = setup_market(&mut rng, &bump);
+    let dex_program_id = accounts.market.owner;
+    let update_accounts: &[AccountInfo] =
+        bump_vec![in &bump; accounts.market.clone(), disable_authority.clone()].into_bump_slice();
+    assert_eq!(
+        State::process(dex_program_id, update_accounts, &fee_schedule),
+        Err(DexErrorCode::InvalidMarketFlags.into())
+    );
+}
+
+#[test]
+fn test_tick_size_and_min_base_qty() {
+    let mut rng = StdRng::seed_from_u64(8);
+    let bump = Bump::new();
+
+    let market_authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let accounts = setup_market_with_authority(&mut rng, &bump, Some(&market_authority));
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+    {
+        let mut market = MarketStateV2::load(&accounts.market, dex_program_id, false).unwrap();
+        market.tick_size = 10;
+        market.min_base_qty = 2;
+    }
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 10_000, &bump);
+    let init_accounts: &[AccountInfo] = bump_vec![in &bump;
+        orders_account.clone(),
+        owner.clone(),
+        accounts.market.clone(),
+        accounts.rent_sysvar.clone(),
+        market_authority.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::InitOpenOrders.pack();
+    State::process(dex_program_id, init_accounts, &instruction_data).unwrap();
+
+    let order = |limit_price: u64, max_coin_qty: u64| {
+        MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side: Side::Ask,
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(max_coin_qty).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(1).unwrap(),
+            order_type: OrderType::Limit,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack()
+    };
+    let order_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        coin_account.clone(),
+        owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+
+    assert_eq!(
+        State::process(dex_program_id, order_accounts, &order(10_005, 2)),
+        Err(DexErrorCode::PriceNotOnTick.into())
+    );
+    assert_eq!(
+        State::process(dex_program_id, order_accounts, &order(10_000, 1)),
+        Err(DexErrorCode::OrderSizeBelowMinimum.into())
+    );
+    State::process(dex_program_id, order_accounts, &order(10_000, 2)).unwrap();
+
+    // Shrinking the order in place is held to the same minimum.
+    let order_id = Market::load(&accounts.market, dex_program_id, false)
+        .unwrap()
+        .load_orders_mut(&orders_account, None, dex_program_id, None, None)
+        .unwrap()
+        .orders[0];
+    let amend = MarketInstruction::AmendOrder(AmendOrderInstruction {
+        side: Side::Ask,
+        order_id,
+        limit_price: NonZeroU64::new(10_000).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(1).unwrap(),
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        order_type: OrderType::Limit,
+        limit: 5,
+    })
+    .pack();
+    assert_eq!(
+        State::process(dex_program_id, order_accounts, &amend),
+        Err(DexErrorCode::OrderSizeBelowMinimum.into())
+    );
+
+    // A bid whose funds only cover one lot is too small to rest.
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 100_000, &bump);
+    let bid_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        pc_account.clone(),
+        owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let bid = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+        side: Side::Bid,
+        limit_price: NonZeroU64::new(9_000).unwrap(),
+        max_coin_qty: NonZeroU64::new(2).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(15_000).unwrap(),
+        order_type: OrderType::Limit,
+        client_order_id: 0,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        limit: 5,
+        max_ts: i64::MAX,
+    })
+    .pack();
+    State::process(dex_program_id, bid_accounts, &bid).unwrap();
+
+    let bbo = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!(bbo.nbid, 0);
+    {
+        let event_q_data = accounts.event_q.try_borrow_data().unwrap();
+        let events_offset = 5 + size_of::<EventQueueHeader>();
+        let events: &[Event] =
+            cast_slice(&event_q_data[events_offset..events_offset + size_of::<Event>()]);
+        match events[0].as_view().unwrap() {
+            EventView::Out {
+                side,
+                reason,
+                native_qty_still_locked,
+                ..
+            } => {
+                assert_eq!(side, Side::Bid);
+                assert_eq!(reason, OutReason::BelowMinimum);
+                assert_eq!(native_qty_still_locked, 0);
+            }
+            _ => panic!("expected an Out event"),
+        }
+    }
+
+    let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+    let open_orders = market
+        .load_orders_mut(&orders_account, None, dex_program_id, None, None)
+        .unwrap();
+    assert_eq!(identity(open_orders.native_coin_total), 2_000);
+    assert_eq!(
+        identity(open_orders.native_pc_free),
+        identity(open_orders.native_pc_total)
+    );
+}
+
+#[test]
+fn test_iceberg_order() {
+    let mut rng = StdRng::seed_from_u64(9);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let maker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_maker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let maker_coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, maker.key, 10_000, &bump);
+    let taker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_taker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let taker_pc_account =
+        new_token_account(&mut rng, accounts.pc_mint.key, taker.key, 1_000_000, &bump);
+
+    let iceberg = |order_type: OrderType| {
+        MarketInstruction::NewIcebergOrder(NewIcebergOrderInstruction {
+            order: NewOrderInstructionV3 {
+                side: Side::Ask,
+                limit_price: NonZeroU64::new(100_000).unwrap(),
+                max_coin_qty: NonZeroU64::new(3).unwrap(),
+                max_native_pc_qty_including_fees: NonZeroU64::new(1).unwrap(),
+                order_type,
+                client_order_id: 0,
+                self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+                limit: 5,
+                max_ts: i64::MAX,
+            },
+            display_qty: NonZeroU64::new(1).unwrap(),
+        })
+        .pack()
+    };
+    let maker_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_maker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        maker_coin_account.clone(),
+        maker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    assert_eq!(
+        State::process(
+            dex_program_id,
+            maker_accounts,
+            &iceberg(OrderType::ImmediateOrCancel)
+        ),
+        Err(DexErrorCode::InvalidIcebergOrder.into())
+    );
+    State::process(dex_program_id, maker_accounts, &iceberg(OrderType::Limit)).unwrap();
+
+    let order_id = {
+        let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+        let open_orders = market
+            .load_orders_mut(&orders_account_maker, None, dex_program_id, None, None)
+            .unwrap();
+        assert_eq!(identity(open_orders.native_coin_total), 3_000);
+        identity(open_orders.orders[0])
+    };
+    // Only the display quantity is visible on the book.
+    let bbo = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!((bbo.ask, bbo.nask), (100_000, 1));
+
+    let taker_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_taker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        taker_pc_account.clone(),
+        taker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+        side: Side::Bid,
+        limit_price: NonZeroU64::new(100_000).unwrap(),
+        max_coin_qty: NonZeroU64::new(2).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(300_000).unwrap(),
+        order_type: OrderType::ImmediateOrCancel,
+        client_order_id: 0,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        limit: 5,
+        max_ts: i64::MAX,
+    })
+    .pack();
+    State::process(dex_program_id, taker_accounts, &instruction_data).unwrap();
+
+    // The visible slice was refilled from the reserve after each fill.
+    let bbo = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!((bbo.ask, bbo.nask), (100_000, 1));
+
+    // Cancelling by the original order id releases whatever is left.
+    let cancel_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        orders_account_maker.clone(),
+        maker.clone(),
+        accounts.event_q.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::CancelOrderV2(CancelOrderInstructionV2 {
+        side: Side::Ask,
+        order_id,
+    })
+    .pack();
+    State::process(dex_program_id, cancel_accounts, &instruction_data).unwrap();
+    {
+        let market = MarketState::load(&accounts.market, dex_program_id, false).unwrap();
+        let asks = market.load_asks_mut(&accounts.asks).unwrap();
+        assert!(asks.find_min().is_none());
+        assert!(!asks.has_reserves());
+    }
+
+    {
+        let crank_accounts = bump_vec![in &bump;
+            orders_account_maker.clone(),
+            orders_account_taker.clone(),
+            accounts.market.clone(),
+            accounts.event_q.clone(),
+            taker_pc_account.clone(),
+            taker_pc_account.clone(),
+        ]
+        .into_bump_slice_mut();
+        crank_accounts[0..2].sort_by_key(|account_info| account_info.key.to_aligned_bytes());
+        let instruction_data = MarketInstruction::ConsumeEvents(200).pack();
+        State::process(dex_program_id, crank_accounts, &instruction_data).unwrap();
+    }
+
+    let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+    let open_orders_maker = market
+        .load_orders_mut(&orders_account_maker, None, dex_program_id, None, None)
+        .unwrap();
+    assert_eq!(identity(open_orders_maker.native_coin_free), 1_000);
+    assert_eq!(identity(open_orders_maker.native_coin_total), 1_000);
+    assert_eq!(identity(open_orders_maker.free_slot_bits), u128::MAX);
+    let open_orders_taker = market
+        .load_orders_mut(&orders_account_taker, None, dex_program_id, None, None)
+        .unwrap();
+    assert_eq!(identity(open_orders_taker.native_coin_free), 2_000);
+}
+
+#[test]
+fn test_amend_order() {
+    let mut rng = StdRng::seed_from_u64(10);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let mut order_accounts = Vec::new();
+    for _ in 0..2 {
+        let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+        let orders_account =
+            new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+        let pc_account =
+            new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 1_000_000, &bump);
+        let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            orders_account.clone(),
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            pc_account.clone(),
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side: Side::Bid,
+            limit_price: NonZeroU64::new(10_000).unwrap(),
+            max_coin_qty: NonZeroU64::new(10).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(100_000).unwrap(),
+            order_type: OrderType::Limit,
+            client_order_id: 0x123a,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack();
+        State::process(dex_program_id, instruction_accounts, &instruction_data).unwrap();
+        order_accounts.push(instruction_accounts);
+    }
+    let (first_accounts, second_accounts) = (order_accounts[0], order_accounts[1]);
+    let first_order_id = || {
+        let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+        let open_orders = market
+            .load_orders_mut(&first_accounts[1], None, dex_program_id, None, None)
+            .unwrap();
+        identity(open_orders.orders[0])
+    };
+    let amend_as = |order_id: u128, limit_price: u64, max_coin_qty: u64, order_type| {
+        MarketInstruction::AmendOrder(AmendOrderInstruction {
+            side: Side::Bid,
+            order_id,
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(max_coin_qty).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(100_000).unwrap(),
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            order_type,
+            limit: 5,
+        })
+        .pack()
+    };
+    let amend = |order_id: u128, limit_price: u64, max_coin_qty: u64| {
+        amend_as(order_id, limit_price, max_coin_qty, OrderType::Limit)
+    };
+
+    let order_id = first_order_id();
+    assert_eq!(
+        State::process(dex_program_id, second_accounts, &amend(order_id, 10_000, 4)),
+        Err(DexErrorCode::OrderNotYours.into())
+    );
+
+    // Shrinking the order keeps it ahead of the second bid at the same price.
+    State::process(dex_program_id, first_accounts, &amend(order_id, 10_000, 4)).unwrap();
+    let bbo = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!((bbo.bid, bbo.nbid), (10_000, 4));
+    assert_eq!(bbo.buyer, first_accounts[1].key.to_aligned_bytes());
+    assert_eq!(first_order_id(), order_id);
+    {
+        let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+        let open_orders = market
+            .load_orders_mut(&first_accounts[1], None, dex_program_id, None, None)
+            .unwrap();
+        assert_eq!(identity(open_orders.native_pc_free), 60_000);
+        assert_eq!(identity(open_orders.native_pc_total), 100_000);
+    }
+    {
+        let event_q_data = accounts.event_q.try_borrow_data().unwrap();
+        let events_offset = 5 + size_of::<EventQueueHeader>();
+        let events: &[Event] =
+            cast_slice(&event_q_data[events_offset..events_offset + 3 * size_of::<Event>()]);
+        match events[2].as_view().unwrap() {
+            EventView::Out {
+                reason,
+                native_qty_unlocked,
+                native_qty_still_locked,
+                ..
+            } => {
+                assert_eq!(reason, OutReason::Amended);
+                assert_eq!(native_qty_unlocked, 60_000);
+                assert_eq!(native_qty_still_locked, 40_000);
+            }
+            _ => panic!("expected an Out event"),
+        }
+    }
+    assert_eq!(
+        State::process(dex_program_id, first_accounts, &amend(order_id, 10_000, 4)),
+        Err(DexErrorCode::InvalidAmendOrder.into())
+    );
+
+    // Growing it again sends it to the back of the queue under a new id.
+    State::process(dex_program_id, first_accounts, &amend(order_id, 10_000, 6)).unwrap();
+    let bbo = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!((bbo.bid, bbo.nbid), (10_000, 10));
+    assert_eq!(bbo.buyer, second_accounts[1].key.to_aligned_bytes());
+
+    {
+        let crank_accounts = bump_vec![in &bump;
+            first_accounts[1].clone(),
+            second_accounts[1].clone(),
+            accounts.market.clone(),
+            accounts.event_q.clone(),
+            first_accounts[6].clone(),
+            first_accounts[6].clone(),
+        ]
+        .into_bump_slice_mut();
+        crank_accounts[0..2].sort_by_key(|account_info| account_info.key.to_aligned_bytes());
+        let instruction_data = MarketInstruction::ConsumeEvents(200).pack();
+        State::process(dex_program_id, crank_accounts, &instruction_data).unwrap();
+    }
+
+    // An amend must be able to rest, and a PostOnly amend that would cross
+    // fails rather than taking or dropping the order.
+    let ask_owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let ask_orders_account =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let coin_account = new_token_account(
+        &mut rng,
+        accounts.coin_mint.key,
+        ask_owner.key,
+        1_000,
+        &bump,
+    );
+    let ask_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        ask_orders_account.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        coin_account.clone(),
+        ask_owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+        side: Side::Ask,
+        limit_price: NonZeroU64::new(10_500).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(1).unwrap(),
+        order_type: OrderType::Limit,
+        client_order_id: 0,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        limit: 5,
+        max_ts: i64::MAX,
+    })
+    .pack();
+    State::process(dex_program_id, ask_accounts, &instruction_data).unwrap();
+    let requeued_order_id = || {
+        let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+        let open_orders = market
+            .load_orders_mut(&first_accounts[1], None, dex_program_id, None, None)
+            .unwrap();
+        let slot = identity(open_orders.free_slot_bits).trailing_ones() as usize;
+        identity(open_orders.orders[slot])
+    };
+    let requeued_id = requeued_order_id();
+    for order_type in [OrderType::ImmediateOrCancel, OrderType::FillOrKill] {
+        assert_eq!(
+            State::process(
+                dex_program_id,
+                first_accounts,
+                &amend_as(requeued_id, 10_000, 6, order_type)
+            ),
+            Err(DexErrorCode::InvalidAmendOrder.into())
+        );
+    }
+    assert_eq!(
+        State::process(
+            dex_program_id,
+            first_accounts,
+            &amend_as(requeued_id, 10_500, 6, OrderType::PostOnly)
+        ),
+        Err(DexErrorCode::InvalidAmendOrder.into())
+    );
+    let bbo = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!(
+        (bbo.bid, bbo.nbid, bbo.ask, bbo.nask),
+        (10_000, 10, 10_500, 1)
+    );
+    assert_eq!(requeued_order_id(), requeued_id);
+
+    let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+    let open_orders = market
+        .load_orders_mut(&first_accounts[1], None, dex_program_id, None, None)
+        .unwrap();
+    assert_eq!(identity(open_orders.native_pc_free), 40_000);
+    assert_eq!(identity(open_orders.native_pc_total), 100_000);
+    assert_eq!(identity(open_orders.free_slot_bits).count_zeros(), 1);
+    let slot = identity(open_orders.free_slot_bits).trailing_ones() as usize;
+    assert_ne!(identity(open_orders.orders[slot]), order_id);
+    assert_eq!(identity(open_orders.client_order_ids[slot]), 0x123a);
+}
+
+#[test]
+fn test_cancel_all_orders() {
+    let mut rng = StdRng::seed_from_u64(11);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 10_000, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 1_000_000, &bump);
+
+    let orders = [
+        (Side::Bid, 10_000, &pc_account),
+        (Side::Bid, 9_000, &pc_account),
+        (Side::Ask, 20_000, &coin_account),
+    ];
+    for (side, limit_price, payer) in orders.iter() {
+        let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            orders_account.clone(),
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            (*payer).clone(),
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side: *side,
+            limit_price: NonZeroU64::new(*limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(1).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(*limit_price).unwrap(),
+            order_type: OrderType::Limit,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack();
+        State::process(dex_program_id, instruction_accounts, &instruction_data).unwrap();
+    }
+
+    let cancel_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        orders_account.clone(),
+        owner.clone(),
+        accounts.event_q.clone(),
+    ]
+    .into_bump_slice();
+    let cancel_all = |side: Option<Side>| {
+        MarketInstruction::CancelAllOrders(CancelAllOrdersInstruction { side, limit: 10 }).pack()
+    };
+
+    State::process(
+        dex_program_id,
+        cancel_accounts,
+        &cancel_all(Some(Side::Bid)),
+    )
+    .unwrap();
+    let bbo = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!((bbo.nbid, bbo.nask), (0, 1));
+    {
+        let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+        let open_orders = market
+            .load_orders_mut(&orders_account, None, dex_program_id, None, None)
+            .unwrap();
+        assert_eq!(identity(open_orders.native_pc_free), 19_000);
+        assert_eq!(identity(open_orders.native_coin_free), 0);
+    }
+
+    State::process(dex_program_id, cancel_accounts, &cancel_all(None)).unwrap();
+    let bbo = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!((bbo.nbid, bbo.nask), (0, 0));
+    let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+    let open_orders = market
+        .load_orders_mut(&orders_account, None, dex_program_id, None, None)
+        .unwrap();
+    assert_eq!(identity(open_orders.native_pc_free), 19_000);
+    assert_eq!(identity(open_orders.native_coin_free), 1_000);
+}
+
+#[test]
+fn test_self_trade_across_open_orders() {
+    let mut rng = StdRng::seed_from_u64(12);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_ask =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let orders_account_bid =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 10_000, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 1_000_000, &bump);
+
+    let order = |side: Side, self_trade_behavior: SelfTradeBehavior| {
+        MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(10_000).unwrap(),
+            max_coin_qty: NonZeroU64::new(2).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(30_000).unwrap(),
+            order_type: OrderType::Limit,
+            client_order_id: 0,
+            self_trade_behavior,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack()
+    };
+    let ask_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_ask.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        coin_account.clone(),
+        owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    State::process(
+        dex_program_id,
+        ask_accounts,
+        &order(Side::Ask, SelfTradeBehavior::AbortTransaction),
+    )
+    .unwrap();
+
+    // The bid comes from another OpenOrders account of the same wallet.
+    let bid_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_bid.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        pc_account.clone(),
+        owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+        orders_account_ask.clone(),
+    ]
+    .into_bump_slice();
+    State::process(
+        dex_program_id,
+        bid_accounts,
+        &order(Side::Bid, SelfTradeBehavior::CancelTake),
+    )
+    .unwrap();
+    let bbo = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!((bbo.nbid, bbo.nask), (0, 2));
+    {
+        let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+        let open_orders = market
+            .load_orders_mut(&orders_account_bid, None, dex_program_id, None, None)
+            .unwrap();
+        assert_eq!(identity(open_orders.native_pc_free), 30_000);
+        assert_eq!(identity(open_orders.native_pc_total), 30_000);
+    }
+
+    State::process(
+        dex_program_id,
+        bid_accounts,
+        &order(Side::Bid, SelfTradeBehavior::CancelBoth),
+    )
+    .unwrap();
+    let bbo = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!((bbo.nbid, bbo.nask), (0, 0));
+
+    {
+        let crank_accounts = bump_vec![in &bump;
+            orders_account_ask.clone(),
+            orders_account_bid.clone(),
+            accounts.market.clone(),
+            accounts.event_q.clone(),
+            coin_account.clone(),
+            pc_account.clone(),
+        ]
+        .into_bump_slice_mut();
+        crank_accounts[0..2].sort_by_key(|account_info| account_info.key.to_aligned_bytes());
+        let instruction_data = MarketInstruction::ConsumeEvents(200).pack();
+        State::process(dex_program_id, crank_accounts, &instruction_data).unwrap();
+    }
+
+    let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+    let open_orders = market
+        .load_orders_mut(&orders_account_ask, None, dex_program_id, None, None)
+        .unwrap();
+    assert_eq!(identity(open_orders.native_coin_free), 2_000);
+    assert_eq!(identity(open_orders.free_slot_bits), u128::MAX);
+    let open_orders = market
+        .load_orders_mut(&orders_account_bid, None, dex_program_id, None, None)
+        .unwrap();
+    assert_eq!(identity(open_orders.native_pc_free), 30_000);
+    assert_eq!(identity(open_orders.free_slot_bits), u128::MAX);
+}
+
+#[test]
+fn test_post_only_slide() {
+    let mut rng = StdRng::seed_from_u64(13);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 10_000, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 1_000_000, &bump);
+
+    let orders = [
+        (Side::Ask, 20_000, OrderType::Limit, &coin_account),
+        (Side::Bid, 25_000, OrderType::PostOnlySlide, &pc_account),
+        (Side::Ask, 15_000, OrderType::PostOnlySlide, &coin_account),
+    ];
+    for (side, limit_price, order_type, payer) in orders.iter() {
+        let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            orders_account.clone(),
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            (*payer).clone(),
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side: *side,
+            limit_price: NonZeroU64::new(*limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(1).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(*limit_price).unwrap(),
+            order_type: *order_type,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack();
+        State::process(dex_program_id, instruction_accounts, &instruction_data).unwrap();
+    }
+
+    // The bid slid down to one tick below the resting ask, and the second ask
+    // then slid up to one tick above that bid.
+    let bbo = get_bbo(
+        dex_program_id,
+        &accounts.market,
+        &accounts.bids,
+        &accounts.asks,
+    );
+    assert_eq!((bbo.bid, bbo.nbid), (19_999, 1));
+    assert_eq!(bbo.ask, 20_000);
+    let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+    let open_orders = market
+        .load_orders_mut(&orders_account, None, dex_program_id, None, None)
+        .unwrap();
+    assert_eq!(identity(open_orders.native_coin_total), 2_000);
+    assert_eq!(identity(open_orders.native_coin_free), 0);
+}
+
+#[test]
+fn test_fill_details() {
+    let mut rng = StdRng::seed_from_u64(14);
+    let bump = Bump::new();
+
+    let market_authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let accounts = setup_market_with_authority(&mut rng, &bump, Some(&market_authority));
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let enable_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        accounts.event_q.clone(),
+        market_authority.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::EnableFillDetails.pack();
+    State::process(dex_program_id, enable_accounts, &instruction_data).unwrap();
+
+    let maker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_maker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let maker_coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, maker.key, 1_000, &bump);
+    let taker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_taker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let taker_pc_account =
+        new_token_account(&mut rng, accounts.pc_mint.key, taker.key, 200_000, &bump);
+    let orders = [
+        (
+            &orders_account_maker,
+            &maker,
+            &maker_coin_account,
+            Side::Ask,
+        ),
+        (&orders_account_taker, &taker, &taker_pc_account, Side::Bid),
+    ];
+    for (open_orders, owner, payer, side) in orders {
+        let init_accounts: &[AccountInfo] = bump_vec![in &bump;
+            open_orders.clone(),
+            owner.clone(),
+            accounts.market.clone(),
+            accounts.rent_sysvar.clone(),
+            market_authority.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::InitOpenOrders.pack();
+        State::process(dex_program_id, init_accounts, &instruction_data).unwrap();
+
+        let order_accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            open_orders.clone(),
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            payer.clone(),
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(100_000).unwrap(),
+            max_coin_qty: NonZeroU64::new(1).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(200_000).unwrap(),
+            order_type: OrderType::Limit,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack();
+        State::process(dex_program_id, order_accounts, &instruction_data).unwrap();
+    }
+
+    // The maker's Fill is followed by its detail, then the maker's Out and
+    // the taker's Fill.
+    {
+        let event_q_data = accounts.event_q.try_borrow_data().unwrap();
+        let events_offset = 5 + size_of::<EventQueueHeader>();
+        let events: &[Event] =
+            cast_slice(&event_q_data[events_offset..events_offset + 4 * size_of::<Event>()]);
+        let taker_order_id = match events[3].as_view().unwrap() {
+            EventView::Fill {
+                maker: false,
+                order_id,
+                ..
+            } => order_id,
+            view => panic!("unexpected event {:?}", view),
+        };
+        match (events[0].as_view().unwrap(), events[1].as_view().unwrap()) {
+            (
+                EventView::Fill {
+                    maker: true,
+                    order_id: maker_order_id,
+                    ..
+                },
+                EventView::FillDetail {
+                    side: Side::Ask,
+                    seq_num: 0,
+                    timestamp,
+                    price: 100_000,
+                    order_id,
+                    owner,
+                    taker_order_id: detail_taker_order_id,
+                    ..
+                },
+            ) => {
+                assert!(timestamp > 0);
+                assert_eq!(order_id, maker_order_id);
+                assert_eq!(owner, orders_account_maker.key.to_aligned_bytes());
+                assert_eq!(detail_taker_order_id, taker_order_id);
+            }
+            views => panic!("unexpected events {:?}", views),
+        }
+    }
+
+    {
+        let crank_accounts = bump_vec![in &bump;
+            orders_account_maker.clone(),
+            orders_account_taker.clone(),
+            accounts.market.clone(),
+            accounts.event_q.clone(),
+            taker_pc_account.clone(),
+            taker_pc_account.clone(),
+        ]
+        .into_bump_slice_mut();
+        crank_accounts[0..2].sort_by_key(|account_info| account_info.key.to_aligned_bytes());
+        let instruction_data = MarketInstruction::ConsumeEvents(200).pack();
+        State::process(dex_program_id, crank_accounts, &instruction_data).unwrap();
+    }
+
+    let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+    let open_orders_maker = market
+        .load_orders_mut(&orders_account_maker, None, dex_program_id, None, None)
+        .unwrap();
+    assert_eq!(identity(open_orders_maker.free_slot_bits), !0);
+    assert!(identity(open_orders_maker.native_pc_free) >= 100_000);
+    drop(open_orders_maker);
+    drop(market);
+
+    // Only the admin can turn them off again.
+    let stranger_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        accounts.event_q.clone(),
+        maker.clone(),
+    ]
+    .into_bump_slice();
+    let disable = MarketInstruction::DisableFillDetails.pack();
+    assert_eq!(
+        State::process(dex_program_id, stranger_accounts, &disable),
+        Err(DexErrorCode::WrongSigner.into())
+    );
+    State::process(dex_program_id, enable_accounts, &disable).unwrap();
+    let event_q_data = accounts.event_q.try_borrow_data().unwrap();
+    // The header starts with the account flags.
+    let account_flags = u64::from_le_bytes(event_q_data[5..13].try_into().unwrap());
+    assert_eq!(
+        account_flags,
+        (AccountFlag::Initialized | AccountFlag::EventQueue).bits()
+    );
+}
+
+#[test]
+fn test_out_reasons() {
+    let mut rng = StdRng::seed_from_u64(15);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let maker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_maker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let maker_coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, maker.key, 2_000, &bump);
+    let taker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_taker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let taker_pc_account =
+        new_token_account(&mut rng, accounts.pc_mint.key, taker.key, 1_000_000, &bump);
+
+    let orders = [
+        (
+            &orders_account_maker,
+            &maker,
+            &maker_coin_account,
+            Side::Ask,
+            100_000,
+            1,
+            OrderType::Limit,
+        ),
+        (
+            &orders_account_taker,
+            &taker,
+            &taker_pc_account,
+            Side::Bid,
+            100_000,
+            2,
+            OrderType::ImmediateOrCancel,
+        ),
+        (
+            &orders_account_maker,
+            &maker,
+            &maker_coin_account,
+            Side::Ask,
+            200_000,
+            1,
+            OrderType::Limit,
+        ),
+        // Crosses the ask above, so it's cancelled rather than posted.
+        (
+            &orders_account_taker,
+            &taker,
+            &taker_pc_account,
+            Side::Bid,
+            200_000,
+            1,
+            OrderType::PostOnly,
+        ),
+    ];
+    for (open_orders, owner, payer, side, limit_price, max_coin_qty, order_type) in orders {
+        let order_accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            open_orders.clone(),
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            payer.clone(),
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(max_coin_qty).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(300_000).unwrap(),
+            order_type,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack();
+        State::process(dex_program_id, order_accounts, &instruction_data).unwrap();
+    }
+
+    let cancel_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        orders_account_maker.clone(),
+        maker.clone(),
+        accounts.event_q.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::CancelAllOrders(CancelAllOrdersInstruction {
+        side: None,
+        limit: 10,
+    })
+    .pack();
+    State::process(dex_program_id, cancel_accounts, &instruction_data).unwrap();
+
+    let event_q_data = accounts.event_q.try_borrow_data().unwrap();
+    let events_offset = 5 + size_of::<EventQueueHeader>();
+    let events: &[Event] =
+        cast_slice(&event_q_data[events_offset..events_offset + 6 * size_of::<Event>()]);
+    let reasons: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event.as_view().unwrap() {
+            EventView::Out { owner, reason, .. } => Some((owner, reason)),
+            EventView::Fill { .. } | EventView::FillDetail { .. } => None,
+        })
+        .collect();
+    let maker_address = orders_account_maker.key.to_aligned_bytes();
+    let taker_address = orders_account_taker.key.to_aligned_bytes();
+    assert_eq!(
+        reasons,
+        [
+            (maker_address, OutReason::Filled),
+            (taker_address, OutReason::ImmediateOrCancel),
+            (taker_address, OutReason::PostOnly),
+            (maker_address, OutReason::Cancelled),
+        ]
+    );
+}
+
+#[test]
+fn test_open_orders_v2() {
+    let mut rng = StdRng::seed_from_u64(16);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account =
+        new_dex_owned_account(&mut rng, OpenOrdersV2::data_len(300), dex_program_id, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 1_000_000, &bump);
+
+    let order_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        pc_account.clone(),
+        owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let new_bid = |i: u64| {
+        MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side: Side::Bid,
+            limit_price: NonZeroU64::new(1_000 + i).unwrap(),
+            max_coin_qty: NonZeroU64::new(1).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(2_000).unwrap(),
+            order_type: OrderType::Limit,
+            client_order_id: i + 1,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack()
+    };
+    for i in 0..300 {
+        State::process(dex_program_id, order_accounts, &new_bid(i)).unwrap();
+    }
+    assert_eq!(
+        State::process(dex_program_id, order_accounts, &new_bid(300)),
+        Err(DexErrorCode::TooManyOpenOrders.into())
+    );
+    {
+        let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+        let open_orders = market
+            .load_orders_mut(&orders_account, None, dex_program_id, None, None)
+            .unwrap();
+        assert_eq!(open_orders.slot_capacity(), 300);
+        assert_eq!(open_orders.slot_side(299), Some(Side::Bid));
+        assert_eq!(open_orders.client_order_id(299), 300);
+        assert_eq!(open_orders.slot_side(300), None);
+    }
+    {
+        // Slots past 255 keep their high bits next to the fee tier.
+        let market = MarketState::load(&accounts.market, dex_program_id, false).unwrap();
+        let bids = market.load_bids_mut(&accounts.bids).unwrap();
+        let leaf = bids
+            .iter(true)
+            .find(|leaf| leaf.client_order_id() == 281)
+            .unwrap();
+        assert_eq!(leaf.owner_slot(), 280);
+        assert!(matches!(leaf.fee_tier(), FeeTier::Base));
+    }
+    let leaf = LeafNode::new(1_023, 0, [0; 4], 1, FeeTier::Stable, 0);
+    assert_eq!(leaf.owner_slot(), 1_023);
+    assert!(matches!(leaf.fee_tier(), FeeTier::Stable));
+
+    let crank_accounts: &mut [AccountInfo] = bump_vec![in &bump;
+        orders_account.clone(),
+        accounts.market.clone(),
+        accounts.event_q.clone(),
+        pc_account.clone(),
+        pc_account.clone(),
+    ]
+    .into_bump_slice_mut();
+    let consume_events = || {
+        let instruction_data = MarketInstruction::ConsumeEvents(500).pack();
+        State::process(dex_program_id, crank_accounts, &instruction_data).unwrap();
+    };
+    let cancel_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        orders_account.clone(),
+        owner.clone(),
+        accounts.event_q.clone(),
+    ]
+    .into_bump_slice();
+    State::process(
+        dex_program_id,
+        cancel_accounts,
+        &MarketInstruction::CancelOrderByClientIdV2(151).pack(),
+    )
+    .unwrap();
+    State::process(
+        dex_program_id,
+        cancel_accounts,
+        &MarketInstruction::CancelOrderByClientIdV2(281).pack(),
+    )
+    .unwrap();
+    consume_events();
+    {
+        let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+        let open_orders = market
+            .load_orders_mut(&orders_account, None, dex_program_id, None, None)
+            .unwrap();
+        assert_eq!(open_orders.slot_side(150), None);
+        assert_eq!(open_orders.order_id(150), 0);
+        assert_eq!(open_orders.slot_side(280), None);
+        assert_eq!(open_orders.slot_side(24), Some(Side::Bid));
+    }
+
+    State::process(
+        dex_program_id,
+        cancel_accounts,
+        &MarketInstruction::CancelAllOrders(CancelAllOrdersInstruction {
+            side: None,
+            limit: 1_000,
+        })
+        .pack(),
+    )
+    .unwrap();
+    consume_events();
+    let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+    let open_orders = market
+        .load_orders_mut(&orders_account, None, dex_program_id, None, None)
+        .unwrap();
+    assert!(!open_orders.has_open_orders());
+}
+
+#[test]
+fn test_l2_depth() {
+    let mut rng = StdRng::seed_from_u64(17);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 10_000, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 1_000_000, &bump);
+
+    let orders = [
+        (Side::Bid, 9_800, 4, &pc_account),
+        (Side::Bid, 10_000, 2, &pc_account),
+        (Side::Bid, 9_900, 1, &pc_account),
+        (Side::Bid, 10_000, 3, &pc_account),
+        (Side::Ask, 20_000, 1, &coin_account),
+        (Side::Ask, 21_000, 2, &coin_account),
+    ];
+    for (side, limit_price, max_coin_qty, payer) in orders.iter() {
+        let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            orders_account.clone(),
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            (*payer).clone(),
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side: *side,
+            limit_price: NonZeroU64::new(*limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(*max_coin_qty).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(limit_price * max_coin_qty).unwrap(),
+            order_type: OrderType::Limit,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack();
+        State::process(dex_program_id, instruction_accounts, &instruction_data).unwrap();
+    }
+
+    let bids_data = accounts.bids.try_borrow_data().unwrap();
+    let bids = load_book_side(&bids_data, Side::Bid).unwrap();
+    let prices: Vec<u64> = bids.iter(true).map(|leaf| leaf.price().get()).collect();
+    assert_eq!(prices, [10_000, 10_000, 9_900, 9_800]);
+    // Orders at the same price come out in time priority.
+    let quantities: Vec<u64> = bids.iter(true).map(LeafNode::quantity).collect();
+    assert_eq!(quantities, [2, 3, 1, 4]);
+
+    let level = |price, quantity, order_count| L2Level {
+        price,
+        quantity,
+        order_count,
+    };
+    assert_eq!(
+        bids.l2_depth(true, None, None),
+        [level(10_000, 5, 2), level(9_900, 1, 1), level(9_800, 4, 1)]
+    );
+    assert_eq!(
+        bids.l2_depth(true, Some(2), None),
+        [level(10_000, 5, 2), level(9_900, 1, 1)]
+    );
+    assert_eq!(bids.l2_depth(true, None, Some(5)), [level(10_000, 5, 2)]);
+    assert_eq!(
+        bids.l2_depth(true, None, Some(6)),
+        [level(10_000, 5, 2), level(9_900, 1, 1)]
+    );
+
+    let asks_data = accounts.asks.try_borrow_data().unwrap();
+    assert!(load_book_side(&asks_data, Side::Bid).is_err());
+    let asks = load_book_side(&asks_data, Side::Ask).unwrap();
+    assert_eq!(
+        asks.l2_depth(false, None, None),
+        [level(20_000, 1, 1), level(21_000, 2, 1)]
+    );
+
+    // Account data cut off inside the slab header is an error, not a panic.
+    let truncated = [&asks_data[..5 + 8 + 20], &asks_data[asks_data.len() - 7..]].concat();
+    assert_eq!(
+        load_book_side(&truncated, Side::Ask).err(),
+        Some(DexErrorCode::SlabTooSmall.into())
+    );
+}
+
+#[test]
+fn test_close_market() {
+    let mut rng = StdRng::seed_from_u64(18);
+    let bump = Bump::new();
+
+    let market_authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let accounts = setup_market_with_authority(&mut rng, &bump, Some(&market_authority));
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let coin_account = new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 0, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 100_000, &bump);
+
+    let init_accounts: &[AccountInfo] = bump_vec![in &bump;
+        orders_account.clone(),
+        owner.clone(),
+        accounts.market.clone(),
+        accounts.rent_sysvar.clone(),
+        market_authority.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::InitOpenOrders.pack();
+    State::process(dex_program_id, init_accounts, &instruction_data).unwrap();
+
+    let order_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        pc_account.clone(),
+        owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+        side: Side::Bid,
+        limit_price: NonZeroU64::new(10_000).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(10_000).unwrap(),
+        order_type: OrderType::Limit,
+        client_order_id: 0,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        limit: 5,
+        max_ts: i64::MAX,
+    })
+    .pack();
+    State::process(dex_program_id, order_accounts, &instruction_data).unwrap();
+
+    // A stop that is cancelled leaves the market with an empty trigger book.
+    let trigger_book = new_trigger_book(&mut rng, &accounts, &market_authority, &bump);
+    let stop_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        trigger_book.clone(),
+        pc_account.clone(),
+        owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::NewTriggerOrder(NewTriggerOrderInstruction {
+        side: Side::Bid,
+        trigger_price: NonZeroU64::new(20_000).unwrap(),
+        limit_price: NonZeroU64::new(20_000).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(30_000).unwrap(),
+        self_trade_behavior: SelfTradeBehavior::DecrementTake,
+        order_type: OrderType::ImmediateOrCancel,
+        client_order_id: 0,
+    })
+    .pack();
+    State::process(dex_program_id, stop_accounts, &instruction_data).unwrap();
+    let stop_id = Market::load(&accounts.market, dex_program_id, false)
+        .unwrap()
+        .load_orders_mut(&orders_account, None, dex_program_id, None, None)
+        .unwrap()
+        .orders[1];
+    let cancel_stop_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        trigger_book.clone(),
+        orders_account.clone(),
+        owner.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::CancelTriggerOrder(stop_id).pack();
+    State::process(dex_program_id, cancel_stop_accounts, &instruction_data).unwrap();
+
+    let destination = new_sol_account(&mut rng, 0, &bump);
+    let stranger = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let close_accounts = |authority, with_trigger_book| {
+        let mut close_accounts = vec![
+            accounts.market.clone(),
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            AccountInfo::clone(authority),
+            destination.clone(),
+        ];
+        if with_trigger_book {
+            close_accounts.push(trigger_book.clone());
+        }
+        close_accounts
+    };
+    let close = MarketInstruction::CloseMarket.pack();
+    assert_eq!(
+        State::process(dex_program_id, &close_accounts(&stranger, true), &close),
+        Err(DexErrorCode::WrongSigner.into())
+    );
+    assert_eq!(
+        State::process(
+            dex_program_id,
+            &close_accounts(&market_authority, true),
+            &close
+        ),
+        Err(DexErrorCode::MarketNotDisabled.into())
+    );
+
+    let cancel_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        orders_account.clone(),
+        owner.clone(),
+        accounts.event_q.clone(),
+    ]
+    .into_bump_slice();
+    let cancel_all = MarketInstruction::CancelAllOrders(CancelAllOrdersInstruction {
+        side: None,
+        limit: 10,
+    })
+    .pack();
+    State::process(dex_program_id, cancel_accounts, &cancel_all).unwrap();
+
+    // The disable authority is global, so sign for it directly.
+    let disable_authority = AccountInfo::new(
+        bump.alloc(disable_authority::ID),
+        true,
+        false,
+        bump.alloc(0),
+        &mut [],
+        &system_program::ID,
+        false,
+        Epoch::default(),
+    );
+    let disable_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        disable_authority.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::DisableMarket.pack();
+    State::process(dex_program_id, disable_accounts, &instruction_data).unwrap();
+
+    // The cancel's Out event is still on the queue.
+    assert_eq!(
+        State::process(
+            dex_program_id,
+            &close_accounts(&market_authority, true),
+            &close
+        ),
+        Err(DexErrorCode::MarketNotEmpty.into())
+    );
+
+    let crank_accounts = bump_vec![in &bump;
+        orders_account.clone(),
+        accounts.market.clone(),
+        accounts.event_q.clone(),
+        coin_account.clone(),
+        pc_account.clone(),
+        trigger_book.clone(),
+    ]
+    .into_bump_slice_mut();
+    let instruction_data = MarketInstruction::ConsumeEvents(10).pack();
+    State::process(dex_program_id, crank_accounts, &instruction_data).unwrap();
+
+    // The order is gone, but its funds are still deposited.
+    assert_eq!(
+        State::process(
+            dex_program_id,
+            &close_accounts(&market_authority, true),
+            &close
+        ),
+        Err(DexErrorCode::MarketNotEmpty.into())
+    );
+
+    let settle_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account.clone(),
+        owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        coin_account.clone(),
+        pc_account.clone(),
+        accounts.vault_signer.clone(),
+        spl_token_program.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::SettleFunds.pack();
+    State::process(dex_program_id, settle_accounts, &instruction_data).unwrap();
+    let destination_start = destination.lamports();
+
+    // The rent can't go to an account that is being closed.
+    let mut self_close_accounts = close_accounts(&market_authority, true);
+    self_close_accounts[6] = accounts.bids.clone();
+    assert_eq!(
+        State::process(dex_program_id, &self_close_accounts, &close),
+        Err(DexErrorCode::InvalidRentDestination.into())
+    );
+
+    // The trigger book has to be closed along with the market.
+    assert_eq!(
+        State::process(
+            dex_program_id,
+            &close_accounts(&market_authority, false),
+            &close
+        ),
+        Err(DexErrorCode::WrongTriggerBookAccount.into())
+    );
+
+    let market_accounts = [
+        &accounts.market,
+        &accounts.req_q,
+        &accounts.event_q,
+        &accounts.bids,
+        &accounts.asks,
+        &trigger_book,
+    ];
+    let rent_total: u64 = market_accounts.iter().map(|acc| acc.lamports()).sum();
+    State::process(
+        dex_program_id,
+        &close_accounts(&market_authority, true),
+        &close,
+    )
+    .unwrap();
+    assert_eq!(destination.lamports() - destination_start, rent_total);
+    for account in market_accounts.iter() {
+        assert_eq!(account.lamports(), 0);
+        assert!(account.try_borrow_data().unwrap().iter().all(|&b| b == 0));
+    }
+    assert!(Market::load(&accounts.market, dex_program_id, true).is_err());
+
+    // Markets without an admin are closed by the disable authority.
+    let accounts = setup_market(&mut rng, &bump);
+    let dex_program_id = accounts.market.owner;
+    let disable_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        disable_authority.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::DisableMarket.pack();
+    State::process(dex_program_id, disable_accounts, &instruction_data).unwrap();
+    let close_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        disable_authority.clone(),
+        destination.clone(),
+    ]
+    .into_bump_slice();
+    State::process(dex_program_id, close_accounts, &close).unwrap();
+    assert_eq!(accounts.market.lamports(), 0);
+}
+
+#[test]
+fn test_cancel_only() {
+    let mut rng = StdRng::seed_from_u64(19);
+    let bump = Bump::new();
+
+    let market_authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let accounts = setup_market_with_authority(&mut rng, &bump, Some(&market_authority));
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let coin_account = new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 0, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 100_000, &bump);
+
+    let init_accounts: &[AccountInfo] = bump_vec![in &bump;
+        orders_account.clone(),
+        owner.clone(),
+        accounts.market.clone(),
+        accounts.rent_sysvar.clone(),
+        market_authority.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::InitOpenOrders.pack();
+    State::process(dex_program_id, init_accounts, &instruction_data).unwrap();
+
+    let order_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        pc_account.clone(),
+        owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let new_order = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+        side: Side::Bid,
+        limit_price: NonZeroU64::new(10_000).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(10_000).unwrap(),
+        order_type: OrderType::Limit,
+        client_order_id: 0,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        limit: 5,
+        max_ts: i64::MAX,
+    })
+    .pack();
+    State::process(dex_program_id, order_accounts, &new_order).unwrap();
+
+    let stranger = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let stranger_accounts: &[AccountInfo] =
+        bump_vec![in &bump; accounts.market.clone(), stranger.clone()].into_bump_slice();
+    let authority_accounts: &[AccountInfo] =
+        bump_vec![in &bump; accounts.market.clone(), market_authority.clone()].into_bump_slice();
+    let enter = MarketInstruction::EnterCancelOnly.pack();
+    assert_eq!(
+        State::process(dex_program_id, stranger_accounts, &enter),
+        Err(DexErrorCode::WrongSigner.into())
+    );
+    State::process(dex_program_id, authority_accounts, &enter).unwrap();
+
+    assert_eq!(
+        State::process(dex_program_id, order_accounts, &new_order),
+        Err(DexErrorCode::MarketIsCancelOnly.into())
+    );
+
+    // Cancelling, cranking and settling still work.
+    let cancel_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        orders_account.clone(),
+        owner.clone(),
+        accounts.event_q.clone(),
+    ]
+    .into_bump_slice();
+    let cancel_all = MarketInstruction::CancelAllOrders(CancelAllOrdersInstruction {
+        side: None,
+        limit: 10,
+    })
+    .pack();
+    State::process(dex_program_id, cancel_accounts, &cancel_all).unwrap();
+    let crank_accounts = bump_vec![in &bump;
+        orders_account.clone(),
+        accounts.market.clone(),
+        accounts.event_q.clone(),
+        coin_account.clone(),
+        pc_account.clone(),
+    ]
+    .into_bump_slice_mut();
+    let instruction_data = MarketInstruction::ConsumeEvents(10).pack();
+    State::process(dex_program_id, crank_accounts, &instruction_data).unwrap();
+    let settle_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account.clone(),
+        owner.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        coin_account.clone(),
+        pc_account.clone(),
+        accounts.vault_signer.clone(),
+        spl_token_program.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::SettleFunds.pack();
+    State::process(dex_program_id, settle_accounts, &instruction_data).unwrap();
+    let pc = TokenAccount::new(&pc_account).unwrap();
+    assert_eq!(pc.balance().unwrap(), 100_000);
+
+    let exit = MarketInstruction::ExitCancelOnly.pack();
+    State::process(dex_program_id, authority_accounts, &exit).unwrap();
+    State::process(dex_program_id, order_accounts, &new_order).unwrap();
+    let bids_data = accounts.bids.try_borrow_data().unwrap();
+    let bids = load_book_side(&bids_data, Side::Bid).unwrap();
+    assert_eq!(bids.iter(true).count(), 1);
+}
+
+#[test]
+fn test_authority_transfer() {
+    let mut rng = StdRng::seed_from_u64(20);
+    let bump = Bump::new();
+
+    let market_authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let accounts = setup_market_with_authority(&mut rng, &bump, Some(&market_authority));
+    let dex_program_id = accounts.market.owner;
+
+    let new_authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let stranger = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let admin_accounts: &[AccountInfo] =
+        bump_vec![in &bump; accounts.market.clone(), market_authority.clone()].into_bump_slice();
+    let new_authority_accounts: &[AccountInfo] =
+        bump_vec![in &bump; accounts.market.clone(), new_authority.clone()].into_bump_slice();
+    let stranger_accounts: &[AccountInfo] =
+        bump_vec![in &bump; accounts.market.clone(), stranger.clone()].into_bump_slice();
+
+    let propose = |kind| {
+        MarketInstruction::ProposeAuthority(ProposeAuthorityInstruction {
+            kind,
+            new_authority: new_authority.key.to_bytes(),
+        })
+        .pack()
+    };
+    assert_eq!(
+        State::process(
+            dex_program_id,
+            stranger_accounts,
+            &propose(AuthorityKind::OpenOrders)
+        ),
+        Err(DexErrorCode::WrongSigner.into())
+    );
+    State::process(
+        dex_program_id,
+        admin_accounts,
+        &propose(AuthorityKind::OpenOrders),
+    )
+    .unwrap();
+
+    // Only the proposed key can accept, and only for the proposed kind.
+    let accept_open_orders = MarketInstruction::AcceptAuthority(AuthorityKind::OpenOrders).pack();
+    assert_eq!(
+        State::process(dex_program_id, stranger_accounts, &accept_open_orders),
+        Err(DexErrorCode::NoPendingAuthority.into())
+    );
+    assert_eq!(
+        State::process(
+            dex_program_id,
+            new_authority_accounts,
+            &MarketInstruction::AcceptAuthority(AuthorityKind::Prune).pack()
+        ),
+        Err(DexErrorCode::NoPendingAuthority.into())
+    );
+    State::process(dex_program_id, new_authority_accounts, &accept_open_orders).unwrap();
+    {
+        let market = Market::load(&accounts.market, &dex_program_id, false).unwrap();
+        assert_eq!(market.open_orders_authority(), Some(new_authority.key));
+        assert_eq!(market.admin(), Some(market_authority.key));
+    }
+    assert_eq!(
+        State::process(dex_program_id, new_authority_accounts, &accept_open_orders),
+        Err(DexErrorCode::NoPendingAuthority.into())
+    );
+
+    // A per-market disable authority can act on the market.
+    State::process(
+        dex_program_id,
+        admin_accounts,
+        &propose(AuthorityKind::Disable),
+    )
+    .unwrap();
+    State::process(
+        dex_program_id,
+        new_authority_accounts,
+        &MarketInstruction::AcceptAuthority(AuthorityKind::Disable).pack(),
+    )
+    .unwrap();
+    let enter_cancel_only = MarketInstruction::EnterCancelOnly.pack();
+    let exit_cancel_only = MarketInstruction::ExitCancelOnly.pack();
+    State::process(dex_program_id, new_authority_accounts, &enter_cancel_only).unwrap();
+
+    // Only the admin can drop it again, and only authorities with a default
+    // can be reset.
+    let reset = |kind| MarketInstruction::ResetAuthority(kind).pack();
+    assert_eq!(
+        State::process(
+            dex_program_id,
+            new_authority_accounts,
+            &reset(AuthorityKind::Disable)
+        ),
+        Err(DexErrorCode::WrongSigner.into())
+    );
+    for kind in [AuthorityKind::Admin, AuthorityKind::OpenOrders] {
+        assert_eq!(
+            State::process(dex_program_id, admin_accounts, &reset(kind)),
+            Err(DexErrorCode::AuthorityHasNoDefault.into())
+        );
+    }
+    State::process(
+        dex_program_id,
+        admin_accounts,
+        &reset(AuthorityKind::Disable),
+    )
+    .unwrap();
+    assert_eq!(
+        State::process(dex_program_id, new_authority_accounts, &exit_cancel_only),
+        Err(DexErrorCode::WrongSigner.into())
+    );
+    State::process(dex_program_id, admin_accounts, &exit_cancel_only).unwrap();
+
+    // The global disable authority keeps working as a kill switch whatever
+    // the market sets.
+    State::process(
+        dex_program_id,
+        admin_accounts,
+        &propose(AuthorityKind::Disable),
+    )
+    .unwrap();
+    State::process(
+        dex_program_id,
+        new_authority_accounts,
+        &MarketInstruction::AcceptAuthority(AuthorityKind::Disable).pack(),
+    )
+    .unwrap();
+    let global_disable_authority = AccountInfo::new(
+        bump.alloc(disable_authority::ID),
+        true,
+        false,
+        bump.alloc(0),
+        &mut [],
+        &system_program::ID,
+        false,
+        Epoch::default(),
+    );
+    let global_disable_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        global_disable_authority,
+    ]
+    .into_bump_slice();
+    let disable = MarketInstruction::DisableMarket.pack();
+    assert!(State::process(dex_program_id, stranger_accounts, &disable).is_err());
+    State::process(dex_program_id, global_disable_accounts, &disable).unwrap();
+    let market = Market::load(&accounts.market, &dex_program_id, true).unwrap();
+    assert_ne!(
+        identity(market.account_flags) & (AccountFlag::Disabled as u64),
+        0
+    );
+}
+
+#[test]
+fn test_market_stats() {
+    let mut rng = StdRng::seed_from_u64(21);
+    let bump = Bump::new();
+
+    let market_authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let accounts = setup_market_with_authority(&mut rng, &bump, Some(&market_authority));
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let maker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_maker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let maker_coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, maker.key, 10_000, &bump);
+    let taker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_taker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let taker_pc_account =
+        new_token_account(&mut rng, accounts.pc_mint.key, taker.key, 500_000, &bump);
+
+    let start_ts = 1_700_000_000;
+    state::UNIX_TIMESTAMP.with(|ts| ts.set(start_ts));
+    let orders = [
+        (
+            &orders_account_maker,
+            &maker,
+            &maker_coin_account,
+            Side::Ask,
+            2,
+        ),
+        (
+            &orders_account_taker,
+            &taker,
+            &taker_pc_account,
+            Side::Bid,
+            1,
+        ),
+    ];
+    let mut order_accounts: &[AccountInfo] = &[];
+    for (open_orders, owner, payer, side, qty) in orders {
+        let init_accounts: &[AccountInfo] = bump_vec![in &bump;
+            open_orders.clone(),
+            owner.clone(),
+            accounts.market.clone(),
+            accounts.rent_sysvar.clone(),
+            market_authority.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::InitOpenOrders.pack();
+        State::process(dex_program_id, init_accounts, &instruction_data).unwrap();
+
+        order_accounts = bump_vec![in &bump;
+            accounts.market.clone(),
+            open_orders.clone(),
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            payer.clone(),
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(100_000).unwrap(),
+            max_coin_qty: NonZeroU64::new(qty).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(200_000).unwrap(),
+            order_type: OrderType::Limit,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack();
+        State::process(dex_program_id, order_accounts, &instruction_data).unwrap();
+    }
+
+    let (coin_lot_size, pc_lot_size, stats) = {
+        let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+        (
+            market.coin_lot_size,
+            market.pc_lot_size,
+            market.stats().unwrap(),
+        )
+    };
+    assert_eq!(identity(stats.last_trade_price), 100_000);
+    assert_eq!(identity(stats.last_trade_ts), start_ts);
+    assert_eq!(identity(stats.trade_count), 1);
+    assert_eq!(identity(stats.base_volume), coin_lot_size as u128);
+    assert_eq!(identity(stats.quote_volume), 100_000 * pc_lot_size as u128);
+    assert_eq!(stats.price_cumulative(start_ts), 0);
+
+    // `order_accounts` are left holding the taker's. The second fill accrues
+    // the first price over the time in between.
+    state::UNIX_TIMESTAMP.with(|ts| ts.set(start_ts + 10));
+    let instruction_data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+        side: Side::Bid,
+        limit_price: NonZeroU64::new(100_000).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(200_000).unwrap(),
+        order_type: OrderType::Limit,
+        client_order_id: 0,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        limit: 5,
+        max_ts: i64::MAX,
+    })
+    .pack();
+    State::process(dex_program_id, order_accounts, &instruction_data).unwrap();
+
+    let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+    let stats = market.stats().unwrap();
+    assert_eq!(identity(stats.trade_count), 2);
+    assert_eq!(identity(stats.base_volume), 2 * coin_lot_size as u128);
+    assert_eq!(stats.price_cumulative(start_ts + 10), 10 * 100_000);
+    assert_eq!(stats.price_cumulative(start_ts + 30), 30 * 100_000);
+}
+
+#[test]
+fn test_price_band() {
+    let mut rng = StdRng::seed_from_u64(22);
+    let bump = Bump::new();
+
+    let market_authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let accounts = setup_market_with_authority(&mut rng, &bump, Some(&market_authority));
+
+    let dex_program_id = accounts.market.owner;
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let maker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_maker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let maker_coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, maker.key, 10_000, &bump);
+    let taker = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let orders_account_taker =
+        new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let taker_pc_account =
+        new_token_account(&mut rng, accounts.pc_mint.key, taker.key, 2_000_000, &bump);
+
+    let mut order_accounts = Vec::new();
+    for (open_orders, owner, payer) in [
+        (&orders_account_maker, &maker, &maker_coin_account),
+        (&orders_account_taker, &taker, &taker_pc_account),
+    ] {
+        let init_accounts: &[AccountInfo] = bump_vec![in &bump;
+            open_orders.clone(),
+            owner.clone(),
+            accounts.market.clone(),
+            accounts.rent_sysvar.clone(),
+            market_authority.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::InitOpenOrders.pack();
+        State::process(dex_program_id, init_accounts, &instruction_data).unwrap();
+
+        let new_order_accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            open_orders.clone(),
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            payer.clone(),
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+        ]
+        .into_bump_slice();
+        order_accounts.push(new_order_accounts);
+    }
+    let order = |side, limit_price, order_type| {
+        MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(1).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(300_000).unwrap(),
+            order_type,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+        .pack()
+    };
+    for price in [100_000, 120_000] {
+        let instruction_data = order(Side::Ask, price, OrderType::Limit);
+        State::process(dex_program_id, order_accounts[0], &instruction_data).unwrap();
+    }
+    // Sets the reference price.
+    let instruction_data = order(Side::Bid, 100_000, OrderType::ImmediateOrCancel);
+    State::process(dex_program_id, order_accounts[1], &instruction_data).unwrap();
+
+    let stranger = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let stranger_accounts: &[AccountInfo] =
+        bump_vec![in &bump; accounts.market.clone(), stranger.clone()].into_bump_slice();
+    let admin_accounts: &[AccountInfo] =
+        bump_vec![in &bump; accounts.market.clone(), market_authority.clone()].into_bump_slice();
+    let set_band = MarketInstruction::SetPriceBand(1_000).pack();
+    assert_eq!(
+        State::process(dex_program_id, stranger_accounts, &set_band),
+        Err(DexErrorCode::WrongSigner.into())
+    );
+    State::process(dex_program_id, admin_accounts, &set_band).unwrap();
+    {
+        let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+        assert_eq!(
+            market.price_band(),
+            Some(PriceBand {
+                min_price: 90_000,
+                max_price: 110_000,
+            })
+        );
+    }
+
+    // The next ask is 20% above the last trade.
+    let sweep = order(Side::Bid, 130_000, OrderType::ImmediateOrCancel);
+    assert_eq!(
+        State::process(dex_program_id, order_accounts[1], &sweep),
+        Err(DexErrorCode::PriceBandExceeded.into())
+    );
+
+    // A stop that would sweep the same ask is cancelled at the band instead
+    // of failing the order that triggers it.
+    let trigger_book = new_trigger_book(&mut rng, &accounts, &market_authority, &bump);
+    let stop_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        orders_account_taker.clone(),
+        accounts.req_q.clone(),
+        accounts.event_q.clone(),
+        trigger_book.clone(),
+        taker_pc_account.clone(),
+        taker.clone(),
+        accounts.coin_vault.clone(),
+        accounts.pc_vault.clone(),
+        spl_token_program.clone(),
+        accounts.rent_sysvar.clone(),
+    ]
+    .into_bump_slice();
+    let instruction_data = MarketInstruction::NewTriggerOrder(NewTriggerOrderInstruction {
+        side: Side::Bid,
+        trigger_price: NonZeroU64::new(100_000).unwrap(),
+        limit_price: NonZeroU64::new(130_000).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(300_000).unwrap(),
+        self_trade_behavior: SelfTradeBehavior::DecrementTake,
+        order_type: OrderType::ImmediateOrCancel,
+        client_order_id: 0,
+    })
+    .pack();
+    State::process(dex_program_id, stop_accounts, &instruction_data).unwrap();
+    let instruction_data = order(Side::Ask, 100_000, OrderType::Limit);
+    State::process(dex_program_id, order_accounts[0], &instruction_data).unwrap();
+    let mut trigger_accounts = order_accounts[1].to_vec();
+    trigger_accounts.push(trigger_book.clone());
+    let instruction_data = order(Side::Bid, 100_000, OrderType::ImmediateOrCancel);
+    State::process(dex_program_id, &trigger_accounts, &instruction_data).unwrap();
+    let events = client::decode_event_queue(&accounts.event_q.try_borrow_data().unwrap()).unwrap();
+    assert!(matches!(
+        events.last(),
+        Some(EventView::Out {
+            side: Side::Bid,
+            release_funds: true,
+            native_qty_unlocked: 300_000,
+            reason: OutReason::PriceBand,
+            ..
+        })
+    ));
+
+    // The ask the stop would have taken is still there.
+    let instruction_data = MarketInstruction::SetPriceBand(2_500).pack();
+    State::process(dex_program_id, admin_accounts, &instruction_data).unwrap();
+    State::process(dex_program_id, order_accounts[1], &sweep).unwrap();
+    let market = Market::load(&accounts.market, dex_program_id, false).unwrap();
+    assert_eq!(identity(market.stats().unwrap().last_trade_price), 120_000);
+}
+
+#[test]
+fn test_simulator() {
+    let mut sim = Simulator::new(SimulatorConfig::default()).unwrap();
+    let maker = sim.add_user(10_000, 0).unwrap();
+    let taker = sim.add_user(0, 1_000_000).unwrap();
+    let order = |side, qty, order_type| {
+        MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(100_000).unwrap(),
+            max_coin_qty: NonZeroU64::new(qty).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(qty * 200_000).unwrap(),
+            order_type,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        })
+    };
+
+    let outcome = sim
+        .execute(maker, order(Side::Ask, 2, OrderType::Limit))
+        .unwrap();
+    assert_eq!(outcome.balances.coin_wallet, 8_000);
+    assert_eq!(outcome.balances.coin_total, 2_000);
+    let level = L2Level {
+        price: 100_000,
+        quantity: 2,
+        order_count: 1,
+    };
+    assert_eq!(sim.l2_depth(Side::Ask, None).unwrap(), vec![level]);
+
+    // A failed instruction leaves the book as it was, even though matching
+    // filled against it before the order was killed.
+    assert_eq!(
+        sim.execute(taker, order(Side::Bid, 5, OrderType::FillOrKill))
+            .unwrap_err(),
+        DexErrorCode::FillOrKillNotFilled.into()
+    );
+    assert_eq!(sim.l2_depth(Side::Ask, None).unwrap(), vec![level]);
+    assert_eq!(sim.balances(taker).unwrap().pc_wallet, 1_000_000);
+
+    let outcome = sim
+        .execute(taker, order(Side::Bid, 1, OrderType::ImmediateOrCancel))
+        .unwrap();
+    assert!(outcome.events.iter().any(|event| matches!(
+        event,
+        EventView::Fill {
+            maker: true,
+            side: Side::Ask,
+            ..
+        }
+    )));
+    assert_eq!(outcome.balances.coin_free, 1_000);
+
+    let outcome = sim.execute(taker, MarketInstruction::SettleFunds).unwrap();
+    assert_eq!(outcome.balances.coin_wallet, 1_000);
+    let outcome = sim.execute(maker, MarketInstruction::SettleFunds).unwrap();
+    assert!(outcome.balances.pc_wallet >= 100_000);
+    assert_eq!(outcome.balances.coin_total, 1_000);
+}
+
+#[test]
+fn test_quote_send_take() {
+    let mut sim = Simulator::new(SimulatorConfig::default()).unwrap();
+    let maker = sim.add_user(20_000, 0).unwrap();
+    let taker = sim.add_user(0, 10_000_000).unwrap();
+    let order = |limit_price, max_coin_qty| NewOrderInstructionV3 {
+        side: Side::Ask,
+        limit_price: NonZeroU64::new(limit_price).unwrap(),
+        max_coin_qty: NonZeroU64::new(max_coin_qty).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(u64::MAX).unwrap(),
+        order_type: OrderType::Limit,
+        client_order_id: 0,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        limit: 5,
+        max_ts: i64::MAX,
+    };
+    sim.execute(
+        maker,
+        MarketInstruction::NewIcebergOrder(NewIcebergOrderInstruction {
+            order: order(100_000, 5),
+            display_qty: NonZeroU64::new(2).unwrap(),
+        }),
+    )
+    .unwrap();
+    sim.execute(maker, MarketInstruction::NewOrderV3(order(100_000, 1)))
+        .unwrap();
+    sim.execute(maker, MarketInstruction::NewOrderV3(order(101_000, 3)))
+        .unwrap();
+    // Matching takes the taker fee out of pc deposits before SendTake pays in.
+    let bid = NewOrderInstructionV3 {
+        side: Side::Bid,
+        max_native_pc_qty_including_fees: NonZeroU64::new(60_000).unwrap(),
+        ..order(50_000, 1)
+    };
+    sim.execute(taker, MarketInstruction::NewOrderV3(bid))
+        .unwrap();
+
+    let take = |side, min_coin_qty, min_native_pc_qty| SendTakeInstruction {
+        side,
+        limit_price: NonZeroU64::new(101_000).unwrap(),
+        max_coin_qty: NonZeroU64::new(8).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(10_000_000).unwrap(),
+        min_coin_qty,
+        min_native_pc_qty,
+        limit: 10,
+    };
+    let quote = |sim: &Simulator, instruction: &SendTakeInstruction| {
+        quote_send_take(
+            &sim.program_id(),
+            sim.account_data(&sim.market()).unwrap(),
+            sim.account_data(&sim.req_q()).unwrap(),
+            sim.account_data(&sim.event_q()).unwrap(),
+            sim.account_data(&sim.bids()).unwrap(),
+            sim.account_data(&sim.asks()).unwrap(),
+            instruction,
+            FeeTier::Base,
+            0,
+        )
+    };
+
+    // The iceberg's replenished slices queue behind the plain order at 100_000.
+    let bid = take(Side::Bid, 8, 0);
+    let expected = quote(&sim, &bid).unwrap();
+    assert_eq!(expected.native_coin_qty, 8_000);
+    assert_eq!(
+        expected.levels,
+        vec![
+            L2Level {
+                price: 100_000,
+                quantity: 6,
+                order_count: 4,
+            },
+            L2Level {
+                price: 101_000,
+                quantity: 2,
+                order_count: 1,
+            },
+        ]
+    );
+    assert_eq!(
+        expected.native_pc_qty,
+        6 * 100_000 + 2 * 101_000 + expected.native_taker_fee
+    );
+    assert_eq!(
+        quote(&sim, &take(Side::Bid, 9, 0)).unwrap_err(),
+        DexErrorCode::MinAmountNotMet.into()
+    );
+    assert_eq!(
+        quote(&sim, &take(Side::Ask, 0, 1)).unwrap_err(),
+        DexErrorCode::MinAmountNotMet.into()
+    );
+
+    // Quoting matches on copies and leaves the accounts as they were.
+    let book_before = sim.account_data(&sim.asks()).unwrap().to_vec();
+    let events_before = sim.account_data(&sim.event_q()).unwrap().to_vec();
+    quote(&sim, &bid).unwrap();
+    assert_eq!(sim.account_data(&sim.asks()).unwrap(), &book_before[..]);
+    assert_eq!(
+        sim.account_data(&sim.event_q()).unwrap(),
+        &events_before[..]
+    );
+
+    let before = sim.balances(taker).unwrap();
+    let outcome = sim
+        .execute(taker, MarketInstruction::SendTake(bid))
+        .unwrap();
+    let after = outcome.balances;
+    assert_eq!(
+        after.coin_wallet - before.coin_wallet,
+        expected.native_coin_qty
+    );
+    assert_eq!(before.pc_wallet - after.pc_wallet, expected.native_pc_qty);
+    let coin_lot_size = SimulatorConfig::default().coin_lot_size;
+    let mut filled: Vec<L2Level> = Vec::new();
+    for event in outcome.events {
+        if let EventView::Fill {
+            maker: true,
+            native_qty_paid,
+            order_id,
+            ..
+        } = event
+        {
+            let price = (order_id >> 64) as u64;
+            match filled.last_mut() {
+                Some(last) if last.price == price => {
+                    last.quantity += native_qty_paid / coin_lot_size;
+                    last.order_count += 1;
+                }
+                _ => filled.push(L2Level {
+                    price,
+                    quantity: native_qty_paid / coin_lot_size,
+                    order_count: 1,
+                }),
+            }
+        }
+    }
+    assert_eq!(filled, expected.levels);
+}
+
+#[test]
+fn test_client_decoders() {
+    let mut sim = Simulator::new(SimulatorConfig::default()).unwrap();
+    let maker = sim.add_user(10_000, 1_000_000).unwrap();
+    let order = |side, limit_price, max_coin_qty| NewOrderInstructionV3 {
+        side,
+        limit_price: NonZeroU64::new(limit_price).unwrap(),
+        max_coin_qty: NonZeroU64::new(max_coin_qty).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(100_000).unwrap(),
+        order_type: OrderType::Limit,
+        client_order_id: 7,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        limit: 5,
+        max_ts: i64::MAX,
+    };
+    sim.execute(
+        maker,
+        MarketInstruction::NewIcebergOrder(NewIcebergOrderInstruction {
+            order: order(Side::Ask, 100_000, 5),
+            display_qty: NonZeroU64::new(2).unwrap(),
+        }),
+    )
+    .unwrap();
+    sim.execute(
+        maker,
+        MarketInstruction::NewOrderV3(order(Side::Bid, 90_000, 1)),
+    )
+    .unwrap();
+    let keys = sim.user_accounts(maker);
+
+    let market_data = sim.account_data(&sim.market()).unwrap();
+    assert_eq!(
+        client::account_layout(market_data).unwrap(),
+        client::AccountLayout::MarketV2
+    );
+    let market = client::decode_market(market_data).unwrap();
+    assert_eq!(market.own_address, sim.market());
+    assert_eq!(market.bids, sim.bids());
+    assert_eq!(market.asks, sim.asks());
+    assert_eq!(market.coin_lot_size, 1_000);
+    let market_v2 = market.v2.unwrap();
+    assert_eq!(market_v2.admin, sim.authority());
+    assert!(market_v2.pending_authority.is_none());
+
+    let open_orders =
+        client::decode_open_orders(sim.account_data(&keys.open_orders).unwrap()).unwrap();
+    assert_eq!(open_orders.owner, keys.owner);
+    assert_eq!(open_orders.native_coin_total, 5_000);
+    assert_eq!(open_orders.orders.len(), 2);
+    let ask = open_orders.orders[0];
+    assert_eq!(
+        (ask.side, ask.price, ask.client_order_id),
+        (Side::Ask, 100_000, 7)
+    );
+
+    let asks = client::decode_book_side(sim.account_data(&sim.asks()).unwrap()).unwrap();
+    assert_eq!(asks.side, Side::Ask);
+    assert_eq!(asks.orders.len(), 1);
+    assert_eq!(asks.orders[0].order_id, ask.order_id);
+    assert_eq!(asks.orders[0].quantity, 2);
+    assert_eq!(asks.orders[0].hidden_quantity, 3);
+    assert_eq!(asks.orders[0].owner, keys.open_orders);
+    assert!(client::decode_market(sim.account_data(&sim.asks()).unwrap()).is_err());
+
+    // Leave the cancel's event on the queue rather than cranking it.
+    let cancel = instruction::cancel_order(
+        &sim.program_id(),
+        &sim.market(),
+        &sim.bids(),
+        &sim.asks(),
+        &keys.open_orders,
+        &keys.owner,
+        &market.event_q,
+        Side::Ask,
+        ask.order_id,
+    )
+    .unwrap();
+    sim.process(&cancel).unwrap();
+    let events = client::decode_event_queue(sim.account_data(&market.event_q).unwrap()).unwrap();
+    assert!(matches!(
+        events[..],
+        [EventView::Out {
+            side: Side::Ask,
+            native_qty_unlocked: 5_000,
+            reason: OutReason::Cancelled,
+            ..
+        }]
+    ));
+    let requests = client::decode_request_queue(sim.account_data(&market.req_q).unwrap()).unwrap();
+    assert!(requests.is_empty());
+}
+
+#[test]
+fn test_describe_instruction() {
+    let program_id = Pubkey::new_unique();
+    let k = Pubkey::new_unique();
+    let one = NonZeroU64::new(1).unwrap();
+    let stb = SelfTradeBehavior::AbortTransaction;
+    let instructions = vec![
+        instruction::initialize_market(
+            &k,
+            &program_id,
+            &k,
+            &k,
+            &k,
+            &k,
+            Some(&k),
+            Some(&k),
+            Some(&k),
+            &k,
+            &k,
+            &k,
+            &k,
+            1,
+            1,
+            0,
+            1,
+            1,
+            1,
+        ),
+        instruction::new_order(
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            Some(&k),
+            &program_id,
+            Side::Bid,
+            one,
+            one,
+            OrderType::Limit,
+            0,
+            stb,
+            1,
+            one,
+            i64::MAX,
+        ),
+        instruction::match_orders(&program_id, &k, &k, &k, &k, &k, &k, &k, Some(&k), 1),
+        instruction::consume_events(&program_id, vec![&k, &k], &k, &k, &k, &k, None, 1),
+        instruction::consume_events_permissioned(&program_id, vec![&k], &k, &k, &k, None, 1),
+        instruction::cancel_order(&program_id, &k, &k, &k, &k, &k, &k, Side::Bid, 1),
+        instruction::settle_funds(&program_id, &k, &k, &k, &k, &k, &k, &k, &k, Some(&k), &k),
+        instruction::cancel_orders_by_client_order_ids(&program_id, &k, &k, &k, &k, &k, &k, [1; 8]),
+        instruction::disable_market(&program_id, &k, &k),
+        instruction::sweep_fees(&program_id, &k, &k, &k, &k, &k, &k),
+        instruction::send_take(
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            Some(&k),
+            &program_id,
+            Side::Ask,
+            one,
+            one,
+            one,
+            0,
+            0,
+            1,
+        ),
+        instruction::close_open_orders(&program_id, &k, &k, &k, &k),
+        instruction::init_open_orders(&program_id, &k, &k, &k, Some(&k)),
+        instruction::prune(&program_id, &k, &k, &k, &k, &k, &k, &k, 1),
+        instruction::cancel_all_orders(&program_id, &k, &k, &k, &k, &k, &k, None, 1),
+        instruction::new_trigger_order(
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            None,
+            &program_id,
+            Side::Bid,
+            one,
+            one,
+            one,
+            OrderType::Limit,
+            0,
+            stb,
+            one,
+        ),
+        instruction::cancel_trigger_order(&program_id, &k, &k, &k, &k, 1),
+        instruction::prune_expired_orders(&program_id, &k, &k, &k, &k, 1),
+        instruction::enable_fill_details(&program_id, &k, &k, &k),
+        instruction::disable_fill_details(&program_id, &k, &k, &k),
+        instruction::init_trigger_book(&program_id, &k, &k, &k),
+        instruction::close_market(&program_id, &k, &k, &k, &k, &k, &k, &k, None),
+        instruction::enter_cancel_only(&program_id, &k, &k),
+        instruction::exit_cancel_only(&program_id, &k, &k),
+        instruction::propose_authority(&program_id, &k, &k, AuthorityKind::Admin, &k),
+        instruction::accept_authority(&program_id, &k, &k, AuthorityKind::Admin),
+        instruction::set_price_band(&program_id, &k, &k, 100),
+        instruction::reset_authority(&program_id, &k, &k, AuthorityKind::Disable),
+        instruction::amend_order(
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            None,
+            &program_id,
+            Side::Bid,
+            1,
+            one,
+            one,
+            OrderType::Limit,
+            stb,
+            1,
+            one,
+        ),
+        instruction::new_order_with_expiry(
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            &k,
+            None,
+            &program_id,
+            Side::Bid,
+            one,
+            one,
+            OrderType::Limit,
+            0,
+            stb,
+            1,
+            one,
+            i64::MAX,
+            1,
+        ),
+    ];
+    // The builders and the documented roles agree on every account.
+    for instruction in instructions {
+        let decoded = client::describe(&instruction.unwrap()).unwrap();
+        for account in &decoded.accounts {
+            assert!(
+                account
+                    .roles
+                    .iter()
+                    .any(|role| (role.writable, role.signer)
+                        == (account.is_writable, account.is_signer)),
+                "{} {:?}",
+                decoded.name,
+                account.roles
+            );
+        }
+    }
+    let role_names = |instruction: &Instruction| -> Vec<Vec<&str>> {
+        let decoded = client::describe(instruction).unwrap();
+        decoded
+            .accounts
+            .iter()
+            .map(|account| account.roles.iter().map(|role| role.name).collect())
+            .collect()
+    };
+
+    // NewOrderV3's optional accounts come before any number of the owner's
+    // other OpenOrders accounts.
+    let order = NewOrderInstructionV3 {
+        side: Side::Bid,
+        limit_price: one,
+        max_coin_qty: one,
+        max_native_pc_qty_including_fees: one,
+        order_type: OrderType::Limit,
+        client_order_id: 1,
+        self_trade_behavior: stb,
+        limit: 1,
+        max_ts: i64::MAX,
+    };
+    let mut replace = Instruction {
+        program_id,
+        accounts: vec![AccountMeta::new(k, false); 16],
+        data: MarketInstruction::ReplaceOrdersByClientIds(vec![order]).pack(),
+    };
+    assert_eq!(
+        client::describe(&replace).unwrap().name,
+        "ReplaceOrdersByClientIds"
+    );
+    let fee_discount = "(M)SRM fee discount account";
+    let sibling = "other open orders of the owner";
+    let roles = role_names(&replace);
+    assert_eq!(roles[0], ["market"]);
+    assert_eq!(roles[12], [sibling, fee_discount, "trigger book"]);
+    assert_eq!(roles[13], [sibling, "trigger book"]);
+    assert_eq!(roles[14..], [[sibling], [sibling]]);
+
+    // The program tells a lone trigger book from a fee discount account by
+    // its owner, so by position it could be either.
+    let mut with_trigger_book = instruction::new_order(
+        &k,
+        &k,
+        &k,
+        &k,
+        &k,
+        &k,
+        &k,
+        &k,
+        &k,
+        &k,
+        &k,
+        &k,
+        None,
+        &program_id,
+        Side::Bid,
+        one,
+        one,
+        OrderType::Limit,
+        0,
+        stb,
+        1,
+        one,
+        i64::MAX,
+    )
+    .unwrap();
+    with_trigger_book.accounts.push(AccountMeta::new(k, false));
+    let roles = role_names(&with_trigger_book);
+    assert_eq!(roles.len(), 13);
+    assert_eq!(roles[12], [sibling, fee_discount, "trigger book"]);
+
+    // ConsumeEvents takes the trigger book after the fee accounts, so every
+    // account before it may be shifted by one.
+    let consume =
+        instruction::consume_events(&program_id, vec![&k, &k], &k, &k, &k, &k, Some(&k), 1)
+            .unwrap();
+    let roles = role_names(&consume);
+    assert_eq!(roles[1], ["open orders"]);
+    assert_eq!(roles[2], ["market", "open orders"]);
+    assert_eq!(
+        roles[5],
+        ["pc fee receivable account", "coin fee receivable account"]
+    );
+    assert_eq!(roles[6], ["trigger book", "pc fee receivable account"]);
+
+    replace.accounts.truncate(11);
+    assert_eq!(
+        client::describe(&replace).unwrap_err(),
+        ProgramError::NotEnoughAccountKeys.into()
+    );
+    let mut disable = instruction::disable_market(&program_id, &k, &k).unwrap();
+    disable.accounts.push(AccountMeta::new(k, false));
+    assert!(client::describe(&disable).is_err());
+}
+
+#[test]
+fn test_idl() {
+    let idl = idl::idl();
+
+    for layout in &idl.layouts {
+        let mut end = 0;
+        for field in &layout.fields {
+            assert_eq!(field.offset, end, "{}.{}", layout.name, field.name);
+            end += field.size;
+        }
+        assert_eq!(end, layout.size, "{} has unlisted fields", layout.name);
+    }
+    let market = &idl.layouts[0];
+    assert_eq!(market.name, "MarketState");
+    assert_eq!(market.size, size_of::<MarketState>());
+    let pc_vault = market.fields.iter().find(|f| f.name == "pc_vault").unwrap();
+    assert_eq!(pc_vault.offset, 20 * 8);
+
+    let samples = idl::instruction_samples();
+    assert_eq!(samples.len(), idl.instructions.len());
+    for (i, (sample, described)) in samples.iter().zip(&idl.instructions).enumerate() {
+        assert_eq!(described.discriminant, i as u32);
+        assert_eq!(described.name, sample.name());
+        let data = sample.pack();
+        assert_eq!(MarketInstruction::unpack(&data).as_ref(), Some(sample));
+        if let Some(len) = described.data_len {
+            assert_eq!(len, data.len(), "{}", described.name);
+        }
+    }
+    let names: Vec<&str> = idl.instructions.iter().map(|i| i.name).collect();
+    let mut runner = proptest::test_runner::TestRunner::default();
+    runner
+        .run(
+            &proptest::prelude::any::<MarketInstruction>(),
+            |instruction| {
+                proptest::prop_assert!(names.contains(&instruction.name()));
+                Ok(())
+            },
+        )
+        .unwrap();
+
+    assert_eq!(idl.errors[0].code, 0);
+    assert_eq!(idl.errors[0].name, "InvalidMarketFlags");
+    let last = idl.errors.last().unwrap();
+    assert_eq!(DexErrorCode::from(last.code), DexErrorCode::AssertionError);
+
+    let generated = serde_json::to_string_pretty(&idl).unwrap() + "\n";
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/idl/serum_dex.json");
+    if std::env::var_os("UPDATE_IDL").is_some() {
+        std::fs::write(path, &generated).unwrap();
+    }
+    let committed = std::fs::read_to_string(path).unwrap_or_default();
+    assert!(
+        committed == generated,
+        "idl/serum_dex.json is out of date, rerun this test with UPDATE_IDL=1"
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&committed).unwrap();
+    let layout_size = |name: &str| {
+        json["layouts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|layout| layout["name"] == name)
+            .unwrap_or_else(|| panic!("{} has no layout", name))["size"]
+            .as_u64()
+            .unwrap() as usize
+    };
+    let sizes = [
+        ("MarketState", size_of::<MarketState>()),
+        ("MarketStateV2", size_of::<MarketStateV2>()),
+        ("OpenOrders", size_of::<OpenOrders>()),
+        ("OpenOrdersV2", size_of::<OpenOrdersV2>()),
+        ("RequestQueueHeader", size_of::<state::RequestQueueHeader>()),
+        ("Request", size_of::<state::Request>()),
+        ("EventQueueHeader", size_of::<EventQueueHeader>()),
+        ("Event", size_of::<Event>()),
+        ("FillDetail", size_of::<state::FillDetail>()),
+        (
+            "OrderBookStateHeader",
+            size_of::<state::OrderBookStateHeader>(),
+        ),
+        ("SlabHeader", size_of::<critbit::SlabHeader>()),
+        ("InnerNode", size_of::<critbit::InnerNode>()),
+        ("LeafNode", size_of::<LeafNode>()),
+        ("FreeNode", size_of::<critbit::FreeNode>()),
+        ("ReserveNode", size_of::<critbit::ReserveNode>()),
+        ("TriggerBookHeader", size_of::<TriggerBookHeader>()),
+        ("TriggerOrder", 112),
+    ];
+    for (name, size) in sizes {
+        assert_eq!(layout_size(name), size, "{}", name);
+    }
+    for account in json["accounts"].as_array().unwrap() {
+        let element_size = account["elementSize"].as_u64().unwrap() as usize;
+        for element in account["elements"].as_array().unwrap() {
+            assert_eq!(layout_size(element.as_str().unwrap()), element_size);
+        }
+        for header in account["headers"].as_array().unwrap() {
+            layout_size(header.as_str().unwrap());
+        }
+    }
+    let flag_value = |flags: &str, flag: &str| {
+        json["flags"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|def| def["name"] == flags)
+            .and_then(|def| {
+                def["flags"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .find(|f| f["name"] == flag)
+            })
+            .unwrap_or_else(|| panic!("{}::{} is not described", flags, flag))["value"]
+            .as_u64()
+            .unwrap()
+    };
+    assert_eq!(
+        flag_value("AccountFlag", "CancelOnly"),
+        AccountFlag::CancelOnly as u64
+    );
+    assert_eq!(flag_value("EventFlag", "Detail"), 0x20);
+    let out_reasons = json["types"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|def| def["name"] == "OutReason")
+        .unwrap()["variants"]
+        .clone();
+    assert_eq!(
+        out_reasons[OutReason::PriceBand as usize],
+        serde_json::json!("PriceBand")
+    );
+}
+
+#[test]
+fn test_decode_custom_error() {
+    let err: ProgramError = DexError::from(DexErrorCode::WouldSelfTrade).into();
+    let decoded = DecodedDexError::from_program_error(&err).unwrap();
+    assert_eq!(decoded.code, DexErrorCode::WouldSelfTrade);
+    assert_eq!(decoded.location, None);
+    assert_eq!(
+        decoded.to_string(),
+        "WouldSelfTrade: the order would trade against the owner's own order"
+    );
+
+    let assertion = AssertionError {
+        line: 1234,
+        file_id: SourceFileId::Matching,
+    };
+    let err: ProgramError = DexError::from(assertion).into();
+    let decoded = DecodedDexError::from_program_error(&err).unwrap();
+    assert_eq!(decoded.code, DexErrorCode::AssertionError);
+    assert_eq!(decoded.location, Some((SourceFileId::Matching, 1234)));
+    assert_eq!(
+        decoded.to_string(),
+        "AssertionError: an internal check failed at src/matching.rs:1234"
+    );
+
+    assert_eq!(DecodedDexError::from_custom(500), None);
+    assert_eq!(DecodedDexError::from_custom(9 << 24 | 7), None);
+    assert_eq!(DecodedDexError::from_custom(1 << 24 | 1 << 16), None);
+    assert_eq!(
+        DecodedDexError::from_program_error(&ProgramError::InvalidArgument),
+        None
+    );
+}